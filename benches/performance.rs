@@ -1,5 +1,6 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion};
 use rustle_facts::{parse_fact_output, ArchitectureFacts};
+use std::hint::black_box;
 
 fn bench_parse_fact_output(c: &mut Criterion) {
     let output = r#"
@@ -26,9 +27,45 @@ fn bench_architecture_normalization(c: &mut Criterion) {
     });
 }
 
+#[cfg(feature = "testsupport")]
+fn bench_docker_gathering(c: &mut Criterion) {
+    use rustle_facts::testsupport::ContainerPool;
+    use rustle_facts::{enrich_with_facts, FactsConfig};
+
+    let Some(pool) = ContainerPool::shared() else {
+        eprintln!("Docker not available, skipping docker benchmarks");
+        return;
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let config = FactsConfig {
+        no_cache: true,
+        force_refresh: true,
+        ..Default::default()
+    };
+    let playbook = pool.playbook_json();
+
+    c.bench_function("docker_gather_pool", |b| {
+        b.iter(|| {
+            let mut output = Vec::new();
+            runtime
+                .block_on(enrich_with_facts(
+                    std::io::Cursor::new(playbook.as_str()),
+                    &mut output,
+                    &config,
+                ))
+                .unwrap()
+        })
+    });
+}
+
+#[cfg(not(feature = "testsupport"))]
+fn bench_docker_gathering(_c: &mut Criterion) {}
+
 criterion_group!(
     benches,
     bench_parse_fact_output,
-    bench_architecture_normalization
+    bench_architecture_normalization,
+    bench_docker_gathering
 );
 criterion_main!(benches);
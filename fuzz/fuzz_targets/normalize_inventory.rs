@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(playbook) = rustle_facts::parse_playbook(data) {
+        let _ = rustle_facts::normalize_inventory(&playbook.inventory);
+    }
+});
@@ -0,0 +1,461 @@
+use crate::assertions::fact_value;
+use crate::hostname::normalize_hostname;
+use crate::overrides::glob_match;
+use crate::types::{ArchitectureFacts, EnrichedPlaybook, InventoryGroups, ParsedInventory};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// One build target of the compilation matrix and the hosts that need it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatrixEntry {
+    pub architecture: String,
+    pub system: String,
+    pub libc: Option<String>,
+    pub hosts: Vec<String>,
+}
+
+/// Hosts listed under each value of one fact, for scheduling one build per
+/// value. Hosts without the fact are listed under `unknown`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostsByFact {
+    pub fact: String,
+    pub values: BTreeMap<String, Vec<String>>,
+}
+
+/// How many hosts of one inventory group have each architecture and OS
+/// family, for choosing one build per homogeneous group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupFacts {
+    pub hosts: usize,
+    pub architectures: BTreeMap<String, usize>,
+    pub os_families: BTreeMap<String, usize>,
+}
+
+impl GroupFacts {
+    pub fn is_homogeneous(&self) -> bool {
+        self.architectures.len() <= 1 && self.os_families.len() <= 1
+    }
+}
+
+/// A group targeted by a play whose hosts do not share one architecture or
+/// OS family, forcing rustle to build for several targets.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MixedGroup {
+    pub group: String,
+    pub plays: Vec<String>,
+    pub architectures: BTreeSet<String>,
+    pub os_families: BTreeSet<String>,
+}
+
+impl MixedGroup {
+    pub fn mixes_architectures(&self) -> bool {
+        self.architectures.len() > 1
+    }
+}
+
+/// Find groups named in play host patterns that mix architectures or OS
+/// families, using the enriched host facts.
+pub fn find_mixed_groups(playbook: &EnrichedPlaybook) -> Vec<MixedGroup> {
+    let inventory = &playbook.inventory.base;
+    let mut plays_by_group: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for play in &playbook.plays {
+        let play_name = play.name.clone().unwrap_or_else(|| play.hosts.clone());
+        for group in pattern_groups(&play.hosts) {
+            let plays = plays_by_group.entry(group.to_string()).or_default();
+            if !plays.contains(&play_name) {
+                plays.push(play_name.clone());
+            }
+        }
+    }
+
+    let mut mixed = Vec::new();
+    for (group, plays) in plays_by_group {
+        let members = if group == "all" {
+            playbook.inventory.host_facts.keys().cloned().collect()
+        } else {
+            match group_members(inventory, &group) {
+                Some(members) => members,
+                None => continue,
+            }
+        };
+
+        let facts: Vec<_> = members
+            .iter()
+            .filter_map(|host| playbook.inventory.host_facts.get(host))
+            .collect();
+        let architectures: BTreeSet<String> = facts
+            .iter()
+            .map(|f| f.ansible_architecture.clone())
+            .collect();
+        let os_families: BTreeSet<String> =
+            facts.iter().map(|f| f.ansible_os_family.clone()).collect();
+
+        if architectures.len() > 1 || os_families.len() > 1 {
+            mixed.push(MixedGroup {
+                group,
+                plays,
+                architectures,
+                os_families,
+            });
+        }
+    }
+
+    mixed
+}
+
+/// Summarize the facts of every inventory group's hosts, plus `all`.
+/// Members without facts, such as excluded hosts, are not counted.
+pub fn group_facts(playbook: &EnrichedPlaybook) -> BTreeMap<String, GroupFacts> {
+    let host_facts = &playbook.inventory.host_facts;
+    let summarize = |members: &mut dyn Iterator<Item = &String>| {
+        let mut summary = GroupFacts {
+            hosts: 0,
+            architectures: BTreeMap::new(),
+            os_families: BTreeMap::new(),
+        };
+        for facts in members.filter_map(|host| host_facts.get(host)) {
+            summary.hosts += 1;
+            *summary
+                .architectures
+                .entry(facts.ansible_architecture.clone())
+                .or_default() += 1;
+            *summary
+                .os_families
+                .entry(facts.ansible_os_family.clone())
+                .or_default() += 1;
+        }
+        summary
+    };
+
+    let inventory = &playbook.inventory.base;
+    let mut groups: BTreeMap<String, GroupFacts> = group_names(inventory)
+        .into_iter()
+        .map(|group| {
+            let members = group_members(inventory, group).unwrap_or_default();
+            (group.to_string(), summarize(&mut members.iter()))
+        })
+        .collect();
+    groups.insert("all".to_string(), summarize(&mut host_facts.keys()));
+    groups
+}
+
+/// Group hosts by their unique (architecture, system, libc) combination,
+/// which is the set of binaries rustle has to build.
+pub fn build_matrix(host_facts: &HashMap<String, ArchitectureFacts>) -> Vec<MatrixEntry> {
+    let mut targets: BTreeMap<(String, String, Option<String>), Vec<String>> = BTreeMap::new();
+    for (host, facts) in host_facts {
+        targets
+            .entry((
+                facts.ansible_architecture.clone(),
+                facts.ansible_system.clone(),
+                facts.rustle_libc.clone(),
+            ))
+            .or_default()
+            .push(host.clone());
+    }
+
+    targets
+        .into_iter()
+        .map(|((architecture, system, libc), mut hosts)| {
+            hosts.sort();
+            MatrixEntry {
+                architecture,
+                system,
+                libc,
+                hosts,
+            }
+        })
+        .collect()
+}
+
+/// Invert the facts map for one fact.
+pub fn hosts_by_fact(host_facts: &HashMap<String, ArchitectureFacts>, fact: &str) -> HostsByFact {
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (host, facts) in host_facts {
+        let value = fact_value(facts, fact).unwrap_or_else(|| "unknown".to_string());
+        values.entry(value).or_default().push(host.clone());
+    }
+    for hosts in values.values_mut() {
+        hosts.sort();
+    }
+    HostsByFact {
+        fact: fact.to_string(),
+        values,
+    }
+}
+
+/// Hosts in a group, including those of child groups. `None` if the
+/// inventory has no such group.
+pub fn group_members(inventory: &ParsedInventory, group: &str) -> Option<BTreeSet<String>> {
+    let mut members = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    collect_members(inventory, group, &mut members, &mut visited).then_some(members)
+}
+
+fn collect_members(
+    inventory: &ParsedInventory,
+    group: &str,
+    members: &mut BTreeSet<String>,
+    visited: &mut BTreeSet<String>,
+) -> bool {
+    if !visited.insert(group.to_string()) {
+        return true;
+    }
+
+    match &inventory.groups {
+        InventoryGroups::Simple(groups) => match groups.get(group) {
+            Some(hosts) => {
+                members.extend(hosts.iter().cloned());
+                true
+            }
+            None => false,
+        },
+        InventoryGroups::Detailed(groups) => match groups.get(group) {
+            Some(entry) => {
+                members.extend(entry.hosts.iter().cloned());
+                for child in &entry.children {
+                    collect_members(inventory, child, members, visited);
+                }
+                true
+            }
+            None => false,
+        },
+    }
+}
+
+/// Hosts matched by a play's `hosts` pattern. Names, groups, `all`/`*` and
+/// `*`/`?` globs are unioned, then `&` terms intersect and `!` terms
+/// exclude, as in Ansible.
+pub fn resolve_pattern(
+    inventory: &ParsedInventory,
+    all_hosts: &BTreeSet<String>,
+    pattern: &str,
+) -> BTreeSet<String> {
+    let resolve = |term: &str| -> BTreeSet<String> {
+        if term == "all" || term == "*" {
+            return all_hosts.clone();
+        }
+        if let Some(members) = group_members(inventory, term) {
+            return members;
+        }
+        if term.contains(['*', '?']) {
+            // Match the lowercase pattern against the canonical names too
+            let lower = term.to_lowercase();
+            let mut matched: BTreeSet<String> = all_hosts
+                .iter()
+                .filter(|host| {
+                    glob_match(term, host) || glob_match(&lower, &normalize_hostname(host))
+                })
+                .cloned()
+                .collect();
+            for group in group_names(inventory) {
+                if glob_match(term, group) {
+                    matched.extend(group_members(inventory, group).unwrap_or_default());
+                }
+            }
+            return matched;
+        }
+        all_hosts
+            .get(term)
+            .or_else(|| {
+                let term = normalize_hostname(term);
+                all_hosts
+                    .iter()
+                    .find(|host| normalize_hostname(host) == term)
+            })
+            .map(|host| BTreeSet::from([host.clone()]))
+            .unwrap_or_default()
+    };
+
+    let terms: Vec<&str> = pattern
+        .split([':', ','])
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .collect();
+
+    let mut hosts: BTreeSet<String> = terms
+        .iter()
+        .filter(|term| !term.starts_with(['!', '&']))
+        .flat_map(|term| resolve(term))
+        .collect();
+    for term in &terms {
+        if let Some(term) = term.strip_prefix('&') {
+            let keep = resolve(term);
+            hosts.retain(|host| keep.contains(host));
+        }
+    }
+    for term in &terms {
+        if let Some(term) = term.strip_prefix('!') {
+            for host in resolve(term) {
+                hosts.remove(&host);
+            }
+        }
+    }
+    hosts
+}
+
+/// The facts of the hosts each play targets, keyed by play index.
+pub fn play_host_facts(
+    playbook: &EnrichedPlaybook,
+) -> BTreeMap<usize, BTreeMap<String, ArchitectureFacts>> {
+    let host_facts = &playbook.inventory.host_facts;
+    let all_hosts: BTreeSet<String> = host_facts.keys().cloned().collect();
+
+    playbook
+        .plays
+        .iter()
+        .enumerate()
+        .map(|(index, play)| {
+            let facts = resolve_pattern(&playbook.inventory.base, &all_hosts, &play.hosts)
+                .into_iter()
+                .filter_map(|host| {
+                    let facts = host_facts.get(&host)?.clone();
+                    Some((host, facts))
+                })
+                .collect();
+            (index, facts)
+        })
+        .collect()
+}
+
+fn group_names(inventory: &ParsedInventory) -> Vec<&str> {
+    match &inventory.groups {
+        InventoryGroups::Simple(groups) => groups.keys().map(String::as_str).collect(),
+        InventoryGroups::Detailed(groups) => groups.keys().map(String::as_str).collect(),
+    }
+}
+
+/// Plain group or host names in a play's `hosts` pattern. Exclusions
+/// (`!name`) and intersections (`&name`) do not add hosts on their own and
+/// are skipped.
+fn pattern_groups(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern
+        .split([':', ','])
+        .map(str::trim)
+        .filter(|token| !token.is_empty() && !token.starts_with(['!', '&']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_matrix() {
+        let musl = ArchitectureFacts {
+            rustle_libc: Some("musl".to_string()),
+            ..ArchitectureFacts::fallback()
+        };
+        let host_facts = HashMap::from([
+            ("b".to_string(), ArchitectureFacts::fallback()),
+            ("a".to_string(), ArchitectureFacts::fallback()),
+            ("c".to_string(), musl),
+        ]);
+
+        let matrix = build_matrix(&host_facts);
+        assert_eq!(matrix.len(), 2);
+        assert_eq!(matrix[0].libc, None);
+        assert_eq!(matrix[0].hosts, vec!["a", "b"]);
+        assert_eq!(matrix[1].libc, Some("musl".to_string()));
+        assert_eq!(matrix[1].hosts, vec!["c"]);
+    }
+
+    #[test]
+    fn test_hosts_by_fact() {
+        let arm = ArchitectureFacts {
+            ansible_architecture: "aarch64".to_string(),
+            ..ArchitectureFacts::fallback()
+        };
+        let host_facts = HashMap::from([
+            ("b".to_string(), ArchitectureFacts::fallback()),
+            ("a".to_string(), ArchitectureFacts::fallback()),
+            ("c".to_string(), arm),
+        ]);
+
+        let by_arch = hosts_by_fact(&host_facts, "ansible_architecture");
+        assert_eq!(by_arch.values["x86_64"], ["a", "b"]);
+        assert_eq!(by_arch.values["aarch64"], ["c"]);
+
+        let by_libc = hosts_by_fact(&host_facts, "rustle_libc");
+        assert_eq!(by_libc.values["unknown"], ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_group_facts() {
+        let playbook: EnrichedPlaybook = serde_json::from_value(serde_json::json!({
+            "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+            "plays": [], "variables": {}, "facts_required": false, "vault_ids": [],
+            "inventory": {
+                "hosts": {}, "variables": {},
+                "groups": {"web": ["w1", "w2"], "edge": ["w2", "e1", "gone"]},
+                "host_facts": {
+                    "w1": ArchitectureFacts::fallback(),
+                    "w2": ArchitectureFacts::fallback(),
+                    "e1": ArchitectureFacts {
+                        ansible_architecture: "aarch64".to_string(),
+                        ..ArchitectureFacts::fallback()
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let groups = group_facts(&playbook);
+        assert_eq!(groups["web"].hosts, 2);
+        assert_eq!(groups["web"].architectures["x86_64"], 2);
+        assert!(groups["web"].is_homogeneous());
+        assert_eq!(groups["edge"].hosts, 2);
+        assert_eq!(groups["edge"].architectures["aarch64"], 1);
+        assert!(!groups["edge"].is_homogeneous());
+        assert_eq!(groups["all"].hosts, 3);
+    }
+
+    #[test]
+    fn test_pattern_groups() {
+        let groups: Vec<&str> = pattern_groups("web:db,!staging:&prod").collect();
+        assert_eq!(groups, vec!["web", "db"]);
+    }
+
+    #[test]
+    fn test_resolve_pattern() {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {},
+            "groups": {
+                "web": ["web1", "web2", "web3"],
+                "db": ["db1"],
+                "prod": ["web1", "web2", "db1"]
+            }
+        }))
+        .unwrap();
+        let all_hosts: BTreeSet<String> = ["web1", "web2", "web3", "db1", "cache1"]
+            .map(String::from)
+            .into();
+
+        let resolve = |pattern| -> Vec<String> {
+            resolve_pattern(&inventory, &all_hosts, pattern)
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(resolve("web:db"), ["db1", "web1", "web2", "web3"]);
+        assert_eq!(resolve("web:&prod:!web2"), ["web1"]);
+        assert_eq!(resolve("all:!prod"), ["cache1", "web3"]);
+        assert_eq!(resolve("cache*,db1"), ["cache1", "db1"]);
+        assert!(resolve("missing").is_empty());
+    }
+
+    #[test]
+    fn test_group_members_follows_children() {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {},
+            "groups": {
+                "app": {"name": "app", "hosts": ["a1"], "children": ["web"], "vars": {}},
+                "web": {"name": "web", "hosts": ["w1", "w2"], "children": ["app"], "vars": {}}
+            }
+        }))
+        .unwrap();
+
+        let members = group_members(&inventory, "app").unwrap();
+        assert_eq!(members.into_iter().collect::<Vec<_>>(), ["a1", "w1", "w2"]);
+        assert!(group_members(&inventory, "missing").is_none());
+    }
+}
@@ -0,0 +1,123 @@
+//! Canonical architecture names.
+//!
+//! `uname -m`, package managers, container runtimes and network devices
+//! spell the same architecture differently (`amd64`, `arm64`, `armhf`,
+//! `ppc64el`). Every architecture is mapped to one canonical name through
+//! a built-in alias table, which `--arch-map` extends with a YAML or JSON
+//! file of `spelling: canonical` pairs. Vendor-specific strings can then be
+//! mapped without waiting for a release; user entries win over built-in
+//! ones.
+
+use crate::error::{FactsError, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Canonical names and the spellings mapped to them, compared lowercase.
+pub const BUILTIN_ARCHITECTURES: &[(&str, &[&str])] = &[
+    ("x86_64", &["x86_64", "amd64"]),
+    ("aarch64", &["aarch64", "arm64"]),
+    ("armv7", &["armv7l", "armhf"]),
+    ("armv6", &["armv6l", "armv6"]),
+    ("riscv64", &["riscv64", "riscv64gc"]),
+    ("s390x", &["s390x"]),
+    ("ppc64le", &["ppc64le", "ppc64el", "powerpc64le"]),
+    ("ppc64", &["ppc64", "powerpc64"]),
+    ("ppc", &["ppc", "powerpc"]),
+    ("sparc64", &["sparc64", "sparcv9", "sun4u", "sun4v"]),
+    ("sparc", &["sparc"]),
+    ("hppa", &["hppa", "parisc"]),
+    ("ia64", &["ia64"]),
+    ("mips", &["mips", "mipsbe"]),
+    ("mipsel", &["mipsel", "mipsle"]),
+    ("mips64", &["mips64"]),
+    ("mips64el", &["mips64el", "mips64le"]),
+];
+
+/// The canonical name of a built-in spelling.
+pub fn builtin_architecture(arch: &str) -> Option<&'static str> {
+    let arch = arch.trim().to_lowercase();
+    BUILTIN_ARCHITECTURES
+        .iter()
+        .find(|(_, aliases)| aliases.contains(&arch.as_str()))
+        .map(|(canonical, _)| *canonical)
+}
+
+/// The built-in aliases plus those from an `--arch-map` file.
+#[derive(Debug, Clone, Default)]
+pub struct ArchTable {
+    /// Lowercase spelling -> canonical name
+    additions: HashMap<String, String>,
+}
+
+impl ArchTable {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            FactsError::InvalidConfig(format!("Failed to read architecture map: {e}"))
+        })?;
+        // JSON is a subset of YAML, so one parser covers both
+        let entries: HashMap<String, String> = serde_yaml::from_str(&content).map_err(|e| {
+            FactsError::InvalidConfig(format!("Invalid architecture map {path:?}: {e}"))
+        })?;
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn from_entries(entries: impl IntoIterator<Item = (String, String)>) -> Self {
+        let additions = entries
+            .into_iter()
+            .map(|(spelling, canonical)| {
+                // A target that is itself a known spelling maps onward
+                let canonical = builtin_architecture(&canonical)
+                    .map(str::to_string)
+                    .unwrap_or_else(|| canonical.trim().to_string());
+                (spelling.trim().to_lowercase(), canonical)
+            })
+            .collect();
+        Self { additions }
+    }
+
+    /// The canonical name of `arch`; unknown spellings are kept as given.
+    pub fn normalize(&self, arch: &str) -> String {
+        if let Some(canonical) = self.additions.get(&arch.trim().to_lowercase()) {
+            return canonical.clone();
+        }
+        builtin_architecture(arch)
+            .map(str::to_string)
+            .unwrap_or_else(|| arch.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_architectures() {
+        assert_eq!(builtin_architecture("ppc64el"), Some("ppc64le"));
+        assert_eq!(builtin_architecture("RISCV64"), Some("riscv64"));
+        assert_eq!(builtin_architecture("armv6l"), Some("armv6"));
+        assert_eq!(builtin_architecture("mips64le"), Some("mips64el"));
+        assert_eq!(builtin_architecture("s390x"), Some("s390x"));
+        assert_eq!(builtin_architecture("sparcv9"), Some("sparc64"));
+        assert_eq!(builtin_architecture("powerpc"), Some("ppc"));
+        assert_eq!(builtin_architecture("loongarch64"), None);
+    }
+
+    #[test]
+    fn test_user_additions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("arch.yml");
+        std::fs::write(
+            &path,
+            "armv7-vendor: armhf\nsparc64: sparc64\nAMD64: i386\n",
+        )
+        .unwrap();
+        let table = ArchTable::load(&path).unwrap();
+
+        assert_eq!(table.normalize("ARMv7-Vendor"), "armv7");
+        assert_eq!(table.normalize("sparc64"), "sparc64");
+        // User entries win over built-in ones
+        assert_eq!(table.normalize("amd64"), "i386");
+        assert_eq!(table.normalize("arm64"), "aarch64");
+        assert_eq!(table.normalize("custom"), "custom");
+    }
+}
@@ -0,0 +1,211 @@
+//! `--assert` rules checked against every host's facts.
+//!
+//! A rule is `<fact> in [a, b]`, `<fact> not in [a, b]`, `<fact> == a` or
+//! `<fact> != a`. Values compare as strings; a fact the host does not have
+//! compares as empty.
+
+use crate::types::ArchitectureFacts;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertOp {
+    In,
+    NotIn,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FactAssertion {
+    pub fact: String,
+    pub op: AssertOp,
+    pub values: Vec<String>,
+}
+
+/// A host whose facts broke a rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertionViolation {
+    pub host: String,
+    pub assertion: FactAssertion,
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for AssertionViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (got {})",
+            self.host,
+            self.assertion,
+            self.actual.as_deref().unwrap_or("nothing")
+        )
+    }
+}
+
+impl FactAssertion {
+    /// The fact's value if it breaks this rule.
+    pub fn violation(&self, facts: &ArchitectureFacts) -> Option<Option<String>> {
+        let actual = fact_value(facts, &self.fact);
+        let value = actual.as_deref().unwrap_or("");
+        let found = self.values.iter().any(|v| v == value);
+        let holds = match self.op {
+            AssertOp::In => found,
+            AssertOp::NotIn => !found,
+        };
+        (!holds).then_some(actual)
+    }
+}
+
+impl FromStr for FactAssertion {
+    type Err = String;
+
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("invalid assertion '{rule}' (expected '<fact> in [a, b]' or '<fact> == a')");
+
+        let (fact, op, rest) = [
+            (" not in ", AssertOp::NotIn),
+            (" in ", AssertOp::In),
+            ("!=", AssertOp::NotIn),
+            ("==", AssertOp::In),
+        ]
+        .into_iter()
+        .find_map(|(token, op)| {
+            rule.split_once(token)
+                .map(|(fact, rest)| (fact.trim(), op, rest.trim()))
+        })
+        .ok_or_else(invalid)?;
+
+        if fact.is_empty() || !fact.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid());
+        }
+
+        let values: Vec<String> = match rest.strip_prefix('[') {
+            Some(list) => list
+                .strip_suffix(']')
+                .ok_or_else(invalid)?
+                .split(',')
+                .map(|v| v.trim().trim_matches(['"', '\'']).to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            None => vec![rest.trim_matches(['"', '\'']).to_string()],
+        };
+
+        if values.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(FactAssertion {
+            fact: fact.to_string(),
+            op,
+            values,
+        })
+    }
+}
+
+impl TryFrom<String> for FactAssertion {
+    type Error = String;
+
+    fn try_from(rule: String) -> Result<Self, Self::Error> {
+        rule.parse()
+    }
+}
+
+impl From<FactAssertion> for String {
+    fn from(assertion: FactAssertion) -> Self {
+        assertion.to_string()
+    }
+}
+
+impl fmt::Display for FactAssertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            AssertOp::In => "in",
+            AssertOp::NotIn => "not in",
+        };
+        write!(f, "{} {} [{}]", self.fact, op, self.values.join(", "))
+    }
+}
+
+/// Parse an `--assert` value.
+pub fn parse_assertion(rule: &str) -> Result<FactAssertion, String> {
+    rule.parse()
+}
+
+/// Check every host against every rule, returning violations sorted by host.
+pub fn check_assertions(
+    assertions: &[FactAssertion],
+    host_facts: &HashMap<String, ArchitectureFacts>,
+) -> Vec<AssertionViolation> {
+    let mut hosts: Vec<&String> = host_facts.keys().collect();
+    hosts.sort();
+
+    let mut violations = Vec::new();
+    for host in hosts {
+        for assertion in assertions {
+            if let Some(actual) = assertion.violation(&host_facts[host]) {
+                violations.push(AssertionViolation {
+                    host: host.clone(),
+                    assertion: assertion.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+    violations
+}
+
+pub(crate) fn fact_value(facts: &ArchitectureFacts, fact: &str) -> Option<String> {
+    match serde_json::to_value(facts).ok()?.get(fact)? {
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Null => None,
+        other => Some(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assertion() {
+        let rule = parse_assertion("ansible_architecture in [x86_64, aarch64]").unwrap();
+        assert_eq!(rule.op, AssertOp::In);
+        assert_eq!(rule.values, vec!["x86_64", "aarch64"]);
+
+        let rule = parse_assertion("ansible_os_family != 'windows'").unwrap();
+        assert_eq!(rule.op, AssertOp::NotIn);
+        assert_eq!(rule.values, vec!["windows"]);
+        assert_eq!(rule.to_string(), "ansible_os_family not in [windows]");
+
+        assert!(parse_assertion("ansible_architecture").is_err());
+        assert!(parse_assertion("ansible_architecture in [").is_err());
+        assert!(parse_assertion("bad key == x").is_err());
+    }
+
+    #[test]
+    fn test_check_assertions() {
+        let arm = ArchitectureFacts {
+            ansible_architecture: "armv7".to_string(),
+            ..ArchitectureFacts::fallback()
+        };
+        let host_facts = HashMap::from([
+            ("web1".to_string(), ArchitectureFacts::fallback()),
+            ("pi".to_string(), arm),
+        ]);
+        let rules = vec![
+            parse_assertion("ansible_architecture in [x86_64, aarch64]").unwrap(),
+            parse_assertion("ansible_distribution == ubuntu").unwrap(),
+        ];
+
+        let violations = check_assertions(&rules, &host_facts);
+        assert_eq!(violations.len(), 3);
+        assert_eq!(
+            violations[0].to_string(),
+            "pi: ansible_architecture in [x86_64, aarch64] (got armv7)"
+        );
+        assert_eq!(violations[2].actual, None);
+    }
+}
@@ -0,0 +1,184 @@
+//! Per-backend health and circuit breakers.
+//!
+//! When a backend itself is down (the Docker daemon is not running, a
+//! gateway refuses connections) every host behind it fails the same way,
+//! one timeout at a time. Each backend's successes and failures are
+//! counted, and with `--circuit-breaker N` a backend that fails N times in
+//! a row is given up on: its remaining hosts fail at once with
+//! `FactsError::CircuitOpen`, and the run reports the backend once with
+//! the error that tripped it. A success in between resets the count.
+//!
+//! Only connection-level errors count against a backend; a host that
+//! answers with unparseable output says nothing about the backend.
+
+use crate::error::{FactsError, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// What one backend has done so far in this run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BackendHealth {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub consecutive_failures: usize,
+    /// The error that opened the breaker, once it has tripped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tripped_by: Option<String>,
+    /// Hosts failed without being tried because the breaker was open
+    pub skipped: usize,
+}
+
+/// The health of every backend used in a run, shared between gatherers.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreakers {
+    backends: Arc<Mutex<HashMap<String, BackendHealth>>>,
+}
+
+impl CircuitBreakers {
+    /// Run `attempt` against `backend` unless its breaker is open, and
+    /// record the outcome. `threshold` is `--circuit-breaker`; without it
+    /// outcomes are only counted.
+    pub async fn call<T>(
+        &self,
+        threshold: Option<usize>,
+        backend: &str,
+        attempt: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if let Some(tripped_by) = self.open_breaker(backend) {
+            return Err(FactsError::CircuitOpen(backend.to_string(), tripped_by));
+        }
+
+        let result = attempt.await;
+        let mut backends = self.lock();
+        let health = backends.entry(backend.to_string()).or_default();
+        match &result {
+            Ok(_) => {
+                health.succeeded += 1;
+                health.consecutive_failures = 0;
+            }
+            Err(e) if counts_against_backend(e) => {
+                health.failed += 1;
+                health.consecutive_failures += 1;
+                let tripped = threshold.is_some_and(|limit| health.consecutive_failures >= limit);
+                if tripped && health.tripped_by.is_none() {
+                    error!(
+                        "{} failed {} times in a row; failing its remaining hosts without trying them. Last error: {}",
+                        backend, health.consecutive_failures, e
+                    );
+                    health.tripped_by = Some(e.to_string());
+                }
+            }
+            Err(_) => health.failed += 1,
+        }
+        result
+    }
+
+    /// Every backend used so far, by name.
+    pub fn health(&self) -> BTreeMap<String, BackendHealth> {
+        self.lock()
+            .iter()
+            .map(|(backend, health)| (backend.clone(), health.clone()))
+            .collect()
+    }
+
+    fn open_breaker(&self, backend: &str) -> Option<String> {
+        let mut backends = self.lock();
+        let health = backends.get_mut(backend)?;
+        let tripped_by = health.tripped_by.clone()?;
+        health.skipped += 1;
+        Some(tripped_by)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, BackendHealth>> {
+        // The map is only ever updated whole, so a poisoned lock is still usable
+        self.backends
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn counts_against_backend(error: &FactsError) -> bool {
+    matches!(
+        error,
+        FactsError::ConnectionFailed(..) | FactsError::Timeout(_) | FactsError::DockerApi(..)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused() -> Result<()> {
+        Err(FactsError::ConnectionFailed(
+            "web1".to_string(),
+            "Connection refused".to_string(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_breaker_trips_after_consecutive_failures() {
+        let breakers = CircuitBreakers::default();
+        let limit = Some(2);
+
+        assert!(breakers
+            .call(limit, "gateway bastion-a", async { refused() })
+            .await
+            .is_err());
+        assert!(breakers
+            .call(limit, "gateway bastion-a", async { Ok(()) })
+            .await
+            .is_ok());
+        assert!(breakers
+            .call(limit, "gateway bastion-a", async { refused() })
+            .await
+            .is_err());
+        assert!(breakers
+            .call(limit, "gateway bastion-a", async { refused() })
+            .await
+            .is_err());
+
+        let skipped = breakers
+            .call(limit, "gateway bastion-a", async { Ok(()) })
+            .await;
+        assert!(matches!(skipped, Err(FactsError::CircuitOpen(..))));
+        assert!(breakers
+            .call(limit, "docker", async { Ok(()) })
+            .await
+            .is_ok());
+
+        let health = breakers.health();
+        let bastion = &health["gateway bastion-a"];
+        assert_eq!(
+            (bastion.succeeded, bastion.failed, bastion.skipped),
+            (1, 3, 1)
+        );
+        assert!(bastion
+            .tripped_by
+            .as_deref()
+            .unwrap()
+            .contains("Connection refused"));
+        assert_eq!(health["docker"].tripped_by, None);
+    }
+
+    #[tokio::test]
+    async fn test_breaker_ignores_host_errors_and_needs_a_threshold() {
+        let breakers = CircuitBreakers::default();
+        for _ in 0..3 {
+            let parse_error: Result<()> = Err(FactsError::ParseError(
+                "web1".to_string(),
+                "no facts".to_string(),
+            ));
+            let _ = breakers.call(Some(2), "ssh", async { parse_error }).await;
+            let _ = breakers.call(None, "docker", async { refused() }).await;
+        }
+
+        let health = breakers.health();
+        assert_eq!(health["ssh"].consecutive_failures, 0);
+        assert_eq!(health["ssh"].failed, 3);
+        assert_eq!(health["docker"].consecutive_failures, 3);
+        assert_eq!(health["docker"].tripped_by, None);
+    }
+}
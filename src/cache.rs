@@ -1,30 +1,185 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::ExportFormat;
 use crate::error::{FactsError, Result};
-use crate::ssh_facts::generate_ssh_fingerprint;
-use crate::types::{ArchitectureFacts, CachedFact, FactCache};
-use std::collections::HashMap;
+use crate::hostname::normalize_hostname;
+use crate::ssh_facts::preferred_host_key;
+use crate::types::{ArchitectureFacts, CachedFact, FactCache, HostEntry, HostKeys};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, info, warn};
 
 impl FactCache {
     pub fn get(&self, host: &str, ttl: u64) -> Option<&ArchitectureFacts> {
+        let now = self.clock.now();
         self.facts
-            .get(host)
-            .filter(|cached| is_cache_valid(cached, ttl))
+            .get(&normalize_hostname(host))
+            .filter(|cached| is_cache_valid_at(cached, ttl, now))
             .map(|cached| &cached.facts)
     }
 
     pub fn update(&mut self, host: String, facts: ArchitectureFacts) {
+        self.update_with_fingerprint(host, facts, None);
+    }
+
+    pub fn update_with_fingerprint(
+        &mut self,
+        host: String,
+        facts: ArchitectureFacts,
+        fingerprint: Option<String>,
+    ) {
         let cached = CachedFact {
             facts,
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64,
-            ssh_fingerprint: generate_ssh_fingerprint(&host),
+            timestamp: self.clock.now(),
+            ssh_fingerprint: fingerprint.unwrap_or_default(),
+            ssh_key_type: None,
+            labels: BTreeMap::new(),
+            inventory: None,
+            address: None,
         };
-        self.facts.insert(host, cached);
+        self.facts.insert(normalize_hostname(&host), cached);
+    }
+
+    /// The SHA-256 host key fingerprint recorded for a host, if any. Entries
+    /// written by older versions hold a hostname hash and are ignored.
+    pub fn fingerprint(&self, host: &str) -> Option<&str> {
+        self.facts
+            .get(&normalize_hostname(host))
+            .map(|cached| cached.ssh_fingerprint.as_str())
+            .filter(|fingerprint| fingerprint.starts_with("SHA256:"))
+    }
+
+    /// The key type of the fingerprint recorded for a host, if known.
+    pub fn fingerprint_type(&self, host: &str) -> Option<&str> {
+        self.fingerprint(host)?;
+        self.facts
+            .get(&normalize_hostname(host))?
+            .ssh_key_type
+            .as_deref()
+    }
+
+    /// Record the host key fingerprint of a host's entry, if it has one.
+    pub fn record_fingerprint(&mut self, host: &str, key_type: &str, fingerprint: String) {
+        if let Some(cached) = self.facts.get_mut(&normalize_hostname(host)) {
+            cached.ssh_fingerprint = fingerprint;
+            cached.ssh_key_type = Some(key_type.to_string());
+        }
+    }
+
+    /// The recorded fingerprint and the one `keys` has for the same key
+    /// type, when they differ. A host that no longer offers the recorded
+    /// type says nothing either way. Entries recorded without a type match
+    /// any of the keys.
+    pub fn host_key_change(&self, host: &str, keys: &HostKeys) -> Option<(String, String)> {
+        let recorded = self.fingerprint(host)?;
+        let cached = &self.facts[&normalize_hostname(host)];
+        let current = match &cached.ssh_key_type {
+            Some(key_type) => keys.get(key_type)?,
+            None if keys.values().any(|current| current == recorded) => return None,
+            None => preferred_host_key(keys)?.1,
+        };
+        (current != recorded).then(|| (recorded.to_string(), current.clone()))
+    }
+
+    /// Replace the labels of a host's entry, if it has one.
+    pub fn set_labels(&mut self, host: &str, labels: &BTreeMap<String, String>) {
+        if let Some(cached) = self.facts.get_mut(&normalize_hostname(host)) {
+            cached.labels = labels.clone();
+        }
+    }
+
+    /// The labels of the run that gathered a host's cached facts.
+    pub fn labels(&self, host: &str) -> Option<&BTreeMap<String, String>> {
+        self.facts
+            .get(&normalize_hostname(host))
+            .map(|cached| &cached.labels)
+    }
+
+    /// Record the connection parameters a host's cached facts were gathered
+    /// with, if it has an entry.
+    pub fn set_inventory(&mut self, host: &str, entry: &HostEntry) {
+        if let Some(cached) = self.facts.get_mut(&normalize_hostname(host)) {
+            cached.inventory = Some(entry.connection_snapshot());
+        }
+    }
+
+    /// Record where an SSH host was reached, if it has an entry.
+    pub fn set_address(&mut self, host: &str, address: &str) {
+        if let Some(cached) = self.facts.get_mut(&normalize_hostname(host)) {
+            cached.address = Some(address.to_string());
+        }
+    }
+
+    /// Drop a host's entry when it was gathered from another address than
+    /// `address`: its ssh_config alias now points at a different machine.
+    /// Entries with no recorded address are kept.
+    pub fn invalidate_moved(&mut self, host: &str, address: &str) -> Option<String> {
+        let host = normalize_hostname(host);
+        let previous = self.facts.get(&host)?.address.clone()?;
+        if previous == address {
+            return None;
+        }
+        self.facts.remove(&host);
+        Some(previous)
+    }
+
+    /// A host's cache entry as `cache show` prints it: the facts, when and
+    /// with which labels they were gathered, and the connection snapshot if
+    /// one was stored.
+    pub fn describe(&self, host: &str) -> Option<serde_json::Value> {
+        let host = normalize_hostname(host);
+        let cached = self.facts.get(&host)?;
+        Some(serde_json::json!({
+            "host": host,
+            "gathered_at": cached.timestamp,
+            "age_seconds": self.clock.now() - cached.timestamp,
+            "ssh_fingerprint": self.fingerprint(&host),
+            "ssh_key_type": self.fingerprint_type(&host),
+            "address": cached.address,
+            "labels": cached.labels,
+            "inventory": cached.inventory,
+            "facts": cached.facts,
+        }))
+    }
+
+    /// A host's cached facts however old they are.
+    pub fn get_any_age(&self, host: &str) -> Option<&ArchitectureFacts> {
+        self.facts
+            .get(&normalize_hostname(host))
+            .map(|cached| &cached.facts)
+    }
+
+    /// Hosts by the host key fingerprint recorded with their unexpired
+    /// facts, so a machine can be recognised under another name.
+    pub fn fingerprint_index(&self, ttl: u64) -> HashMap<String, String> {
+        let now = self.clock.now();
+        let mut index = HashMap::new();
+        for (host, cached) in &self.facts {
+            if cached.ssh_fingerprint.starts_with("SHA256:") && is_cache_valid_at(cached, ttl, now)
+            {
+                // Several names for one key resolve to the same one each run
+                let entry = index
+                    .entry(cached.ssh_fingerprint.clone())
+                    .or_insert_with(|| host.clone());
+                if host < entry {
+                    *entry = host.clone();
+                }
+            }
+        }
+        index
+    }
+
+    /// Remove a host's entry and return it, to be put back with `restore`.
+    pub fn take(&mut self, host: &str) -> Option<CachedFact> {
+        self.facts.remove(&normalize_hostname(host))
+    }
+
+    pub fn restore(&mut self, host: &str, cached: CachedFact) {
+        self.facts.insert(normalize_hostname(host), cached);
+    }
+
+    pub fn invalidate(&mut self, host: &str) -> bool {
+        self.facts.remove(&normalize_hostname(host)).is_some()
     }
 
     pub fn merge_facts(&mut self, new_facts: &HashMap<String, ArchitectureFacts>) {
@@ -33,11 +188,29 @@ impl FactCache {
         }
     }
 
-    pub fn cleanup_stale(&mut self, ttl: u64) {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    /// Take the entries of an exported cache that are newer than this
+    /// cache's entries for the same hosts, and return how many were taken.
+    pub fn import(&mut self, other: FactCache) -> usize {
+        let mut imported = 0;
+        for (host, cached) in other.facts {
+            let host = normalize_hostname(&host);
+            if self
+                .facts
+                .get(&host)
+                .is_some_and(|current| current.timestamp >= cached.timestamp)
+            {
+                continue;
+            }
+            self.facts.insert(host, cached);
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Remove entries older than `ttl` and return how many were removed.
+    pub fn cleanup_stale(&mut self, ttl: u64) -> usize {
+        let now = self.clock.now();
+        let before = self.facts.len();
 
         self.facts.retain(|host, cached| {
             let is_valid = (now - cached.timestamp) < ttl as i64;
@@ -46,19 +219,19 @@ impl FactCache {
             }
             is_valid
         });
+        before - self.facts.len()
     }
 }
 
 pub fn is_cache_valid(fact: &CachedFact, ttl: u64) -> bool {
+    is_cache_valid_at(fact, ttl, SystemClock.now())
+}
+
+pub fn is_cache_valid_at(fact: &CachedFact, ttl: u64, now: i64) -> bool {
     if ttl == 0 {
         return false;
     }
 
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-
     (now - fact.timestamp) < ttl as i64
 }
 
@@ -109,6 +282,31 @@ pub fn save_cache(path: &Path, cache: &FactCache) -> Result<()> {
     Ok(())
 }
 
+/// A cache as `cache export` writes it.
+pub fn export_cache(cache: &FactCache, format: ExportFormat) -> Result<Vec<u8>> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_vec_pretty(cache)?),
+        ExportFormat::Msgpack => rmp_serde::to_vec_named(cache)
+            .map_err(|e| FactsError::CacheError(format!("Failed to encode cache: {e}"))),
+    }
+}
+
+/// Read a cache written by `cache export` in either format. A JSON export
+/// is an object, so starts with `{`; a MessagePack map never does.
+pub fn parse_exported_cache(data: &[u8]) -> Result<FactCache> {
+    let is_json = data
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'{');
+    if is_json {
+        serde_json::from_slice(data)
+            .map_err(|e| FactsError::CacheError(format!("Invalid exported cache: {e}")))
+    } else {
+        rmp_serde::from_slice(data)
+            .map_err(|e| FactsError::CacheError(format!("Invalid exported cache: {e}")))
+    }
+}
+
 pub fn load_or_create_cache(path: &Path) -> Result<FactCache> {
     load_cache(path)
 }
@@ -121,6 +319,18 @@ pub fn update_cache(
     Ok(())
 }
 
+/// Parse a `KEY=VALUE` label. Keys may not be empty; values may.
+pub fn parse_label(label: &str) -> std::result::Result<(String, String), String> {
+    let (key, value) = label
+        .split_once('=')
+        .ok_or_else(|| format!("label {label:?} is not KEY=VALUE"))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("label {label:?} has an empty key"));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
 pub fn filter_hosts_needing_facts(
     hosts: &[String],
     cache: &FactCache,
@@ -141,6 +351,9 @@ pub fn filter_hosts_needing_facts(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::Arc;
+    use std::time::{SystemTime, UNIX_EPOCH};
     use tempfile::tempdir;
 
     #[test]
@@ -152,6 +365,10 @@ mod tests {
                 .unwrap()
                 .as_secs() as i64,
             ssh_fingerprint: "test".to_string(),
+            ssh_key_type: None,
+            labels: BTreeMap::new(),
+            inventory: None,
+            address: None,
         };
 
         assert!(is_cache_valid(&fact, 3600));
@@ -161,11 +378,167 @@ mod tests {
             facts: ArchitectureFacts::fallback(),
             timestamp: 1000,
             ssh_fingerprint: "test".to_string(),
+            ssh_key_type: None,
+            labels: BTreeMap::new(),
+            inventory: None,
+            address: None,
         };
 
         assert!(!is_cache_valid(&old_fact, 3600));
     }
 
+    #[test]
+    fn test_invalidate_moved() {
+        let mut cache = FactCache::new();
+        cache.update("web1".to_string(), ArchitectureFacts::fallback());
+        assert_eq!(cache.invalidate_moved("web1", "10.0.0.5"), None);
+
+        cache.set_address("web1", "10.0.0.5");
+        assert_eq!(cache.invalidate_moved("web1", "10.0.0.5"), None);
+        assert_eq!(
+            cache.invalidate_moved("web1", "[10.0.0.9]:2222"),
+            Some("10.0.0.5".to_string())
+        );
+        assert!(cache.get_any_age("web1").is_none());
+    }
+
+    #[test]
+    fn test_fingerprint_index() {
+        let clock = Arc::new(ManualClock::new(10_000));
+        let mut cache = FactCache::new().with_clock(clock.clone());
+        cache.update_with_fingerprint(
+            "web1".to_string(),
+            ArchitectureFacts::fallback(),
+            Some("SHA256:abc".to_string()),
+        );
+        cache.update_with_fingerprint(
+            "old-web1".to_string(),
+            ArchitectureFacts::fallback(),
+            Some("SHA256:abc".to_string()),
+        );
+        cache.update_with_fingerprint(
+            "legacy".to_string(),
+            ArchitectureFacts::fallback(),
+            Some("deadbeef".to_string()),
+        );
+
+        let index = cache.fingerprint_index(3600);
+        assert_eq!(index.len(), 1);
+        assert_eq!(index["SHA256:abc"], "old-web1");
+
+        clock.advance(7200);
+        assert!(cache.fingerprint_index(3600).is_empty());
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(
+            parse_label("datacenter=eu1"),
+            Ok(("datacenter".to_string(), "eu1".to_string()))
+        );
+        assert_eq!(
+            parse_label("note=a=b"),
+            Ok(("note".to_string(), "a=b".to_string()))
+        );
+        assert!(parse_label("datacenter").is_err());
+        assert!(parse_label("=eu1").is_err());
+
+        let mut cache = FactCache::new();
+        let labels = BTreeMap::from([("run_id".to_string(), "42".to_string())]);
+        cache.set_labels("web1", &labels);
+        assert_eq!(cache.labels("web1"), None);
+
+        cache.update("web1".to_string(), ArchitectureFacts::fallback());
+        cache.set_labels("Web1", &labels);
+        assert_eq!(cache.labels("web1"), Some(&labels));
+
+        // Gathering again starts a fresh entry
+        cache.update("web1".to_string(), ArchitectureFacts::fallback());
+        assert!(cache.labels("web1").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_inventory_snapshot() {
+        let clock = Arc::new(ManualClock::new(10_000));
+        let mut cache = FactCache::new().with_clock(clock.clone());
+        let mut entry = HostEntry::new("web1");
+        entry.port = Some(2222);
+        for (name, value) in [
+            ("ansible_host", "10.0.0.5"),
+            ("ansible_password", "hunter2"),
+            ("ansible_become_pass", "hunter2"),
+            ("docker_context", "edge"),
+            ("app_release", "2024.1"),
+        ] {
+            entry
+                .vars
+                .insert(name.to_string(), serde_json::json!(value));
+        }
+
+        cache.update("web1".to_string(), ArchitectureFacts::fallback());
+        cache.set_inventory("web1", &entry);
+        clock.advance(60);
+
+        let shown = cache.describe("WEB1").unwrap();
+        assert_eq!(shown["age_seconds"], 60);
+        assert_eq!(shown["inventory"]["port"], 2222);
+        let vars = shown["inventory"]["vars"].as_object().unwrap();
+        let mut names: Vec<&str> = vars.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["ansible_host", "docker_context"]);
+        assert!(cache.describe("web2").is_none());
+    }
+
+    #[test]
+    fn test_cache_keys_are_normalized_hostnames() {
+        let mut cache = FactCache::new();
+        cache.update(
+            "Web01.Example.COM.".to_string(),
+            ArchitectureFacts::fallback(),
+        );
+
+        assert!(cache.facts.contains_key("web01.example.com"));
+        assert!(cache.get("web01.example.com", 3600).is_some());
+        assert!(cache.invalidate("WEB01.example.com"));
+    }
+
+    #[test]
+    fn test_expiry_with_manual_clock() {
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let mut cache = FactCache::new().with_clock(clock.clone());
+        cache.update("host1".to_string(), ArchitectureFacts::fallback());
+
+        clock.advance(3599);
+        assert!(cache.get("host1", 3600).is_some());
+
+        clock.advance(1);
+        assert!(cache.get("host1", 3600).is_none());
+
+        cache.update("host2".to_string(), ArchitectureFacts::fallback());
+        assert_eq!(cache.cleanup_stale(3600), 1);
+        assert!(!cache.facts.contains_key("host1"));
+        assert!(cache.facts.contains_key("host2"));
+    }
+
+    #[test]
+    fn test_import_keeps_newer_entries() {
+        let clock = Arc::new(ManualClock::new(1_000));
+        let mut exported = FactCache::new().with_clock(clock.clone());
+        let mut local = FactCache::new().with_clock(clock.clone());
+        exported.update("web1".to_string(), ArchitectureFacts::fallback());
+        local.update("web2".to_string(), ArchitectureFacts::fallback());
+
+        clock.advance(60);
+        exported.update("web2".to_string(), ArchitectureFacts::fallback());
+        exported.update("WEB3".to_string(), ArchitectureFacts::fallback());
+        local.update("web1".to_string(), ArchitectureFacts::fallback());
+
+        assert_eq!(local.import(exported), 2);
+        assert_eq!(local.facts["web1"].timestamp, 1_060);
+        assert_eq!(local.facts["web2"].timestamp, 1_060);
+        assert!(local.facts.contains_key("web3"));
+    }
+
     #[test]
     fn test_cache_operations() {
         let mut cache = FactCache::new();
@@ -175,6 +548,8 @@ mod tests {
             ansible_system: "Linux".to_string(),
             ansible_os_family: "debian".to_string(),
             ansible_distribution: Some("ubuntu".to_string()),
+            rustle_libc: None,
+            extra: serde_json::Map::new(),
         };
 
         cache.update("host1".to_string(), facts.clone());
@@ -200,6 +575,8 @@ mod tests {
                 ansible_system: "Linux".to_string(),
                 ansible_os_family: "redhat".to_string(),
                 ansible_distribution: Some("centos".to_string()),
+                rustle_libc: None,
+                extra: serde_json::Map::new(),
             },
         );
 
@@ -210,6 +587,37 @@ mod tests {
         assert!(loaded_cache.get("testhost", 3600).is_some());
     }
 
+    #[test]
+    fn test_unknown_facts_round_trip() {
+        let dir = tempdir().unwrap();
+        let cache_path = dir.path().join("test-cache.json");
+        // An entry from before rustle_libc, beside one from a newer version
+        // that carries a fact this one does not know
+        fs::write(
+            &cache_path,
+            r#"{"version": "1.0", "facts": {
+                "old": {"facts": {"ansible_architecture": "x86_64", "ansible_system": "Linux",
+                    "ansible_os_family": "debian", "ansible_distribution": "debian"},
+                    "timestamp": 0},
+                "new": {"facts": {"ansible_architecture": "aarch64", "ansible_system": "Linux",
+                    "ansible_os_family": "alpine", "ansible_distribution": null,
+                    "rustle_libc": "musl", "rustle_page_size": 16384},
+                    "timestamp": 0}
+            }}"#,
+        )
+        .unwrap();
+
+        let cache = load_cache(&cache_path).unwrap();
+        assert_eq!(cache.facts.len(), 2);
+        assert_eq!(cache.facts["old"].facts.rustle_libc, None);
+        assert!(cache.facts["old"].facts.extra.is_empty());
+        assert_eq!(cache.facts["new"].facts.extra["rustle_page_size"], 16384);
+
+        save_cache(&cache_path, &cache).unwrap();
+        let reloaded = load_cache(&cache_path).unwrap();
+        assert_eq!(reloaded.facts["new"].facts, cache.facts["new"].facts);
+    }
+
     #[test]
     fn test_filter_hosts_needing_facts() {
         let mut cache = FactCache::new();
@@ -229,4 +637,62 @@ mod tests {
         let all_needed = filter_hosts_needing_facts(&hosts, &cache, 3600, true);
         assert_eq!(all_needed.len(), 3);
     }
+
+    #[test]
+    fn test_fingerprint_tracking() {
+        let mut cache = FactCache::new();
+        cache.update("host1".to_string(), ArchitectureFacts::fallback());
+        assert_eq!(cache.fingerprint("host1"), None);
+
+        cache.record_fingerprint("host1", "ED25519", "SHA256:abc".to_string());
+        assert_eq!(cache.fingerprint("host1"), Some("SHA256:abc"));
+
+        // Legacy hostname hashes are not treated as host keys
+        cache.update_with_fingerprint(
+            "host2".to_string(),
+            ArchitectureFacts::fallback(),
+            Some("5f3a9c".to_string()),
+        );
+        assert_eq!(cache.fingerprint("host2"), None);
+
+        assert!(cache.invalidate("host1"));
+        assert!(!cache.invalidate("host1"));
+    }
+
+    #[test]
+    fn test_host_key_change_compares_the_same_key_type() {
+        let keys = |pairs: &[(&str, &str)]| -> HostKeys {
+            pairs
+                .iter()
+                .map(|(key_type, fingerprint)| (key_type.to_string(), fingerprint.to_string()))
+                .collect()
+        };
+        let mut cache = FactCache::new();
+        cache.update("web1".to_string(), ArchitectureFacts::fallback());
+        cache.record_fingerprint("web1", "RSA", "SHA256:rsa".to_string());
+
+        // The host also offers an ED25519 key; its RSA key is unchanged
+        let offered = keys(&[("ED25519", "SHA256:ed"), ("RSA", "SHA256:rsa")]);
+        assert_eq!(cache.host_key_change("web1", &offered), None);
+        assert_eq!(
+            cache.host_key_change("web1", &keys(&[("RSA", "SHA256:new")])),
+            Some(("SHA256:rsa".to_string(), "SHA256:new".to_string()))
+        );
+        assert_eq!(
+            cache.host_key_change("web1", &keys(&[("ED25519", "SHA256:ed")])),
+            None
+        );
+
+        // Untyped entries match any offered key
+        cache.update_with_fingerprint(
+            "web2".to_string(),
+            ArchitectureFacts::fallback(),
+            Some("SHA256:rsa".to_string()),
+        );
+        assert_eq!(cache.host_key_change("web2", &offered), None);
+        assert_eq!(
+            cache.host_key_change("web2", &keys(&[("ED25519", "SHA256:ed")])),
+            Some(("SHA256:rsa".to_string(), "SHA256:ed".to_string()))
+        );
+    }
 }
@@ -0,0 +1,162 @@
+//! The `--capabilities` document.
+//!
+//! Orchestrators read it to find out what this build can do instead of
+//! parsing `--version`. Lists come from the enums the CLI and the backends
+//! dispatch on, so they cannot drift from what the binary accepts; only the
+//! Cargo features are listed by hand.
+
+use crate::config::{ExportFormat, FactNamespace, FactPreference, OutputCompression};
+use crate::gateway_facts;
+use crate::session::SESSION_VERSION;
+use crate::types::{ArchitectureFacts, ConnectionType, CACHE_VERSION, FACTS_SCHEMA_VERSION};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Version of the capabilities document itself. Fields are only ever
+/// added within a version.
+pub const CAPABILITIES_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Versions of the documents this build reads and writes, by document
+    pub schema_versions: BTreeMap<&'static str, u32>,
+    /// `ansible_connection` values facts can be gathered through
+    pub connection_backends: Vec<String>,
+    /// Connections that can also be reached through a `rustle_facts_gateway`
+    pub gateway_backends: Vec<String>,
+    /// Facts gathered for each host
+    pub facts: Vec<&'static str>,
+    pub fact_namespaces: Vec<String>,
+    pub fact_preferences: Vec<String>,
+    pub output_compression: Vec<String>,
+    /// Formats `cache export` writes and `cache import` reads
+    pub cache_export_formats: Vec<String>,
+    /// Cargo features compiled into this build
+    pub features: Vec<&'static str>,
+}
+
+/// Describe this build.
+pub fn capabilities() -> Capabilities {
+    let backends: Vec<ConnectionType> = ConnectionType::HANDLED
+        .into_iter()
+        .filter(|connection| *connection != ConnectionType::Mock || cfg!(feature = "mock"))
+        .collect();
+    let gateway_backends = backends
+        .iter()
+        .filter(|connection| gateway_facts::can_relay(connection))
+        .map(ToString::to_string)
+        .collect();
+
+    let features = [
+        ("bollard", cfg!(feature = "bollard")),
+        ("keyring", cfg!(feature = "keyring")),
+        ("mock", cfg!(feature = "mock")),
+        ("native-ssh", cfg!(feature = "native-ssh")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect();
+
+    Capabilities {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        schema_versions: BTreeMap::from([
+            ("capabilities", CAPABILITIES_VERSION),
+            ("facts", FACTS_SCHEMA_VERSION),
+            ("cache", CACHE_VERSION),
+            ("session", SESSION_VERSION),
+        ]),
+        connection_backends: backends.iter().map(ToString::to_string).collect(),
+        gateway_backends,
+        facts: ArchitectureFacts::FIELDS.to_vec(),
+        fact_namespaces: value_names::<FactNamespace>(),
+        fact_preferences: value_names::<FactPreference>(),
+        output_compression: value_names::<OutputCompression>(),
+        cache_export_formats: value_names::<ExportFormat>(),
+        features,
+    }
+}
+
+/// The values clap accepts for a `ValueEnum` argument.
+fn value_names<T: ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|value| Some(value.to_possible_value()?.get_name().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = capabilities();
+        assert!(capabilities
+            .connection_backends
+            .contains(&"ssh".to_string()));
+        assert_eq!(capabilities.output_compression, ["none", "gzip", "zstd"]);
+        assert_eq!(capabilities.cache_export_formats, ["json", "msgpack"]);
+        assert_eq!(capabilities.schema_versions["capabilities"], 1);
+        assert_eq!(
+            capabilities
+                .schema_versions
+                .keys()
+                .copied()
+                .collect::<Vec<_>>(),
+            ["cache", "capabilities", "facts", "session"]
+        );
+        assert_eq!(
+            capabilities.gateway_backends,
+            ["ssh", "docker", "podman", "jail", "pct"]
+        );
+
+        // The advertised facts are the ones a fully gathered host carries
+        let facts = ArchitectureFacts {
+            rustle_libc: Some("gnu".to_string()),
+            ansible_distribution: Some("ubuntu".to_string()),
+            ..ArchitectureFacts::fallback()
+        };
+        let serialized = serde_json::to_value(facts).unwrap();
+        let mut names: Vec<&str> = serialized
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        let mut advertised = capabilities.facts.clone();
+        advertised.sort_unstable();
+        assert_eq!(names, advertised);
+    }
+
+    #[test]
+    fn test_every_connection_is_handled() {
+        // A new variant fails to compile here until it is in HANDLED
+        let position = |connection: &ConnectionType| match connection {
+            ConnectionType::Local => 0,
+            ConnectionType::Ssh => 1,
+            ConnectionType::Docker => 2,
+            ConnectionType::Podman => 3,
+            ConnectionType::Nerdctl => 4,
+            ConnectionType::Kubectl => 5,
+            ConnectionType::Serial => 6,
+            ConnectionType::Lxd => 7,
+            ConnectionType::Jail => 8,
+            ConnectionType::Pct => 9,
+            ConnectionType::LibvirtQemu => 10,
+            ConnectionType::Mock => 11,
+            ConnectionType::Other(_) => unreachable!(),
+        };
+        for (index, connection) in ConnectionType::HANDLED.iter().enumerate() {
+            assert_eq!(position(connection), index);
+            assert_eq!(
+                connection.as_str().parse::<ConnectionType>().unwrap(),
+                *connection
+            );
+        }
+    }
+}
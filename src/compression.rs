@@ -0,0 +1,80 @@
+//! Compressed pipeline streams.
+//!
+//! Parsed playbooks for large estates run to hundreds of megabytes, so input
+//! compressed with gzip or zstd is recognised by its magic bytes and
+//! decompressed, and `--compress-output` compresses the enriched document.
+
+use crate::config::OutputCompression;
+use crate::error::Result;
+use std::io::{Read, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Decompress `input` if it starts with a gzip or zstd header; anything else
+/// is returned unchanged.
+pub fn decompress(input: Vec<u8>) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    if input.starts_with(GZIP_MAGIC) {
+        flate2::read::MultiGzDecoder::new(input.as_slice()).read_to_end(&mut decompressed)?;
+    } else if input.starts_with(ZSTD_MAGIC) {
+        zstd::stream::read::Decoder::new(input.as_slice())?.read_to_end(&mut decompressed)?;
+    } else {
+        return Ok(input);
+    }
+    Ok(decompressed)
+}
+
+/// Write `data` to `output`, compressed as requested.
+pub fn write_compressed<W: Write>(
+    output: W,
+    data: &[u8],
+    compression: OutputCompression,
+) -> Result<()> {
+    match compression {
+        OutputCompression::None => {
+            let mut output = output;
+            output.write_all(data)?;
+        }
+        OutputCompression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()?;
+        }
+        OutputCompression::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(output, 0)?;
+            encoder.write_all(data)?;
+            encoder.finish()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = br#"{"plays": []}"#;
+        for compression in [
+            OutputCompression::None,
+            OutputCompression::Gzip,
+            OutputCompression::Zstd,
+        ] {
+            let mut compressed = Vec::new();
+            write_compressed(&mut compressed, data, compression).unwrap();
+            assert_eq!(
+                compressed != data,
+                compression != OutputCompression::None,
+                "{compression:?}"
+            );
+            assert_eq!(decompress(compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_input_is_an_error() {
+        assert!(decompress(vec![0x1f, 0x8b, 0x00]).is_err());
+    }
+}
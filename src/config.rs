@@ -1,6 +1,24 @@
-use clap::Parser;
+use crate::arch_table::ArchTable;
+use crate::assertions::{parse_assertion, FactAssertion};
+use crate::breaker::CircuitBreakers;
+use crate::cache::parse_label;
+use crate::error::{FactsError, Result};
+use crate::fact_plugins::FactPlugins;
+use crate::faults::{parse_injected_failure, parse_latency, FaultInjection, FaultKind};
+use crate::secrets::{prompt_secret, read_keyring_secret, Credentials, Secret};
+use crate::select::{parse_selection, Selection};
+use crate::session::Session;
+use crate::shared_cache::SharedCache;
+use crate::shutdown::Shutdown;
+use crate::ssh_config::SshConfig;
+use crate::types::HostEntry;
+use crate::verify::parse_sample;
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Parser)]
 #[command(
@@ -10,11 +28,15 @@ use std::path::PathBuf;
     author
 )]
 pub struct CliArgs {
-    #[arg(long, value_name = "PATH", help = "Path to cache file")]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Path to cache file")]
     pub cache_file: Option<PathBuf>,
 
     #[arg(
         long,
+        global = true,
         value_name = "SECONDS",
         default_value = "86400",
         help = "Cache TTL in seconds"
@@ -29,13 +51,46 @@ pub struct CliArgs {
     )]
     pub parallel: usize,
 
+    #[arg(
+        long,
+        alias = "timeout",
+        value_name = "SECONDS",
+        default_value = "10",
+        help = "Seconds allowed to establish a connection to each host"
+    )]
+    pub connect_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "30",
+        help = "Seconds allowed for the fact probe to run once connected"
+    )]
+    pub command_timeout: u64,
+
     #[arg(
         long,
         value_name = "SECONDS",
         default_value = "10",
-        help = "SSH timeout per host"
+        help = "Log progress, throughput and ETA this often while gathering (0 to disable)"
+    )]
+    pub progress_interval: u64,
+
+    #[arg(
+        long,
+        value_name = "PER_SECOND",
+        default_value = "0",
+        help = "Max new connections started per second (0 for no limit)"
+    )]
+    pub max_connection_rate: u32,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        default_value = "0",
+        help = "Spread each connection start by up to this much per-host jitter"
     )]
-    pub timeout: u64,
+    pub connect_jitter_ms: u64,
 
     #[arg(long, help = "Disable caching")]
     pub no_cache: bool,
@@ -43,12 +98,367 @@ pub struct CliArgs {
     #[arg(long, help = "Force refresh all facts regardless of cache")]
     pub force_refresh: bool,
 
+    #[arg(
+        long,
+        help = "Ignore ansible_architecture, ansible_os_family and rustle_target_triple declared in inventory vars"
+    )]
+    pub no_overrides: bool,
+
+    #[arg(
+        long,
+        help = "Only use local detection for hosts with ansible_connection: local, not for localhost, loopback or this machine's own addresses"
+    )]
+    pub no_implicit_local: bool,
+
+    #[arg(
+        long,
+        help = "Connect to SSH hosts without first checking that their names resolve"
+    )]
+    pub no_dns_precheck: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "YAML/JSON file pinning fact values per host, group or glob pattern; pinned values win over gathered facts and are never cached"
+    )]
+    pub facts_override: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "YAML/JSON file mapping vendor-specific architecture strings to canonical names, on top of the built-in table"
+    )]
+    pub arch_map: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Shell script run on hosts in place of the built-in probe; it must print ARCH= and SYSTEM= lines"
+    )]
+    pub probe_script: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SNIPPET",
+        help = "Shell appended to the probe; the KEY=VALUE lines it prints are reported under the `custom` fact"
+    )]
+    pub probe_append: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "SUBSET",
+        help = "Extra facts the probe gathers at some cost in latency; `cloud` asks the cloud metadata service for the provider, instance type and region"
+    )]
+    pub gather_subset: Vec<GatherSubset>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "declared",
+        help = "Fact source that wins when declared, gathered and cached values disagree; the rest keep the order declared > gathered > cached"
+    )]
+    pub prefer: FactPreference,
+
+    #[arg(
+        long,
+        help = "Fail when a group targeted by a play mixes architectures"
+    )]
+    pub fail_on_mixed_arch: bool,
+
+    #[arg(
+        long,
+        help = "On SIGINT/SIGTERM, still write the enriched playbook with the facts gathered so far, marked partial: true"
+    )]
+    pub write_partial: bool,
+
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "0",
+        help = "Overall time budget for the run; when it expires, write the output with the facts gathered so far (0 = no limit)"
+    )]
+    pub deadline: u64,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Abort gathering once more than N hosts have failed; facts gathered so far are still cached"
+    )]
+    pub max_failed_hosts: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Give up on a backend (the Docker daemon, a gateway, SSH) after N consecutive connection failures and fail its remaining hosts without trying them"
+    )]
+    pub circuit_breaker: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "P",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Abort gathering once more than P percent of the hosts being gathered have failed"
+    )]
+    pub max_failed_percent: Option<u8>,
+
+    #[arg(
+        long,
+        help = "Print the build matrix (unique architecture/system/libc combinations and their hosts) instead of the enriched playbook"
+    )]
+    pub matrix: bool,
+
+    #[arg(
+        long,
+        help = "Add a play_host_facts section mapping each play index to the facts of the hosts it targets"
+    )]
+    pub play_host_facts: bool,
+
+    #[arg(
+        long,
+        help = "Add a group_facts section counting the hosts of each inventory group per architecture and OS family"
+    )]
+    pub group_facts: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "ansible",
+        help = "Prefix of emitted fact keys"
+    )]
+    pub fact_namespace: FactNamespace,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "none",
+        help = "Compress the output; gzip and zstd input is always detected and decompressed"
+    )]
+    pub compress_output: OutputCompression,
+
+    #[arg(
+        long,
+        value_name = "FACT",
+        num_args = 0..=1,
+        default_missing_value = "ansible_architecture",
+        help = "Add a hosts_by_fact section listing the hosts for each value of FACT (default ansible_architecture)"
+    )]
+    pub group_by_fact: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "EXPR",
+        value_parser = parse_selection,
+        help = "Write only the part of the output selected by EXPR, e.g. 'inventory.host_facts' or 'inventory.host_facts.*{ansible_architecture}'; quote names with dots, as in 'inventory.host_facts.\"web1.example.com\"'"
+    )]
+    pub select: Option<Selection>,
+
+    #[arg(
+        long = "assert",
+        value_name = "RULE",
+        value_parser = parse_assertion,
+        help = "Fail unless every host's facts satisfy RULE, e.g. 'ansible_architecture in [x86_64, aarch64]' (repeatable)"
+    )]
+    pub assertions: Vec<FactAssertion>,
+
     #[arg(long, value_name = "PATH", help = "Path to SSH config file")]
     pub ssh_config: Option<PathBuf>,
 
-    #[arg(long, help = "Enable debug logging")]
+    #[arg(
+        long,
+        help = "Re-scan host keys of cached SSH hosts and re-gather those whose key changed"
+    )]
+    pub verify_host_keys: bool,
+
+    #[arg(
+        long,
+        help = "Scan the host keys of uncached SSH hosts and reuse the cached facts of a host with the same key, so renamed hosts are not gathered again"
+    )]
+    pub match_host_keys: bool,
+
+    #[arg(
+        long,
+        help = "Store each host's connection parameters, without secrets, alongside its cached facts so `cache show` can tell how they were gathered"
+    )]
+    pub cache_inventory: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "ignore",
+        help = "How to treat SSH host keys"
+    )]
+    pub host_key_policy: HostKeyPolicy,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "openssh",
+        help = "SSH client to connect with: the system ssh binary, or the built-in client (needs the native-ssh feature)"
+    )]
+    pub ssh_backend: SshBackend,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "api",
+        help = "How to gather facts for kubectl hosts"
+    )]
+    pub kubernetes_facts: KubernetesFactsMode,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "warn",
+        help = "Whether hosts given fallback facts are a warning or an error"
+    )]
+    pub fallback_severity: FallbackSeverity,
+
+    #[arg(
+        long = "fail-on-fallback-group",
+        value_name = "GROUP",
+        help = "Fail if any host in GROUP is given fallback facts, whatever --fallback-severity is (repeatable)"
+    )]
+    pub fallback_error_groups: Vec<String>,
+
+    #[arg(
+        long = "exclude",
+        value_name = "PATTERN",
+        help = "Skip hosts matching PATTERN (a host, group or glob); they are not gathered and are listed as skipped (repeatable)"
+    )]
+    pub excluded_hosts: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        help = "Gather only hosts matching PATTERN (a host, group or glob); others are skipped (repeatable)"
+    )]
+    pub limit: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Pass input whose inventory has no hosts through with an empty host_facts map instead of failing"
+    )]
+    pub allow_empty: bool,
+
+    #[arg(
+        long = "label",
+        value_name = "KEY=VALUE",
+        value_parser = parse_label,
+        help = "Attach a label such as datacenter=eu1 to every cache entry and host written by this run (repeatable)"
+    )]
+    pub labels: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        value_parser = parse_sample,
+        help = "Re-gather a random PERCENT (e.g. 5%) of the hosts served from cache and report facts that changed"
+    )]
+    pub verify_sample: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Write the hosts whose facts could not be gathered to PATH, one per line"
+    )]
+    pub failed_hosts_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Gather only the hosts listed in PATH, such as a --failed-hosts-out file; others are skipped"
+    )]
+    pub only_hosts_from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "Docker daemon for docker hosts without ansible_docker_host or docker_context, e.g. tcp://10.0.0.5:2376 or ssh://user@host"
+    )]
+    pub docker_host: Option<String>,
+
+    #[arg(
+        long,
+        help = "Give hosts that cannot be gathered their expired cached facts, marked stale, instead of fallback facts; expired entries are kept in the cache"
+    )]
+    pub allow_stale_on_error: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Known hosts file used by the tofu host key policy"
+    )]
+    pub known_hosts_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "replay",
+        help = "Record every remote command and its output to a session file"
+    )]
+    pub record: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Replay remote command output from a recorded session instead of connecting (bypasses the cache)"
+    )]
+    pub replay: Option<PathBuf>,
+
+    #[arg(long, hide = true, value_name = "host=NAME:FAULT", value_parser = parse_injected_failure)]
+    pub inject_failure: Vec<(String, FaultKind)>,
+
+    #[arg(long, hide = true, value_name = "DURATION", value_parser = parse_latency)]
+    pub inject_latency: Option<Duration>,
+
+    #[cfg(feature = "mock")]
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Fixture file with per-host facts for mock connections"
+    )]
+    pub mock_fixture: Option<PathBuf>,
+
+    #[arg(long, global = true, help = "Enable debug logging")]
     pub debug: bool,
 
+    #[arg(
+        long,
+        help = "Print the backends, facts and formats this build supports as JSON and exit"
+    )]
+    pub capabilities: bool,
+
+    #[arg(
+        long,
+        value_name = "ENTRY",
+        help = "Keyring entry holding the SSH key passphrase"
+    )]
+    pub ssh_passphrase_keyring: Option<String>,
+
+    #[arg(
+        long,
+        short = 'k',
+        help = "Prompt once for the SSH password of hosts without ansible_ssh_pass"
+    )]
+    pub ask_pass: bool,
+
+    #[arg(
+        long,
+        value_name = "ENTRY",
+        help = "Keyring entry holding the become password"
+    )]
+    pub become_password_keyring: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "File containing the become password"
+    )]
+    pub become_pass_file: Option<PathBuf>,
+
     #[arg(
         value_name = "FILE",
         help = "Input JSON file (use stdin if not provided)"
@@ -56,16 +466,260 @@ pub struct CliArgs {
     pub input: Option<PathBuf>,
 }
 
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Inspect and maintain rustle-facts state
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Check a parsed playbook's inventory and list problems without gathering facts
+    Validate {
+        /// Parsed playbook JSON; reads stdin when omitted
+        input: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum CacheCommand {
+    /// Forget the recorded host key of a host so the next connection re-trusts it
+    ForgetKey {
+        host: String,
+        /// The SSH port, for hosts recorded as `[host]:port`
+        #[arg(long)]
+        port: Option<u16>,
+    },
+    /// Print a host's cached facts, their age and how they were gathered;
+    /// every host's when none is named
+    Show { host: Option<String> },
+    /// Remove entries older than --cache-ttl
+    Prune,
+    /// Remove every entry
+    Clear,
+    /// Remove a host's entry so its facts are gathered again, e.g. after it
+    /// was reimaged
+    Rm { host: String },
+    /// Write the cache as JSON or MessagePack, e.g. to carry facts gathered
+    /// on a bastion to an air-gapped runner
+    Export {
+        /// Where to write; stdout when omitted
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        #[arg(long, value_enum, default_value = "none")]
+        compress: OutputCompression,
+    },
+    /// Merge an exported cache into this one, keeping the newer entry of a
+    /// host found in both
+    Import {
+        /// Exported cache in either format, optionally gzip or zstd
+        /// compressed; stdin when omitted
+        input: Option<PathBuf>,
+        /// Replace the cache instead of merging into it
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HostKeyPolicy {
+    /// Accept any host key without recording it
+    #[default]
+    Ignore,
+    /// Trust on first use: record new keys, refuse hosts whose key changed
+    Tofu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SshBackend {
+    /// Spawn the system `ssh` for each host, honouring ssh_config
+    #[default]
+    Openssh,
+    /// Connect in-process, without an ssh binary
+    Native,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum KubernetesFactsMode {
+    /// Read the node's info from the API server, exec only if that fails
+    #[default]
+    Api,
+    /// Always run the probe inside the pod
+    Exec,
+}
+
+/// Optional groups of facts, off unless named by `--gather-subset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum GatherSubset {
+    /// `cloud_provider`, `instance_type` and `region` from the EC2, GCE or
+    /// Azure metadata service
+    Cloud,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputCompression {
+    /// Plain JSON
+    #[default]
+    None,
+    /// gzip, for consumers without zstd
+    Gzip,
+    /// zstd, the fastest to compress and decompress
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// The cache file's own format
+    #[default]
+    Json,
+    /// MessagePack, smaller and faster to parse for large caches
+    Msgpack,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FactPreference {
+    /// Inventory vars and `--facts-override` values; such hosts are not probed
+    #[default]
+    Declared,
+    /// Facts gathered in this run, even for hosts with declared values
+    Gathered,
+    /// Facts cached by an earlier run
+    Cached,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FactNamespace {
+    /// `ansible_architecture`, as Ansible itself names them
+    #[default]
+    Ansible,
+    /// `rustle_architecture`, so they cannot be mistaken for gathered Ansible facts
+    Rustle,
+    /// Both names, for consumers migrating between them
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FallbackSeverity {
+    /// Log the hosts and carry on
+    #[default]
+    Warn,
+    /// Fail the run before any output is written
+    Error,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FactsConfig {
     pub cache_file: PathBuf,
     pub cache_ttl: u64,
     pub parallel_connections: usize,
-    pub timeout: u64,
+    pub connect_timeout: u64,
+    pub command_timeout: u64,
+    pub progress_interval: u64,
+    pub max_connection_rate: u32,
+    pub connect_jitter_ms: u64,
     pub no_cache: bool,
     pub force_refresh: bool,
+    pub no_overrides: bool,
+    pub no_implicit_local: bool,
+    /// Skip the up-front DNS lookup of SSH targets
+    pub no_dns_precheck: bool,
+    pub facts_override: Option<PathBuf>,
+    /// Architecture spellings added to the built-in table
+    pub arch_map: Option<PathBuf>,
+    /// Script run in place of the built-in probe
+    pub probe_script: Option<PathBuf>,
+    /// Shell appended to the probe, for custom facts
+    pub probe_append: Option<String>,
+    /// Optional fact groups added to the probe
+    pub gather_subset: Vec<GatherSubset>,
+    pub prefer: FactPreference,
+    pub fail_on_mixed_arch: bool,
+    pub write_partial: bool,
+    pub deadline: u64,
+    pub max_failed_hosts: Option<usize>,
+    pub max_failed_percent: Option<u8>,
+    /// Consecutive failures after which a backend is given up on
+    pub circuit_breaker: Option<usize>,
+    pub matrix: bool,
+    pub play_host_facts: bool,
+    pub group_facts: bool,
+    pub fact_namespace: FactNamespace,
+    pub compress_output: OutputCompression,
+    pub group_by_fact: Option<String>,
+    pub select: Option<Selection>,
+    pub assertions: Vec<FactAssertion>,
     pub ssh_config: Option<PathBuf>,
+    pub verify_host_keys: bool,
+    /// Recognise renamed SSH hosts by their host key
+    pub match_host_keys: bool,
+    /// Cache a snapshot of each host's connection parameters
+    pub cache_inventory: bool,
+    pub host_key_policy: HostKeyPolicy,
+    /// SSH client used to reach SSH hosts
+    pub ssh_backend: SshBackend,
+    pub kubernetes_facts: KubernetesFactsMode,
+    pub fallback_severity: FallbackSeverity,
+    pub fallback_error_groups: Vec<String>,
+    /// Host patterns removed from gathering and reported as skipped
+    pub excluded_hosts: Vec<String>,
+    /// Host patterns limiting the run to the hosts they match
+    pub limit: Vec<String>,
+    /// Enrich an inventory with no hosts instead of refusing it
+    pub allow_empty: bool,
+    /// Labels recorded on cache entries and hosts gathered by this run
+    pub labels: BTreeMap<String, String>,
+    /// Percentage of cache hits re-gathered to check for drift
+    pub verify_sample: Option<f64>,
+    /// Where to list the hosts that could not be gathered
+    pub failed_hosts_out: Option<PathBuf>,
+    /// A host list limiting the run to those hosts
+    pub only_hosts_from: Option<PathBuf>,
+    /// Default Docker daemon address for docker hosts
+    pub docker_host: Option<String>,
+    /// Serve expired cache entries for hosts whose gathering failed
+    pub allow_stale_on_error: bool,
+    pub known_hosts_file: PathBuf,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub fault_injection: FaultInjection,
+    #[cfg(feature = "mock")]
+    pub mock_fixture: Option<PathBuf>,
     pub debug: bool,
+    pub ssh_passphrase_keyring: Option<String>,
+    /// Prompt for an SSH password when credentials are resolved
+    pub ask_pass: bool,
+    pub become_password_keyring: Option<String>,
+    pub become_pass_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub credentials: Credentials,
+    #[serde(skip)]
+    pub session: Option<Arc<Session>>,
+    /// Set by the signal handler; gatherers stop when it is requested
+    #[serde(skip)]
+    pub shutdown: Shutdown,
+    /// Health of each backend, shared by the gatherers of a run
+    #[serde(skip)]
+    pub breakers: CircuitBreakers,
+    /// In-memory cache used instead of reading and writing `cache_file` on
+    /// every run, for callers that enrich many inputs
+    #[serde(skip)]
+    pub shared_cache: Option<SharedCache>,
+    /// The `ssh_config` file, or `~/.ssh/config`, read once per run
+    #[serde(skip)]
+    pub parsed_ssh_config: Option<Arc<SshConfig>>,
+    /// The built-in architecture names plus `arch_map`, read once per run
+    #[serde(skip)]
+    pub arch_table: ArchTable,
+    /// Plugins registered by library callers, run on each gathered host
+    #[serde(skip)]
+    pub fact_plugins: FactPlugins,
 }
 
 impl Default for FactsConfig {
@@ -78,11 +732,73 @@ impl Default for FactsConfig {
             cache_file: cache_dir.join("arch-facts.json"),
             cache_ttl: 86400,
             parallel_connections: 20,
-            timeout: 10,
+            connect_timeout: 10,
+            command_timeout: 30,
+            progress_interval: 10,
+            max_connection_rate: 0,
+            connect_jitter_ms: 0,
             no_cache: false,
             force_refresh: false,
+            no_overrides: false,
+            no_implicit_local: false,
+            no_dns_precheck: false,
+            facts_override: None,
+            arch_map: None,
+            probe_script: None,
+            probe_append: None,
+            gather_subset: Vec::new(),
+            prefer: FactPreference::Declared,
+            fail_on_mixed_arch: false,
+            write_partial: false,
+            deadline: 0,
+            max_failed_hosts: None,
+            max_failed_percent: None,
+            circuit_breaker: None,
+            matrix: false,
+            play_host_facts: false,
+            group_facts: false,
+            fact_namespace: FactNamespace::Ansible,
+            compress_output: OutputCompression::None,
+            group_by_fact: None,
+            select: None,
+            assertions: Vec::new(),
             ssh_config: None,
+            verify_host_keys: false,
+            match_host_keys: false,
+            cache_inventory: false,
+            host_key_policy: HostKeyPolicy::Ignore,
+            ssh_backend: SshBackend::Openssh,
+            kubernetes_facts: KubernetesFactsMode::Api,
+            fallback_severity: FallbackSeverity::Warn,
+            fallback_error_groups: Vec::new(),
+            excluded_hosts: Vec::new(),
+            limit: Vec::new(),
+            allow_empty: false,
+            labels: BTreeMap::new(),
+            verify_sample: None,
+            failed_hosts_out: None,
+            only_hosts_from: None,
+            docker_host: None,
+            allow_stale_on_error: false,
+            known_hosts_file: cache_dir.join("known_hosts"),
+            record: None,
+            replay: None,
+            fault_injection: FaultInjection::default(),
+            #[cfg(feature = "mock")]
+            mock_fixture: None,
             debug: false,
+            ssh_passphrase_keyring: None,
+            ask_pass: false,
+            become_password_keyring: None,
+            become_pass_file: None,
+            credentials: Credentials::default(),
+            session: None,
+            shutdown: Shutdown::default(),
+            breakers: CircuitBreakers::default(),
+            shared_cache: None,
+            parsed_ssh_config: None,
+            arch_table: ArchTable::default(),
+            fact_plugins: FactPlugins::default(),
         }
     }
 }
@@ -97,11 +813,72 @@ impl From<CliArgs> for FactsConfig {
 
         config.cache_ttl = args.cache_ttl;
         config.parallel_connections = args.parallel;
-        config.timeout = args.timeout;
+        config.connect_timeout = args.connect_timeout;
+        config.command_timeout = args.command_timeout;
+        config.progress_interval = args.progress_interval;
+        config.max_connection_rate = args.max_connection_rate;
+        config.connect_jitter_ms = args.connect_jitter_ms;
         config.no_cache = args.no_cache;
         config.force_refresh = args.force_refresh;
+        config.no_overrides = args.no_overrides;
+        config.no_implicit_local = args.no_implicit_local;
+        config.no_dns_precheck = args.no_dns_precheck;
+        config.facts_override = args.facts_override;
+        config.arch_map = args.arch_map;
+        config.probe_script = args.probe_script;
+        config.probe_append = args.probe_append;
+        config.gather_subset = args.gather_subset;
+        config.prefer = args.prefer;
+        config.fail_on_mixed_arch = args.fail_on_mixed_arch;
+        config.write_partial = args.write_partial;
+        config.deadline = args.deadline;
+        config.max_failed_hosts = args.max_failed_hosts;
+        config.max_failed_percent = args.max_failed_percent;
+        config.circuit_breaker = args.circuit_breaker;
+        config.matrix = args.matrix;
+        config.play_host_facts = args.play_host_facts;
+        config.group_facts = args.group_facts;
+        config.fact_namespace = args.fact_namespace;
+        config.compress_output = args.compress_output;
+        config.group_by_fact = args.group_by_fact;
+        config.select = args.select;
+        config.assertions = args.assertions;
         config.ssh_config = args.ssh_config;
+        config.verify_host_keys = args.verify_host_keys;
+        config.match_host_keys = args.match_host_keys;
+        config.cache_inventory = args.cache_inventory;
+        config.host_key_policy = args.host_key_policy;
+        config.ssh_backend = args.ssh_backend;
+        config.kubernetes_facts = args.kubernetes_facts;
+        config.fallback_severity = args.fallback_severity;
+        config.fallback_error_groups = args.fallback_error_groups;
+        config.excluded_hosts = args.excluded_hosts;
+        config.limit = args.limit;
+        config.allow_empty = args.allow_empty;
+        config.labels = args.labels.into_iter().collect();
+        config.verify_sample = args.verify_sample;
+        config.failed_hosts_out = args.failed_hosts_out;
+        config.only_hosts_from = args.only_hosts_from;
+        config.docker_host = args.docker_host;
+        config.allow_stale_on_error = args.allow_stale_on_error;
+        if let Some(known_hosts_file) = args.known_hosts_file {
+            config.known_hosts_file = known_hosts_file;
+        }
+        config.record = args.record;
+        config.replay = args.replay;
+        config.fault_injection = FaultInjection {
+            failures: args.inject_failure.into_iter().collect(),
+            latency: args.inject_latency,
+        };
+        #[cfg(feature = "mock")]
+        {
+            config.mock_fixture = args.mock_fixture;
+        }
         config.debug = args.debug;
+        config.ssh_passphrase_keyring = args.ssh_passphrase_keyring;
+        config.ask_pass = args.ask_pass;
+        config.become_password_keyring = args.become_password_keyring;
+        config.become_pass_file = args.become_pass_file;
 
         config
     }
@@ -127,9 +904,56 @@ impl FactsConfig {
             }
         }
 
-        if let Ok(timeout) = std::env::var("RUSTLE_FACTS_SSH_TIMEOUT") {
+        // RUSTLE_FACTS_SSH_TIMEOUT predates the split and sets the connect timeout
+        for var in ["RUSTLE_FACTS_SSH_TIMEOUT", "RUSTLE_FACTS_CONNECT_TIMEOUT"] {
+            if let Ok(timeout) = std::env::var(var) {
+                if let Ok(timeout_secs) = timeout.parse() {
+                    config.connect_timeout = timeout_secs;
+                }
+            }
+        }
+
+        if let Ok(timeout) = std::env::var("RUSTLE_FACTS_COMMAND_TIMEOUT") {
             if let Ok(timeout_secs) = timeout.parse() {
-                config.timeout = timeout_secs;
+                config.command_timeout = timeout_secs;
+            }
+        }
+
+        if let Ok(entry) = std::env::var("RUSTLE_FACTS_SSH_PASSPHRASE_KEYRING") {
+            config.ssh_passphrase_keyring = Some(entry);
+        }
+
+        if let Ok(entry) = std::env::var("RUSTLE_FACTS_BECOME_PASSWORD_KEYRING") {
+            config.become_password_keyring = Some(entry);
+        }
+
+        // Rules are separated by semicolons; invalid ones are reported and skipped
+        if let Ok(rules) = std::env::var("RUSTLE_FACTS_ASSERT") {
+            for rule in rules.split(';').filter(|r| !r.trim().is_empty()) {
+                match parse_assertion(rule) {
+                    Ok(assertion) => config.assertions.push(assertion),
+                    Err(e) => tracing::warn!("Ignoring RUSTLE_FACTS_ASSERT rule: {}", e),
+                }
+            }
+        }
+
+        if let Ok(patterns) = std::env::var("RUSTLE_FACTS_EXCLUDE") {
+            config.excluded_hosts = patterns
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        if let Ok(labels) = std::env::var("RUSTLE_FACTS_LABELS") {
+            for label in labels.split(',').filter(|l| !l.trim().is_empty()) {
+                match parse_label(label) {
+                    Ok((key, value)) => {
+                        config.labels.insert(key, value);
+                    }
+                    Err(e) => tracing::warn!("Ignoring RUSTLE_FACTS_LABELS entry: {}", e),
+                }
             }
         }
 
@@ -151,10 +975,107 @@ impl FactsConfig {
             self.parallel_connections = env_config.parallel_connections;
         }
 
-        if std::env::var("RUSTLE_FACTS_SSH_TIMEOUT").is_ok() {
-            self.timeout = env_config.timeout;
+        if std::env::var("RUSTLE_FACTS_SSH_TIMEOUT").is_ok()
+            || std::env::var("RUSTLE_FACTS_CONNECT_TIMEOUT").is_ok()
+        {
+            self.connect_timeout = env_config.connect_timeout;
+        }
+
+        if std::env::var("RUSTLE_FACTS_COMMAND_TIMEOUT").is_ok() {
+            self.command_timeout = env_config.command_timeout;
+        }
+
+        if self.ssh_passphrase_keyring.is_none() {
+            self.ssh_passphrase_keyring = env_config.ssh_passphrase_keyring;
+        }
+
+        if self.become_password_keyring.is_none() {
+            self.become_password_keyring = env_config.become_password_keyring;
+        }
+
+        self.assertions.extend(env_config.assertions);
+        self.excluded_hosts.extend(env_config.excluded_hosts);
+        // Labels given on the command line win over the environment
+        for (key, value) in env_config.labels {
+            self.labels.entry(key).or_insert(value);
         }
 
         self
     }
+
+    /// This config with the host's own timeouts applied: `ansible_timeout`
+    /// (or the entry's `connection_timeout`) for connecting and
+    /// `rustle_command_timeout` for the probe.
+    pub fn for_host(&self, host: &HostEntry) -> Self {
+        let secs = |key: &str| {
+            host.vars.get(key).and_then(|v| match v {
+                serde_json::Value::Number(n) => n.as_u64(),
+                serde_json::Value::String(s) => s.trim().parse().ok(),
+                _ => None,
+            })
+        };
+
+        let mut config = self.clone();
+        if let Some(connect) = secs("ansible_timeout").or(host.connection_timeout.map(u64::from)) {
+            config.connect_timeout = connect;
+        }
+        if let Some(command) = secs("rustle_command_timeout") {
+            config.command_timeout = command;
+        }
+        config
+    }
+
+    /// Total time a host may take: connecting plus running the probe.
+    pub fn host_budget(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout + self.command_timeout)
+    }
+
+    /// Run `attempt` through `backend`'s circuit breaker.
+    pub async fn through_breaker<T>(
+        &self,
+        backend: &str,
+        attempt: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        self.breakers
+            .call(self.circuit_breaker, backend, attempt)
+            .await
+    }
+
+    /// How many of `total` hosts may fail before gathering is aborted, from
+    /// the stricter of `--max-failed-hosts` and `--max-failed-percent`.
+    pub fn failure_limit(&self, total: usize) -> Option<usize> {
+        let by_percent = self
+            .max_failed_percent
+            .map(|percent| total * usize::from(percent) / 100);
+        match (self.max_failed_hosts, by_percent) {
+            (Some(hosts), Some(percent)) => Some(hosts.min(percent)),
+            (hosts, percent) => hosts.or(percent),
+        }
+    }
+
+    /// Resolve keyring references into in-memory credentials.
+    pub fn resolve_credentials(mut self) -> Result<Self> {
+        if let Some(entry) = &self.ssh_passphrase_keyring {
+            self.credentials.ssh_passphrase = Some(read_keyring_secret(entry)?);
+        }
+
+        if self.ask_pass {
+            self.credentials.ssh_password = Some(prompt_secret("SSH password: ")?);
+        }
+
+        // An explicit file wins over the keyring, which wins over the environment
+        if let Some(path) = &self.become_pass_file {
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                FactsError::InvalidConfig(format!("Failed to read become password file: {e}"))
+            })?;
+            self.credentials.become_password =
+                Some(Secret::new(content.trim_end_matches(['\r', '\n'])));
+        } else if let Some(entry) = &self.become_password_keyring {
+            self.credentials.become_password = Some(read_keyring_secret(entry)?);
+        } else if let Ok(password) = std::env::var("RUSTLE_FACTS_BECOME_PASSWORD") {
+            self.credentials.become_password = Some(Secret::new(password));
+        }
+
+        Ok(self)
+    }
 }
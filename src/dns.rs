@@ -0,0 +1,200 @@
+//! Up-front DNS resolution of SSH targets.
+//!
+//! A host whose name does not exist would otherwise cost a full connect
+//! timeout before ssh gives up. Each target's effective hostname has the
+//! run's ssh_config applied, so aliases are honoured, and every distinct
+//! name is looked up once. Hosts reached through a proxy are left alone because the
+//! proxy does the resolving. Only a definitive "no such name" fails a host;
+//! transient resolver errors and slow lookups let ssh try as usual.
+
+use crate::config::FactsConfig;
+use crate::ssh_config::SshConfig;
+use crate::ssh_facts::SshTarget;
+use crate::types::HostEntry;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::debug;
+
+/// The outcome of looking up one name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Resolved,
+    /// The resolver says the name does not exist
+    NotFound(String),
+    /// Timed out or failed transiently; ssh is left to try
+    Inconclusive,
+}
+
+/// SSH hosts whose target name does not resolve, with the resolver error.
+pub async fn find_unresolvable(
+    hosts: &[HostEntry],
+    config: &FactsConfig,
+) -> BTreeMap<String, String> {
+    let ssh_config = config.parsed_ssh_config.as_deref();
+    let targets: HashMap<String, String> = hosts
+        .iter()
+        .filter_map(|host| Some((host.name.clone(), effective_hostname(host, ssh_config)?)))
+        .collect();
+
+    let mut names: Vec<String> = targets.values().cloned().collect();
+    names.sort();
+    names.dedup();
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections.max(1)));
+    let lookup_timeout = Duration::from_secs(config.connect_timeout.max(1));
+    let mut tasks = JoinSet::new();
+    for name in names {
+        let sem = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await.ok();
+            let resolution = resolve(&name, lookup_timeout).await;
+            (name, resolution)
+        });
+    }
+    let mut resolutions = HashMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((name, resolution)) = joined {
+            resolutions.insert(name, resolution);
+        }
+    }
+
+    targets
+        .into_iter()
+        .filter_map(|(host, target)| match resolutions.get(&target)? {
+            Resolution::NotFound(detail) => Some((host, format!("{target}: {detail}"))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Look up `name`, giving up (inconclusively) after `limit`.
+pub async fn resolve(name: &str, limit: Duration) -> Resolution {
+    if name.parse::<IpAddr>().is_ok() {
+        return Resolution::Resolved;
+    }
+    match tokio::time::timeout(limit, tokio::net::lookup_host((name, 22))).await {
+        Ok(Ok(mut addrs)) => match addrs.next() {
+            Some(_) => Resolution::Resolved,
+            None => Resolution::NotFound("no addresses".to_string()),
+        },
+        Ok(Err(e)) => classify_lookup_error(&e.to_string()),
+        Err(_) => {
+            debug!("DNS lookup of {} timed out", name);
+            Resolution::Inconclusive
+        }
+    }
+}
+
+/// getaddrinfo errors only differ in their message. Only a name the
+/// resolver says does not exist (`EAI_NONAME`, NXDOMAIN) fails the host;
+/// anything else, such as a timeout or a broken resolver, is left to ssh.
+const NOT_FOUND_MESSAGES: &[&str] = &[
+    // glibc
+    "name or service not known",
+    // macOS and the BSDs
+    "nodename nor servname provided, or not known",
+    // musl
+    "name does not resolve",
+    // Windows
+    "no such host is known",
+];
+
+fn classify_lookup_error(message: &str) -> Resolution {
+    let lower = message.to_lowercase();
+    if NOT_FOUND_MESSAGES.iter().any(|known| lower.contains(known)) {
+        Resolution::NotFound(message.to_string())
+    } else {
+        Resolution::Inconclusive
+    }
+}
+
+/// The name ssh would connect to, or `None` when a proxy is involved and
+/// does the resolving instead.
+fn effective_hostname(host: &HostEntry, ssh_config: Option<&SshConfig>) -> Option<String> {
+    if ["ansible_ssh_proxy_command", "proxy_command"]
+        .iter()
+        .any(|key| host.vars.contains_key(*key))
+    {
+        return None;
+    }
+
+    let target = SshTarget::with_ssh_config(host, ssh_config).ok()?;
+    let proxied =
+        ssh_config.is_some_and(|ssh_config| ssh_config.lookup(&target.hostname).proxy.is_some());
+    (!proxied).then_some(target.address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_hostname() {
+        let ssh_config =
+            SshConfig::parse("Host web1\n    HostName 10.0.0.5\nHost db1\n    ProxyJump bastion\n")
+                .unwrap();
+
+        let web1 = HostEntry::new("web1");
+        assert_eq!(
+            effective_hostname(&web1, Some(&ssh_config)),
+            Some("10.0.0.5".to_string())
+        );
+        assert_eq!(effective_hostname(&web1, None), Some("web1".to_string()));
+        assert_eq!(
+            effective_hostname(&HostEntry::new("db1"), Some(&ssh_config)),
+            None
+        );
+
+        let mut proxied = HostEntry::new("web2");
+        proxied.vars.insert(
+            "ansible_ssh_proxy_command".to_string(),
+            serde_json::json!("ssh -W %h:%p bastion"),
+        );
+        assert_eq!(effective_hostname(&proxied, Some(&ssh_config)), None);
+    }
+
+    #[test]
+    fn test_classify_lookup_error() {
+        assert_eq!(
+            classify_lookup_error(
+                "failed to lookup address information: Temporary failure in name resolution"
+            ),
+            Resolution::Inconclusive
+        );
+        assert!(matches!(
+            classify_lookup_error(
+                "failed to lookup address information: Name or service not known"
+            ),
+            Resolution::NotFound(_)
+        ));
+        assert!(matches!(
+            classify_lookup_error(
+                "failed to lookup address information: nodename nor servname provided, or not known"
+            ),
+            Resolution::NotFound(_)
+        ));
+        assert_eq!(
+            classify_lookup_error(
+                "failed to lookup address information: Non-recoverable failure in name resolution"
+            ),
+            Resolution::Inconclusive
+        );
+        assert_eq!(
+            classify_lookup_error(
+                "failed to lookup address information: Address family for hostname not supported"
+            ),
+            Resolution::Inconclusive
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ip_literal() {
+        assert_eq!(
+            resolve("192.0.2.1", Duration::from_secs(1)).await,
+            Resolution::Resolved
+        );
+    }
+}
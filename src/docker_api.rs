@@ -0,0 +1,154 @@
+//! Docker Engine and Podman socket API backend, enabled with the `bollard`
+//! feature.
+//!
+//! Docker is reached through `DOCKER_HOST` (a unix socket by default, or
+//! TCP with TLS when `DOCKER_TLS_VERIFY`/`DOCKER_CERT_PATH` are set). Podman
+//! is reached through `CONTAINER_HOST`, else the rootless socket under
+//! `$XDG_RUNTIME_DIR`, else `/run/podman/podman.sock`, using the
+//! Docker-compatible endpoints Podman serves. Neither needs the CLI installed,
+//! but when the socket does not answer the CLI is used instead.
+
+use crate::docker_facts::ContainerRuntime;
+use crate::error::{FactsError, Result};
+use bollard::errors::Error as ApiError;
+use bollard::exec::{CreateExecOptions, StartExecOptions, StartExecResults};
+use bollard::Docker;
+use futures_util::StreamExt;
+use std::sync::OnceLock;
+use tokio::sync::OnceCell;
+use tracing::debug;
+
+/// Whether the runtime's socket answers a ping, checked once per process.
+pub async fn available(runtime: ContainerRuntime) -> bool {
+    static DOCKER: OnceCell<bool> = OnceCell::const_new();
+    static PODMAN: OnceCell<bool> = OnceCell::const_new();
+
+    let checked = match runtime {
+        ContainerRuntime::Docker => &DOCKER,
+        ContainerRuntime::Podman => &PODMAN,
+        ContainerRuntime::Nerdctl => return false,
+    };
+    *checked
+        .get_or_init(|| async {
+            let reply = match client(runtime) {
+                Ok(docker) => docker.ping().await.map_err(describe),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = &reply {
+                debug!(
+                    "{} API unavailable, using its CLI instead: {}",
+                    runtime.as_str(),
+                    e
+                );
+            }
+            reply.is_ok()
+        })
+        .await
+}
+
+/// Run `command` in a container and return its exit code, stdout and stderr.
+pub async fn exec(
+    runtime: ContainerRuntime,
+    container: &str,
+    user: Option<&str>,
+    workdir: Option<&str>,
+    command: &[&str],
+) -> Result<(Option<i32>, String, String)> {
+    let docker = client(runtime)?;
+    let api_error = |e: ApiError| {
+        let detail = describe(e);
+        if detail.to_lowercase().contains("permission denied") {
+            FactsError::AuthenticationFailed(container.to_string(), detail)
+        } else {
+            FactsError::DockerApi(container.to_string(), detail)
+        }
+    };
+
+    let created = docker
+        .create_exec(
+            container,
+            CreateExecOptions {
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                cmd: Some(command.to_vec()),
+                user,
+                working_dir: workdir,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(api_error)?;
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let started = docker
+        .start_exec(&created.id, None::<StartExecOptions>)
+        .await
+        .map_err(api_error)?;
+    if let StartExecResults::Attached { mut output, .. } = started {
+        while let Some(chunk) = output.next().await {
+            match chunk.map_err(api_error)? {
+                bollard::container::LogOutput::StdErr { message } => {
+                    stderr.push_str(&String::from_utf8_lossy(&message))
+                }
+                other => stdout.push_str(&other.to_string()),
+            }
+        }
+    }
+
+    let inspected = docker.inspect_exec(&created.id).await.map_err(api_error)?;
+    let exit_code = inspected
+        .exit_code
+        .and_then(|code| i32::try_from(code).ok());
+    Ok((exit_code, stdout, stderr))
+}
+
+/// The process-wide client for a runtime. Connection setup only reads
+/// configuration, so a failure here means the socket settings are unusable.
+fn client(runtime: ContainerRuntime) -> Result<&'static Docker> {
+    type Client = OnceLock<std::result::Result<Docker, String>>;
+    static DOCKER: Client = OnceLock::new();
+    static PODMAN: Client = OnceLock::new();
+
+    let client = match runtime {
+        ContainerRuntime::Docker => &DOCKER,
+        ContainerRuntime::Podman => &PODMAN,
+        ContainerRuntime::Nerdctl => {
+            return Err(FactsError::DockerApi(
+                runtime.as_str().to_string(),
+                "containerd serves no Docker API; nerdctl is run through its CLI".to_string(),
+            ))
+        }
+    };
+    client
+        .get_or_init(|| connect(runtime).map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| FactsError::DockerApi(runtime.as_str().to_string(), e.clone()))
+}
+
+fn connect(runtime: ContainerRuntime) -> std::result::Result<Docker, ApiError> {
+    match runtime {
+        // nerdctl is refused by `client` before it gets here
+        ContainerRuntime::Docker | ContainerRuntime::Nerdctl => Docker::connect_with_defaults(),
+        ContainerRuntime::Podman => match std::env::var("CONTAINER_HOST") {
+            Ok(host) if host.starts_with("unix://") => {
+                Docker::connect_with_unix(&host, 120, bollard::API_DEFAULT_VERSION)
+            }
+            _ => Docker::connect_with_podman_defaults(),
+        },
+    }
+}
+
+fn describe(error: ApiError) -> String {
+    match error {
+        ApiError::DockerResponseServerError {
+            status_code: 404,
+            message,
+        } => format!("no such container ({message})"),
+        ApiError::DockerResponseServerError {
+            status_code: 409,
+            message,
+        } => format!("container is not running ({message})"),
+        other => other.to_string(),
+    }
+}
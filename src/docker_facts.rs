@@ -1,56 +1,121 @@
 use crate::config::FactsConfig;
+use crate::error::FactsError;
+use crate::faults::inject_faults;
+use crate::sanitize::{
+    validate_container_name, validate_daemon_address, validate_docker_user, validate_path,
+};
+use crate::session::run_recorded;
+use crate::ssh_facts::{fact_probe, parse_fact_output_with};
 use crate::types::{ArchitectureFacts, HostEntry};
 use anyhow::Context;
 use std::collections::HashMap;
-use std::process::Stdio;
-use tokio::process::Command;
+use tokio::task::JoinSet;
 use tokio::time::{timeout, Duration};
 use tracing::{debug, error, instrument};
 
+/// The container engine a host is reached through. Podman serves the same
+/// exec API as Docker, and nerdctl mirrors the Docker CLI for containerd, so
+/// all three share this gatherer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+    Nerdctl,
+}
+
+impl ContainerRuntime {
+    /// The CLI binary, which is also the `ansible_connection` value.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Nerdctl => "nerdctl",
+        }
+    }
+
+    /// The runtime named by a host's `rustle_container_runtime` or
+    /// `container_runtime` var.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "docker" => Some(ContainerRuntime::Docker),
+            "podman" => Some(ContainerRuntime::Podman),
+            "nerdctl" | "containerd" => Some(ContainerRuntime::Nerdctl),
+            _ => None,
+        }
+    }
+}
+
 /// Gather minimal facts for hosts using Docker connections
-#[instrument(skip(hosts, config))]
 pub async fn gather_minimal_facts(
     hosts: Vec<HostEntry>,
     config: &FactsConfig,
+) -> crate::error::Result<HashMap<String, ArchitectureFacts>> {
+    gather_runtime_facts(ContainerRuntime::Docker, hosts, config).await
+}
+
+/// Gather minimal facts for hosts in containers of the given runtime
+#[instrument(skip(hosts, config))]
+pub async fn gather_runtime_facts(
+    runtime: ContainerRuntime,
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
 ) -> crate::error::Result<HashMap<String, ArchitectureFacts>> {
     let mut facts = HashMap::new();
     let max_concurrent = config.parallel_connections;
 
     // Process hosts in batches to limit concurrent Docker operations
     for chunk in hosts.chunks(max_concurrent) {
-        let mut handles = vec![];
+        if config.shutdown.is_requested() {
+            break;
+        }
+        let mut tasks = JoinSet::new();
 
         for host in chunk {
             let host_clone = host.clone();
-            let timeout_secs = config.timeout;
-
-            let handle = tokio::spawn(async move {
-                match gather_host_facts(&host_clone, timeout_secs).await {
-                    Ok(host_facts) => (host_clone.name.clone(), Ok(host_facts)),
-                    Err(e) => (
-                        host_clone.name.clone(),
-                        Err(crate::error::FactsError::ConnectionFailed(
-                            host_clone.name.clone(),
-                            e.to_string(),
-                        )),
-                    ),
-                }
+            let config = config.clone();
+
+            tasks.spawn(async move {
+                let backend = breaker_backend(runtime, &host_clone, &config);
+                let attempt = async {
+                    gather_host_facts(runtime, &host_clone, &config)
+                        .await
+                        .map_err(|e| match e.downcast_ref::<FactsError>() {
+                            Some(FactsError::AuthenticationFailed(_, detail)) => {
+                                FactsError::AuthenticationFailed(
+                                    host_clone.name.clone(),
+                                    detail.clone(),
+                                )
+                            }
+                            // Output the probe could not parse came back
+                            // over a working daemon, so it must not trip
+                            // the breaker
+                            Some(FactsError::ParseError(_, detail)) => {
+                                FactsError::ParseError(host_clone.name.clone(), detail.clone())
+                            }
+                            Some(FactsError::InvalidConfig(detail)) => {
+                                FactsError::InvalidConfig(detail.clone())
+                            }
+                            _ => {
+                                FactsError::ConnectionFailed(host_clone.name.clone(), e.to_string())
+                            }
+                        })
+                };
+                let result = config.through_breaker(&backend, attempt).await;
+                (host_clone.name.clone(), result)
             });
-
-            handles.push(handle);
         }
 
-        // Wait for all tasks in this batch to complete
-        for handle in handles {
-            match handle.await {
+        // Wait for this batch, keeping what finished if interrupted
+        while let Some(joined) = config.shutdown.join_next(&mut tasks).await {
+            match joined {
                 Ok((hostname, result)) => match result {
                     Ok(host_facts) => {
                         facts.insert(hostname, host_facts);
                     }
-                    Err(e) => {
-                        error!("Failed to gather facts for {}: {}", hostname, e);
-                        return Err(e);
-                    }
+                    // Reported once, when the breaker opened
+                    Err(e @ FactsError::CircuitOpen(..)) => debug!("{}", e),
+                    // The host falls back like an unreachable SSH host
+                    Err(e) => error!("Failed to gather facts for {}: {}", hostname, e),
                 },
                 Err(e) => {
                     error!("Task panicked: {}", e);
@@ -62,216 +127,267 @@ pub async fn gather_minimal_facts(
     Ok(facts)
 }
 
+/// The daemon a container host is reached through, for its circuit
+/// breaker: the runtime, plus the remote Docker daemon when there is one.
+fn breaker_backend(runtime: ContainerRuntime, host: &HostEntry, config: &FactsConfig) -> String {
+    let daemon = DockerTarget::from_host(runtime, host, config.docker_host.as_deref())
+        .ok()
+        .and_then(|target| target.daemon);
+    match daemon {
+        Some(DockerDaemon::Host(address)) => format!("{} {address}", runtime.as_str()),
+        Some(DockerDaemon::Context(context)) => format!("{} context {context}", runtime.as_str()),
+        None => runtime.as_str().to_string(),
+    }
+}
+
+/// The container to exec into and who and where to run the probe as.
+#[derive(Debug, Clone, PartialEq)]
+struct DockerTarget<'a> {
+    runtime: ContainerRuntime,
+    container: &'a str,
+    user: Option<&'a str>,
+    workdir: Option<&'a str>,
+    /// The containerd namespace, for nerdctl only
+    namespace: Option<&'a str>,
+    /// A remote Docker daemon, for docker only
+    daemon: Option<DockerDaemon<'a>>,
+}
+
+/// Where the `docker` CLI sends its requests when not to the local daemon.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DockerDaemon<'a> {
+    /// A daemon address such as `tcp://10.0.0.5:2376` or `ssh://user@host`,
+    /// passed as `--host`
+    Host(&'a str),
+    /// A named `docker context`
+    Context(&'a str),
+}
+
+impl DockerDaemon<'_> {
+    fn arg(&self) -> String {
+        match self {
+            DockerDaemon::Host(host) => format!("--host={host}"),
+            DockerDaemon::Context(context) => format!("--context={context}"),
+        }
+    }
+}
+
+impl<'a> DockerTarget<'a> {
+    /// Read `ansible_host` (or the entry address), `ansible_<runtime>_user`,
+    /// `ansible_<runtime>_working_dir` and, for nerdctl,
+    /// `ansible_nerdctl_namespace` from the host. Docker hosts are reached
+    /// through `ansible_docker_host`, else `docker_context`, else
+    /// `default_daemon` (`--docker-host`).
+    fn from_host(
+        runtime: ContainerRuntime,
+        host: &'a HostEntry,
+        default_daemon: Option<&'a str>,
+    ) -> anyhow::Result<Self> {
+        let var = |key: &str| host.vars.get(key).and_then(|v| v.as_str());
+        let runtime_var = |suffix: &str| var(&format!("ansible_{}_{suffix}", runtime.as_str()));
+
+        let container = var("ansible_host")
+            .or(host.address.as_deref())
+            .ok_or_else(|| anyhow::anyhow!("No container name found for host {}", host.name))?;
+        validate_container_name(container)?;
+
+        let user = runtime_var("user");
+        if let Some(user) = user {
+            validate_docker_user(user)?;
+        }
+        let workdir = runtime_var("working_dir");
+        if let Some(workdir) = workdir {
+            validate_path(workdir)?;
+        }
+
+        let namespace = match runtime {
+            ContainerRuntime::Nerdctl => runtime_var("namespace"),
+            _ => None,
+        };
+        if let Some(namespace) = namespace {
+            validate_container_name(namespace)?;
+        }
+
+        let daemon = match runtime {
+            ContainerRuntime::Docker => var("ansible_docker_host")
+                .or(default_daemon.filter(|_| var("docker_context").is_none()))
+                .map(DockerDaemon::Host)
+                .or_else(|| var("docker_context").map(DockerDaemon::Context)),
+            _ => None,
+        };
+        match daemon {
+            Some(DockerDaemon::Host(address)) => validate_daemon_address(address)?,
+            Some(DockerDaemon::Context(context)) => validate_container_name(context)?,
+            None => {}
+        }
+
+        Ok(Self {
+            runtime,
+            container,
+            user,
+            workdir,
+            namespace,
+            daemon,
+        })
+    }
+}
+
 /// Gather facts for a single host using Docker
-#[instrument(skip(host))]
+#[instrument(skip(host, config))]
 async fn gather_host_facts(
+    runtime: ContainerRuntime,
     host: &HostEntry,
-    timeout_secs: u64,
+    config: &FactsConfig,
 ) -> anyhow::Result<ArchitectureFacts> {
-    let container_name = host
-        .vars
-        .get("ansible_host")
-        .and_then(|v| v.as_str())
-        .or(host.address.as_deref())
-        .ok_or_else(|| anyhow::anyhow!("No container name found for host {}", host.name))?;
+    let target = DockerTarget::from_host(runtime, host, config.docker_host.as_deref())?;
+    let container_name = target.container;
+    let config = &config.for_host(host);
+    inject_faults(config, &host.name).await?;
 
     debug!("Gathering facts for Docker container: {}", container_name);
 
-    // First check if container is running
-    check_container_running(container_name, timeout_secs)
+    // The shared probe gathers every fact in a single exec
+    let probe = fact_probe(config)?;
+    let output = execute_docker_command(&target, &["sh", "-c", &probe], config)
         .await
         .with_context(|| format!("Container {container_name} is not running or accessible"))?;
 
-    // Gather facts in parallel
-    let (os_type, _hostname, _kernel, _cpu_info) = tokio::try_join!(
-        get_os_type(container_name, timeout_secs),
-        get_hostname(container_name, timeout_secs),
-        get_kernel_info(container_name, timeout_secs),
-        get_cpu_info(container_name, timeout_secs)
-    )?;
-
-    let architecture = get_architecture(container_name, timeout_secs).await?;
-    let distribution = match get_distribution(container_name, timeout_secs, &os_type).await {
-        Ok(dist) => Some(dist),
-        Err(e) => {
-            debug!("Failed to get distribution: {}", e);
-            None
-        }
-    };
-    let os_family = get_os_family(&os_type, &distribution);
-
-    Ok(ArchitectureFacts {
-        ansible_architecture: architecture,
-        ansible_system: os_type,
-        ansible_os_family: os_family,
-        ansible_distribution: distribution,
-    })
+    parse_fact_output_with(&output, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()).into())
 }
 
 /// Execute a command in a Docker container
 async fn execute_docker_command(
-    container: &str,
+    target: &DockerTarget<'_>,
     command: &[&str],
-    timeout_secs: u64,
+    config: &FactsConfig,
 ) -> anyhow::Result<String> {
-    let mut cmd = Command::new("docker");
-    cmd.arg("exec").arg(container);
-
-    for arg in command {
-        cmd.arg(arg);
-    }
-
-    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
-
-    let output = timeout(Duration::from_secs(timeout_secs), cmd.output())
-        .await
-        .context("Command timed out")?
-        .context("Failed to execute docker command")?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    let container = target.container;
+    let timeout_secs = config.command_timeout;
+    let output = run_recorded(
+        config.session.as_deref(),
+        target.runtime.as_str(),
+        container,
+        &command.join(" "),
+        || async move {
+            timeout(Duration::from_secs(timeout_secs), run_exec(target, command))
+                .await
+                .map_err(|_| FactsError::Timeout(container.to_string()))?
+        },
+    )
+    .await
+    .context("Failed to execute docker command")?;
+
+    if output.exit_code != Some(0) {
+        if let Some(denied) = socket_permission_denied(&output.stderr) {
+            return Err(FactsError::AuthenticationFailed(
+                container.to_string(),
+                denied.to_string(),
+            )
+            .into());
+        }
         return Err(anyhow::anyhow!(
             "Docker command failed with exit code {}: {}",
-            output.status.code().unwrap_or(-1),
-            stderr
+            output.exit_code.unwrap_or(-1),
+            output.stderr
         ));
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-}
-
-/// Check if container is running
-async fn check_container_running(container: &str, timeout_secs: u64) -> anyhow::Result<()> {
-    let _output = execute_docker_command(container, &["true"], timeout_secs).await?;
-
-    Ok(())
-}
-
-/// Get OS type
-async fn get_os_type(container: &str, timeout_secs: u64) -> anyhow::Result<String> {
-    execute_docker_command(
-        container,
-        &["sh", "-c", "uname -s 2>/dev/null || echo Unknown"],
-        timeout_secs,
-    )
-    .await
+    Ok(output.stdout.trim().to_string())
 }
 
-/// Get hostname
-async fn get_hostname(container: &str, timeout_secs: u64) -> anyhow::Result<String> {
-    execute_docker_command(container, &["hostname"], timeout_secs).await
-}
-
-/// Get kernel info
-async fn get_kernel_info(container: &str, timeout_secs: u64) -> anyhow::Result<String> {
-    execute_docker_command(container, &["uname", "-r"], timeout_secs).await
-}
-
-/// Get CPU info
-async fn get_cpu_info(container: &str, timeout_secs: u64) -> anyhow::Result<String> {
-    execute_docker_command(
-        container,
-        &[
-            "sh",
-            "-c",
-            "grep -c ^processor /proc/cpuinfo 2>/dev/null || echo 1",
-        ],
-        timeout_secs,
-    )
-    .await
+/// The error a container CLI prints when the user may not open the
+/// daemon's socket, which adding them to its group fixes and retrying
+/// does not.
+fn socket_permission_denied(stderr: &str) -> Option<&str> {
+    stderr.lines().map(str::trim).find(|line| {
+        line.to_lowercase()
+            .contains("permission denied while trying to connect")
+    })
 }
 
-/// Get architecture
-async fn get_architecture(container: &str, timeout_secs: u64) -> anyhow::Result<String> {
-    execute_docker_command(container, &["uname", "-m"], timeout_secs).await
+/// Run a probe command through the runtime's CLI.
+#[cfg(not(feature = "bollard"))]
+async fn run_exec(
+    target: &DockerTarget<'_>,
+    command: &[&str],
+) -> crate::error::Result<(Option<i32>, String, String)> {
+    run_cli_exec(target, command).await
 }
 
-/// Get distribution name
-async fn get_distribution(
-    container: &str,
-    timeout_secs: u64,
-    os_type: &str,
-) -> anyhow::Result<String> {
-    debug!(
-        "Getting distribution for container {} with os_type {}",
-        container, os_type
-    );
+/// Run a probe command through the `docker`, `podman` or `nerdctl` CLI.
+async fn run_cli_exec(
+    target: &DockerTarget<'_>,
+    command: &[&str],
+) -> crate::error::Result<(Option<i32>, String, String)> {
+    use std::process::Stdio;
 
-    if os_type != "Linux" {
-        return Ok(os_type.to_string());
+    let mut cmd = tokio::process::Command::new(target.runtime.as_str());
+    if let Some(namespace) = target.namespace {
+        cmd.arg(format!("--namespace={namespace}"));
+    }
+    if let Some(daemon) = target.daemon {
+        cmd.arg(daemon.arg());
+    }
+    cmd.arg("exec");
+    if let Some(user) = target.user {
+        cmd.arg(format!("--user={user}"));
     }
+    if let Some(workdir) = target.workdir {
+        cmd.arg(format!("--workdir={workdir}"));
+    }
+    cmd.arg(target.container)
+        .args(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let output = cmd.output().await?;
+    Ok((
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ))
+}
 
-    // Try various methods to detect distribution
-    if let Ok(lsb_release) = execute_docker_command(
-        container,
-        &["sh", "-c", "lsb_release -si 2>/dev/null"],
-        timeout_secs,
-    )
-    .await
+/// Run a probe command through the Docker or Podman socket API. containerd
+/// has no such API, so nerdctl always uses its CLI, as do remote Docker
+/// daemons, which the CLI knows how to reach, and runtimes whose socket
+/// does not answer.
+#[cfg(feature = "bollard")]
+async fn run_exec(
+    target: &DockerTarget<'_>,
+    command: &[&str],
+) -> crate::error::Result<(Option<i32>, String, String)> {
+    if target.runtime == ContainerRuntime::Nerdctl
+        || target.daemon.is_some()
+        || !crate::docker_api::available(target.runtime).await
     {
-        debug!("lsb_release result: '{}'", lsb_release);
-        if !lsb_release.is_empty() {
-            return Ok(lsb_release);
-        }
+        return run_cli_exec(target, command).await;
     }
-
-    // Try parsing /etc/os-release
-    if let Ok(os_release) = execute_docker_command(
-        container,
-        &[
-            "sh",
-            "-c",
-            "grep '^ID=' /etc/os-release 2>/dev/null | cut -d= -f2 | tr -d '\"'",
-        ],
-        timeout_secs,
+    crate::docker_api::exec(
+        target.runtime,
+        target.container,
+        target.user,
+        target.workdir,
+        command,
     )
     .await
-    {
-        debug!("/etc/os-release ID result: '{}'", os_release);
-        if !os_release.is_empty() {
-            return Ok(os_release);
-        }
-    }
-
-    // Fallback to checking for specific distribution files
-    for (file, distro) in &[
-        ("/etc/redhat-release", "RedHat"),
-        ("/etc/debian_version", "Debian"),
-        ("/etc/alpine-release", "Alpine"),
-        ("/etc/arch-release", "Arch"),
-    ] {
-        if execute_docker_command(container, &["test", "-f", file], timeout_secs)
-            .await
-            .is_ok()
-        {
-            return Ok(distro.to_string());
-        }
-    }
-
-    Ok("Unknown".to_string())
 }
 
 /// Get OS family based on OS type and distribution
-fn get_os_family(os_type: &str, distribution: &Option<String>) -> String {
+pub(crate) fn get_os_family(os_type: &str, distribution: &Option<String>) -> String {
     match os_type.to_lowercase().as_str() {
-        "linux" => {
-            if let Some(distro) = distribution {
-                match distro.to_lowercase().as_str() {
-                    "ubuntu" | "debian" | "mint" => "debian".to_string(),
-                    "rhel" | "redhat" | "centos" | "fedora" | "rocky" | "almalinux" => {
-                        "redhat".to_string()
-                    }
-                    "suse" | "opensuse" => "suse".to_string(),
-                    "arch" | "manjaro" => "archlinux".to_string(),
-                    "alpine" => "alpine".to_string(),
-                    _ => "debian".to_string(), // Default fallback
-                }
-            } else {
-                "debian".to_string() // Default for Linux
-            }
-        }
+        "linux" => match distribution {
+            Some(distro) => ArchitectureFacts::normalize_os_family(Some(distro), None)
+                .map(str::to_string)
+                .unwrap_or_else(|| distro.to_lowercase()),
+            None => "unknown".to_string(),
+        },
         "darwin" => "darwin".to_string(),
         "freebsd" | "openbsd" | "netbsd" => "bsd".to_string(),
+        "sunos" | "solaris" => "solaris".to_string(),
+        "aix" => "aix".to_string(),
+        "hp-ux" => "hp-ux".to_string(),
         _ => "unknown".to_string(),
     }
 }
@@ -280,6 +396,82 @@ fn get_os_family(os_type: &str, distribution: &Option<String>) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_docker_target_from_host() {
+        let mut host = HostEntry::new("app");
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("app-1"));
+        assert_eq!(
+            DockerTarget::from_host(ContainerRuntime::Docker, &host, None).unwrap(),
+            DockerTarget {
+                runtime: ContainerRuntime::Docker,
+                container: "app-1",
+                user: None,
+                workdir: None,
+                namespace: None,
+                daemon: None,
+            }
+        );
+
+        host.vars.insert(
+            "ansible_docker_user".to_string(),
+            serde_json::json!("1000:1000"),
+        );
+        host.vars.insert(
+            "ansible_docker_working_dir".to_string(),
+            serde_json::json!("/srv/app"),
+        );
+        let target = DockerTarget::from_host(ContainerRuntime::Docker, &host, None).unwrap();
+        assert_eq!(target.user, Some("1000:1000"));
+        assert_eq!(target.workdir, Some("/srv/app"));
+
+        // Podman reads its own user var
+        let target = DockerTarget::from_host(ContainerRuntime::Podman, &host, None).unwrap();
+        assert_eq!(target.user, None);
+
+        host.vars.insert(
+            "ansible_nerdctl_namespace".to_string(),
+            serde_json::json!("k8s.io"),
+        );
+        let target = DockerTarget::from_host(ContainerRuntime::Nerdctl, &host, None).unwrap();
+        assert_eq!(target.namespace, Some("k8s.io"));
+        let target = DockerTarget::from_host(ContainerRuntime::Docker, &host, None).unwrap();
+        assert_eq!(target.namespace, None);
+
+        // The host's own daemon wins over --docker-host, which wins over a
+        // context only when the host names none
+        let target =
+            DockerTarget::from_host(ContainerRuntime::Docker, &host, Some("tcp://build:2376"))
+                .unwrap();
+        assert_eq!(target.daemon, Some(DockerDaemon::Host("tcp://build:2376")));
+        host.vars
+            .insert("docker_context".to_string(), serde_json::json!("prod"));
+        let target =
+            DockerTarget::from_host(ContainerRuntime::Docker, &host, Some("tcp://build:2376"))
+                .unwrap();
+        assert_eq!(target.daemon.unwrap().arg(), "--context=prod");
+        host.vars.insert(
+            "ansible_docker_host".to_string(),
+            serde_json::json!("ssh://deploy@docker1"),
+        );
+        let target = DockerTarget::from_host(ContainerRuntime::Docker, &host, None).unwrap();
+        assert_eq!(target.daemon.unwrap().arg(), "--host=ssh://deploy@docker1");
+        let target = DockerTarget::from_host(ContainerRuntime::Podman, &host, None).unwrap();
+        assert_eq!(target.daemon, None);
+        host.vars.insert(
+            "ansible_docker_host".to_string(),
+            serde_json::json!("tcp://docker1 --tls"),
+        );
+        assert!(DockerTarget::from_host(ContainerRuntime::Docker, &host, None).is_err());
+        host.vars.remove("ansible_docker_host");
+
+        host.vars.insert(
+            "ansible_docker_user".to_string(),
+            serde_json::json!("--privileged"),
+        );
+        assert!(DockerTarget::from_host(ContainerRuntime::Docker, &host, None).is_err());
+    }
+
     #[test]
     fn test_get_os_family() {
         assert_eq!(
@@ -294,8 +486,54 @@ mod tests {
             get_os_family("Linux", &Some("alpine".to_string())),
             "alpine"
         );
+        assert_eq!(
+            get_os_family("Linux", &Some("openSUSE".to_string())),
+            "suse"
+        );
+        assert_eq!(get_os_family("Linux", &Some("NixOS".to_string())), "nixos");
+        assert_eq!(get_os_family("Linux", &Some("ol".to_string())), "redhat");
+        assert_eq!(
+            get_os_family("Linux", &Some("raspbian".to_string())),
+            "debian"
+        );
+        assert_eq!(get_os_family("Linux", &Some("void".to_string())), "void");
+        assert_eq!(
+            get_os_family("Linux", &Some("OpenWrt".to_string())),
+            "openwrt"
+        );
+        assert_eq!(get_os_family("Linux", &None), "unknown");
         assert_eq!(get_os_family("Darwin", &None), "darwin");
+        assert_eq!(get_os_family("SunOS", &None), "solaris");
+        assert_eq!(get_os_family("AIX", &None), "aix");
+        assert_eq!(get_os_family("HP-UX", &None), "hp-ux");
         assert_eq!(get_os_family("FreeBSD", &None), "bsd");
         assert_eq!(get_os_family("Windows", &None), "unknown");
     }
+
+    #[test]
+    fn test_socket_permission_denied() {
+        let stderr = "permission denied while trying to connect to the Docker daemon socket \
+at unix:///var/run/docker.sock: connect: permission denied\n";
+        assert!(socket_permission_denied(stderr).is_some());
+        assert!(socket_permission_denied("sh: /root: Permission denied\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_hosts_do_not_abort_the_runtime() {
+        let config = FactsConfig {
+            parallel_connections: 1,
+            circuit_breaker: Some(1),
+            ..Default::default()
+        };
+        let hosts = vec![HostEntry::new("c1"), HostEntry::new("c2")];
+        // There is no nerdctl here, so the first exec fails to start
+        let facts = gather_runtime_facts(ContainerRuntime::Nerdctl, hosts, &config)
+            .await
+            .unwrap();
+        assert!(facts.is_empty());
+
+        let health = &config.breakers.health()["nerdctl"];
+        assert_eq!(health.failed, 1);
+        assert_eq!(health.skipped, 1);
+    }
 }
@@ -1,32 +1,207 @@
+use crate::analysis::{
+    build_matrix, find_mixed_groups, group_facts, group_members, hosts_by_fact, play_host_facts,
+    resolve_pattern,
+};
+use crate::arch_table::ArchTable;
+use crate::assertions::check_assertions;
 use crate::cache::{filter_hosts_needing_facts, load_or_create_cache, save_cache, update_cache};
-use crate::config::FactsConfig;
-use crate::docker_facts;
+use crate::compression;
+use crate::config::{FactNamespace, FactPreference, FactsConfig, FallbackSeverity};
+use crate::docker_facts::{self, ContainerRuntime};
 use crate::error::{FactsError, Result};
+use crate::fact_plugins;
+use crate::gateway_facts;
+use crate::host_list::{read_host_list, write_host_list};
+use crate::hostname::{normalize_hostname, normalize_inventory_hostnames};
+use crate::jail_facts;
+use crate::kubectl_facts;
+use crate::lxd_facts;
+use crate::network_facts;
+use crate::overrides::{merge_sources, source_order, target_triple, FactOverrides, OverrideFile};
+use crate::pct_facts;
+use crate::podman_facts;
+use crate::qemu_facts;
+use crate::ramp::ConnectionRamp;
+use crate::serial_facts;
+use crate::session::Session;
+use crate::shutdown::StopReason;
+use crate::ssh_config::SshConfig;
 use crate::ssh_facts;
+use crate::templating;
 use crate::types::{
-    ArchitectureFacts, EnrichedInventory, EnrichedPlaybook, EnrichmentReport, FactCache, HostEntry,
-    InventoryGroups, InventoryHosts, ParsedPlaybook,
+    ArchitectureFacts, ConnectionType, EnrichedInventory, EnrichedPlaybook, EnrichmentReport,
+    FactCache, FactSource, HostEntry, InventoryDiagnostic, InventoryGroups, InventoryHosts,
+    ParsedInventory, ParsedPlaybook, FACTS_SCHEMA_VERSION,
 };
-use std::collections::HashMap;
+use crate::verify::{choose_sample, find_drift};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::{Read, Write};
-use std::time::Instant;
-use tracing::{debug, info, warn};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, info, trace, warn};
 
 pub async fn enrich_with_facts<R: Read, W: Write>(
+    input: R,
+    output: W,
+    config: &FactsConfig,
+) -> Result<EnrichmentReport> {
+    let mut failed_hosts = None;
+    let result = enrich(input, output, config, &mut failed_hosts).await;
+    // Written whether or not the run succeeded, so it can be retried
+    if let (Some(path), Some(failed)) = (&config.failed_hosts_out, &failed_hosts) {
+        match write_host_list(path, failed) {
+            Ok(()) => info!("Wrote {} failed hosts to {:?}", failed.len(), path),
+            Err(e) if result.is_err() => {
+                warn!("Could not write the failed hosts to {:?}: {}", path, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    result
+}
+
+/// The run behind `enrich_with_facts`. `failed_hosts` is kept up to date
+/// with the hosts that have no facts yet, so an error return still leaves
+/// it accurate once the inventory is known.
+async fn enrich<R: Read, W: Write>(
     mut input: R,
     mut output: W,
     config: &FactsConfig,
+    failed_hosts: &mut Option<BTreeSet<String>>,
 ) -> Result<EnrichmentReport> {
     let start = Instant::now();
+    let mut config = attach_session(config)?;
+    // ssh reports a broken ssh_config itself; here it only costs aliasing
+    if config.parsed_ssh_config.is_none() {
+        let ssh_config = SshConfig::load(config.ssh_config.as_deref()).unwrap_or_else(|e| {
+            warn!("{}; ssh_config aliases will not be applied", e);
+            SshConfig::default()
+        });
+        config.parsed_ssh_config = Some(Arc::new(ssh_config));
+    }
+    // Backends normalize with it as they parse, while the host's own
+    // spelling of the architecture is still known
+    if let Some(path) = &config.arch_map {
+        config.arch_table = ArchTable::load(path)?;
+    }
+    let _deadline = (config.deadline > 0).then(|| {
+        let (shutdown, guard) = config
+            .shutdown
+            .with_deadline(Duration::from_secs(config.deadline));
+        config.shutdown = shutdown;
+        guard
+    });
+    let config = &config;
 
     let mut buffer = Vec::new();
     input.read_to_end(&mut buffer)?;
+    let buffer = compression::decompress(buffer)?;
 
-    let parsed: ParsedPlaybook = serde_json::from_slice(&buffer)
-        .map_err(|e| FactsError::InvalidInventory(format!("Failed to parse input JSON: {e}")))?;
+    let mut parsed = parse_playbook(&buffer)?;
 
-    let hosts = extract_unique_hosts(&parsed)?;
-    let total_hosts = hosts.len();
+    // Diagnostics see the names as written, before spellings are merged
+    let inventory_diagnostics = inventory_diagnostics(&parsed.inventory);
+    for diagnostic in &inventory_diagnostics {
+        warn!("Inventory: {}", diagnostic);
+    }
+    normalize_inventory_hostnames(&mut parsed.inventory);
+    let mut host_entries = if config.allow_empty && listed_host_names(&parsed.inventory).is_empty()
+    {
+        info!("Inventory has no hosts; passing the input through");
+        Vec::new()
+    } else {
+        normalize_inventory(&parsed.inventory)?
+    };
+    let mut excluded_hosts =
+        matching_hosts(&parsed.inventory, &host_entries, &config.excluded_hosts);
+    if !excluded_hosts.is_empty() {
+        info!(
+            "Skipping {} hosts matched by --exclude: {}",
+            excluded_hosts.len(),
+            excluded_hosts
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    let mut limited_hosts = BTreeSet::new();
+    if !config.limit.is_empty() {
+        let limited = matching_hosts(&parsed.inventory, &host_entries, &config.limit);
+        limited_hosts.extend(
+            host_entries
+                .iter()
+                .filter(|entry| {
+                    !limited.contains(&entry.name) && !excluded_hosts.contains(&entry.name)
+                })
+                .map(|entry| entry.name.clone()),
+        );
+        info!(
+            "Gathering only the {} hosts matched by --limit {}",
+            limited.len(),
+            config.limit.join(",")
+        );
+    }
+    if let Some(path) = &config.only_hosts_from {
+        let only_hosts = read_host_list(path)?;
+        let unknown: Vec<&String> = only_hosts
+            .iter()
+            .filter(|host| {
+                !host_entries
+                    .iter()
+                    .any(|entry| normalize_hostname(&entry.name) == **host)
+            })
+            .collect();
+        if !unknown.is_empty() {
+            warn!(
+                "{} hosts listed in {:?} are not in the inventory: {}",
+                unknown.len(),
+                path,
+                unknown
+                    .iter()
+                    .map(|host| host.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        excluded_hosts.extend(
+            host_entries
+                .iter()
+                .filter(|entry| !only_hosts.contains(&normalize_hostname(&entry.name)))
+                .filter(|entry| !limited_hosts.contains(&entry.name))
+                .map(|entry| entry.name.clone()),
+        );
+        info!(
+            "Gathering only the {} hosts listed in {:?}",
+            only_hosts.len() - unknown.len(),
+            path
+        );
+    }
+    let skipped_hosts: BTreeSet<String> = excluded_hosts.union(&limited_hosts).cloned().collect();
+    host_entries.retain(|entry| !skipped_hosts.contains(&entry.name));
+    let total_hosts = host_entries.len();
+    // Kept for the cache, which records how each host was reached
+    let inventory_entries: HashMap<String, HostEntry> = if config.cache_inventory {
+        host_entries
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.clone()))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+    // Group-level connection vars are resolved by now
+    let connections = classify_connections(&host_entries, !config.no_implicit_local);
+    let local_targets: HashSet<String> = connections
+        .iter()
+        .filter(|(_, connection)| **connection == ConnectionType::Local)
+        .map(|(host, _)| host.clone())
+        .collect();
+    let network_devices: HashMap<String, String> = host_entries
+        .iter()
+        .filter_map(|entry| Some((entry.name.clone(), network_facts::network_os(entry)?)))
+        .collect();
     info!("Found {} unique hosts in inventory", total_hosts);
 
     // Debug inventory format
@@ -35,64 +210,217 @@ pub async fn enrich_with_facts<R: Read, W: Write>(
         InventoryHosts::Detailed(_) => info!("Using Detailed inventory format"),
     }
 
-    let mut cache = if !config.no_cache {
-        load_or_create_cache(&config.cache_file)?
-    } else {
-        FactCache::new()
+    let mut cache = match &config.shared_cache {
+        _ if config.no_cache => FactCache::new(),
+        Some(shared) => shared.snapshot(),
+        None => load_or_create_cache(&config.cache_file)?,
     };
 
-    if !config.no_cache {
-        cache.cleanup_stale(config.cache_ttl);
+    // Expired entries are what --allow-stale-on-error falls back on
+    let evicts_stale = !config.no_cache && !config.allow_stale_on_error;
+    let mut cache_changed = evicts_stale && cache.cleanup_stale(config.cache_ttl) > 0;
+    // Hosts whose entries this run replaced, restored or dropped
+    let mut touched_hosts = BTreeSet::new();
+
+    // Hosts with a declared or pinned architecture are not probed
+    let override_file = match &config.facts_override {
+        Some(path) => Some(OverrideFile::load(path)?),
+        None => None,
+    };
+    // An unreadable --probe-script fails the run before any host is tried
+    ssh_facts::fact_probe(config)?;
+    let overrides: HashMap<String, FactOverrides> = host_entries
+        .iter()
+        .filter_map(|entry| {
+            let declared = (!config.no_overrides)
+                .then(|| FactOverrides::from_vars(&entry.name, &entry.vars))
+                .flatten();
+            let pinned = override_file
+                .as_ref()
+                .and_then(|file| file.resolve(&entry.name, &parsed.inventory));
+            let merged = match (declared, pinned) {
+                (Some(declared), Some(pinned)) => Some(declared.merge(pinned)),
+                (declared, pinned) => declared.or(pinned),
+            };
+            merged.map(|overrides| (entry.name.clone(), overrides))
+        })
+        .collect();
+    // Gateways and Proxmox nodes, with their own vars when in the inventory
+    let gateway_names: HashSet<String> = host_entries
+        .iter()
+        .flat_map(|entry| [gateway_facts::gateway(entry), pct_facts::node(entry)])
+        .flatten()
+        .map(normalize_hostname)
+        .collect();
+    let gateways: HashMap<String, HostEntry> = host_entries
+        .iter()
+        .map(|entry| (normalize_hostname(&entry.name), entry))
+        .filter(|(name, _)| gateway_names.contains(name))
+        .map(|(name, entry)| (name, entry.clone()))
+        .collect();
+
+    // Unless gathered facts are preferred, probing them would be wasted
+    let (declared_hosts, host_entries): (Vec<HostEntry>, Vec<HostEntry>) =
+        host_entries.into_iter().partition(|entry| {
+            config.prefer != FactPreference::Gathered
+                && overrides
+                    .get(&entry.name)
+                    .is_some_and(FactOverrides::is_complete)
+        });
+    if !declared_hosts.is_empty() {
+        info!(
+            "Using declared facts for {} hosts without gathering",
+            declared_hosts.len()
+        );
     }
 
-    // Convert host names to HostEntry objects
-    let host_entries = hosts
-        .into_iter()
-        .map(|host| {
-            let entry = get_host_entry(&host, &parsed.inventory);
-            debug!(
-                "Created HostEntry for {}: connection={:?}",
-                host, entry.connection
+    // Aliases of the same machine are gathered once, through their primary
+    let shared_facts = find_host_aliases(&host_entries, &connections);
+
+    // A sample of cache hits is re-gathered to check the cache still holds
+    let mut sampled = HashMap::new();
+    if let Some(percent) = config.verify_sample.filter(|_| !config.force_refresh) {
+        let cache_hits: Vec<String> = host_entries
+            .iter()
+            .filter(|entry| !shared_facts.contains_key(&entry.name))
+            .filter(|entry| cache.get(&entry.name, config.cache_ttl).is_some())
+            .map(|entry| entry.name.clone())
+            .collect();
+        for host in choose_sample(&cache_hits, percent) {
+            if let Some(cached) = cache.take(&host) {
+                sampled.insert(host, cached);
+            }
+        }
+        if !sampled.is_empty() {
+            info!(
+                "Re-gathering {} of {} cached hosts to verify them",
+                sampled.len(),
+                cache_hits.len()
             );
-            entry
-        })
-        .collect::<Vec<_>>();
+        }
+    }
+    let mut gather_targets: Vec<String> = par_map(&host_entries, |entry| {
+        needs_gathering(&cache, config, entry).then(|| entry.name.clone())
+    })
+    .into_iter()
+    .flatten()
+    .collect();
+    if !shared_facts.is_empty() {
+        info!(
+            "{} inventory aliases share a target with another host",
+            shared_facts.len()
+        );
+    }
 
     // Separate hosts by connection type
     let mut local_hosts = Vec::new();
     let mut ssh_hosts = Vec::new();
-    let mut docker_hosts = Vec::new();
+    let mut backend_hosts: BTreeMap<ConnectionType, Vec<HostEntry>> = BTreeMap::new();
+    let mut gateway_hosts = Vec::new();
+    let mut unsupported_connections = BTreeMap::new();
 
+    let plugin_hosts: Vec<HostEntry> = if config.fact_plugins.is_empty() {
+        Vec::new()
+    } else {
+        host_entries.clone()
+    };
     for entry in host_entries {
-        let connection_type = get_connection_type(&entry);
+        if shared_facts.contains_key(&entry.name) {
+            continue;
+        }
+        let connection_type = connections[&entry.name].clone();
         debug!(
             "Host {} has connection type: {}",
             entry.name, connection_type
         );
-        match connection_type.as_str() {
-            "local" => local_hosts.push(entry),
-            "docker" => docker_hosts.push(entry),
-            _ => ssh_hosts.push(entry), // Default to SSH
+        if gateway_facts::can_relay(&connection_type) && gateway_facts::gateway(&entry).is_some() {
+            gateway_hosts.push((entry, connection_type));
+            continue;
+        }
+        match connection_type {
+            ConnectionType::Local => local_hosts.push(entry),
+            ConnectionType::Ssh => ssh_hosts.push(entry),
+            connection if backend_name(&connection).is_some() => {
+                backend_hosts.entry(connection).or_default().push(entry)
+            }
+            unsupported => {
+                unsupported_connections.insert(entry.name, unsupported);
+            }
         }
     }
+    if !unsupported_connections.is_empty() {
+        let hosts: Vec<String> = unsupported_connections
+            .iter()
+            .map(|(host, connection)| format!("{host} ({connection})"))
+            .collect();
+        warn!(
+            "No backend supports the connection of {} hosts, which get fallback facts: {}",
+            hosts.len(),
+            hosts.join(", ")
+        );
+        gather_targets.retain(|host| !unsupported_connections.contains_key(host));
+    }
+    // Every host due a gather counts as failed until it is gathered
+    *failed_hosts = Some(
+        gather_targets
+            .iter()
+            .chain(unsupported_connections.keys())
+            .cloned()
+            .collect(),
+    );
 
     info!(
-        "Found {} local hosts, {} SSH hosts, and {} Docker hosts",
+        "Found {} local hosts, {} SSH hosts, {}{} hosts behind gateways",
         local_hosts.len(),
         ssh_hosts.len(),
-        docker_hosts.len()
+        backend_hosts
+            .iter()
+            .filter_map(|(connection, hosts)| {
+                Some(format!("{} {}, ", hosts.len(), backend_name(connection)?))
+            })
+            .collect::<String>(),
+        gateway_hosts.len()
     );
 
     // Handle localhost hosts directly
     let mut new_facts = HashMap::new();
-    for host in &local_hosts {
-        if config.force_refresh || cache.get(&host.name, config.cache_ttl).is_none() {
-            info!("Using direct local detection for host {}", host.name);
-            new_facts.insert(host.name.clone(), ArchitectureFacts::from_local_system());
-        }
+    for host in local_hosts
+        .iter()
+        .filter(|host| needs_gathering(&cache, config, host))
+    {
+        info!("Using direct local detection for host {}", host.name);
+        new_facts.insert(host.name.clone(), ArchitectureFacts::from_local_system());
     }
 
     // Handle SSH hosts
+    let ssh_addresses: HashMap<String, String> = ssh_hosts
+        .iter()
+        .filter_map(|host| {
+            let target = ssh_facts::SshTarget::for_config(host, config).ok()?;
+            Some((host.name.clone(), target.known_hosts_name()))
+        })
+        .collect();
+    for (host, address) in &ssh_addresses {
+        if let Some(previous) = cache.invalidate_moved(host, address) {
+            touched_hosts.insert(host.clone());
+            info!(
+                "{} now resolves to {} instead of {}; gathering its facts again",
+                host, address, previous
+            );
+        }
+    }
+    let mut host_key_changes = Vec::new();
+    if config.verify_host_keys && !config.no_cache && !config.force_refresh {
+        host_key_changes = verify_cached_host_keys(&ssh_hosts, &mut cache, config).await;
+        touched_hosts.extend(host_key_changes.iter().cloned());
+    }
+    let mut renamed_hosts = BTreeMap::new();
+    if config.match_host_keys && !config.no_cache && !config.force_refresh {
+        renamed_hosts = match_cached_host_keys(&ssh_hosts, &mut cache, config).await;
+        touched_hosts.extend(renamed_hosts.keys().cloned());
+        gather_targets.retain(|host| !renamed_hosts.contains_key(host));
+    }
+
     let ssh_host_names: Vec<String> = ssh_hosts.iter().map(|h| h.name.clone()).collect();
     let ssh_hosts_needing_facts = filter_hosts_needing_facts(
         &ssh_host_names,
@@ -107,56 +435,899 @@ pub async fn enrich_with_facts<R: Read, W: Write>(
         ssh_hosts.len() - ssh_hosts_needing_facts.len()
     );
 
+    let mut host_keys = HashMap::new();
+    let mut unreachable_hosts = BTreeMap::new();
     if !ssh_hosts_needing_facts.is_empty() {
-        let ssh_facts = ssh_facts::gather_minimal_facts(&ssh_hosts_needing_facts, config).await?;
-        new_facts.extend(ssh_facts);
+        let needing: HashSet<&String> = ssh_hosts_needing_facts.iter().collect();
+        let ssh_entries: Vec<HostEntry> = ssh_hosts
+            .into_iter()
+            .filter(|host| needing.contains(&host.name))
+            .collect();
+        let gathering = ssh_facts::gather_facts_with_host_keys(&ssh_entries, config).await?;
+        // Whatever stands in for an unreachable host was not gathered, so it
+        // is neither labelled nor cached as such
+        new_facts.extend(
+            gathering
+                .facts
+                .into_iter()
+                .filter(|(host, _)| !gathering.unreachable.contains_key(host)),
+        );
+        host_keys = gathering.host_keys;
+        unreachable_hosts = gathering.unreachable;
     }
 
-    // Handle Docker hosts
-    let docker_host_count = docker_hosts.len();
-    let docker_hosts_needing_facts: Vec<HostEntry> = docker_hosts
+    // Each host keeps the key type it was recorded with, so later runs
+    // compare like with like
+    let mut recorded_keys = Vec::new();
+    for (host, keys) in &host_keys {
+        if let Some((previous, current)) = cache.host_key_change(host, keys) {
+            warn!(
+                "Host key for {} changed ({} -> {}); the machine was likely rebuilt",
+                host, previous, current
+            );
+            host_key_changes.push(host.clone());
+        }
+        let recorded = cache
+            .fingerprint_type(host)
+            .and_then(|key_type| keys.get_key_value(key_type))
+            .or_else(|| ssh_facts::preferred_host_key(keys));
+        if let Some((key_type, fingerprint)) = recorded {
+            recorded_keys.push((host.clone(), key_type.clone(), fingerprint.clone()));
+        }
+    }
+
+    // Handle hosts reached through a gateway
+    let gateway_hosts_needing_facts: Vec<(HostEntry, ConnectionType)> = gateway_hosts
         .into_iter()
-        .filter(|host| config.force_refresh || cache.get(&host.name, config.cache_ttl).is_none())
+        .filter(|(host, _)| needs_gathering(&cache, config, host))
         .collect();
 
-    info!(
-        "Need to gather facts for {} Docker hosts (cache hits: {})",
-        docker_hosts_needing_facts.len(),
-        docker_host_count - docker_hosts_needing_facts.len()
-    );
+    if !gateway_hosts_needing_facts.is_empty() {
+        info!(
+            "Gathering facts for {} hosts through gateways",
+            gateway_hosts_needing_facts.len()
+        );
+        let gateway_facts =
+            gateway_facts::gather_minimal_facts(gateway_hosts_needing_facts, &gateways, config)
+                .await?;
+        new_facts.extend(gateway_facts);
+    }
+
+    // Handle the hosts each other backend reaches
+    for (connection, hosts) in backend_hosts {
+        let host_count = hosts.len();
+        let hosts_needing_facts: Vec<HostEntry> = hosts
+            .into_iter()
+            .filter(|host| needs_gathering(&cache, config, host))
+            .collect();
+        if hosts_needing_facts.is_empty() {
+            continue;
+        }
+        info!(
+            "Gathering facts for {} {} (cache hits: {})",
+            hosts_needing_facts.len(),
+            backend_name(&connection).unwrap_or_default(),
+            host_count - hosts_needing_facts.len()
+        );
+        let facts = gather_backend(&connection, hosts_needing_facts, &gateways, config).await?;
+        new_facts.extend(facts);
+    }
+
+    if !plugin_hosts.is_empty() {
+        let gathered: Vec<(HostEntry, ConnectionType)> = plugin_hosts
+            .into_iter()
+            .filter(|host| {
+                new_facts.contains_key(&host.name) && !unreachable_hosts.contains_key(&host.name)
+            })
+            .map(|host| {
+                let connection = connections[&host.name].clone();
+                (host, connection)
+            })
+            .collect();
+        let plugin_facts = tokio::select! {
+            biased;
+            _ = config.shutdown.wait() => HashMap::new(),
+            facts = fact_plugins::gather_plugin_facts(gathered, config) => facts,
+        };
+        for (host, extra) in plugin_facts {
+            if let Some(facts) = new_facts.get_mut(&host) {
+                facts.extra.extend(extra);
+            }
+        }
+    }
+
+    // Sampled hosts that could not be re-gathered keep their cached facts
+    let mut verified_hosts = Vec::new();
+    let mut sampled_facts = HashMap::new();
+    for (host, cached) in sampled {
+        if new_facts.contains_key(&host) && !unreachable_hosts.contains_key(&host) {
+            sampled_facts.insert(host.clone(), cached.facts);
+            verified_hosts.push(host);
+        } else {
+            new_facts.remove(&host);
+            cache.restore(&host, cached);
+        }
+    }
+    verified_hosts.sort();
+    let fact_drift = find_drift(&sampled_facts, &new_facts);
+    for drift in &fact_drift {
+        warn!("Cached facts drifted for {}", drift);
+    }
+    if !verified_hosts.is_empty() {
+        info!(
+            "Verified {} cached hosts; {} facts had drifted",
+            verified_hosts.len(),
+            fact_drift.len()
+        );
+    }
 
-    if !docker_hosts_needing_facts.is_empty() {
-        let docker_facts =
-            docker_facts::gather_minimal_facts(docker_hosts_needing_facts, config).await?;
-        new_facts.extend(docker_facts);
+    // Hosts that could not be gathered keep their expired facts on request
+    let mut stale_facts = HashMap::new();
+    if config.allow_stale_on_error {
+        for host in &gather_targets {
+            if new_facts.contains_key(host) && !unreachable_hosts.contains_key(host) {
+                continue;
+            }
+            if let Some(facts) = cache.get_any_age(host) {
+                warn!(
+                    "Could not gather facts for {}; using its expired cache entry",
+                    host
+                );
+                stale_facts.insert(host.clone(), facts.clone());
+                new_facts.remove(host);
+            }
+        }
     }
 
+    for (alias, primary) in &shared_facts {
+        let facts = match new_facts.get(primary) {
+            Some(facts) => Some(facts.clone()),
+            None if cache.get(alias, config.cache_ttl).is_none() => {
+                cache.get(primary, config.cache_ttl).cloned()
+            }
+            None => None,
+        };
+        if let Some(facts) = facts {
+            debug!("Sharing facts of {} with alias {}", primary, alias);
+            new_facts.insert(alias.clone(), facts);
+        }
+        if let Some(fingerprint) = host_keys.get(primary).cloned() {
+            host_keys.insert(alias.clone(), fingerprint);
+        }
+    }
+
+    *failed_hosts = Some(
+        gather_targets
+            .iter()
+            .filter(|host| !new_facts.contains_key(*host) && !stale_facts.contains_key(*host))
+            .chain(unreachable_hosts.keys())
+            .chain(unsupported_connections.keys())
+            .cloned()
+            .collect(),
+    );
+
+    // Cached values as they were before this run, for --prefer cached
+    let cached_facts: HashMap<String, ArchitectureFacts> = cache
+        .facts
+        .keys()
+        .filter_map(|host| Some((host.clone(), cache.get(host, config.cache_ttl)?.clone())))
+        .collect();
+    let machine_id_changes = changed_machine_ids(&cache, &new_facts);
+    for host in &machine_id_changes {
+        warn!(
+            "Machine ID of {} changed; another machine, such as a clone, now answers to the name",
+            host
+        );
+    }
     update_cache(&mut cache, &new_facts)?;
+    for (host, key_type, fingerprint) in recorded_keys {
+        cache.record_fingerprint(&host, &key_type, fingerprint);
+    }
+    for host in new_facts.keys() {
+        cache.set_labels(host, &config.labels);
+        if let Some(entry) = inventory_entries.get(host) {
+            cache.set_inventory(host, entry);
+        }
+        if let Some(address) = ssh_addresses.get(host) {
+            cache.set_address(host, address);
+        }
+    }
+
+    touched_hosts.extend(new_facts.keys().cloned());
+    cache_changed |= !touched_hosts.is_empty();
+    if !config.no_cache && cache_changed {
+        match &config.shared_cache {
+            Some(shared) => {
+                if evicts_stale {
+                    shared.cleanup_stale(config.cache_ttl);
+                }
+                shared.store(&cache, &touched_hosts)?
+            }
+            None => save_cache(&config.cache_file, &cache)?,
+        }
+    }
 
-    if !config.no_cache && !new_facts.is_empty() {
-        save_cache(&config.cache_file, &cache)?;
+    if let (Some(path), Some(session)) = (&config.record, &config.session) {
+        session.save(path)?;
     }
 
-    let enriched = build_enriched_playbook(parsed, &cache, &new_facts, config.cache_ttl)?;
+    // Gathered facts are cached above; the output is only written on request
+    let stop = config.shutdown.reason();
+    if let (Some(e), false) = (stop.and_then(StopReason::error), config.write_partial) {
+        return Err(e);
+    }
+    let interrupted = stop.and_then(StopReason::error).is_some();
+
+    let timed_out_hosts: Vec<String> = match stop {
+        Some(StopReason::DeadlineExpired) => gather_targets
+            .into_iter()
+            .filter(|host| !new_facts.contains_key(host))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if !timed_out_hosts.is_empty() {
+        warn!(
+            "Run deadline of {}s expired before facts were gathered for {} hosts: {}",
+            config.deadline,
+            timed_out_hosts.len(),
+            timed_out_hosts.join(", ")
+        );
+    }
+
+    let (mut enriched, fallback_hosts) = build_enriched_playbook(
+        parsed,
+        FactSources {
+            declared: &overrides,
+            gathered: &new_facts,
+            cached: &cached_facts,
+            local: &local_targets,
+            network: &network_devices,
+            stale: &stale_facts,
+            excluded: &skipped_hosts,
+            probed: &ssh_facts::probed_facts(config),
+            prefer: config.prefer,
+            arch_table: &config.arch_table,
+        },
+    )?;
+    enriched.partial = stop.is_some();
+    if let Some(failed) = failed_hosts {
+        failed.extend(fallback_hosts.iter().cloned());
+    }
+    enriched.inventory.host_labels = host_labels(&enriched.inventory, &cache, &config.labels);
+    if !interrupted {
+        check_fallback_hosts(&fallback_hosts, &enriched.inventory.base, config)?;
+    }
+    if config.play_host_facts {
+        enriched.play_host_facts = Some(play_host_facts(&enriched));
+    }
+    if let Some(fact) = &config.group_by_fact {
+        enriched.hosts_by_fact = Some(hosts_by_fact(&enriched.inventory.host_facts, fact));
+    }
+    if config.group_facts {
+        enriched.group_facts = Some(group_facts(&enriched));
+    }
+
+    let mixed_groups = find_mixed_groups(&enriched);
+    for group in &mixed_groups {
+        warn!(
+            "Group '{}' (plays: {}) mixes architectures [{}] and OS families [{}]; it needs multiple build targets",
+            group.group,
+            group.plays.join(", "),
+            group.architectures.iter().cloned().collect::<Vec<_>>().join(", "),
+            group.os_families.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
 
-    serde_json::to_writer_pretty(&mut output, &enriched)?;
-    output.write_all(b"\n")?;
+    // Checks that fail the run are skipped for partial output, which is
+    // already an error
+    if config.fail_on_mixed_arch && !interrupted {
+        let offending: Vec<String> = mixed_groups
+            .iter()
+            .filter(|group| group.mixes_architectures())
+            .map(|group| {
+                let archs: Vec<_> = group.architectures.iter().cloned().collect();
+                format!("{} ({})", group.group, archs.join(", "))
+            })
+            .collect();
+        if !offending.is_empty() {
+            return Err(FactsError::MixedArchitectures(offending.join("; ")));
+        }
+    }
+
+    let violations = check_assertions(&config.assertions, &enriched.inventory.host_facts);
+    if !violations.is_empty() && !interrupted {
+        let lines: Vec<String> = violations.iter().map(|v| format!("  {v}")).collect();
+        return Err(FactsError::AssertionFailed(lines.join("\n")));
+    }
+
+    let matrix = build_matrix(&enriched.inventory.host_facts);
+    let document = if config.matrix {
+        serde_json::json!({ "matrix": matrix })
+    } else {
+        let mut document = serde_json::to_value(&enriched)?;
+        apply_fact_namespace(&mut document, config.fact_namespace);
+        document
+    };
+    let mut rendered = match &config.select {
+        Some(selection) => serde_json::to_vec_pretty(&selection.apply(&document))?,
+        None => serde_json::to_vec_pretty(&document)?,
+    };
+    rendered.push(b'\n');
+    compression::write_compressed(&mut output, &rendered, config.compress_output)?;
+
+    if let Some(e) = stop.and_then(StopReason::error) {
+        return Err(e);
+    }
 
     let duration = start.elapsed();
 
     Ok(EnrichmentReport {
         total_hosts,
         facts_gathered: new_facts.len(),
-        cache_hits: total_hosts - new_facts.len(),
+        facts_declared: declared_hosts.len(),
+        cache_hits: total_hosts
+            .saturating_sub(new_facts.len() + declared_hosts.len() + timed_out_hosts.len()),
         duration,
+        host_key_changes,
+        machine_id_changes,
+        shared_facts,
+        mixed_groups,
+        matrix,
+        hosts_by_fact: enriched.hosts_by_fact.clone(),
+        fallback_hosts: fallback_hosts.into_iter().collect(),
+        timed_out_hosts,
+        unsupported_connections,
+        excluded_hosts: excluded_hosts.into_iter().collect(),
+        limited_hosts: limited_hosts.into_iter().collect(),
+        unreachable_hosts,
+        inventory_diagnostics,
+        stale_hosts: enriched.inventory.stale_hosts.clone(),
+        renamed_hosts,
+        verified_hosts,
+        fact_drift,
+        backend_health: config.breakers.health(),
     })
 }
 
-fn extract_unique_hosts(playbook: &ParsedPlaybook) -> Result<Vec<String>> {
+/// Hosts matched by any `--exclude` or `--limit` pattern, resolved like a
+/// play's `hosts`.
+fn matching_hosts(
+    inventory: &ParsedInventory,
+    entries: &[HostEntry],
+    patterns: &[String],
+) -> BTreeSet<String> {
+    if patterns.is_empty() {
+        return BTreeSet::new();
+    }
+    let all_hosts: BTreeSet<String> = entries.iter().map(|entry| entry.name.clone()).collect();
+    patterns
+        .iter()
+        .flat_map(|pattern| resolve_pattern(inventory, &all_hosts, pattern))
+        .collect()
+}
+
+/// Rename (or copy) the `ansible_*` keys of every emitted facts object to
+/// `rustle_*`.
+fn apply_fact_namespace(document: &mut serde_json::Value, namespace: FactNamespace) {
+    if namespace == FactNamespace::Ansible {
+        return;
+    }
+
+    let Some(sections) = document.as_object_mut() else {
+        return;
+    };
+    let mut fact_objects: Vec<&mut serde_json::Value> = Vec::new();
+    for (name, section) in sections.iter_mut() {
+        match name.as_str() {
+            "inventory" => {
+                for (name, hosts) in section.as_object_mut().into_iter().flatten() {
+                    if name == "host_facts" || name == "fact_sources" {
+                        fact_objects.extend(
+                            hosts
+                                .as_object_mut()
+                                .into_iter()
+                                .flat_map(|h| h.values_mut()),
+                        );
+                    }
+                }
+            }
+            "play_host_facts" => {
+                for play in section
+                    .as_object_mut()
+                    .into_iter()
+                    .flat_map(|p| p.values_mut())
+                {
+                    fact_objects.extend(
+                        play.as_object_mut()
+                            .into_iter()
+                            .flat_map(|p| p.values_mut()),
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for facts in fact_objects.into_iter().filter_map(|f| f.as_object_mut()) {
+        let keys: Vec<String> = facts
+            .keys()
+            .filter(|key| key.starts_with("ansible_"))
+            .cloned()
+            .collect();
+        for key in keys {
+            let renamed = key.replacen("ansible_", "rustle_", 1);
+            let value = match namespace {
+                FactNamespace::Both => facts[&key].clone(),
+                _ => facts.remove(&key).unwrap_or_default(),
+            };
+            facts.insert(renamed, value);
+        }
+    }
+}
+
+/// Whether `host` has no facts in the cache that this run may use.
+fn needs_gathering(cache: &FactCache, config: &FactsConfig, host: &HostEntry) -> bool {
+    config.force_refresh || cache.get(&host.name, config.cache_ttl).is_none()
+}
+
+/// How the hosts of a connection gathered by [`gather_backend`] are named in
+/// the log; `None` for connections no such backend handles. Local and SSH
+/// hosts are gathered apart from the others.
+fn backend_name(connection: &ConnectionType) -> Option<&'static str> {
+    Some(match connection {
+        ConnectionType::Docker => "Docker hosts",
+        ConnectionType::Podman => "Podman hosts",
+        ConnectionType::Nerdctl => "nerdctl hosts",
+        ConnectionType::Kubectl => "kubectl hosts",
+        ConnectionType::Lxd => "LXD hosts",
+        ConnectionType::Jail => "jails",
+        ConnectionType::Pct => "Proxmox containers",
+        ConnectionType::LibvirtQemu => "libvirt VMs",
+        ConnectionType::Serial => "serial console hosts",
+        ConnectionType::Mock if cfg!(feature = "mock") => "mock hosts",
+        _ => return None,
+    })
+}
+
+/// Gather `hosts`, which all have `connection`, through its backend.
+async fn gather_backend(
+    connection: &ConnectionType,
+    hosts: Vec<HostEntry>,
+    gateways: &HashMap<String, HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    match connection {
+        ConnectionType::Docker => docker_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Podman => podman_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Nerdctl => {
+            docker_facts::gather_runtime_facts(ContainerRuntime::Nerdctl, hosts, config).await
+        }
+        ConnectionType::Kubectl => kubectl_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Lxd => lxd_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Jail => jail_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Pct => pct_facts::gather_minimal_facts(hosts, gateways, config).await,
+        ConnectionType::LibvirtQemu => qemu_facts::gather_minimal_facts(hosts, config).await,
+        ConnectionType::Serial => serial_facts::gather_minimal_facts(hosts, config).await,
+        #[cfg(feature = "mock")]
+        ConnectionType::Mock => crate::mock_facts::gather_minimal_facts(hosts, config).await,
+        // backend_name kept these hosts out of the backends
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// Warn about each host given fallback facts, naming its groups, and fail
+/// when `--fallback-severity error` or a `--fail-on-fallback-group` group
+/// covers any of them.
+fn check_fallback_hosts(
+    fallback_hosts: &BTreeSet<String>,
+    inventory: &ParsedInventory,
+    config: &FactsConfig,
+) -> Result<()> {
+    if fallback_hosts.is_empty() {
+        return Ok(());
+    }
+
+    let groups = host_groups(inventory);
+    let fallback = ArchitectureFacts::fallback();
+    for host in fallback_hosts {
+        let host_groups = groups.get(host).cloned().unwrap_or_default();
+        warn!(
+            host = %host,
+            groups = %host_groups.join(","),
+            architecture = %fallback.ansible_architecture,
+            os_family = %fallback.ansible_os_family,
+            "No facts available for host {}, using fallback facts",
+            host
+        );
+    }
+
+    let escalated: Vec<&str> = fallback_hosts
+        .iter()
+        .filter(|host| {
+            config.fallback_severity == FallbackSeverity::Error
+                || groups.get(*host).is_some_and(|host_groups| {
+                    host_groups
+                        .iter()
+                        .any(|group| config.fallback_error_groups.contains(group))
+                })
+        })
+        .map(String::as_str)
+        .collect();
+    if escalated.is_empty() {
+        return Ok(());
+    }
+    Err(FactsError::FallbackFacts(escalated.join(", ")))
+}
+
+/// Every group each host belongs to, directly or through a child group.
+fn host_groups(inventory: &ParsedInventory) -> BTreeMap<String, Vec<String>> {
+    let names: Vec<String> = match &inventory.groups {
+        InventoryGroups::Simple(groups) => groups.keys().cloned().collect(),
+        InventoryGroups::Detailed(groups) => groups.keys().cloned().collect(),
+    };
+
+    let mut by_host: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for group in names {
+        for host in group_members(inventory, &group).unwrap_or_default() {
+            by_host.entry(host).or_default().push(group.clone());
+        }
+    }
+    by_host
+}
+
+/// Host vars that pick which daemon, cluster, remote or gateway a host is
+/// reached through, or which container there. Two entries with the same
+/// `ansible_host` but different values are different machines.
+const ROUTE_VARS: &[&str] = &[
+    "ansible_docker_host",
+    "docker_context",
+    "ansible_nerdctl_namespace",
+    "ansible_kubectl_pod",
+    "ansible_kubectl_container",
+    "ansible_kubectl_namespace",
+    "ansible_kubectl_context",
+    "ansible_kubectl_kubeconfig",
+    "ansible_lxd_remote",
+    "ansible_lxd_project",
+    "proxmox_vmid",
+    pct_facts::NODE_VAR,
+    gateway_facts::GATEWAY_VAR,
+    "ansible_ssh_proxy_command",
+    "proxy_command",
+];
+
+/// Host vars that decide who a host is logged in and probed as. The same
+/// address reached as another user or with become may be a different
+/// environment, such as a chroot or a user's own container runtime.
+const LOGIN_VARS: &[&str] = &[
+    "ansible_user",
+    "ansible_ssh_user",
+    "ansible_private_key_file",
+    "ansible_ssh_private_key_file",
+    "ansible_become",
+    "ansible_become_user",
+    "ansible_become_method",
+];
+
+/// Map inventory aliases to the host whose facts they share. Hosts share
+/// facts when they use the same connection type, declare the same
+/// `ansible_host` and port and agree on every [`ROUTE_VARS`] and
+/// [`LOGIN_VARS`] entry; the entry named after the target (or else the
+/// first by name) is the one gathered.
+fn find_host_aliases(
+    entries: &[HostEntry],
+    connections: &HashMap<String, ConnectionType>,
+) -> BTreeMap<String, String> {
+    type AliasKey = (ConnectionType, String, Option<u16>, Vec<Option<String>>);
+    let mut by_target: BTreeMap<AliasKey, Vec<&str>> = BTreeMap::new();
+
+    for entry in entries {
+        let connection_type = connections[&entry.name].clone();
+        if connection_type == ConnectionType::Local {
+            continue;
+        }
+        let target = entry
+            .vars
+            .get("ansible_host")
+            .and_then(|v| v.as_str())
+            .or(entry.address.as_deref())
+            .unwrap_or(&entry.name);
+        let port = entry.port.or_else(|| {
+            ["ansible_port", "ansible_ssh_port"].iter().find_map(|key| {
+                match entry.vars.get(*key)? {
+                    serde_json::Value::Number(port) => port.as_u64()?.try_into().ok(),
+                    serde_json::Value::String(port) => port.trim().parse().ok(),
+                    _ => None,
+                }
+            })
+        });
+        let route = ROUTE_VARS
+            .iter()
+            .chain(LOGIN_VARS)
+            .map(|key| match entry.vars.get(*key)? {
+                serde_json::Value::String(value) => Some(value.trim().to_string()),
+                value => Some(value.to_string()),
+            })
+            .chain([
+                entry.user.clone(),
+                entry.ssh_private_key_file.clone(),
+                entry.ansible_become.map(|enabled| enabled.to_string()),
+                entry.become_user.clone(),
+                entry.become_method.clone(),
+            ])
+            .collect();
+        by_target
+            .entry((connection_type, target.to_string(), port, route))
+            .or_default()
+            .push(&entry.name);
+    }
+
+    let mut aliases = BTreeMap::new();
+    for ((_, target, _, _), mut names) in by_target {
+        if names.len() < 2 {
+            continue;
+        }
+        names.sort_unstable();
+        let primary = names
+            .iter()
+            .find(|name| **name == target)
+            .unwrap_or(&names[0])
+            .to_string();
+        for name in names {
+            if name != primary {
+                aliases.insert(name.to_string(), primary.clone());
+            }
+        }
+    }
+
+    aliases
+}
+
+/// Give uncached SSH hosts the cached facts of a host with the same host
+/// key, so a machine renamed in the inventory is not gathered again.
+/// Returns new name -> cached name.
+async fn match_cached_host_keys(
+    ssh_hosts: &[HostEntry],
+    cache: &mut FactCache,
+    config: &FactsConfig,
+) -> BTreeMap<String, String> {
+    let index = cache.fingerprint_index(config.cache_ttl);
+    if index.is_empty() {
+        return BTreeMap::new();
+    }
+
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections));
+    let ramp = Arc::new(ConnectionRamp::from_config(config));
+    let mut tasks = JoinSet::new();
+
+    for host in ssh_hosts {
+        if cache.get(&host.name, config.cache_ttl).is_some() {
+            continue;
+        }
+        let Ok(target) = ssh_facts::SshTarget::for_config(host, config) else {
+            continue;
+        };
+        let name = host.name.clone();
+        let timeout = config.for_host(host).connect_timeout;
+        let sem = semaphore.clone();
+        let ramp = ramp.clone();
+
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await.ok()?;
+            ramp.wait(&name).await;
+            match ssh_facts::scan_host_keys(&target.address, target.port, timeout).await {
+                Ok(keys) if !keys.is_empty() => Some((name, keys)),
+                Ok(_) => None,
+                Err(e) => {
+                    debug!("Host key scan failed for {}: {}", name, e);
+                    None
+                }
+            }
+        });
+    }
+
+    let mut renamed = BTreeMap::new();
+    while let Some(result) = tasks.join_next().await {
+        let Ok(Some((host, keys))) = result else {
+            continue;
+        };
+        let Some(cached_name) = keys.values().find_map(|fingerprint| index.get(fingerprint)) else {
+            continue;
+        };
+        let Some(cached) = cache.facts.get(cached_name).cloned() else {
+            continue;
+        };
+        info!(
+            "{} has the host key of cached host {}; reusing its facts",
+            host, cached_name
+        );
+        cache.restore(&host, cached);
+        renamed.insert(host, cached_name.clone());
+    }
+
+    renamed
+}
+
+/// Gathered hosts whose `ansible_machine_id` differs from the one in their
+/// cache entry, expired or not, sorted.
+fn changed_machine_ids(
+    cache: &FactCache,
+    new_facts: &HashMap<String, ArchitectureFacts>,
+) -> Vec<String> {
+    let machine_id = |facts: &ArchitectureFacts| facts.extra.get("ansible_machine_id").cloned();
+    let mut changed: Vec<String> = new_facts
+        .iter()
+        .filter(|(host, facts)| {
+            let cached = cache
+                .facts
+                .get(&normalize_hostname(host))
+                .and_then(|cached| machine_id(&cached.facts));
+            matches!((cached, machine_id(facts)), (Some(old), Some(new)) if old != new)
+        })
+        .map(|(host, _)| host.clone())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Re-scan host keys for SSH hosts served from cache and drop entries whose
+/// key no longer matches, so those hosts are gathered again.
+async fn verify_cached_host_keys(
+    ssh_hosts: &[HostEntry],
+    cache: &mut FactCache,
+    config: &FactsConfig,
+) -> Vec<String> {
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections));
+    let ramp = Arc::new(ConnectionRamp::from_config(config));
+    let mut tasks = JoinSet::new();
+
+    for host in ssh_hosts {
+        if cache.get(&host.name, config.cache_ttl).is_none() {
+            continue;
+        }
+        if cache.fingerprint(&host.name).is_none() {
+            continue;
+        }
+
+        let Ok(target) = ssh_facts::SshTarget::for_config(host, config) else {
+            continue;
+        };
+        let name = host.name.clone();
+        let timeout = config.for_host(host).connect_timeout;
+        let sem = semaphore.clone();
+        let ramp = ramp.clone();
+
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await.ok()?;
+            ramp.wait(&name).await;
+            match ssh_facts::scan_host_keys(&target.address, target.port, timeout).await {
+                Ok(keys) => Some((name, keys)),
+                Err(e) => {
+                    debug!("Host key scan failed for {}: {}", name, e);
+                    None
+                }
+            }
+        });
+    }
+
+    let mut changed = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        let Ok(Some((host, keys))) = result else {
+            continue;
+        };
+        if let Some((expected, current)) = cache.host_key_change(&host, &keys) {
+            warn!(
+                "Host key for {} changed ({} -> {}); invalidating cached facts",
+                host, expected, current
+            );
+            cache.invalidate(&host);
+            changed.push(host);
+        }
+    }
+
+    changed
+}
+
+/// Set up the record/replay session for this run. Replays never touch the
+/// cache so results come from the recording alone.
+fn attach_session(config: &FactsConfig) -> Result<FactsConfig> {
+    let mut config = config.clone();
+
+    if config.session.is_none() {
+        if let Some(path) = &config.replay {
+            config.session = Some(Arc::new(Session::load_replay(path)?));
+        } else if config.record.is_some() {
+            config.session = Some(Arc::new(Session::recording()));
+        }
+    }
+
+    if config.replay.is_some() {
+        config.no_cache = true;
+        config.force_refresh = true;
+    }
+
+    Ok(config)
+}
+
+/// Parse rustle-parse output. Never panics on malformed input.
+pub fn parse_playbook(input: &[u8]) -> Result<ParsedPlaybook> {
+    serde_json::from_slice(input)
+        .map_err(|e| FactsError::InvalidInventory(format!("Failed to parse input JSON: {e}")))
+}
+
+/// Resolve every host named in the inventory's hosts or groups into a
+/// `HostEntry`, sorted by name.
+pub fn normalize_inventory(inventory: &ParsedInventory) -> Result<Vec<HostEntry>> {
+    Ok(par_map(&inventory_host_names(inventory)?, |host| {
+        let mut entry = get_host_entry(host, inventory);
+        resolve_connection_vars(&mut entry, inventory);
+        debug!(
+            "Created HostEntry for {}: connection={:?}",
+            host, entry.connection
+        );
+        entry
+    }))
+}
+
+/// Inventories smaller than this are classified on the calling thread.
+const PARALLEL_THRESHOLD: usize = 512;
+
+/// Map `items` with `f` across the available cores, keeping their order.
+/// Host classification is CPU-bound and touches every host, so inventories
+/// of tens of thousands of hosts are split into one chunk per core.
+fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if items.len() < PARALLEL_THRESHOLD || threads == 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let chunk_size = items.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let chunks: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<R>>()))
+            .collect();
+        chunks
+            .into_iter()
+            .flat_map(|chunk| {
+                chunk
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
+
+/// The connection type of every host, by name.
+fn classify_connections(
+    entries: &[HostEntry],
+    implicit_local: bool,
+) -> HashMap<String, ConnectionType> {
+    entries
+        .iter()
+        .map(|entry| entry.name.clone())
+        .zip(par_map(entries, |entry| {
+            get_connection_type(entry, implicit_local)
+        }))
+        .collect()
+}
+
+fn inventory_host_names(inventory: &ParsedInventory) -> Result<Vec<String>> {
+    let hosts = listed_host_names(inventory);
+    if hosts.is_empty() {
+        return Err(FactsError::InvalidInventory(
+            "No hosts found in inventory".to_string(),
+        ));
+    }
+    Ok(hosts)
+}
+
+/// Every host named in the inventory's hosts or groups, sorted.
+fn listed_host_names(inventory: &ParsedInventory) -> Vec<String> {
     let mut hosts = Vec::new();
 
     // Extract hosts from the hosts section
-    match &playbook.inventory.hosts {
+    match &inventory.hosts {
         InventoryHosts::Simple(simple_hosts) => {
             for host in simple_hosts.keys() {
                 hosts.push(host.clone());
@@ -170,7 +1341,7 @@ fn extract_unique_hosts(playbook: &ParsedPlaybook) -> Result<Vec<String>> {
     }
 
     // Extract hosts from the groups section
-    match &playbook.inventory.groups {
+    match &inventory.groups {
         InventoryGroups::Simple(simple_groups) => {
             for (group_name, group_hosts) in simple_groups {
                 if group_name != "all" && group_name != "ungrouped" {
@@ -197,89 +1368,275 @@ fn extract_unique_hosts(playbook: &ParsedPlaybook) -> Result<Vec<String>> {
 
     hosts.sort();
     hosts.dedup();
+    hosts
+}
 
-    if hosts.is_empty() {
-        return Err(FactsError::InvalidInventory(
-            "No hosts found in inventory".to_string(),
-        ));
+/// Problems in the inventory that enrichment works around, sorted.
+pub fn inventory_diagnostics(inventory: &ParsedInventory) -> Vec<InventoryDiagnostic> {
+    let mut diagnostics = BTreeSet::new();
+
+    let defined: BTreeSet<&String> = match &inventory.hosts {
+        InventoryHosts::Simple(simple_hosts) => {
+            for (host, vars) in simple_hosts {
+                if !vars.is_object() && !vars.is_null() {
+                    diagnostics.insert(InventoryDiagnostic::NonObjectVars { host: host.clone() });
+                }
+            }
+            simple_hosts.keys().collect()
+        }
+        InventoryHosts::Detailed(detailed_hosts) => detailed_hosts.keys().collect(),
+    };
+
+    let group_hosts: Vec<(&String, &Vec<String>)> = match &inventory.groups {
+        InventoryGroups::Simple(simple_groups) => simple_groups.iter().collect(),
+        InventoryGroups::Detailed(detailed_groups) => {
+            for (group, entry) in detailed_groups {
+                for child in &entry.children {
+                    if !detailed_groups.contains_key(child) {
+                        diagnostics.insert(InventoryDiagnostic::UnknownChildGroup {
+                            group: group.clone(),
+                            child: child.clone(),
+                        });
+                    }
+                }
+            }
+            detailed_groups
+                .iter()
+                .map(|(group, entry)| (group, &entry.hosts))
+                .collect()
+        }
+    };
+    let mut all_hosts: BTreeSet<&String> = defined.clone();
+    for (group, hosts) in group_hosts {
+        for host in hosts {
+            if !defined.contains(host) {
+                diagnostics.insert(InventoryDiagnostic::UndefinedGroupHost {
+                    group: group.clone(),
+                    host: host.clone(),
+                });
+            }
+            all_hosts.insert(host);
+        }
     }
 
-    Ok(hosts)
+    let mut by_canonical: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for host in all_hosts {
+        by_canonical
+            .entry(normalize_hostname(host))
+            .or_default()
+            .push(host.clone());
+    }
+    for hosts in by_canonical.into_values().filter(|hosts| hosts.len() > 1) {
+        diagnostics.insert(InventoryDiagnostic::CaseDuplicateHosts { hosts });
+    }
+
+    diagnostics.into_iter().collect()
 }
 
-fn get_host_entry(hostname: &str, inventory: &crate::types::ParsedInventory) -> HostEntry {
+fn get_host_entry(hostname: &str, inventory: &ParsedInventory) -> HostEntry {
+    let fallback_entry = || HostEntry {
+        vars: get_host_vars(inventory, hostname),
+        ..HostEntry::new(hostname)
+    };
+
     match &inventory.hosts {
         InventoryHosts::Detailed(detailed_hosts) => detailed_hosts
             .get(hostname)
             .cloned()
-            .unwrap_or_else(|| HostEntry {
-                name: hostname.to_string(),
-                address: None,
-                port: None,
-                user: None,
-                vars: get_host_vars(inventory, hostname),
-                groups: vec![],
-                connection: None,
-                ssh_private_key_file: None,
-                ssh_common_args: None,
-                ssh_extra_args: None,
-                ssh_pipelining: None,
-                connection_timeout: None,
-                ansible_become: None,
-                become_method: None,
-                become_user: None,
-                become_flags: None,
-            }),
-        InventoryHosts::Simple(_) => HostEntry {
-            name: hostname.to_string(),
-            address: None,
-            port: None,
-            user: None,
-            vars: get_host_vars(inventory, hostname),
-            groups: vec![],
-            connection: None,
-            ssh_private_key_file: None,
-            ssh_common_args: None,
-            ssh_extra_args: None,
-            ssh_pipelining: None,
-            connection_timeout: None,
-            ansible_become: None,
-            become_method: None,
-            become_user: None,
-            become_flags: None,
-        },
+            .unwrap_or_else(fallback_entry),
+        InventoryHosts::Simple(_) => fallback_entry(),
+    }
+}
+
+/// The transport for hosts that name none and are not implicitly local.
+/// Unlike `ansible_connection` in the inventory-wide vars, it leaves
+/// `localhost` to local detection.
+const DEFAULT_CONNECTION_VAR: &str = "rustle_default_connection";
+
+/// The container engine for a host. Unlike the common `container_runtime`
+/// var, which Kubespray and others set on every node to describe what the
+/// node runs, it also makes a host without a connection a container.
+const CONTAINER_RUNTIME_VAR: &str = "rustle_container_runtime";
+
+/// Vars that decide how a host is reached. Group-level values are inherited
+/// by hosts that do not set them, and templates in them are rendered.
+const CONNECTION_VARS: &[&str] = &[
+    "ansible_host",
+    "ansible_port",
+    "ansible_user",
+    "ansible_connection",
+    "ansible_ssh_private_key_file",
+    "ansible_ssh_common_args",
+    "ansible_ssh_extra_args",
+    "ansible_ssh_proxy_command",
+    "proxy_command",
+    "ansible_lxd_remote",
+    "ansible_lxd_project",
+    "ansible_nerdctl_namespace",
+    "container_runtime",
+    CONTAINER_RUNTIME_VAR,
+    "proxmox_vmid",
+    "ansible_libvirt_uri",
+    "ansible_docker_host",
+    "docker_context",
+    pct_facts::NODE_VAR,
+    DEFAULT_CONNECTION_VAR,
+    gateway_facts::GATEWAY_VAR,
+];
+
+/// Fill in connection vars inherited from groups and render templates such
+/// as `{{ inventory_hostname }}.internal.example.com`. Values that fail to
+/// render are left as written.
+fn resolve_connection_vars(entry: &mut HostEntry, inventory: &ParsedInventory) {
+    let context = template_context(entry, inventory);
+
+    for key in CONNECTION_VARS {
+        if let Some(value) = context.get(*key) {
+            entry
+                .vars
+                .entry(key.to_string())
+                .or_insert_with(|| value.clone());
+        }
+        if let Some(serde_json::Value::String(value)) = entry.vars.get(*key) {
+            if let Some(rendered) = render_var(&entry.name, key, value, &context) {
+                entry
+                    .vars
+                    .insert(key.to_string(), serde_json::json!(rendered));
+            }
+        }
+    }
+
+    if let Some(address) = &entry.address {
+        if let Some(rendered) = render_var(&entry.name, "address", address, &context) {
+            entry.address = Some(rendered);
+        }
+    }
+}
+
+fn render_var(
+    host: &str,
+    key: &str,
+    value: &str,
+    context: &HashMap<String, serde_json::Value>,
+) -> Option<String> {
+    if !templating::is_template(value) {
+        return None;
+    }
+    match templating::render(value, context) {
+        Ok(rendered) => Some(rendered),
+        Err(e) => {
+            warn!("Could not render {} for {}: {}", key, host, e);
+            None
+        }
     }
 }
 
-fn get_connection_type(host: &HostEntry) -> String {
+/// Vars visible to a host's templates: inventory-wide vars, then group vars
+/// from the broadest group to the narrowest, then the host's own vars.
+fn template_context(
+    entry: &HostEntry,
+    inventory: &ParsedInventory,
+) -> HashMap<String, serde_json::Value> {
+    let mut context = inventory.variables.clone();
+
+    if let InventoryGroups::Detailed(groups) = &inventory.groups {
+        let mut scoped: Vec<(usize, &String, &HashMap<String, serde_json::Value>)> = groups
+            .iter()
+            .filter_map(|(name, group)| {
+                let members = group_members(inventory, name)?;
+                members
+                    .contains(&entry.name)
+                    .then_some((members.len(), name, &group.vars))
+            })
+            .collect();
+        scoped.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        for (_, _, vars) in scoped {
+            context.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+
+    context.extend(entry.vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+    let short = entry.name.split('.').next().unwrap_or(&entry.name);
+    context.insert(
+        "inventory_hostname".to_string(),
+        serde_json::json!(entry.name),
+    );
+    context.insert(
+        "inventory_hostname_short".to_string(),
+        serde_json::json!(short),
+    );
+    context
+}
+
+/// The host's connection type. Hosts without one that are this machine are
+/// `local` unless `implicit_local` is off.
+fn get_connection_type(host: &HostEntry, implicit_local: bool) -> ConnectionType {
     debug!(
-        "Checking connection type for host {}: connection field = {:?}, vars = {:?}",
-        host.name, host.connection, host.vars
+        "Checking connection type for host {}: connection field = {:?}",
+        host.name, host.connection
     );
+    trace!("Vars of host {}: {:?}", host.name, host.vars);
 
-    // Check explicit connection field
-    if let Some(connection) = &host.connection {
-        debug!("Using explicit connection field: {}", connection);
-        return connection.clone();
-    }
+    let explicit = host.connection.clone().or_else(|| {
+        host.vars
+            .get("ansible_connection")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    });
 
-    // Check ansible_connection in vars
-    if let Some(ansible_connection) = host.vars.get("ansible_connection") {
-        if let Some(conn_str) = ansible_connection.as_str() {
-            debug!("Using ansible_connection from vars: {}", conn_str);
-            return conn_str.to_string();
+    // `rustle_container_runtime` picks the engine for container hosts, or
+    // makes a host without a connection a container. `container_runtime` only
+    // picks the engine of hosts already connected to a container.
+    let is_container = |connection: &str| {
+        matches!(
+            ConnectionType::from(connection.to_string()),
+            ConnectionType::Docker | ConnectionType::Podman | ConnectionType::Nerdctl
+        )
+    };
+    let runtime = |key: &str| {
+        host.vars
+            .get(key)
+            .and_then(|v| v.as_str())
+            .and_then(ContainerRuntime::from_name)
+    };
+    let runtime = match explicit.as_deref() {
+        None => runtime(CONTAINER_RUNTIME_VAR),
+        Some(connection) if is_container(connection) => {
+            runtime(CONTAINER_RUNTIME_VAR).or_else(|| runtime("container_runtime"))
         }
+        Some(_) => None,
+    };
+    if let Some(runtime) = runtime {
+        debug!("Using container runtime from vars: {}", runtime.as_str());
+        return ConnectionType::from(runtime.as_str().to_string());
+    }
+
+    // Check explicit connection field, then ansible_connection in vars
+    if let Some(connection) = explicit {
+        debug!("Using explicit connection: {}", connection);
+        return ConnectionType::from(connection);
     }
 
     // Check if it should use local detection
-    if ArchitectureFacts::should_use_local_detection(&host.name, &host.vars) {
+    if implicit_local && ArchitectureFacts::is_implicitly_local(&host.name, &host.vars) {
         debug!("Using local detection for host {}", host.name);
-        return "local".to_string();
+        return ConnectionType::Local;
     }
 
-    // Default to SSH
+    // Fall back to the inventory's default transport, then SSH
+    if let Some(default) = host
+        .vars
+        .get(DEFAULT_CONNECTION_VAR)
+        .and_then(|v| v.as_str())
+    {
+        debug!(
+            "Using default connection {} for host {}",
+            default, host.name
+        );
+        return ConnectionType::from(default.to_string());
+    }
     debug!("Defaulting to SSH for host {}", host.name);
-    "ssh".to_string()
+    ConnectionType::Ssh
 }
 
 fn get_host_vars(
@@ -299,120 +1656,149 @@ fn get_host_vars(
     }
 }
 
+/// Everything known about hosts' facts before they are merged.
+struct FactSources<'a> {
+    declared: &'a HashMap<String, FactOverrides>,
+    gathered: &'a HashMap<String, ArchitectureFacts>,
+    cached: &'a HashMap<String, ArchitectureFacts>,
+    /// Hosts that are this machine, used when nothing else is known
+    local: &'a HashSet<String>,
+    /// Platform of each network device, whose fallback facts name it
+    network: &'a HashMap<String, String>,
+    /// Expired cached facts of hosts that could not be gathered
+    stale: &'a HashMap<String, ArchitectureFacts>,
+    /// Hosts left out of the output entirely
+    excluded: &'a BTreeSet<String>,
+    /// Facts a gathered host is known not to have when they are missing
+    probed: &'a [&'static str],
+    prefer: FactPreference,
+    arch_table: &'a ArchTable,
+}
+
+/// Each host's labels: those of the run its cached facts came from, else
+/// this run's. Hosts without labels are left out.
+fn host_labels(
+    inventory: &EnrichedInventory,
+    cache: &FactCache,
+    labels: &BTreeMap<String, String>,
+) -> BTreeMap<String, BTreeMap<String, String>> {
+    inventory
+        .host_facts
+        .keys()
+        .filter_map(|host| {
+            let labels = cache
+                .labels(host)
+                .filter(|cached| !cached.is_empty())
+                .unwrap_or(labels);
+            (!labels.is_empty()).then(|| (host.clone(), labels.clone()))
+        })
+        .collect()
+}
+
 fn build_enriched_playbook(
     parsed: ParsedPlaybook,
-    cache: &FactCache,
-    new_facts: &HashMap<String, ArchitectureFacts>,
-    cache_ttl: u64,
-) -> Result<EnrichedPlaybook> {
+    sources: FactSources<'_>,
+) -> Result<(EnrichedPlaybook, BTreeSet<String>)> {
     let mut host_facts = HashMap::new();
+    let mut fact_sources = HashMap::new();
+    let mut fallback_hosts = BTreeSet::new();
+    let mut stale_hosts = Vec::new();
 
-    // Get all host names from inventory
-    let host_names: Vec<String> = match &parsed.inventory.hosts {
+    // Hosts from the inventory and any only listed in groups
+    let mut host_names: BTreeSet<String> = match &parsed.inventory.hosts {
         InventoryHosts::Simple(simple_hosts) => simple_hosts.keys().cloned().collect(),
         InventoryHosts::Detailed(detailed_hosts) => detailed_hosts.keys().cloned().collect(),
     };
+    let group_hosts: Vec<&String> = match &parsed.inventory.groups {
+        InventoryGroups::Simple(simple_groups) => simple_groups
+            .iter()
+            .filter(|(name, _)| *name != "all" && *name != "ungrouped")
+            .flat_map(|(_, hosts)| hosts)
+            .collect(),
+        InventoryGroups::Detailed(detailed_groups) => detailed_groups
+            .iter()
+            .filter(|(name, _)| *name != "all" && *name != "ungrouped")
+            .flat_map(|(_, group)| &group.hosts)
+            .collect(),
+    };
+    host_names.extend(group_hosts.into_iter().cloned());
+    host_names.retain(|host| !sources.excluded.contains(host));
 
     for host in &host_names {
-        if let Some(facts) = new_facts.get(host) {
-            host_facts.insert(host.clone(), facts.clone());
-        } else if let Some(facts) = cache.get(host, cache_ttl) {
-            host_facts.insert(host.clone(), facts.clone());
-        } else {
-            let host_vars = get_host_vars(&parsed.inventory, host);
-            if ArchitectureFacts::should_use_local_detection(host, &host_vars) {
-                info!("Using local system detection for host {}", host);
-                host_facts.insert(host.clone(), ArchitectureFacts::from_local_system());
-            } else {
-                warn!("No facts available for host {}, using fallback", host);
-                host_facts.insert(host.clone(), ArchitectureFacts::fallback());
-            }
-        }
-    }
+        let declared = sources.declared.get(host);
+        let gathered = sources.gathered.get(host);
+        let cached = sources.cached.get(host);
+        let candidates = source_order(sources.prefer)
+            .into_iter()
+            .filter_map(|source| {
+                let values = match source {
+                    FactSource::Declared => declared.cloned(),
+                    FactSource::Gathered => gathered.map(FactOverrides::from),
+                    // A fresh gather says which facts the host no longer has
+                    FactSource::Cached => cached.map(|cached| match gathered {
+                        Some(_) => FactOverrides::from(cached).without(sources.probed),
+                        None => FactOverrides::from(cached),
+                    }),
+                    FactSource::Local | FactSource::Stale | FactSource::Fallback => None,
+                }?;
+                Some((source, values))
+            })
+            .collect();
 
-    // Process groups based on format
-    match &parsed.inventory.groups {
-        InventoryGroups::Simple(simple_groups) => {
-            for (group_name, group_hosts) in simple_groups {
-                if group_name != "all" && group_name != "ungrouped" {
-                    for host in group_hosts {
-                        if !host_facts.contains_key(host) {
-                            if let Some(facts) = new_facts.get(host) {
-                                host_facts.insert(host.clone(), facts.clone());
-                            } else if let Some(facts) = cache.get(host, cache_ttl) {
-                                host_facts.insert(host.clone(), facts.clone());
-                            } else {
-                                let host_vars = get_host_vars(&parsed.inventory, host);
-                                if ArchitectureFacts::should_use_local_detection(host, &host_vars) {
-                                    info!(
-                                        "Using local system detection for host {} in group {}",
-                                        host, group_name
-                                    );
-                                    host_facts.insert(
-                                        host.clone(),
-                                        ArchitectureFacts::from_local_system(),
-                                    );
-                                } else {
-                                    warn!(
-                                        "No facts available for host {} in group {}, using fallback",
-                                        host, group_name
-                                    );
-                                    host_facts.insert(host.clone(), ArchitectureFacts::fallback());
-                                }
-                            }
-                        }
-                    }
-                }
+        let (mut facts, field_sources) = merge_sources(candidates, || {
+            if let Some(declared) = declared.filter(|declared| declared.is_complete()) {
+                return (FactSource::Declared, declared.to_facts());
             }
-        }
-        InventoryGroups::Detailed(detailed_groups) => {
-            for (group_name, group_entry) in detailed_groups {
-                if group_name != "all" && group_name != "ungrouped" {
-                    for host in &group_entry.hosts {
-                        if !host_facts.contains_key(host) {
-                            if let Some(facts) = new_facts.get(host) {
-                                host_facts.insert(host.clone(), facts.clone());
-                            } else if let Some(facts) = cache.get(host, cache_ttl) {
-                                host_facts.insert(host.clone(), facts.clone());
-                            } else {
-                                let host_vars = get_host_vars(&parsed.inventory, host);
-                                if ArchitectureFacts::should_use_local_detection(host, &host_vars) {
-                                    info!(
-                                        "Using local system detection for host {} in group {}",
-                                        host, group_name
-                                    );
-                                    host_facts.insert(
-                                        host.clone(),
-                                        ArchitectureFacts::from_local_system(),
-                                    );
-                                } else {
-                                    warn!(
-                                        "No facts available for host {} in group {}, using fallback",
-                                        host, group_name
-                                    );
-                                    host_facts.insert(host.clone(), ArchitectureFacts::fallback());
-                                }
-                            }
-                        }
-                    }
-                }
+            if let Some(stale) = sources.stale.get(host) {
+                stale_hosts.push(host.clone());
+                return (FactSource::Stale, stale.clone());
+            }
+            if sources.local.contains(host) {
+                info!("Using local system detection for host {}", host);
+                (FactSource::Local, ArchitectureFacts::from_local_system())
+            } else {
+                fallback_hosts.insert(host.clone());
+                let fallback = match sources.network.get(host) {
+                    Some(network_os) => network_facts::fallback_facts(network_os),
+                    None => ArchitectureFacts::fallback(),
+                };
+                (FactSource::Fallback, fallback)
             }
+        });
+        facts.ansible_architecture = sources.arch_table.normalize(&facts.ansible_architecture);
+        // Derived from the merged facts, so it is never cached
+        if let Some(triple) = target_triple(&facts) {
+            facts
+                .extra
+                .insert("rustle_target_triple".to_string(), triple.into());
         }
+        host_facts.insert(host.clone(), facts);
+        fact_sources.insert(host.clone(), field_sources);
     }
 
     let enriched_inventory = EnrichedInventory {
+        facts_schema_version: FACTS_SCHEMA_VERSION,
         base: parsed.inventory.clone(),
         host_facts,
+        fact_sources,
+        skipped_hosts: sources.excluded.iter().cloned().collect(),
+        stale_hosts,
+        host_labels: BTreeMap::new(),
     };
 
-    Ok(EnrichedPlaybook {
+    let enriched = EnrichedPlaybook {
         metadata: parsed.metadata,
         plays: parsed.plays,
         variables: parsed.variables,
         facts_required: parsed.facts_required,
         vault_ids: parsed.vault_ids,
         inventory: enriched_inventory,
-    })
+        play_host_facts: None,
+        hosts_by_fact: None,
+        group_facts: None,
+        partial: false,
+    };
+    Ok((enriched, fallback_hosts))
 }
 
 #[cfg(test)]
@@ -455,10 +1841,173 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_normalize_inventory_renders_group_connection_templates() {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {
+                "web1": {},
+                "web2.corp": {"ansible_port": 2200},
+                "db1": {"ansible_host": "10.0.0.5"}
+            },
+            "groups": {
+                "all_servers": {
+                    "name": "all_servers",
+                    "hosts": ["db1"],
+                    "children": ["web"],
+                    "vars": {"ansible_host": "{{ inventory_hostname_short }}.{{ zone }}", "zone": "dc1.example.com"}
+                },
+                "web": {
+                    "name": "web",
+                    "hosts": ["web1", "web2.corp"],
+                    "children": [],
+                    "vars": {"zone": "internal.example.com", "ansible_port": "{{ web_port | default(22) }}"}
+                }
+            },
+            "variables": {}
+        }))
+        .unwrap();
+
+        let entries = normalize_inventory(&inventory).unwrap();
+        let var = |host: &str, key: &str| {
+            entries.iter().find(|e| e.name == host).unwrap().vars[key].clone()
+        };
+
+        // The narrower group's zone wins over the parent's
+        assert_eq!(var("web1", "ansible_host"), "web1.internal.example.com");
+        assert_eq!(var("web1", "ansible_port"), "22");
+        assert_eq!(
+            var("web2.corp", "ansible_host"),
+            "web2.internal.example.com"
+        );
+        assert_eq!(var("web2.corp", "ansible_port"), 2200);
+        // Host vars are never replaced by group vars
+        assert_eq!(var("db1", "ansible_host"), "10.0.0.5");
+    }
+
+    #[test]
+    fn test_inventory_diagnostics() {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {"Web1": {}, "web1": null, "db1": "not vars"},
+            "groups": {
+                "web": {"name": "web", "hosts": ["web1", "web2"], "children": [], "vars": {}},
+                "prod": {"name": "prod", "hosts": [], "children": ["web", "dbs"], "vars": {}}
+            },
+            "variables": {}
+        }))
+        .unwrap();
+
+        assert_eq!(
+            inventory_diagnostics(&inventory),
+            vec![
+                InventoryDiagnostic::UndefinedGroupHost {
+                    group: "web".to_string(),
+                    host: "web2".to_string()
+                },
+                InventoryDiagnostic::UnknownChildGroup {
+                    group: "prod".to_string(),
+                    child: "dbs".to_string()
+                },
+                InventoryDiagnostic::CaseDuplicateHosts {
+                    hosts: vec!["Web1".to_string(), "web1".to_string()]
+                },
+                InventoryDiagnostic::NonObjectVars {
+                    host: "db1".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changed_machine_ids() {
+        let with_id = |id: &str| {
+            let mut facts = ArchitectureFacts::fallback();
+            facts
+                .extra
+                .insert("ansible_machine_id".to_string(), id.into());
+            facts
+        };
+        let mut cache = FactCache::new();
+        cache.update("web1".to_string(), with_id("aaaa"));
+        cache.update("web2".to_string(), with_id("bbbb"));
+        cache.update("web3".to_string(), ArchitectureFacts::fallback());
+
+        let new_facts = HashMap::from([
+            ("web1".to_string(), with_id("aaaa")),
+            ("web2".to_string(), with_id("cccc")),
+            ("web3".to_string(), with_id("dddd")),
+            ("web4".to_string(), with_id("eeee")),
+        ]);
+        assert_eq!(changed_machine_ids(&cache, &new_facts), ["web2"]);
+    }
+
+    #[test]
+    fn test_every_connection_has_a_gatherer() {
+        for connection in ConnectionType::HANDLED {
+            let gathered_apart = matches!(connection, ConnectionType::Local | ConnectionType::Ssh);
+            let compiled_out = connection == ConnectionType::Mock && !cfg!(feature = "mock");
+            assert_eq!(
+                backend_name(&connection).is_some(),
+                !gathered_apart && !compiled_out,
+                "{connection}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_fallback_hosts_severity() {
+        let inventory = create_test_playbook().inventory;
+        let fallback_hosts = BTreeSet::from(["web1".to_string()]);
+        let mut config = FactsConfig::default();
+        assert!(check_fallback_hosts(&fallback_hosts, &inventory, &config).is_ok());
+
+        config.fallback_error_groups = vec!["databases".to_string()];
+        assert!(check_fallback_hosts(&fallback_hosts, &inventory, &config).is_ok());
+
+        config.fallback_error_groups = vec!["webservers".to_string()];
+        match check_fallback_hosts(&fallback_hosts, &inventory, &config) {
+            Err(FactsError::FallbackFacts(hosts)) => assert_eq!(hosts, "web1"),
+            other => panic!("expected fallback error, got {other:?}"),
+        }
+
+        config.fallback_error_groups.clear();
+        config.fallback_severity = FallbackSeverity::Error;
+        assert!(check_fallback_hosts(&fallback_hosts, &inventory, &config).is_err());
+        assert!(check_fallback_hosts(&BTreeSet::new(), &inventory, &config).is_ok());
+    }
+
+    #[test]
+    fn test_apply_fact_namespace() {
+        let document = serde_json::json!({
+            "inventory": {"host_facts": {"web1": {"ansible_architecture": "x86_64", "rustle_libc": "gnu"}}},
+            "play_host_facts": {"0": {"web1": {"ansible_architecture": "x86_64"}}}
+        });
+
+        let mut rustle = document.clone();
+        apply_fact_namespace(&mut rustle, FactNamespace::Rustle);
+        assert_eq!(
+            rustle["inventory"]["host_facts"]["web1"],
+            serde_json::json!({"rustle_architecture": "x86_64", "rustle_libc": "gnu"})
+        );
+        assert_eq!(
+            rustle["play_host_facts"]["0"]["web1"],
+            serde_json::json!({"rustle_architecture": "x86_64"})
+        );
+
+        let mut both = document.clone();
+        apply_fact_namespace(&mut both, FactNamespace::Both);
+        let web1 = &both["inventory"]["host_facts"]["web1"];
+        assert_eq!(web1["ansible_architecture"], "x86_64");
+        assert_eq!(web1["rustle_architecture"], "x86_64");
+
+        let mut ansible = document.clone();
+        apply_fact_namespace(&mut ansible, FactNamespace::Ansible);
+        assert_eq!(ansible, document);
+    }
+
     #[test]
     fn test_extract_unique_hosts() {
         let playbook = create_test_playbook();
-        let hosts = extract_unique_hosts(&playbook).unwrap();
+        let hosts = inventory_host_names(&playbook.inventory).unwrap();
 
         assert_eq!(hosts.len(), 3);
         assert!(hosts.contains(&"web1".to_string()));
@@ -488,7 +2037,7 @@ mod tests {
             },
         };
 
-        let result = extract_unique_hosts(&playbook);
+        let result = inventory_host_names(&playbook.inventory);
         assert!(result.is_err());
     }
 
@@ -530,4 +2079,228 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_connection_type_from_str() {
+        let parse = |value: &str| value.parse::<ConnectionType>().unwrap();
+        assert_eq!(parse("ssh"), ConnectionType::Ssh);
+        assert_eq!(parse("ansible.netcommon.network_cli"), ConnectionType::Ssh);
+        assert_eq!(parse("community.docker.docker_api"), ConnectionType::Docker);
+        assert_eq!(parse(" Podman "), ConnectionType::Podman);
+        assert_eq!(parse("nerdctl"), ConnectionType::Nerdctl);
+        assert_eq!(parse("kubernetes.core.kubectl"), ConnectionType::Kubectl);
+        assert_eq!(parse("community.general.lxd"), ConnectionType::Lxd);
+        assert_eq!(parse("community.general.jail"), ConnectionType::Jail);
+        assert_eq!(
+            parse("community.libvirt.libvirt_qemu"),
+            ConnectionType::LibvirtQemu
+        );
+        assert_eq!(
+            parse("ansible.builtin.winrm"),
+            ConnectionType::Other("ansible.builtin.winrm".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(ConnectionType::Other("winrm".to_string())).unwrap(),
+            "winrm"
+        );
+    }
+
+    #[test]
+    fn test_get_connection_type_implicit_local() {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {
+                "localhost": {},
+                "loop": {"ansible_host": "127.0.0.2"},
+                "builder": {},
+                "web1": {"ansible_host": "192.0.2.10"}
+            },
+            "groups": {
+                "build": {
+                    "name": "build",
+                    "hosts": ["builder"],
+                    "children": [],
+                    "vars": {"ansible_connection": "local"}
+                }
+            },
+            "variables": {}
+        }))
+        .unwrap();
+        let entries = normalize_inventory(&inventory).unwrap();
+        let connection = |host: &str, implicit_local: bool| {
+            let entry = entries.iter().find(|e| e.name == host).unwrap();
+            get_connection_type(entry, implicit_local)
+        };
+
+        assert_eq!(connection("localhost", true), ConnectionType::Local);
+        assert_eq!(connection("loop", true), ConnectionType::Local);
+        assert_eq!(connection("builder", true), ConnectionType::Local);
+        assert_eq!(connection("web1", true), ConnectionType::Ssh);
+
+        // Only the explicit group-level connection survives --no-implicit-local
+        assert_eq!(connection("localhost", false), ConnectionType::Ssh);
+        assert_eq!(connection("loop", false), ConnectionType::Ssh);
+        assert_eq!(connection("builder", false), ConnectionType::Local);
+    }
+
+    #[test]
+    fn test_inventory_default_connection() {
+        let connections = |variables: serde_json::Value| {
+            let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+                "hosts": {
+                    "localhost": {},
+                    "app1": {},
+                    "app2": {"ansible_connection": "ssh"}
+                },
+                "groups": {},
+                "variables": variables
+            }))
+            .unwrap();
+            let entries = normalize_inventory(&inventory).unwrap();
+            let connection = |host: &str| {
+                let entry = entries.iter().find(|e| e.name == host).unwrap();
+                get_connection_type(entry, true)
+            };
+            (
+                connection("localhost"),
+                connection("app1"),
+                connection("app2"),
+            )
+        };
+
+        assert_eq!(
+            connections(serde_json::json!({"rustle_default_connection": "docker"})),
+            (
+                ConnectionType::Local,
+                ConnectionType::Docker,
+                ConnectionType::Ssh
+            )
+        );
+        assert_eq!(
+            connections(serde_json::json!({"ansible_connection": "docker"})),
+            (
+                ConnectionType::Docker,
+                ConnectionType::Docker,
+                ConnectionType::Ssh
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_connection_type_container_runtime() {
+        let mut host = HostEntry::new("app1");
+        host.vars.insert(
+            CONTAINER_RUNTIME_VAR.to_string(),
+            serde_json::json!("nerdctl"),
+        );
+        assert_eq!(get_connection_type(&host, true), ConnectionType::Nerdctl);
+
+        host.vars.insert(
+            "ansible_connection".to_string(),
+            serde_json::json!("community.docker.docker"),
+        );
+        assert_eq!(get_connection_type(&host, true), ConnectionType::Nerdctl);
+
+        // Non-container connections are left alone
+        host.vars
+            .insert("ansible_connection".to_string(), serde_json::json!("ssh"));
+        assert_eq!(get_connection_type(&host, true), ConnectionType::Ssh);
+
+        // A Kubespray node describing its own runtime stays an SSH host
+        let mut node = HostEntry::new("node1");
+        node.vars.insert(
+            "container_runtime".to_string(),
+            serde_json::json!("containerd"),
+        );
+        assert_eq!(get_connection_type(&node, true), ConnectionType::Ssh);
+
+        // but picks the engine of a host already connected to a container
+        node.vars.insert(
+            "ansible_connection".to_string(),
+            serde_json::json!("docker"),
+        );
+        assert_eq!(get_connection_type(&node, true), ConnectionType::Nerdctl);
+    }
+
+    #[test]
+    fn test_par_map_keeps_order() {
+        let items: Vec<usize> = (0..PARALLEL_THRESHOLD * 4 + 3).collect();
+        let doubled = par_map(&items, |n| n * 2);
+        assert_eq!(doubled, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_find_host_aliases() {
+        let with_target = |name: &str, target: &str| {
+            let mut entry = HostEntry::new(name);
+            entry
+                .vars
+                .insert("ansible_host".to_string(), serde_json::json!(target));
+            entry
+        };
+
+        let entries = vec![
+            with_target("web-frontend", "10.0.0.5"),
+            with_target("web-api", "10.0.0.5"),
+            HostEntry::new("db1"),
+            with_target("db-primary", "db1"),
+            with_target("other", "10.0.0.6"),
+        ];
+
+        let aliases = find_host_aliases(&entries, &classify_connections(&entries, true));
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases["web-frontend"], "web-api");
+        assert_eq!(aliases["db-primary"], "db1");
+
+        // The same container name on two daemons, pod name in two
+        // namespaces or address behind two gateways is two machines
+        let with_var = |name: &str, target: &str, key: &str, value: &str| {
+            let mut entry = with_target(name, target);
+            entry.vars.insert(key.to_string(), serde_json::json!(value));
+            entry.vars.insert(
+                "ansible_connection".to_string(),
+                serde_json::json!(if key.starts_with("ansible_kubectl") {
+                    "kubectl"
+                } else if key.starts_with("rustle") {
+                    "ssh"
+                } else {
+                    "docker"
+                }),
+            );
+            entry
+        };
+        let entries = vec![
+            with_var("app-prod", "app", "ansible_docker_host", "tcp://prod:2376"),
+            with_var(
+                "app-staging",
+                "app",
+                "ansible_docker_host",
+                "tcp://staging:2376",
+            ),
+            with_var("api-a", "api", "ansible_kubectl_namespace", "team-a"),
+            with_var("api-b", "api", "ansible_kubectl_namespace", "team-b"),
+            with_var("lan-east", "10.0.0.5", "rustle_facts_gateway", "east"),
+            with_var("lan-west", "10.0.0.5", "rustle_facts_gateway", "west"),
+            with_var("lan-east-alias", "10.0.0.5", "rustle_facts_gateway", "east"),
+        ];
+        let aliases = find_host_aliases(&entries, &classify_connections(&entries, true));
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases["lan-east-alias"], "lan-east");
+
+        // Another login or port on the same address is gathered on its own
+        let with_login = |name: &str, key: &str, value: serde_json::Value| {
+            let mut entry = with_target(name, "10.0.0.5");
+            entry.vars.insert(key.to_string(), value);
+            entry
+        };
+        let entries = vec![
+            with_target("web", "10.0.0.5"),
+            with_login("web-deploy", "ansible_user", serde_json::json!("deploy")),
+            with_login("web-root", "ansible_become", serde_json::json!(true)),
+            with_login("web-alt", "ansible_port", serde_json::json!("2222")),
+            with_login("web-alt-alias", "ansible_ssh_port", serde_json::json!(2222)),
+        ];
+        let aliases = find_host_aliases(&entries, &classify_connections(&entries, true));
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases["web-alt-alias"], "web-alt");
+    }
 }
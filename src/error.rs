@@ -14,8 +14,11 @@ pub enum FactsError {
     #[error("SSH connection failed for host {0}: {1}")]
     ConnectionFailed(String, String),
 
-    #[error("Authentication failed for host {0}")]
-    AuthenticationFailed(String),
+    #[error("DNS lookup failed for host {0}: {1}")]
+    DnsResolution(String, String),
+
+    #[error("Authentication failed for host {0}: {1}")]
+    AuthenticationFailed(String, String),
 
     #[error("Failed to parse facts from host {0}: {1}")]
     ParseError(String, String),
@@ -29,11 +32,43 @@ pub enum FactsError {
     #[error("Task join error: {0}")]
     TaskJoin(String),
 
+    #[error("Privilege escalation via {1} failed for host {0}: {2}")]
+    BecomeFailed(String, String, String),
+
     #[error("Timeout while gathering facts from host {0}")]
     Timeout(String),
 
+    #[error("Host key verification failed for {0}; if the change is expected, run `rustle-facts cache forget-key <host>`")]
+    HostKeyMismatch(String),
+
+    #[error("Refusing unsafe {0} value: {1:?}")]
+    UnsafeIdentifier(String, String),
+
+    #[error("Fact assertions failed:\n{0}")]
+    AssertionFailed(String),
+
+    #[error("Fallback facts were used for: {0}")]
+    FallbackFacts(String),
+
+    #[error("Groups mix architectures: {0}")]
+    MixedArchitectures(String),
+
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+
+    #[error("Interrupted before all facts were gathered")]
+    Interrupted,
+
+    #[error(
+        "Aborted because more hosts failed than --max-failed-hosts/--max-failed-percent allow"
+    )]
+    TooManyFailures,
+
+    #[error("Docker API error for {0}: {1}")]
+    DockerApi(String, String),
+
+    #[error("Not tried because {0} keeps failing: {1}")]
+    CircuitOpen(String, String),
 }
 
 pub type Result<T> = std::result::Result<T, FactsError>;
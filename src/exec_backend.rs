@@ -0,0 +1,163 @@
+//! What the backends that reach a host through a local command share.
+//!
+//! `jexec`, `lxc exec`, `kubectl exec` and `pct exec` differ only in the
+//! command they build for a host. Running it with the host's timeouts,
+//! recording or replaying it and checking its exit status happen here, as
+//! does the fan-out over hosts that the guest agent and serial backends
+//! also use. A host that fails is logged and left out, so it gets fallback
+//! facts without costing the others theirs.
+
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::session::run_recorded;
+use crate::ssh_facts::parse_fact_output_with;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use std::future::Future;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, error};
+
+/// Run `gather` for each host with its own timeouts, at most
+/// `parallel_connections` at a time, and keep the hosts that succeed.
+pub(crate) async fn gather_each<F, Fut>(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+    gather: F,
+) -> HashMap<String, ArchitectureFacts>
+where
+    F: Fn(HostEntry, FactsConfig) -> Fut,
+    Fut: Future<Output = Result<ArchitectureFacts>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for host in hosts {
+        let name = host.name.clone();
+        let host_config = config.for_host(&host);
+        let attempt = gather(host, host_config);
+        let sem = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await;
+            (name, attempt.await)
+        });
+    }
+
+    let mut facts = HashMap::new();
+    while let Some(joined) = config.shutdown.join_next(&mut tasks).await {
+        match joined {
+            Ok((host, Ok(host_facts))) => {
+                facts.insert(host, host_facts);
+            }
+            Ok((host, Err(e))) => error!("Failed to gather facts for {}: {}", host, e),
+            Err(e) => error!("Task panicked: {}", e),
+        }
+    }
+    facts
+}
+
+/// Run `command` with `args` for `host` and return its stdout. `backend`
+/// and `target` name the command in recorded sessions; a non-zero exit
+/// fails the host with the command's stderr.
+pub(crate) async fn run(
+    host: &HostEntry,
+    backend: &str,
+    target: &str,
+    mut command: Command,
+    args: &[String],
+    config: &FactsConfig,
+) -> Result<String> {
+    debug!("Running {} {} for {}", backend, target, host.name);
+    let timeout_secs = config.connect_timeout + config.command_timeout;
+    let output = run_recorded(
+        config.session.as_deref(),
+        backend,
+        target,
+        &args.join(" "),
+        || async move {
+            command
+                .args(args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let output = timeout(Duration::from_secs(timeout_secs), command.output())
+                .await
+                .map_err(|_| FactsError::Timeout(host.name.clone()))??;
+            Ok((
+                output.status.code(),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ))
+        },
+    )
+    .await?;
+
+    if output.exit_code != Some(0) {
+        return Err(FactsError::ConnectionFailed(
+            host.name.clone(),
+            output.stderr.trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// [`run`] a command that runs the fact probe, and parse what it prints.
+pub(crate) async fn probe(
+    host: &HostEntry,
+    backend: &str,
+    target: &str,
+    command: Command,
+    args: &[String],
+    config: &FactsConfig,
+) -> Result<ArchitectureFacts> {
+    let output = run(host, backend, target, command, args, config).await?;
+    parse_fact_output_with(&output, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_hosts_do_not_stop_the_others() {
+        let hosts = vec![HostEntry::new("good"), HostEntry::new("bad")];
+        let facts = gather_each(hosts, &FactsConfig::default(), |host, _| async move {
+            match host.name.as_str() {
+                "good" => Ok(ArchitectureFacts::fallback()),
+                _ => Err(FactsError::ConnectionFailed(
+                    host.name,
+                    "no such container".to_string(),
+                )),
+            }
+        })
+        .await;
+        assert_eq!(facts.keys().collect::<Vec<_>>(), ["good"]);
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_the_exit_status() {
+        let host = HostEntry::new("box1");
+        let config = FactsConfig::default();
+        let args = [
+            "-c".to_string(),
+            "echo out; echo err >&2; exit 3".to_string(),
+        ];
+
+        let e = run(&host, "sh", "box1", Command::new("sh"), &args, &config)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, FactsError::ConnectionFailed(host, detail) if host == "box1" && detail == "err")
+        );
+
+        let args = ["-c".to_string(), "echo out".to_string()];
+        let stdout = run(&host, "sh", "box1", Command::new("sh"), &args, &config)
+            .await
+            .unwrap();
+        assert_eq!(stdout, "out\n");
+    }
+}
@@ -0,0 +1,242 @@
+//! Facts contributed by plugins that library callers register.
+//!
+//! A plugin is an async closure handed a [`Transport`] to one freshly
+//! gathered host. The keys it returns are merged into the host's
+//! [`ArchitectureFacts::extra`](crate::types::ArchitectureFacts) before the
+//! facts are cached, so cached hosts are not asked again until they expire.
+//! A plugin that fails is logged and skipped; the host keeps its probed
+//! facts.
+
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::ssh_facts;
+use crate::types::{ConnectionType, HostEntry};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+/// Key/values a plugin adds to a host's facts.
+pub type PluginFacts = serde_json::Map<String, serde_json::Value>;
+
+type PluginFuture = Pin<Box<dyn Future<Output = Result<PluginFacts>> + Send>>;
+type Plugin = dyn Fn(Transport) -> PluginFuture + Send + Sync;
+
+/// The plugins run against every host whose facts are gathered.
+#[derive(Clone, Default)]
+pub struct FactPlugins {
+    plugins: Vec<(String, Arc<Plugin>)>,
+}
+
+impl fmt::Debug for FactPlugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.plugins.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl FactPlugins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a plugin. Plugins run in the order they were registered, and a
+    /// key returned by a later plugin replaces the same key from an earlier
+    /// one.
+    pub fn register<F, Fut>(&mut self, name: impl Into<String>, plugin: F) -> &mut Self
+    where
+        F: Fn(Transport) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<PluginFacts>> + Send + 'static,
+    {
+        let plugin: Arc<Plugin> = Arc::new(move |transport| Box::pin(plugin(transport)));
+        self.plugins.push((name.into(), plugin));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Run every plugin against one host, merging what they return.
+    pub async fn gather(&self, transport: Transport) -> PluginFacts {
+        let mut facts = PluginFacts::new();
+        for (name, plugin) in &self.plugins {
+            match plugin(transport.clone()).await {
+                Ok(extra) => facts.extend(extra),
+                Err(e) => warn!(
+                    "Fact plugin {} failed for {}: {}",
+                    name, transport.host.name, e
+                ),
+            }
+        }
+        facts
+    }
+}
+
+/// A handle for running commands on the host a plugin was given.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    host: HostEntry,
+    connection: ConnectionType,
+    config: FactsConfig,
+}
+
+impl Transport {
+    pub fn new(host: HostEntry, connection: ConnectionType, config: FactsConfig) -> Self {
+        Self {
+            host,
+            connection,
+            config,
+        }
+    }
+
+    pub fn host(&self) -> &HostEntry {
+        &self.host
+    }
+
+    pub fn connection(&self) -> &ConnectionType {
+        &self.connection
+    }
+
+    /// Run a shell command on the host and return its standard output.
+    /// Local and SSH hosts are supported; other connections return an
+    /// error.
+    pub async fn run(&self, command: &str) -> Result<String> {
+        match self.connection {
+            ConnectionType::Local => self.run_local(command).await,
+            ConnectionType::Ssh => {
+                let budget =
+                    Duration::from_secs(self.config.connect_timeout + self.config.command_timeout);
+                timeout(
+                    budget,
+                    ssh_facts::run_on_host(&self.host, command, &self.config),
+                )
+                .await
+                .map_err(|_| FactsError::Timeout(self.host.name.clone()))?
+            }
+            ref other => Err(FactsError::InvalidConfig(format!(
+                "fact plugins cannot run commands over {} connections (host {})",
+                other, self.host.name
+            ))),
+        }
+    }
+
+    async fn run_local(&self, command: &str) -> Result<String> {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(command)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = timeout(
+            Duration::from_secs(self.config.command_timeout),
+            cmd.output(),
+        )
+        .await
+        .map_err(|_| FactsError::Timeout(self.host.name.clone()))??;
+
+        if !output.status.success() {
+            return Err(FactsError::ConnectionFailed(
+                self.host.name.clone(),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// Run the configured plugins against each host, at most
+/// `parallel_connections` hosts at a time.
+pub(crate) async fn gather_plugin_facts(
+    hosts: Vec<(HostEntry, ConnectionType)>,
+    config: &FactsConfig,
+) -> HashMap<String, PluginFacts> {
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections));
+    let mut tasks = JoinSet::new();
+
+    for (host, connection) in hosts {
+        let plugins = config.fact_plugins.clone();
+        let transport = Transport::new(host, connection, config.clone());
+        let sem = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await.ok()?;
+            debug!("Running fact plugins for {}", transport.host.name);
+            let name = transport.host.name.clone();
+            Some((name, plugins.gather(transport).await))
+        });
+    }
+
+    let mut results = HashMap::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some((host, facts))) = result {
+            if !facts.is_empty() {
+                results.insert(host, facts);
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_plugins_merge_in_order() {
+        let mut plugins = FactPlugins::new();
+        plugins
+            .register("first", |_transport| async {
+                let mut facts = PluginFacts::new();
+                facts.insert("rack".to_string(), serde_json::json!("a1"));
+                facts.insert("tier".to_string(), serde_json::json!("web"));
+                Ok(facts)
+            })
+            .register("failing", |transport| async move {
+                Err(FactsError::InvalidConfig(transport.host().name.clone()))
+            })
+            .register("second", |transport| async move {
+                let mut facts = PluginFacts::new();
+                facts.insert(
+                    "tier".to_string(),
+                    serde_json::json!(transport.host().name.clone()),
+                );
+                Ok(facts)
+            });
+        assert_eq!(format!("{plugins:?}"), r#"["first", "failing", "second"]"#);
+
+        let transport = Transport::new(
+            HostEntry::new("web1"),
+            ConnectionType::Local,
+            FactsConfig::default(),
+        );
+        let facts = plugins.gather(transport).await;
+        assert_eq!(facts["rack"], "a1");
+        assert_eq!(facts["tier"], "web1");
+    }
+
+    #[tokio::test]
+    async fn test_transport_runs_local_commands() {
+        let transport = Transport::new(
+            HostEntry::new("localhost"),
+            ConnectionType::Local,
+            FactsConfig::default(),
+        );
+        assert_eq!(transport.run("echo hello").await.unwrap(), "hello\n");
+        assert!(transport.run("exit 3").await.is_err());
+
+        let docker = Transport::new(
+            HostEntry::new("c1"),
+            ConnectionType::Docker,
+            FactsConfig::default(),
+        );
+        assert!(docker.run("true").await.is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::debug;
+
+/// Failure modes that can be injected into a host's gathering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FaultKind {
+    /// Hang until the gathering timeout expires
+    Timeout,
+    /// Fail as if the connection was refused
+    Refused,
+    /// Fail as if authentication was rejected
+    Auth,
+}
+
+impl FromStr for FaultKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "timeout" => Ok(FaultKind::Timeout),
+            "refused" => Ok(FaultKind::Refused),
+            "auth" => Ok(FaultKind::Auth),
+            other => Err(format!(
+                "unknown fault '{other}' (expected timeout, refused or auth)"
+            )),
+        }
+    }
+}
+
+/// Faults injected into gatherers for resilience testing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaultInjection {
+    pub failures: HashMap<String, FaultKind>,
+    pub latency: Option<Duration>,
+}
+
+impl FaultInjection {
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty() && self.latency.is_none()
+    }
+}
+
+/// Parse `host=<name>:<fault>` as accepted by `--inject-failure`.
+pub fn parse_injected_failure(value: &str) -> std::result::Result<(String, FaultKind), String> {
+    let spec = value
+        .strip_prefix("host=")
+        .ok_or_else(|| format!("expected host=<name>:<fault>, got '{value}'"))?;
+    let (host, kind) = spec
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected host=<name>:<fault>, got '{value}'"))?;
+
+    if host.is_empty() {
+        return Err(format!("missing host name in '{value}'"));
+    }
+
+    Ok((host.to_string(), kind.parse()?))
+}
+
+/// Parse a latency such as `500ms`, `2s`, or a bare number of milliseconds.
+pub fn parse_latency(value: &str) -> std::result::Result<Duration, String> {
+    let (number, unit_ms) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        (secs, 1000)
+    } else {
+        (value, 1)
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .map(|n| Duration::from_millis(n * unit_ms))
+        .map_err(|_| format!("invalid latency '{value}'"))
+}
+
+/// Apply any configured latency and failure for `host` before gathering.
+pub async fn inject_faults(config: &FactsConfig, host: &str) -> Result<()> {
+    let faults = &config.fault_injection;
+    if faults.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(latency) = faults.latency {
+        debug!("Injecting {:?} latency for {}", latency, host);
+        tokio::time::sleep(latency).await;
+    }
+
+    match faults.failures.get(host) {
+        None => Ok(()),
+        Some(FaultKind::Timeout) => {
+            debug!("Injecting timeout for {}", host);
+            tokio::time::sleep(config.host_budget()).await;
+            Err(FactsError::Timeout(host.to_string()))
+        }
+        Some(FaultKind::Refused) => Err(FactsError::ConnectionFailed(
+            host.to_string(),
+            "injected fault: connection refused".to_string(),
+        )),
+        Some(FaultKind::Auth) => Err(FactsError::AuthenticationFailed(
+            host.to_string(),
+            "injected fault: permission denied".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_injected_failure() {
+        assert_eq!(
+            parse_injected_failure("host=web1:timeout").unwrap(),
+            ("web1".to_string(), FaultKind::Timeout)
+        );
+        assert_eq!(
+            parse_injected_failure("host=deploy@web1:auth").unwrap(),
+            ("deploy@web1".to_string(), FaultKind::Auth)
+        );
+        assert!(parse_injected_failure("web1:timeout").is_err());
+        assert!(parse_injected_failure("host=web1:explode").is_err());
+        assert!(parse_injected_failure("host=:refused").is_err());
+    }
+
+    #[test]
+    fn test_parse_latency() {
+        assert_eq!(parse_latency("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_latency("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_latency("250").unwrap(), Duration::from_millis(250));
+        assert!(parse_latency("fast").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inject_faults() {
+        let mut config = FactsConfig::default();
+        assert!(inject_faults(&config, "web1").await.is_ok());
+
+        config
+            .fault_injection
+            .failures
+            .insert("web1".to_string(), FaultKind::Refused);
+        assert!(matches!(
+            inject_faults(&config, "web1").await,
+            Err(FactsError::ConnectionFailed(_, _))
+        ));
+        assert!(inject_faults(&config, "web2").await.is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+//! Facts gathered through a gateway host.
+//!
+//! In segmented networks the runner can often reach only one host per
+//! segment. Setting the group var `rustle_facts_gateway: bastion-a` makes
+//! the probe run on that gateway against each member of the group:
+//! `ssh bastion-a 'ssh member …'` for SSH members, and `docker exec`,
+//! `podman exec`, `pct exec` or `jexec` for containers and jails that live on the gateway
+//! itself. The gateway is reached like any SSH host, with its own inventory
+//! vars when it is in the inventory. The inner hop uses the gateway's SSH
+//! configuration and keys, and follows `--host-key-policy`: with `tofu` the
+//! gateway's own known_hosts records and checks member keys, with `ignore`
+//! nothing is recorded.
+
+use crate::config::{FactsConfig, HostKeyPolicy};
+use crate::error::{FactsError, Result};
+use crate::hostname::normalize_hostname;
+use crate::pct_facts;
+use crate::sanitize::{shell_quote, validate_container_name, validate_hostname, validate_user};
+use crate::ssh_facts::{self, fact_probe};
+use crate::types::{ArchitectureFacts, ConnectionType, HostEntry};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+/// The host var, usually set on a group, naming the gateway of a host.
+pub const GATEWAY_VAR: &str = "rustle_facts_gateway";
+
+/// The gateway a host's probe is relayed through, if any. A gateway that
+/// is a member of its own group is reached directly.
+pub fn gateway(host: &HostEntry) -> Option<&str> {
+    host.vars
+        .get(GATEWAY_VAR)?
+        .as_str()
+        .map(str::trim)
+        .filter(|gateway| {
+            !gateway.is_empty() && normalize_hostname(gateway) != normalize_hostname(&host.name)
+        })
+}
+
+/// Whether hosts with this connection can be probed from a gateway.
+pub fn can_relay(connection: &ConnectionType) -> bool {
+    matches!(
+        connection,
+        ConnectionType::Ssh
+            | ConnectionType::Docker
+            | ConnectionType::Podman
+            | ConnectionType::Pct
+            | ConnectionType::Jail
+    )
+}
+
+/// The command the gateway runs to probe `member`.
+pub fn relay_command(
+    member: &HostEntry,
+    connection: &ConnectionType,
+    config: &FactsConfig,
+) -> Result<String> {
+    let probe = shell_quote(&fact_probe(config)?);
+    let target = member
+        .vars
+        .get("ansible_host")
+        .and_then(|v| v.as_str())
+        .or(member.address.as_deref())
+        .unwrap_or(&member.name);
+
+    match connection {
+        ConnectionType::Ssh => {
+            validate_hostname(target)?;
+            let host_key_options = match config.host_key_policy {
+                HostKeyPolicy::Tofu => "-o StrictHostKeyChecking=accept-new",
+                HostKeyPolicy::Ignore => {
+                    "-o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null"
+                }
+            };
+            let mut command = format!(
+                "ssh -o BatchMode=yes {host_key_options} -o ConnectTimeout={}",
+                config.connect_timeout
+            );
+            let port = member
+                .port
+                .or_else(|| member.vars.get("ansible_port")?.as_u64()?.try_into().ok());
+            if let Some(port) = port {
+                command.push_str(&format!(" -p {port}"));
+            }
+            let user = member
+                .user
+                .as_deref()
+                .or_else(|| member.vars.get("ansible_user")?.as_str());
+            match user {
+                Some(user) => {
+                    validate_user(user)?;
+                    command.push_str(&format!(" {user}@{target}"));
+                }
+                None => command.push_str(&format!(" {target}")),
+            }
+            Ok(format!("{command} {probe}"))
+        }
+        ConnectionType::Docker | ConnectionType::Podman => {
+            validate_container_name(target)?;
+            Ok(format!("{connection} exec {target} sh -c {probe}"))
+        }
+        ConnectionType::Pct => {
+            let vmid = pct_facts::vmid(member)?;
+            Ok(format!("pct exec {vmid} -- sh -c {probe}"))
+        }
+        ConnectionType::Jail => {
+            validate_container_name(target)?;
+            Ok(format!("jexec {target} sh -c {probe}"))
+        }
+        other => Err(FactsError::InvalidConfig(format!(
+            "host {} has connection {other}, which cannot be relayed through a gateway",
+            member.name
+        ))),
+    }
+}
+
+/// Gather facts for hosts behind gateways. `gateways` holds the inventory
+/// entries of gateways that are themselves inventory hosts; others are
+/// reached by name. Hosts that fail are logged and left out.
+pub async fn gather_minimal_facts(
+    hosts: Vec<(HostEntry, ConnectionType)>,
+    gateways: &HashMap<String, HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    let semaphore = Arc::new(Semaphore::new(config.parallel_connections));
+    let mut tasks = JoinSet::new();
+
+    for (member, connection) in hosts {
+        let Some(gateway_name) = gateway(&member) else {
+            continue;
+        };
+        let gateway = gateways
+            .get(&normalize_hostname(gateway_name))
+            .cloned()
+            .unwrap_or_else(|| HostEntry::new(gateway_name));
+        let config = config.for_host(&member);
+        let sem = semaphore.clone();
+
+        tasks.spawn(async move {
+            let _permit = sem.acquire().await.ok();
+            let result = config
+                .through_breaker(
+                    &format!("gateway {}", gateway.name),
+                    gather_through(&member, &connection, &gateway, &config),
+                )
+                .await;
+            (member.name, gateway.name, result)
+        });
+    }
+
+    let mut facts = HashMap::new();
+    while let Some(joined) = config.shutdown.join_next(&mut tasks).await {
+        match joined {
+            Ok((host, gateway, Ok(host_facts))) => {
+                info!("Gathered facts from {} through {}", host, gateway);
+                facts.insert(host, host_facts);
+            }
+            Ok((host, _, Err(e @ FactsError::CircuitOpen(..)))) => {
+                debug!("Skipping {}: {}", host, e);
+            }
+            Ok((host, gateway, Err(e))) => {
+                warn!(
+                    "Failed to gather facts from {} through {}: {}",
+                    host, gateway, e
+                );
+            }
+            Err(e) => warn!("Gateway task panicked: {}", e),
+        }
+    }
+
+    Ok(facts)
+}
+
+async fn gather_through(
+    member: &HostEntry,
+    connection: &ConnectionType,
+    gateway: &HostEntry,
+    config: &FactsConfig,
+) -> Result<ArchitectureFacts> {
+    let command = relay_command(member, connection, config)?;
+    debug!(
+        "Relaying probe for {} through {}",
+        member.name, gateway.name
+    );
+
+    // Two connections are made before the probe runs
+    let budget = config.host_budget() + Duration::from_secs(config.connect_timeout);
+    let output = timeout(budget, ssh_facts::run_on_host(gateway, &command, config))
+        .await
+        .map_err(|_| FactsError::Timeout(member.name.clone()))??;
+    ssh_facts::parse_fact_output_with(&output, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(member.name.clone(), e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_command() {
+        let config = FactsConfig {
+            connect_timeout: 5,
+            ..Default::default()
+        };
+        let mut member = HostEntry::new("db1");
+        member
+            .vars
+            .insert("ansible_host".to_string(), serde_json::json!("10.1.0.5"));
+        member
+            .vars
+            .insert("ansible_user".to_string(), serde_json::json!("deploy"));
+        member
+            .vars
+            .insert(GATEWAY_VAR.to_string(), serde_json::json!("bastion-a"));
+        assert_eq!(gateway(&member), Some("bastion-a"));
+
+        let command = relay_command(&member, &ConnectionType::Ssh, &config).unwrap();
+        assert!(command.starts_with(
+            "ssh -o BatchMode=yes -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null \
+-o ConnectTimeout=5 deploy@10.1.0.5 '"
+        ));
+        assert!(command.contains("uname -m"));
+
+        let tofu = FactsConfig {
+            host_key_policy: HostKeyPolicy::Tofu,
+            ..config.clone()
+        };
+        let command = relay_command(&member, &ConnectionType::Ssh, &tofu).unwrap();
+        assert!(command.starts_with(
+            "ssh -o BatchMode=yes -o StrictHostKeyChecking=accept-new -o ConnectTimeout=5 "
+        ));
+
+        let mut container = HostEntry::new("app");
+        container
+            .vars
+            .insert("ansible_host".to_string(), serde_json::json!("101"));
+        let command = relay_command(&container, &ConnectionType::Pct, &config).unwrap();
+        assert!(command.starts_with("pct exec 101 -- sh -c '"));
+
+        assert!(relay_command(&container, &ConnectionType::Kubectl, &config).is_err());
+    }
+}
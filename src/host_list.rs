@@ -0,0 +1,54 @@
+//! Plain host list files, one host per line.
+//!
+//! `--failed-hosts-out` writes the hosts a run could not gather and
+//! `--only-hosts-from` reads such a list back, so a large run can be
+//! followed by quick runs over just the problem hosts. Blank lines and
+//! lines starting with `#` are ignored; names are matched like inventory
+//! names.
+
+use crate::error::Result;
+use crate::hostname::normalize_hostname;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Read the hosts listed in `path`.
+pub fn read_host_list(path: &Path) -> Result<BTreeSet<String>> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(parse_host_list(&content))
+}
+
+/// Write `hosts` to `path`, one per line.
+pub fn write_host_list<'a>(path: &Path, hosts: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    let content: String = hosts.into_iter().map(|host| format!("{host}\n")).collect();
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+fn parse_host_list(content: &str) -> BTreeSet<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(normalize_hostname)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_list_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("failed.txt");
+        let hosts = BTreeSet::from(["db1".to_string(), "web2".to_string()]);
+        write_host_list(&path, &hosts).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "db1\nweb2\n");
+        assert_eq!(read_host_list(&path).unwrap(), hosts);
+
+        assert_eq!(
+            parse_host_list("# from the 02:00 run\n\n  Web2.\ndb1\n"),
+            hosts
+        );
+    }
+}
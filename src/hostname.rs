@@ -0,0 +1,193 @@
+//! One spelling per host.
+//!
+//! DNS names are case-insensitive, may carry a trailing root dot and may be
+//! written in Unicode or as punycode, so `Web01.Example.COM.` and
+//! `web01.example.com` are the same machine. Spellings of one name in the
+//! inventory are merged into a single host before hosts are extracted, so
+//! each machine is connected to once. The merged host keeps a name the
+//! user wrote, which is what is emitted; only cache keys and the lookups
+//! that compare names use the lowercase ASCII form.
+
+use crate::types::{InventoryGroups, InventoryHosts, ParsedInventory};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+/// The canonical form of a host name: lowercase, without a trailing dot,
+/// with internationalized labels in punycode. A `user@` prefix is kept as
+/// written. Names that do not survive normalization are returned unchanged.
+pub fn normalize_hostname(name: &str) -> String {
+    let (user, host) = match name.rsplit_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, name),
+    };
+    let host = host.trim().trim_end_matches('.');
+    let host = if host.is_ascii() {
+        host.to_ascii_lowercase()
+    } else {
+        idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_lowercase())
+    };
+    if host.is_empty() {
+        return name.to_string();
+    }
+    match user {
+        Some(user) => format!("{user}@{host}"),
+        None => host,
+    }
+}
+
+/// Merge hosts whose names are spellings of one name. The merged host is
+/// named by the spelling that is already canonical, else the one that sorts
+/// first, and keeps the vars of each spelling; where they disagree, the
+/// spelling that sorts first wins. Group members are pointed at the merged
+/// host's name; other names are left as written.
+pub fn normalize_inventory_hostnames(inventory: &mut ParsedInventory) {
+    let mut names: BTreeMap<String, String> = BTreeMap::new();
+    let written: Vec<String> = match &inventory.hosts {
+        InventoryHosts::Simple(hosts) => hosts.keys().cloned().collect(),
+        InventoryHosts::Detailed(hosts) => hosts.keys().cloned().collect(),
+    };
+    let mut written: Vec<&String> = written.iter().collect();
+    written.sort();
+    for name in written {
+        let canonical = normalize_hostname(name);
+        match names.get(&canonical) {
+            Some(kept) if *kept == canonical => {}
+            Some(_) if *name != canonical => {}
+            _ => {
+                names.insert(canonical, name.clone());
+            }
+        }
+    }
+    let kept_name = |name: &str| names.get(&normalize_hostname(name)).cloned();
+
+    match &mut inventory.hosts {
+        InventoryHosts::Simple(hosts) => {
+            let sorted: BTreeMap<String, serde_json::Value> = hosts.drain().collect();
+            let mut merged: HashMap<String, serde_json::Value> = HashMap::new();
+            for (name, vars) in sorted {
+                let kept = kept_name(&name).unwrap_or(name);
+                match merged.entry(kept) {
+                    Entry::Vacant(slot) => {
+                        slot.insert(vars);
+                    }
+                    Entry::Occupied(mut slot) => merge_vars(slot.get_mut(), vars),
+                }
+            }
+            *hosts = merged;
+        }
+        InventoryHosts::Detailed(hosts) => {
+            let sorted: BTreeMap<String, _> = hosts.drain().collect();
+            for (name, mut entry) in sorted {
+                let kept = kept_name(&name).unwrap_or(name);
+                match hosts.entry(kept) {
+                    Entry::Occupied(mut slot) => {
+                        for (key, value) in entry.vars {
+                            slot.get_mut().vars.entry(key).or_insert(value);
+                        }
+                    }
+                    Entry::Vacant(slot) => {
+                        entry.name = slot.key().clone();
+                        slot.insert(entry);
+                    }
+                }
+            }
+        }
+    }
+
+    let normalize_list = |hosts: &mut Vec<String>| {
+        let mut seen = Vec::with_capacity(hosts.len());
+        for host in hosts.drain(..) {
+            let host = kept_name(&host).unwrap_or(host);
+            if !seen.contains(&host) {
+                seen.push(host);
+            }
+        }
+        *hosts = seen;
+    };
+    match &mut inventory.groups {
+        InventoryGroups::Simple(groups) => groups.values_mut().for_each(normalize_list),
+        InventoryGroups::Detailed(groups) => groups
+            .values_mut()
+            .for_each(|group| normalize_list(&mut group.hosts)),
+    }
+}
+
+fn merge_vars(existing: &mut serde_json::Value, vars: serde_json::Value) {
+    match (existing, vars) {
+        (existing @ serde_json::Value::Null, vars) => *existing = vars,
+        (serde_json::Value::Object(existing), serde_json::Value::Object(vars)) => {
+            for (key, value) in vars {
+                existing.entry(key).or_insert(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_hostname() {
+        assert_eq!(normalize_hostname("Web01.Example.COM"), "web01.example.com");
+        assert_eq!(
+            normalize_hostname("web01.example.com."),
+            "web01.example.com"
+        );
+        assert_eq!(normalize_hostname("Deploy@DB1"), "Deploy@db1");
+        assert_eq!(
+            normalize_hostname("bücher.example"),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(normalize_hostname("10.0.0.5"), "10.0.0.5");
+        assert_eq!(normalize_hostname("."), ".");
+    }
+
+    #[test]
+    fn test_normalize_inventory_hostnames() {
+        let mut inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {
+                "Web01.Example.COM": {"ansible_port": 2222},
+                "web01.example.com.": {"ansible_user": "deploy", "ansible_port": 22}
+            },
+            "groups": {"web": ["web01.example.com", "WEB01.example.com"]},
+            "variables": {}
+        }))
+        .unwrap();
+
+        normalize_inventory_hostnames(&mut inventory);
+
+        let InventoryHosts::Simple(hosts) = &inventory.hosts else {
+            panic!("expected simple hosts");
+        };
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(
+            hosts["Web01.Example.COM"],
+            serde_json::json!({"ansible_port": 2222, "ansible_user": "deploy"})
+        );
+        let InventoryGroups::Simple(groups) = &inventory.groups else {
+            panic!("expected simple groups");
+        };
+        assert_eq!(groups["web"], vec!["Web01.Example.COM".to_string()]);
+
+        // A spelling that is already canonical names the merged host
+        let mut inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {"DB1": {}, "db1": {}, "Cache.Example": {}},
+            "groups": {"data": ["DB1", "cache.example."]},
+            "variables": {}
+        }))
+        .unwrap();
+        normalize_inventory_hostnames(&mut inventory);
+        let InventoryHosts::Simple(hosts) = &inventory.hosts else {
+            panic!("expected simple hosts");
+        };
+        let mut names: Vec<&String> = hosts.keys().collect();
+        names.sort();
+        assert_eq!(names, ["Cache.Example", "db1"]);
+        let InventoryGroups::Simple(groups) = &inventory.groups else {
+            panic!("expected simple groups");
+        };
+        assert_eq!(groups["data"], ["db1", "Cache.Example"]);
+    }
+}
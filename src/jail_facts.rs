@@ -0,0 +1,116 @@
+//! Facts for `ansible_connection: jail` hosts.
+//!
+//! The probe is run with `jexec <jail> sh -c ...` on the FreeBSD host
+//! rustle-facts runs on, which needs root as it does for the
+//! `community.general.jail` connection plugin. The jail name comes from
+//! `ansible_host`, then the inventory name, and `ansible_user` picks the
+//! user inside the jail. Jails on another FreeBSD host are reached by
+//! setting that host as their gateway.
+
+use crate::config::FactsConfig;
+use crate::error::Result;
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::sanitize::{validate_container_name, validate_user};
+use crate::ssh_facts::fact_probe;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tokio::process::Command;
+
+/// A jail and the user to run the probe as, from the host's vars.
+#[derive(Debug, Clone, PartialEq)]
+struct JailTarget {
+    jail: String,
+    user: Option<String>,
+}
+
+impl JailTarget {
+    fn from_host(host: &HostEntry) -> Result<Self> {
+        let var = |key: &str| {
+            host.vars
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+
+        let jail = var("ansible_host")
+            .or_else(|| host.address.clone())
+            .unwrap_or_else(|| host.name.clone());
+        validate_container_name(&jail)?;
+        let user = host.user.clone().or_else(|| var("ansible_user"));
+        if let Some(user) = &user {
+            validate_user(user)?;
+        }
+        Ok(JailTarget { jail, user })
+    }
+
+    fn exec_args(&self, probe: String) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(user) = &self.user {
+            args.extend(["-U".to_string(), user.clone()]);
+        }
+        args.push(self.jail.clone());
+        args.extend(["sh", "-c"].map(str::to_string));
+        args.push(probe);
+        args
+    }
+}
+
+/// Gather minimal facts for hosts using jail connections
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(
+        exec_backend::gather_each(hosts, config, |host, config| async move {
+            gather_host_facts(&host, &config).await
+        })
+        .await,
+    )
+}
+
+async fn gather_host_facts(host: &HostEntry, config: &FactsConfig) -> Result<ArchitectureFacts> {
+    let target = JailTarget::from_host(host)?;
+    inject_faults(config, &host.name).await?;
+
+    let args = target.exec_args(fact_probe(config)?);
+    exec_backend::probe(
+        host,
+        "jail",
+        &target.jail,
+        Command::new("jexec"),
+        &args,
+        config,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_facts::build_fact_gathering_command;
+
+    #[test]
+    fn test_jail_target_from_host() {
+        let mut host = HostEntry::new("www");
+        let target = JailTarget::from_host(&host).unwrap();
+        assert_eq!(
+            &target.exec_args(build_fact_gathering_command())[..3],
+            ["www", "sh", "-c"]
+        );
+
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("www-jail"));
+        host.vars
+            .insert("ansible_user".to_string(), serde_json::json!("www"));
+        let target = JailTarget::from_host(&host).unwrap();
+        assert_eq!(
+            &target.exec_args(build_fact_gathering_command())[..5],
+            ["-U", "www", "www-jail", "sh", "-c"]
+        );
+
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("www; id"));
+        assert!(JailTarget::from_host(&host).is_err());
+    }
+}
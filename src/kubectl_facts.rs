@@ -0,0 +1,266 @@
+//! Facts for `ansible_connection: kubectl` hosts.
+//!
+//! In `api` mode the pod's node is read from the API server with
+//! `kubectl get` and its `status.nodeInfo` (architecture, operating system,
+//! OS image) is used directly, so no process is started inside the pod. The probe is only exec'd when the API
+//! does not answer, for example when RBAC forbids reading nodes. `exec` mode
+//! always runs the probe in the pod, which reports the container image's
+//! distribution rather than the node's.
+
+use crate::config::{FactsConfig, KubernetesFactsMode};
+use crate::error::{FactsError, Result};
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::sanitize::{validate_container_name, validate_path};
+use crate::ssh_facts::fact_probe;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tokio::process::Command;
+use tracing::warn;
+
+/// Where a `kubectl` host lives, from the `ansible_kubectl_*` vars.
+#[derive(Debug, Clone, PartialEq)]
+struct PodTarget {
+    pod: String,
+    namespace: Option<String>,
+    container: Option<String>,
+    context: Option<String>,
+    kubeconfig: Option<String>,
+}
+
+impl PodTarget {
+    fn from_host(host: &HostEntry) -> Result<Self> {
+        let var = |key: &str| {
+            host.vars
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+
+        let pod = var("ansible_kubectl_pod")
+            .or_else(|| var("ansible_host"))
+            .or_else(|| host.address.clone())
+            .unwrap_or_else(|| host.name.clone());
+        validate_container_name(&pod)?;
+
+        let target = PodTarget {
+            pod,
+            namespace: var("ansible_kubectl_namespace"),
+            container: var("ansible_kubectl_container"),
+            context: var("ansible_kubectl_context"),
+            kubeconfig: var("ansible_kubectl_kubeconfig"),
+        };
+        for name in [&target.namespace, &target.container, &target.context]
+            .into_iter()
+            .flatten()
+        {
+            validate_container_name(name)?;
+        }
+        if let Some(kubeconfig) = &target.kubeconfig {
+            validate_path(kubeconfig)?;
+        }
+        Ok(target)
+    }
+
+    /// `kubectl` with the context, kubeconfig and namespace flags applied.
+    fn kubectl(&self) -> Command {
+        let mut cmd = Command::new("kubectl");
+        if let Some(kubeconfig) = &self.kubeconfig {
+            cmd.arg(format!("--kubeconfig={kubeconfig}"));
+        }
+        if let Some(context) = &self.context {
+            cmd.arg(format!("--context={context}"));
+        }
+        if let Some(namespace) = &self.namespace {
+            cmd.arg(format!("--namespace={namespace}"));
+        }
+        cmd
+    }
+}
+
+/// Gather minimal facts for hosts using kubectl connections
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(
+        exec_backend::gather_each(hosts, config, |host, config| async move {
+            gather_host_facts(&host, &config).await
+        })
+        .await,
+    )
+}
+
+async fn gather_host_facts(host: &HostEntry, config: &FactsConfig) -> Result<ArchitectureFacts> {
+    let target = PodTarget::from_host(host)?;
+    inject_faults(config, &host.name).await?;
+
+    if config.kubernetes_facts == KubernetesFactsMode::Api {
+        match node_facts(host, &target, config).await {
+            Ok(facts) => return Ok(facts),
+            Err(e) => warn!(
+                "Could not read node info for pod {}, falling back to exec: {}",
+                target.pod, e
+            ),
+        }
+    }
+
+    let args = exec_args(&target, fact_probe(config)?);
+    exec_backend::probe(
+        host,
+        "kubectl",
+        &target.pod,
+        target.kubectl(),
+        &args,
+        config,
+    )
+    .await
+}
+
+fn exec_args(target: &PodTarget, probe: String) -> Vec<String> {
+    let mut args = vec!["exec".to_string(), target.pod.clone()];
+    if let Some(container) = &target.container {
+        args.push(format!("--container={container}"));
+    }
+    args.extend(["--", "sh", "-c"].map(str::to_string));
+    args.push(probe);
+    args
+}
+
+/// Facts from the `nodeInfo` of the node the pod is scheduled on.
+async fn node_facts(
+    host: &HostEntry,
+    target: &PodTarget,
+    config: &FactsConfig,
+) -> Result<ArchitectureFacts> {
+    let pod = run_kubectl(
+        host,
+        target,
+        config,
+        &["get", "pod", &target.pod, "-o", "jsonpath={.spec.nodeName}"].map(str::to_string),
+    )
+    .await?;
+    let node = pod.trim();
+    if node.is_empty() {
+        return Err(FactsError::ParseError(
+            target.pod.clone(),
+            "pod is not scheduled on a node".to_string(),
+        ));
+    }
+    validate_container_name(node)?;
+
+    let node_json = run_kubectl(
+        host,
+        target,
+        config,
+        &["get", "node", node, "-o", "json"].map(str::to_string),
+    )
+    .await?;
+    let node: serde_json::Value = serde_json::from_str(&node_json)?;
+    parse_node_info(&node["status"]["nodeInfo"])
+        .ok_or_else(|| FactsError::ParseError(target.pod.clone(), "node has no nodeInfo".into()))
+}
+
+fn parse_node_info(info: &serde_json::Value) -> Option<ArchitectureFacts> {
+    let architecture = info["architecture"].as_str()?;
+    let system = match info["operatingSystem"].as_str()? {
+        "linux" => "Linux".to_string(),
+        "windows" => "Windows".to_string(),
+        other => other.to_string(),
+    };
+    let distribution = info["osImage"].as_str().map(distribution_from_os_image);
+    let os_family = match system.as_str() {
+        "Windows" => "windows".to_string(),
+        _ => crate::docker_facts::get_os_family(&system, &distribution),
+    };
+
+    Some(ArchitectureFacts {
+        ansible_architecture: ArchitectureFacts::normalize_architecture(architecture),
+        ansible_system: system,
+        ansible_os_family: os_family,
+        ansible_distribution: distribution,
+        rustle_libc: None,
+        extra: serde_json::Map::new(),
+    })
+}
+
+/// Map an `osImage` such as `Ubuntu 22.04.3 LTS` to a distribution ID.
+fn distribution_from_os_image(image: &str) -> String {
+    let image = image.to_lowercase();
+    for (prefix, id) in [
+        ("red hat", "rhel"),
+        ("amazon linux", "amzn"),
+        ("container-optimized os", "cos"),
+    ] {
+        if image.starts_with(prefix) {
+            return id.to_string();
+        }
+    }
+    image
+        .split_whitespace()
+        .next()
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+async fn run_kubectl(
+    host: &HostEntry,
+    target: &PodTarget,
+    config: &FactsConfig,
+    args: &[String],
+) -> Result<String> {
+    exec_backend::run(host, "kubectl", &target.pod, target.kubectl(), args, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_facts::build_fact_gathering_command;
+
+    #[test]
+    fn test_parse_node_info() {
+        let info = serde_json::json!({
+            "architecture": "arm64",
+            "operatingSystem": "linux",
+            "osImage": "Ubuntu 22.04.3 LTS",
+            "kernelVersion": "5.15.0-1049-aws"
+        });
+        let facts = parse_node_info(&info).unwrap();
+        assert_eq!(facts.ansible_architecture, "aarch64");
+        assert_eq!(facts.ansible_system, "Linux");
+        assert_eq!(facts.ansible_distribution, Some("ubuntu".to_string()));
+        assert_eq!(facts.ansible_os_family, "debian");
+
+        assert_eq!(
+            distribution_from_os_image("Amazon Linux 2023.4.20240528"),
+            "amzn"
+        );
+        assert!(parse_node_info(&serde_json::json!({})).is_none());
+    }
+
+    #[test]
+    fn test_pod_target_from_host() {
+        let mut host = HostEntry::new("api");
+        host.vars.insert(
+            "ansible_kubectl_pod".to_string(),
+            serde_json::json!("api-7d9f"),
+        );
+        host.vars.insert(
+            "ansible_kubectl_namespace".to_string(),
+            serde_json::json!("prod"),
+        );
+        let target = PodTarget::from_host(&host).unwrap();
+        assert_eq!(target.pod, "api-7d9f");
+        assert_eq!(target.namespace, Some("prod".to_string()));
+
+        let args = exec_args(&target, build_fact_gathering_command());
+        assert_eq!(&args[..2], ["exec", "api-7d9f"]);
+        assert_eq!(&args[2..5], ["--", "sh", "-c"]);
+
+        host.vars.insert(
+            "ansible_kubectl_namespace".to_string(),
+            serde_json::json!("prod; id"),
+        );
+        assert!(PodTarget::from_host(&host).is_err());
+    }
+}
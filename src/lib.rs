@@ -1,16 +1,68 @@
+pub mod analysis;
+pub mod arch_table;
+pub mod assertions;
+pub mod breaker;
 pub mod cache;
+pub mod capabilities;
+pub mod clock;
+pub mod compression;
 pub mod config;
+pub mod dns;
+#[cfg(feature = "bollard")]
+pub mod docker_api;
 pub mod docker_facts;
 pub mod enrichment;
 pub mod error;
+pub mod exec_backend;
+pub mod fact_plugins;
+pub mod faults;
+pub mod gateway_facts;
+pub mod host_list;
+pub mod hostname;
+pub mod jail_facts;
+pub mod kubectl_facts;
+pub mod localhost;
+pub mod lxd_facts;
+#[cfg(feature = "mock")]
+pub mod mock_facts;
+#[cfg(feature = "native-ssh")]
+pub mod native_ssh;
+pub mod network_facts;
+pub mod overrides;
+pub mod pct_facts;
+pub mod podman_facts;
+pub mod privilege;
+pub mod progress;
+pub mod qemu_facts;
+pub mod ramp;
+pub mod request_overrides;
+pub mod sanitize;
+pub mod secrets;
+pub mod select;
+pub mod serial_facts;
+pub mod session;
+pub mod shared_cache;
+pub mod shutdown;
+pub mod ssh_client;
+pub mod ssh_config;
 pub mod ssh_facts;
+pub mod templating;
+#[cfg(feature = "testsupport")]
+pub mod testsupport;
 pub mod types;
+pub mod verify;
+pub mod windows_facts;
 
-pub use config::{CliArgs, FactsConfig};
-pub use enrichment::enrich_with_facts;
+pub use config::{CliArgs, FactsConfig, HostKeyPolicy};
+pub use enrichment::{enrich_with_facts, normalize_inventory, parse_playbook};
 pub use error::{FactsError, Result};
-pub use ssh_facts::{gather_minimal_facts, parse_fact_output};
+pub use secrets::{Credentials, Secret};
+pub use ssh_facts::{
+    gather_minimal_facts, parse_fact_bytes, parse_fact_output, parse_fact_output_with,
+    parse_fact_pairs,
+};
 pub use types::{
-    ArchitectureFacts, CachedFact, EnrichedInventory, EnrichedPlaybook, EnrichmentReport,
-    FactCache, ParsedInventory, ParsedPlay, ParsedPlaybook, PlaybookMetadata, Task,
+    ArchitectureFacts, CachedFact, ConnectionType, EnrichedInventory, EnrichedPlaybook,
+    EnrichmentReport, FactCache, HostEntry, ParsedInventory, ParsedPlay, ParsedPlaybook,
+    PlaybookMetadata, Task, UnreachableReason,
 };
@@ -0,0 +1,271 @@
+//! Recognising inventory hosts that are this machine.
+//!
+//! Besides `localhost`, a host is local when its name or `ansible_host` is a
+//! loopback address (`127.0.0.0/8`, `::1`), this machine's hostname, or an
+//! address of one of its interfaces. Names other than the machine's own are
+//! not resolved, so classifying an inventory never waits on DNS.
+//!
+//! Local Linux facts are read the way the remote probe reads them: the OS
+//! family and distribution from `/etc/os-release`, plus the C library from
+//! the dynamic loaders installed under `/lib`. The service manager is told
+//! from `/run/systemd/system`, `/run/openrc` or the name of PID 1, and the
+//! kernel release and machine ID from `/proc` and `/etc/machine-id`. The
+//! cgroup version comes from `/sys/fs/cgroup`, and container markers such
+//! as `/.dockerenv` or a runtime in `/proc/1/cgroup` tell a container.
+
+use crate::ssh_facts::{parse_fact_pairs, service_manager};
+use crate::types::ArchitectureFacts;
+use std::net::{IpAddr, UdpSocket};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The `ansible_service_mgr` of this Linux machine, found as the probe
+/// finds it on remote hosts.
+pub fn linux_service_manager() -> Option<&'static str> {
+    if Path::new("/run/systemd/system").is_dir() {
+        return Some("systemd");
+    }
+    if Path::new("/run/openrc").is_dir() {
+        return Some("openrc");
+    }
+    service_manager(&std::fs::read_to_string("/proc/1/comm").ok()?)
+}
+
+/// The kernel release and machine ID of this Linux machine.
+pub fn linux_identity() -> serde_json::Map<String, serde_json::Value> {
+    [
+        ("ansible_kernel", "/proc/sys/kernel/osrelease"),
+        ("ansible_machine_id", "/etc/machine-id"),
+    ]
+    .into_iter()
+    .filter_map(|(fact, path)| {
+        let value = std::fs::read_to_string(path).ok()?.trim().to_string();
+        (!value.is_empty()).then(|| (fact.to_string(), value.into()))
+    })
+    .collect()
+}
+
+/// The cgroup version of this Linux machine and whether it is a container,
+/// found as the probe finds them.
+pub fn linux_isolation() -> serde_json::Map<String, serde_json::Value> {
+    let mut facts = serde_json::Map::new();
+    let cgroup = Path::new("/sys/fs/cgroup");
+    if cgroup.join("cgroup.controllers").is_file() {
+        facts.insert("rustle_cgroup_version".to_string(), 2.into());
+    } else if cgroup.is_dir() {
+        facts.insert("rustle_cgroup_version".to_string(), 1.into());
+    }
+
+    let in_container = [
+        "/.dockerenv",
+        "/run/.containerenv",
+        "/run/systemd/container",
+    ]
+    .iter()
+    .any(|marker| Path::new(marker).is_file())
+        || std::fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroups| {
+            ["docker", "kubepods", "containerd", "libpod", "lxc"]
+                .iter()
+                .any(|runtime| cgroups.contains(runtime))
+        });
+    facts.insert("rustle_in_container".to_string(), in_container.into());
+    facts
+}
+
+/// The local Linux distribution and C library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinuxRelease {
+    pub os_family: String,
+    pub distribution: Option<String>,
+    pub libc: Option<String>,
+}
+
+impl LinuxRelease {
+    pub fn detect() -> Self {
+        let os_release = ["/etc/os-release", "/usr/lib/os-release"]
+            .iter()
+            .find_map(|path| std::fs::read_to_string(path).ok());
+        let loaders: Vec<String> = ["/lib", "/lib64", "/usr/lib"]
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|name| name.starts_with("ld-"))
+            .collect();
+
+        match os_release {
+            Some(content) => Self::from_os_release(&content, &loaders),
+            // As the probe reports hosts without os-release
+            None if Path::new("/etc/alpine-release").exists() => Self {
+                os_family: "alpine".to_string(),
+                distribution: Some("alpine".to_string()),
+                libc: Some("musl".to_string()),
+            },
+            None if Path::new("/etc/openwrt_release").exists() => Self {
+                os_family: "openwrt".to_string(),
+                distribution: Some("openwrt".to_string()),
+                libc: libc_from_loaders(&loaders),
+            },
+            None if Path::new("/etc/redhat-release").exists() => Self {
+                os_family: "rhel".to_string(),
+                distribution: Some("rhel".to_string()),
+                libc: libc_from_loaders(&loaders),
+            },
+            None => Self {
+                os_family: "unknown".to_string(),
+                distribution: None,
+                libc: libc_from_loaders(&loaders),
+            },
+        }
+    }
+
+    /// Facts from `os-release` content and the names of the dynamic loaders
+    /// found, e.g. `ld-musl-x86_64.so.1`.
+    pub fn from_os_release(content: &str, loaders: &[String]) -> Self {
+        let pairs = parse_fact_pairs(content);
+        let id = pairs.get("ID").cloned();
+        let id_like = pairs.get("ID_LIKE");
+        let os_family =
+            ArchitectureFacts::normalize_os_family(id.as_deref(), id_like.map(String::as_str))
+                .map(str::to_string)
+                .or_else(|| id_like.or(id.as_ref()).cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+
+        // Alpine ships glibc loaders with gcompat, so its ID decides
+        let libc = if id.as_deref() == Some("alpine") {
+            Some("musl".to_string())
+        } else {
+            libc_from_loaders(loaders)
+        };
+
+        Self {
+            os_family,
+            distribution: id,
+            libc,
+        }
+    }
+}
+
+/// glibc when its loader is present (a musl loader may sit beside it from
+/// `musl-tools`), else musl when only the musl loader is.
+fn libc_from_loaders(loaders: &[String]) -> Option<String> {
+    if loaders.iter().any(|name| name.starts_with("ld-linux")) {
+        Some("gnu".to_string())
+    } else if loaders.iter().any(|name| name.starts_with("ld-musl")) {
+        Some("musl".to_string())
+    } else {
+        None
+    }
+}
+
+/// `localhost` and loopback addresses.
+pub fn is_loopback(target: &str) -> bool {
+    let target = target.trim().trim_start_matches('[').trim_end_matches(']');
+    matches!(
+        target.to_lowercase().as_str(),
+        "localhost" | "localhost.localdomain" | "localhost6" | "ip6-localhost"
+    ) || target.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Whether `target` (a host name or address) is this machine.
+pub fn is_local_target(target: &str) -> bool {
+    let target = target.trim();
+    if target.is_empty() {
+        return false;
+    }
+    if is_loopback(target) || is_own_hostname(target) {
+        return true;
+    }
+    // Binding only succeeds for addresses assigned to a local interface
+    match target.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() && !ip.is_multicast() => UdpSocket::bind((ip, 0)).is_ok(),
+        _ => false,
+    }
+}
+
+fn is_own_hostname(target: &str) -> bool {
+    let Some(hostname) = local_hostname() else {
+        return false;
+    };
+    let short = |name: &str| name.split('.').next().unwrap_or(name).to_lowercase();
+    let target = target.to_lowercase();
+    // Match the short name only when one side is unqualified
+    target == hostname
+        || ((!target.contains('.') || !hostname.contains('.')) && short(&target) == short(hostname))
+}
+
+/// This machine's hostname, lowercased.
+pub fn local_hostname() -> Option<&'static str> {
+    static HOSTNAME: OnceLock<Option<String>> = OnceLock::new();
+    HOSTNAME
+        .get_or_init(|| {
+            let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .ok()
+                .or_else(|| {
+                    let output = std::process::Command::new("hostname").output().ok()?;
+                    output
+                        .status
+                        .success()
+                        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+                })?;
+            let hostname = hostname.trim().to_lowercase();
+            (!hostname.is_empty()).then_some(hostname)
+        })
+        .as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("localhost"));
+        assert!(is_loopback("127.0.0.1"));
+        assert!(is_loopback("127.1.2.3"));
+        assert!(is_loopback("::1"));
+        assert!(is_loopback("[::1]"));
+        assert!(!is_loopback("128.0.0.1"));
+        assert!(!is_loopback("web1.example.com"));
+    }
+
+    #[test]
+    fn test_is_local_target() {
+        assert!(is_local_target("127.0.0.2"));
+        assert!(!is_local_target("192.0.2.10"));
+        assert!(!is_local_target("0.0.0.0"));
+        assert!(!is_local_target(""));
+        if let Some(hostname) = local_hostname() {
+            assert!(is_local_target(hostname));
+            assert!(is_local_target(&hostname.to_uppercase()));
+        }
+    }
+
+    #[test]
+    fn test_linux_release_from_os_release() {
+        let loaders = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let rocky = "NAME=\"Rocky Linux\"\nID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\n";
+        let release = LinuxRelease::from_os_release(rocky, &loaders(&["ld-linux-x86-64.so.2"]));
+        assert_eq!(release.os_family, "redhat");
+        assert_eq!(release.distribution, Some("rocky".to_string()));
+        assert_eq!(release.libc, Some("gnu".to_string()));
+
+        let alpine = "NAME=\"Alpine Linux\"\nID=alpine\nVERSION_ID=3.19.1\n";
+        let release = LinuxRelease::from_os_release(
+            alpine,
+            &loaders(&["ld-musl-x86_64.so.1", "ld-linux-x86-64.so.2"]),
+        );
+        assert_eq!(release.os_family, "alpine");
+        assert_eq!(release.libc, Some("musl".to_string()));
+
+        let void = "NAME=\"Void\"\nID=\"void\"\n";
+        let release = LinuxRelease::from_os_release(void, &loaders(&["ld-musl-aarch64.so.1"]));
+        assert_eq!(release.libc, Some("musl".to_string()));
+
+        let release = LinuxRelease::from_os_release("", &[]);
+        assert_eq!(release.os_family, "unknown");
+        assert_eq!(release.distribution, None);
+        assert_eq!(release.libc, None);
+    }
+}
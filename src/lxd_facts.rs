@@ -0,0 +1,146 @@
+//! Facts for `ansible_connection: lxd` (and `lxc`) hosts.
+//!
+//! The probe is run with `lxc exec <container> -- sh -c ...` through the LXD
+//! client, so containers and VMs on remote LXD servers work as long as the
+//! remote is configured locally. The container name comes from
+//! `ansible_lxd_host`, then `ansible_host`, then the inventory name, as in
+//! the `community.general.lxd` connection plugin; `ansible_lxd_remote` and
+//! `ansible_lxd_project` select where it lives.
+
+use crate::config::FactsConfig;
+use crate::error::Result;
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::sanitize::validate_container_name;
+use crate::ssh_facts::fact_probe;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tokio::process::Command;
+
+/// Where an LXD instance lives, from the `ansible_lxd_*` vars.
+#[derive(Debug, Clone, PartialEq)]
+struct LxdTarget {
+    instance: String,
+    remote: Option<String>,
+    project: Option<String>,
+}
+
+impl LxdTarget {
+    fn from_host(host: &HostEntry) -> Result<Self> {
+        let var = |key: &str| {
+            host.vars
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+
+        let instance = var("ansible_lxd_host")
+            .or_else(|| var("ansible_host"))
+            .or_else(|| host.address.clone())
+            .unwrap_or_else(|| host.name.clone());
+        let target = LxdTarget {
+            instance,
+            // `local` is the client's default remote
+            remote: var("ansible_lxd_remote").filter(|remote| remote != "local"),
+            project: var("ansible_lxd_project"),
+        };
+        for name in [
+            Some(&target.instance),
+            target.remote.as_ref(),
+            target.project.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            validate_container_name(name)?;
+        }
+        Ok(target)
+    }
+
+    /// `remote:instance`, or the instance on the default remote.
+    fn qualified_name(&self) -> String {
+        match &self.remote {
+            Some(remote) => format!("{remote}:{}", self.instance),
+            None => self.instance.clone(),
+        }
+    }
+
+    fn exec_args(&self, probe: String) -> Vec<String> {
+        let mut args = vec!["exec".to_string()];
+        if let Some(project) = &self.project {
+            args.push(format!("--project={project}"));
+        }
+        args.push(self.qualified_name());
+        args.extend(["--", "sh", "-c"].map(str::to_string));
+        args.push(probe);
+        args
+    }
+}
+
+/// Gather minimal facts for hosts using LXD connections
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(
+        exec_backend::gather_each(hosts, config, |host, config| async move {
+            gather_host_facts(&host, &config).await
+        })
+        .await,
+    )
+}
+
+async fn gather_host_facts(host: &HostEntry, config: &FactsConfig) -> Result<ArchitectureFacts> {
+    let target = LxdTarget::from_host(host)?;
+    inject_faults(config, &host.name).await?;
+
+    let args = target.exec_args(fact_probe(config)?);
+    exec_backend::probe(
+        host,
+        "lxd",
+        &target.qualified_name(),
+        Command::new("lxc"),
+        &args,
+        config,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh_facts::build_fact_gathering_command;
+
+    #[test]
+    fn test_lxd_target_from_host() {
+        let mut host = HostEntry::new("web1");
+        let target = LxdTarget::from_host(&host).unwrap();
+        assert_eq!(target.qualified_name(), "web1");
+
+        host.vars.insert(
+            "ansible_lxd_remote".to_string(),
+            serde_json::json!("cluster-a"),
+        );
+        host.vars
+            .insert("ansible_lxd_project".to_string(), serde_json::json!("prod"));
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("web1-c"));
+        let target = LxdTarget::from_host(&host).unwrap();
+        let args = target.exec_args(build_fact_gathering_command());
+        assert_eq!(&args[..3], ["exec", "--project=prod", "cluster-a:web1-c"]);
+        assert_eq!(&args[3..6], ["--", "sh", "-c"]);
+
+        host.vars
+            .insert("ansible_lxd_remote".to_string(), serde_json::json!("local"));
+        assert_eq!(
+            LxdTarget::from_host(&host).unwrap().qualified_name(),
+            "web1-c"
+        );
+
+        host.vars.insert(
+            "ansible_lxd_host".to_string(),
+            serde_json::json!("web1; id"),
+        );
+        assert!(LxdTarget::from_host(&host).is_err());
+    }
+}
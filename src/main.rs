@@ -1,9 +1,21 @@
 use clap::Parser;
-use rustle_facts::{enrich_with_facts, CliArgs, EnrichmentReport, FactsConfig};
+use rustle_facts::cache::{export_cache, load_cache, parse_exported_cache, save_cache};
+use rustle_facts::capabilities::capabilities;
+use rustle_facts::compression;
+use rustle_facts::config::{CacheCommand, Command};
+use rustle_facts::enrichment::inventory_diagnostics;
+use rustle_facts::hostname::normalize_hostname;
+use rustle_facts::shutdown::INTERRUPTED_EXIT_CODE;
+use rustle_facts::ssh_facts::forget_host_key;
+use rustle_facts::{
+    enrich_with_facts, normalize_inventory, parse_playbook, CliArgs, EnrichmentReport, FactCache,
+    FactsConfig, FactsError,
+};
 use std::fs::File;
-use std::io::{self, BufReader, IsTerminal};
+use std::io::{self, BufReader, IsTerminal, Read};
+use std::path::PathBuf;
 use std::process;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
@@ -12,6 +24,27 @@ async fn main() {
 
     init_logging(args.debug);
 
+    if args.capabilities {
+        match serde_json::to_string_pretty(&capabilities()) {
+            Ok(document) => println!("{document}"),
+            Err(e) => {
+                error!("Failed to describe capabilities: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(command) = args.command.clone() {
+        let config: FactsConfig = args.into();
+        let config = config.merge_with_env();
+        if let Err(e) = run_command(command, &config).await {
+            error!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     if args.input.is_none() && io::stdin().is_terminal() {
         error!("No input provided. This tool expects parsed JSON from stdin or a file.");
         eprintln!("\nUsage: ");
@@ -24,14 +57,55 @@ async fn main() {
 
     let input_file = args.input.clone();
     let config: FactsConfig = args.into();
-    let config = config.merge_with_env();
+    config.shutdown.listen_for_signals();
+    let config = match config.merge_with_env().resolve_credentials() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to resolve credentials: {}", e);
+            process::exit(1);
+        }
+    };
 
     match run_enrichment(config, input_file).await {
         Ok(report) => {
             info!(
-                "Enrichment complete: {} hosts processed, {} facts gathered, {} cache hits in {:?}",
-                report.total_hosts, report.facts_gathered, report.cache_hits, report.duration
+                "Enrichment complete: {} hosts processed, {} facts gathered, {} declared, {} cache hits in {:?}",
+                report.total_hosts,
+                report.facts_gathered,
+                report.facts_declared,
+                report.cache_hits,
+                report.duration
             );
+            if !report.host_key_changes.is_empty() {
+                warn!(
+                    "Host keys changed for: {}",
+                    report.host_key_changes.join(", ")
+                );
+            }
+            if !report.machine_id_changes.is_empty() {
+                warn!(
+                    "Machine IDs changed for: {}",
+                    report.machine_id_changes.join(", ")
+                );
+            }
+            for (backend, health) in &report.backend_health {
+                if let Some(tripped_by) = &health.tripped_by {
+                    error!(
+                        "Gave up on {} after {} consecutive failures; {} hosts were not tried ({})",
+                        backend, health.consecutive_failures, health.skipped, tripped_by
+                    );
+                }
+            }
+            for (alias, primary) in &report.shared_facts {
+                info!(
+                    "{} shares facts with {} (same ansible_host)",
+                    alias, primary
+                );
+            }
+        }
+        Err(FactsError::Interrupted) => {
+            error!("Interrupted; facts gathered so far were cached");
+            process::exit(INTERRUPTED_EXIT_CODE);
         }
         Err(e) => {
             error!("Failed to enrich playbook: {}", e);
@@ -43,12 +117,12 @@ async fn main() {
 async fn run_enrichment(
     config: FactsConfig,
     input_file: Option<std::path::PathBuf>,
-) -> Result<EnrichmentReport, rustle_facts::FactsError> {
+) -> Result<EnrichmentReport, FactsError> {
     let stdout = io::stdout();
 
     match input_file {
         Some(file_path) => {
-            let file = File::open(&file_path).map_err(rustle_facts::FactsError::Io)?;
+            let file = File::open(&file_path).map_err(FactsError::Io)?;
             let reader = BufReader::new(file);
             enrich_with_facts(reader, stdout.lock(), &config).await
         }
@@ -59,6 +133,142 @@ async fn run_enrichment(
     }
 }
 
+async fn run_command(command: Command, config: &FactsConfig) -> Result<(), FactsError> {
+    match command {
+        Command::Cache(CacheCommand::ForgetKey { host, port }) => {
+            if forget_host_key(&config.known_hosts_file, &host, port).await? {
+                println!(
+                    "Removed host key for {} from {:?}",
+                    host, config.known_hosts_file
+                );
+            } else {
+                println!("No host key recorded for {host}");
+            }
+        }
+        Command::Cache(CacheCommand::Show { host: None }) => {
+            let cache = load_cache(&config.cache_file)?;
+            let mut hosts: Vec<&String> = cache.facts.keys().collect();
+            hosts.sort();
+            let entries: Vec<serde_json::Value> = hosts
+                .into_iter()
+                .filter_map(|host| cache.describe(host))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        }
+        Command::Cache(CacheCommand::Show { host: Some(host) }) => {
+            let cache = load_cache(&config.cache_file)?;
+            match cache.describe(&host) {
+                Some(entry) => println!("{}", serde_json::to_string_pretty(&entry)?),
+                None => {
+                    return Err(FactsError::CacheError(format!(
+                        "No cached facts for {host} in {:?}",
+                        config.cache_file
+                    )))
+                }
+            }
+        }
+        Command::Cache(CacheCommand::Prune) => {
+            let mut cache = load_cache(&config.cache_file)?;
+            let removed = cache.cleanup_stale(config.cache_ttl);
+            save_cache(&config.cache_file, &cache)?;
+            println!(
+                "Pruned {} expired entries from {:?}; {} remain",
+                removed,
+                config.cache_file,
+                cache.facts.len()
+            );
+        }
+        Command::Cache(CacheCommand::Clear) => {
+            let cache = load_cache(&config.cache_file)?;
+            save_cache(&config.cache_file, &FactCache::new())?;
+            println!(
+                "Removed {} entries from {:?}",
+                cache.facts.len(),
+                config.cache_file
+            );
+        }
+        Command::Cache(CacheCommand::Rm { host }) => {
+            let mut cache = load_cache(&config.cache_file)?;
+            let removed = cache.invalidate(&host);
+            if removed {
+                save_cache(&config.cache_file, &cache)?;
+            }
+            println!("{}", removal_message(&host, removed));
+        }
+        Command::Cache(CacheCommand::Export {
+            output,
+            format,
+            compress,
+        }) => {
+            let cache = load_cache(&config.cache_file)?;
+            let exported = export_cache(&cache, format)?;
+            match output {
+                Some(path) => {
+                    compression::write_compressed(File::create(&path)?, &exported, compress)?;
+                    eprintln!("Exported {} entries to {:?}", cache.facts.len(), path);
+                }
+                None => compression::write_compressed(io::stdout().lock(), &exported, compress)?,
+            }
+        }
+        Command::Cache(CacheCommand::Import { input, replace }) => {
+            let mut buffer = Vec::new();
+            match input {
+                Some(path) => File::open(path)?.read_to_end(&mut buffer)?,
+                None => io::stdin().lock().read_to_end(&mut buffer)?,
+            };
+            let exported = parse_exported_cache(&compression::decompress(buffer)?)?;
+
+            let mut cache = if replace {
+                FactCache::new()
+            } else {
+                load_cache(&config.cache_file)?
+            };
+            let imported = cache.import(exported);
+            save_cache(&config.cache_file, &cache)?;
+            println!("Imported {} entries into {:?}", imported, config.cache_file);
+        }
+        Command::Validate { input } => validate(input)?,
+    }
+
+    Ok(())
+}
+
+/// What `cache rm` says, naming the cache key when `host` was normalized
+/// to find it.
+fn removal_message(host: &str, removed: bool) -> String {
+    let key = normalize_hostname(host);
+    match (removed, key == host) {
+        (true, true) => format!("Removed cached facts for {host}"),
+        (true, false) => format!("Removed cached facts for {host} (cached as {key})"),
+        (false, true) => format!("No cached facts for {host}"),
+        (false, false) => format!("No cached facts for {host} (looked up as {key})"),
+    }
+}
+
+/// Print the inventory's diagnostics; any make the command fail.
+fn validate(input: Option<PathBuf>) -> Result<(), FactsError> {
+    let mut buffer = Vec::new();
+    match input {
+        Some(path) => File::open(path)?.read_to_end(&mut buffer)?,
+        None => io::stdin().lock().read_to_end(&mut buffer)?,
+    };
+    let parsed = parse_playbook(&compression::decompress(buffer)?)?;
+    let hosts = normalize_inventory(&parsed.inventory)?;
+
+    let diagnostics = inventory_diagnostics(&parsed.inventory);
+    if diagnostics.is_empty() {
+        println!("Inventory OK: {} hosts", hosts.len());
+        return Ok(());
+    }
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+    Err(FactsError::InvalidInventory(format!(
+        "{} problems found",
+        diagnostics.len()
+    )))
+}
+
 fn init_logging(debug: bool) {
     let filter = if debug {
         EnvFilter::new("debug")
@@ -72,3 +282,154 @@ fn init_logging(debug: bool) {
         .with_writer(io::stderr)
         .init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustle_facts::config::{ExportFormat, OutputCompression};
+    use rustle_facts::ArchitectureFacts;
+
+    fn config_with_cache(path: PathBuf) -> FactsConfig {
+        FactsConfig {
+            cache_file: path,
+            parsed_ssh_config: Some(Default::default()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = config_with_cache(dir.path().join("source.json"));
+        let mut cache = FactCache::new();
+        let mut facts = ArchitectureFacts::fallback();
+        facts
+            .extra
+            .insert("rustle_page_size".to_string(), 16384.into());
+        cache.update("web1".to_string(), facts);
+        cache.update("db1".to_string(), ArchitectureFacts::fallback());
+        save_cache(&source.cache_file, &cache).unwrap();
+
+        for (format, compress) in [
+            (ExportFormat::Json, OutputCompression::None),
+            (ExportFormat::Msgpack, OutputCompression::None),
+            (ExportFormat::Msgpack, OutputCompression::Zstd),
+        ] {
+            let exported = dir.path().join("exported");
+            run_command(
+                Command::Cache(CacheCommand::Export {
+                    output: Some(exported.clone()),
+                    format,
+                    compress,
+                }),
+                &source,
+            )
+            .await
+            .unwrap();
+
+            let target = config_with_cache(dir.path().join("target.json"));
+            run_command(
+                Command::Cache(CacheCommand::Import {
+                    input: Some(exported),
+                    replace: true,
+                }),
+                &target,
+            )
+            .await
+            .unwrap();
+
+            let imported = load_cache(&target.cache_file).unwrap();
+            assert_eq!(imported.facts.len(), 2, "{format:?}");
+            assert_eq!(
+                imported.facts["web1"].facts, cache.facts["web1"].facts,
+                "{format:?}"
+            );
+            assert_eq!(
+                imported.facts["db1"].timestamp,
+                cache.facts["db1"].timestamp
+            );
+        }
+    }
+
+    fn cache_with(hosts: &[&str]) -> FactCache {
+        let mut cache = FactCache::new();
+        for host in hosts {
+            cache.update(host.to_string(), ArchitectureFacts::fallback());
+        }
+        cache
+    }
+
+    #[tokio::test]
+    async fn test_prune_removes_only_expired_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FactsConfig {
+            cache_ttl: 3600,
+            ..config_with_cache(dir.path().join("cache.json"))
+        };
+        let mut cache = cache_with(&["web1", "db1"]);
+        cache.facts.get_mut("db1").unwrap().timestamp -= 7200;
+        save_cache(&config.cache_file, &cache).unwrap();
+
+        run_command(Command::Cache(CacheCommand::Prune), &config)
+            .await
+            .unwrap();
+
+        let pruned = load_cache(&config.cache_file).unwrap();
+        assert_eq!(pruned.facts.keys().collect::<Vec<_>>(), ["web1"]);
+    }
+
+    #[tokio::test]
+    async fn test_clear_removes_every_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_with_cache(dir.path().join("cache.json"));
+        save_cache(&config.cache_file, &cache_with(&["web1", "db1"])).unwrap();
+
+        run_command(Command::Cache(CacheCommand::Clear), &config)
+            .await
+            .unwrap();
+
+        assert!(load_cache(&config.cache_file).unwrap().facts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rm_removes_one_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config_with_cache(dir.path().join("cache.json"));
+        save_cache(&config.cache_file, &cache_with(&["web1", "db1"])).unwrap();
+
+        // The name is normalized the way the cache keys are
+        run_command(
+            Command::Cache(CacheCommand::Rm {
+                host: "WEB1.".to_string(),
+            }),
+            &config,
+        )
+        .await
+        .unwrap();
+        let cache = load_cache(&config.cache_file).unwrap();
+        assert_eq!(cache.facts.keys().collect::<Vec<_>>(), ["db1"]);
+
+        // A host that is not cached leaves the file as it was
+        run_command(
+            Command::Cache(CacheCommand::Rm {
+                host: "web2".to_string(),
+            }),
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(load_cache(&config.cache_file).unwrap().facts.len(), 1);
+    }
+
+    #[test]
+    fn test_removal_message_names_the_cache_key() {
+        assert_eq!(
+            removal_message("web1", true),
+            "Removed cached facts for web1"
+        );
+        assert_eq!(
+            removal_message("WEB1.", false),
+            "No cached facts for WEB1. (looked up as web1)"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Deterministic `mock` connection backend for tests and pipeline dry runs.
+//!
+//! Facts for a host come from, in order:
+//! 1. the `rustle_mock_facts` host var (an object shaped like `ArchitectureFacts`),
+//! 2. the `rustle_mock_output` host var (raw probe output, run through `parse_fact_output`),
+//! 3. the fixture file given by `--mock-fixture`, keyed by host name, holding either shape.
+//!
+//! A host with `rustle_mock_fail: true` (or no mock data at all) fails like an
+//! unreachable host would.
+
+use crate::arch_table::ArchTable;
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::faults::inject_faults;
+use crate::shutdown::StopReason;
+use crate::ssh_facts::parse_fact_output_with;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tracing::{debug, error, warn};
+
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    let fixture = match &config.mock_fixture {
+        Some(path) => load_fixture(path)?,
+        None => HashMap::new(),
+    };
+
+    let failure_limit = config.failure_limit(hosts.len());
+    let mut failures = 0;
+    let mut facts = HashMap::new();
+    for host in hosts {
+        if config.shutdown.is_requested() {
+            break;
+        }
+        let result = inject_faults(config, &host.name)
+            .await
+            .and_then(|()| mock_host_facts(&host, &fixture, &config.arch_table));
+        match result {
+            Ok(host_facts) => {
+                debug!("Mock facts for {}: {:?}", host.name, host_facts);
+                facts.insert(host.name, host_facts);
+            }
+            Err(e) => {
+                warn!("Mock gathering failed for {}: {}", host.name, e);
+                failures += 1;
+                if failure_limit.is_some_and(|limit| failures > limit) {
+                    error!("{} mock hosts failed; aborting", failures);
+                    config.shutdown.stop(StopReason::TooManyFailures);
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(facts)
+}
+
+fn load_fixture(path: &std::path::Path) -> Result<HashMap<String, serde_json::Value>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| FactsError::InvalidConfig(format!("Failed to read mock fixture: {e}")))?;
+    serde_json::from_str(&content)
+        .map_err(|e| FactsError::InvalidConfig(format!("Invalid mock fixture: {e}")))
+}
+
+fn mock_host_facts(
+    host: &HostEntry,
+    fixture: &HashMap<String, serde_json::Value>,
+    arch_table: &ArchTable,
+) -> Result<ArchitectureFacts> {
+    if host
+        .vars
+        .get("rustle_mock_fail")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+    {
+        return Err(FactsError::ConnectionFailed(
+            host.name.clone(),
+            "mock failure requested".to_string(),
+        ));
+    }
+
+    let value = host
+        .vars
+        .get("rustle_mock_facts")
+        .or_else(|| host.vars.get("rustle_mock_output"))
+        .or_else(|| fixture.get(&host.name))
+        .ok_or_else(|| {
+            FactsError::ConnectionFailed(host.name.clone(), "no mock facts defined".to_string())
+        })?;
+
+    facts_from_value(&host.name, value, arch_table)
+}
+
+fn facts_from_value(
+    host: &str,
+    value: &serde_json::Value,
+    arch_table: &ArchTable,
+) -> Result<ArchitectureFacts> {
+    match value {
+        serde_json::Value::String(output) => parse_fact_output_with(output, arch_table)
+            .map_err(|e| FactsError::ParseError(host.to_string(), e.to_string())),
+        other => serde_json::from_value(other.clone())
+            .map_err(|e| FactsError::ParseError(host.to_string(), e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_facts_from_vars() {
+        let mut host = HostEntry::new("web1");
+        host.vars.insert(
+            "rustle_mock_output".to_string(),
+            serde_json::json!("ARCH=arm64\nSYSTEM=Linux\nOS_FAMILY=debian\nDISTRIBUTION=ubuntu"),
+        );
+
+        let facts = mock_host_facts(&host, &HashMap::new(), &ArchTable::default()).unwrap();
+        assert_eq!(facts.ansible_architecture, "aarch64");
+        assert_eq!(facts.ansible_distribution, Some("ubuntu".to_string()));
+    }
+
+    #[test]
+    fn test_mock_facts_from_fixture_and_failure() {
+        let mut fixture = HashMap::new();
+        fixture.insert(
+            "db1".to_string(),
+            serde_json::json!({
+                "ansible_architecture": "x86_64",
+                "ansible_system": "Linux",
+                "ansible_os_family": "redhat",
+                "ansible_distribution": "rocky"
+            }),
+        );
+
+        let facts =
+            mock_host_facts(&HostEntry::new("db1"), &fixture, &ArchTable::default()).unwrap();
+        assert_eq!(facts.ansible_os_family, "redhat");
+
+        assert!(
+            mock_host_facts(&HostEntry::new("unknown"), &fixture, &ArchTable::default()).is_err()
+        );
+
+        let mut failing = HostEntry::new("db1");
+        failing
+            .vars
+            .insert("rustle_mock_fail".to_string(), serde_json::json!(true));
+        assert!(mock_host_facts(&failing, &fixture, &ArchTable::default()).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! Built-in SSH client for `--ssh-backend native`, enabled with the
+//! `native-ssh` feature.
+//!
+//! The default backend spawns one `ssh` process per host, which stops
+//! scaling past a few hundred hosts and does not work at all in containers
+//! without an ssh binary. This one speaks SSH in-process through russh.
+//! Keys are offered from the agent at `SSH_AUTH_SOCK`, then the host's
+//! private key file, then the default `~/.ssh/id_*` keys, which are
+//! decrypted with the SSH passphrase when one is configured. A host with a
+//! password (`ansible_ssh_pass` or `--ask-pass`) is then logged into with
+//! it.
+//!
+//! ssh_config is not read: the address, port, user and key come from the
+//! inventory, and hosts that need a ProxyCommand are refused so they can be
+//! moved to the openssh backend.
+
+use crate::config::{FactsConfig, HostKeyPolicy};
+use crate::error::{FactsError, Result};
+use crate::privilege::awaits_password;
+use crate::secrets::Secret;
+use crate::ssh_facts::SshTarget;
+use crate::types::HostEntry;
+use russh::client::{self, Handle};
+use russh::keys::{
+    known_hosts, load_secret_key, PrivateKeyWithHashAlg, PublicKey, PublicKeyOrCertificate,
+};
+use russh::{ChannelMsg, Disconnect};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::debug;
+
+/// Keys tried after the agent's when the host names none.
+const DEFAULT_IDENTITIES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Run `command` on `host`, reached at `target`, and return its exit code,
+/// stdout and stderr. `host_options` are the `-o` options the openssh backend would
+/// pass; the ones this client cannot honour are refused or ignored.
+#[allow(clippy::too_many_arguments)]
+pub async fn exec(
+    host: &HostEntry,
+    target: &SshTarget,
+    host_options: &[String],
+    command: &str,
+    stdin_secret: Option<&Secret>,
+    request_tty: bool,
+    known_hosts: &Path,
+    config: &FactsConfig,
+) -> Result<(Option<i32>, String, String)> {
+    if host_options.iter().any(|o| o.starts_with("ProxyCommand=")) {
+        return Err(FactsError::InvalidConfig(format!(
+            "host {} needs a ProxyCommand, which the native SSH backend does not support; use --ssh-backend openssh",
+            host.name
+        )));
+    }
+
+    let connection_failed =
+        |e: russh::Error| FactsError::ConnectionFailed(host.name.clone(), e.to_string());
+    let hostname = target.address.as_str();
+    let port = target.port.unwrap_or(22);
+
+    // Keepalives mirror the ServerAlive options given to ssh(1)
+    let client_config = Arc::new(client::Config {
+        keepalive_interval: Some(Duration::from_secs(
+            (config.connect_timeout / 3).clamp(1, 15),
+        )),
+        keepalive_max: 3,
+        ..Default::default()
+    });
+    let checker = HostKeyCheck {
+        hostname: hostname.to_string(),
+        port,
+        known_hosts: known_hosts.to_path_buf(),
+        policy: config.host_key_policy,
+        changed: Arc::new(AtomicBool::new(false)),
+    };
+    let changed = checker.changed.clone();
+
+    let connect = client::connect(client_config, (hostname, port), checker);
+    let mut session = match timeout(Duration::from_secs(config.connect_timeout), connect).await {
+        Ok(Ok(session)) => session,
+        Ok(Err(_)) if changed.load(Ordering::Relaxed) => {
+            return Err(FactsError::HostKeyMismatch(host.name.clone()))
+        }
+        Ok(Err(e)) => return Err(connection_failed(e)),
+        Err(_) => {
+            return Err(FactsError::ConnectionFailed(
+                host.name.clone(),
+                "Connection timed out".to_string(),
+            ))
+        }
+    };
+
+    if !authenticate(&mut session, target, host, config).await? {
+        return Err(FactsError::AuthenticationFailed(
+            host.name.clone(),
+            "no offered key or password was accepted".to_string(),
+        ));
+    }
+
+    let mut channel = session
+        .channel_open_session()
+        .await
+        .map_err(connection_failed)?;
+    if request_tty {
+        channel
+            .request_pty(false, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .map_err(connection_failed)?;
+    }
+    channel
+        .exec(true, command)
+        .await
+        .map_err(connection_failed)?;
+    // On a terminal the password waits for the prompt; see
+    // `BecomeSettings::needs_tty`
+    let mut prompted_secret = stdin_secret.filter(|_| request_tty);
+    if let Some(secret) = stdin_secret.filter(|_| !request_tty) {
+        let line = format!("{}\n", secret.expose());
+        channel
+            .data(line.as_bytes())
+            .await
+            .map_err(connection_failed)?;
+    }
+    if prompted_secret.is_none() {
+        channel.eof().await.map_err(connection_failed)?;
+    }
+
+    let mut exit_code = None;
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    while let Some(message) = channel.wait().await {
+        match message {
+            ChannelMsg::Data { data } => {
+                stdout.extend_from_slice(&data);
+                if let Some(secret) = prompted_secret.filter(|_| awaits_password(&stdout)) {
+                    let line = format!("{}\n", secret.expose());
+                    channel
+                        .data(line.as_bytes())
+                        .await
+                        .map_err(connection_failed)?;
+                    channel.eof().await.map_err(connection_failed)?;
+                    prompted_secret = None;
+                }
+            }
+            ChannelMsg::ExtendedData { data, ext: 1 } => stderr.extend_from_slice(&data),
+            ChannelMsg::ExitStatus { exit_status } => {
+                exit_code = i32::try_from(exit_status).ok();
+            }
+            _ => {}
+        }
+    }
+
+    let _ = session
+        .disconnect(Disconnect::ByApplication, "", "en")
+        .await;
+    Ok((
+        exit_code,
+        String::from_utf8_lossy(&stdout).to_string(),
+        String::from_utf8_lossy(&stderr).to_string(),
+    ))
+}
+
+/// Offer the agent's keys, then the key files, then the password, until
+/// one is accepted.
+async fn authenticate(
+    session: &mut Handle<HostKeyCheck>,
+    target: &SshTarget,
+    host: &HostEntry,
+    config: &FactsConfig,
+) -> Result<bool> {
+    let connection_failed =
+        |e: russh::Error| FactsError::ConnectionFailed(host.name.clone(), e.to_string());
+    let rsa_hash = session
+        .best_supported_rsa_hash()
+        .await
+        .map_err(connection_failed)?
+        .flatten();
+
+    #[cfg(unix)]
+    if let Ok(mut agent) = russh::keys::agent::client::AgentClient::connect_env().await {
+        use russh::keys::agent::AgentIdentity;
+
+        for identity in agent.request_identities().await.unwrap_or_default() {
+            let AgentIdentity::PublicKey { key, .. } = identity else {
+                continue;
+            };
+            match session
+                .authenticate_publickey_with(&target.user, key, rsa_hash, &mut agent)
+                .await
+            {
+                Ok(result) if result.success() => return Ok(true),
+                Ok(_) => {}
+                Err(e) => debug!("Agent signing failed for {}: {}", host.name, e),
+            }
+        }
+    }
+
+    let passphrase = config
+        .credentials
+        .ssh_passphrase
+        .as_ref()
+        .map(Secret::expose);
+    for path in identity_files(target) {
+        let key = match load_secret_key(&path, passphrase) {
+            Ok(key) => key,
+            Err(e) => {
+                debug!("Skipping SSH key {:?}: {}", path, e);
+                continue;
+            }
+        };
+        let result = session
+            .authenticate_publickey(
+                &target.user,
+                PrivateKeyWithHashAlg::new(Arc::new(key), rsa_hash),
+            )
+            .await
+            .map_err(connection_failed)?;
+        if result.success() {
+            return Ok(true);
+        }
+    }
+
+    if let Some(password) = target.password(config) {
+        let result = session
+            .authenticate_password(&target.user, password.expose())
+            .await
+            .map_err(connection_failed)?;
+        return Ok(result.success());
+    }
+
+    Ok(false)
+}
+
+/// The host's own key file, if it names one, else the default keys that
+/// exist.
+fn identity_files(target: &SshTarget) -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+    match &target.key_file {
+        Some(path) => match path.strip_prefix("~/") {
+            Some(relative) => vec![home.join(relative)],
+            None => vec![PathBuf::from(path)],
+        },
+        None => DEFAULT_IDENTITIES
+            .iter()
+            .map(|name| home.join(".ssh").join(name))
+            .filter(|path| path.exists())
+            .collect(),
+    }
+}
+
+/// Checks the server's key against `known_hosts` and records unknown keys
+/// there, as ssh(1) does, so the fingerprint can be read back afterwards.
+struct HostKeyCheck {
+    hostname: String,
+    port: u16,
+    known_hosts: PathBuf,
+    policy: HostKeyPolicy,
+    /// Set when a recorded key was refused, so the caller can report it
+    changed: Arc<AtomicBool>,
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKeyOrCertificate,
+    ) -> std::result::Result<bool, Self::Error> {
+        let key = match server_public_key {
+            PublicKeyOrCertificate::PublicKey { key, .. } => key.clone(),
+            PublicKeyOrCertificate::Certificate(certificate) => {
+                PublicKey::new(certificate.public_key().clone(), "")
+            }
+        };
+
+        match known_hosts::check_known_hosts_path(
+            &self.hostname,
+            self.port,
+            &key,
+            &self.known_hosts,
+        ) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                known_hosts::learn_known_hosts_path(
+                    &self.hostname,
+                    self.port,
+                    &key,
+                    &self.known_hosts,
+                )?;
+                Ok(true)
+            }
+            Err(russh::keys::Error::KeyChanged { .. }) if self.policy == HostKeyPolicy::Tofu => {
+                self.changed.store(true, Ordering::Relaxed);
+                Ok(false)
+            }
+            Err(russh::keys::Error::KeyChanged { .. }) => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_files() {
+        let mut host = HostEntry::new("db1");
+        host.vars.insert(
+            "ansible_ssh_private_key_file".to_string(),
+            serde_json::json!("/etc/keys/deploy"),
+        );
+        let target = SshTarget::for_host(&host).unwrap();
+        assert_eq!(identity_files(&target), [PathBuf::from("/etc/keys/deploy")]);
+
+        host.ssh_private_key_file = Some("~/.ssh/db".to_string());
+        let target = SshTarget::for_host(&host).unwrap();
+        let home = dirs::home_dir().unwrap_or_default();
+        assert_eq!(identity_files(&target), [home.join(".ssh/db")]);
+    }
+}
@@ -0,0 +1,128 @@
+//! Facts for network devices that declare `ansible_network_os`.
+//!
+//! Routers and switches reject the POSIX probe, so these hosts are sent the
+//! vendor `show version` command over SSH instead. The reported platform is
+//! mapped to `ansible_system` (`ios`, `junos`, `eos`, ...) with a `network`
+//! OS family; the architecture is taken from the output when the vendor
+//! prints one and is `unknown` otherwise. Devices that cannot be reached
+//! keep their declared platform instead of the Linux fallback facts.
+
+use crate::types::{ArchitectureFacts, HostEntry};
+
+/// The command sent to every network device.
+pub const SHOW_VERSION: &str = "show version";
+
+/// The device platform, with collection prefixes such as `cisco.ios.ios`
+/// reduced to `ios`. `None` for hosts that are not network devices.
+pub fn network_os(host: &HostEntry) -> Option<String> {
+    let value = host.vars.get("ansible_network_os")?.as_str()?.trim();
+    let platform = value.rsplit('.').next().unwrap_or(value).to_lowercase();
+    (!platform.is_empty()).then_some(platform)
+}
+
+/// Build facts from `show version` output.
+pub fn parse_show_version(network_os: &str, output: &str) -> ArchitectureFacts {
+    ArchitectureFacts {
+        ansible_architecture: detect_architecture(output),
+        ansible_system: network_os.to_string(),
+        ansible_os_family: "network".to_string(),
+        ansible_distribution: Some(detect_platform(network_os, output)),
+        rustle_libc: None,
+        extra: serde_json::Map::new(),
+    }
+}
+
+/// Facts for a device that could not be reached: its declared platform and
+/// an `unknown` architecture, rather than the Linux fallback.
+pub fn fallback_facts(network_os: &str) -> ArchitectureFacts {
+    parse_show_version(network_os, "")
+}
+
+/// Refine the declared platform from the banner, e.g. IOS XE vs classic IOS.
+fn detect_platform(network_os: &str, output: &str) -> String {
+    let lower = output.to_lowercase();
+    match network_os {
+        "ios" if lower.contains("ios xe") || lower.contains("ios-xe") => "iosxe".to_string(),
+        "ios" if lower.contains("ios xr") || lower.contains("ios-xr") => "iosxr".to_string(),
+        "ios" if lower.contains("nx-os") => "nxos".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn detect_architecture(output: &str) -> String {
+    let lower = output.to_lowercase();
+
+    // EOS prints "Architecture: x86_64"; take it verbatim when present
+    if let Some(arch) = lower
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("architecture:"))
+        .map(str::trim)
+        .filter(|arch| !arch.is_empty())
+    {
+        return ArchitectureFacts::normalize_architecture(arch);
+    }
+
+    for (needle, arch) in [
+        ("x86_64", "x86_64"),
+        ("x86-64", "x86_64"),
+        ("amd64", "x86_64"),
+        ("aarch64", "aarch64"),
+        ("arm64", "aarch64"),
+        ("powerpc", "ppc"),
+        ("ppc", "ppc"),
+        ("mips", "mips"),
+        ("i386", "i386"),
+    ] {
+        if lower.contains(needle) {
+            return arch.to_string();
+        }
+    }
+    "unknown".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_os() {
+        let mut host = HostEntry::new("core-sw1");
+        assert_eq!(network_os(&host), None);
+
+        host.vars.insert(
+            "ansible_network_os".to_string(),
+            serde_json::json!("cisco.ios.ios"),
+        );
+        assert_eq!(network_os(&host), Some("ios".to_string()));
+    }
+
+    #[test]
+    fn test_parse_show_version() {
+        let eos = "Arista DCS-7050SX-64\nSoftware image version: 4.28.3M\nArchitecture: i686\n";
+        let facts = parse_show_version("eos", eos);
+        assert_eq!(facts.ansible_architecture, "i686");
+        assert_eq!(facts.ansible_system, "eos");
+        assert_eq!(facts.ansible_os_family, "network");
+
+        let ios = "Cisco IOS XE Software, Version 17.03.04a\n\
+cisco ISR4451-X/K9 (2RU) processor with 7941237K/6147K bytes of memory.\n";
+        let facts = parse_show_version("ios", ios);
+        assert_eq!(facts.ansible_distribution, Some("iosxe".to_string()));
+        assert_eq!(facts.ansible_architecture, "unknown");
+
+        let classic = "Cisco IOS Software, C3750 Software\n\
+cisco WS-C3750X-48P (PowerPC405) processor (revision A0)\n";
+        assert_eq!(
+            parse_show_version("ios", classic).ansible_architecture,
+            "ppc"
+        );
+
+        let fallback = fallback_facts("junos");
+        assert_eq!(fallback.ansible_architecture, "unknown");
+        assert_eq!(fallback.ansible_os_family, "network");
+
+        let junos = "Hostname: edge1\nModel: mx240\nJunos: 21.4R3-S1\n";
+        let facts = parse_show_version("junos", junos);
+        assert_eq!(facts.ansible_distribution, Some("junos".to_string()));
+    }
+}
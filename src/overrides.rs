@@ -0,0 +1,649 @@
+//! Fact values declared directly in inventory vars.
+//!
+//! Hosts that cannot be probed (appliances, locked-down devices) can declare
+//! `ansible_architecture`, `ansible_system`, `ansible_os_family`,
+//! `ansible_distribution`, `rustle_libc` or a `rustle_target_triple`.
+//! Declared values always win over gathered ones, and a declared
+//! architecture is enough to skip gathering the host entirely.
+//!
+//! The same keys can be pinned from a `--facts-override` file, which maps
+//! host names, group names or glob patterns to fact values and wins over
+//! inventory vars. Declared facts are never written to the cache.
+//!
+//! A host's facts are merged field by field from declared, freshly gathered
+//! and cached values, in that order unless `--prefer` moves one source to
+//! the front; fields none of them know fall back to local detection or the
+//! default facts. The source of each field is recorded.
+
+use crate::analysis::group_members;
+use crate::config::FactPreference;
+use crate::error::{FactsError, Result};
+use crate::hostname::normalize_hostname;
+use crate::types::{ArchitectureFacts, FactSource, ParsedInventory};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tracing::warn;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FactOverrides {
+    pub architecture: Option<String>,
+    pub system: Option<String>,
+    pub os_family: Option<String>,
+    pub distribution: Option<String>,
+    pub libc: Option<String>,
+    /// Facts without a field of their own, see [`ArchitectureFacts::extra`]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// The parts of a Rust target triple that map onto host facts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetTriple {
+    pub architecture: String,
+    pub system: String,
+    pub libc: Option<String>,
+}
+
+impl FactOverrides {
+    /// Read declared facts from host vars. Explicit `ansible_*` vars take
+    /// precedence over values derived from `rustle_target_triple`.
+    pub fn from_vars(host: &str, vars: &HashMap<String, serde_json::Value>) -> Option<Self> {
+        let var = |key: &str| {
+            vars.get(key)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+
+        let mut overrides = FactOverrides {
+            // Kept as spelled so `--arch-map` sees it before the built-ins
+            architecture: var("ansible_architecture"),
+            system: var("ansible_system"),
+            os_family: var("ansible_os_family"),
+            distribution: var("ansible_distribution"),
+            libc: var("rustle_libc"),
+            extra: serde_json::Map::new(),
+        };
+
+        if let Some(triple) = var("rustle_target_triple") {
+            match parse_target_triple(&triple) {
+                Some(target) => {
+                    overrides.architecture.get_or_insert(target.architecture);
+                    overrides.system.get_or_insert(target.system);
+                    if let Some(libc) = target.libc {
+                        overrides.libc.get_or_insert(libc);
+                    }
+                }
+                None => warn!(
+                    "Ignoring unrecognized rustle_target_triple '{}' for {}",
+                    triple, host
+                ),
+            }
+        }
+
+        (overrides != FactOverrides::default()).then_some(overrides)
+    }
+
+    /// Layer `other` on top of these values; fields it declares win.
+    pub fn merge(self, other: FactOverrides) -> FactOverrides {
+        let mut extra = self.extra;
+        extra.extend(other.extra);
+        FactOverrides {
+            architecture: other.architecture.or(self.architecture),
+            system: other.system.or(self.system),
+            os_family: other.os_family.or(self.os_family),
+            distribution: other.distribution.or(self.distribution),
+            libc: other.libc.or(self.libc),
+            extra,
+        }
+    }
+
+    /// Whether the declared values are enough to skip gathering.
+    pub fn is_complete(&self) -> bool {
+        self.architecture.is_some()
+    }
+
+    /// Overlay the declared values on gathered facts.
+    pub fn apply(&self, facts: &ArchitectureFacts) -> ArchitectureFacts {
+        ArchitectureFacts {
+            ansible_architecture: self
+                .architecture
+                .clone()
+                .unwrap_or_else(|| facts.ansible_architecture.clone()),
+            ansible_system: self
+                .system
+                .clone()
+                .unwrap_or_else(|| facts.ansible_system.clone()),
+            ansible_os_family: self
+                .os_family
+                .clone()
+                .unwrap_or_else(|| facts.ansible_os_family.clone()),
+            ansible_distribution: self
+                .distribution
+                .clone()
+                .or_else(|| facts.ansible_distribution.clone()),
+            rustle_libc: self.libc.clone().or_else(|| facts.rustle_libc.clone()),
+            extra: facts
+                .extra
+                .iter()
+                .chain(&self.extra)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Facts for a host that is not gathered at all. Undeclared fields are
+    /// derived from the system where possible.
+    pub fn to_facts(&self) -> ArchitectureFacts {
+        let system = self.system.clone().unwrap_or_else(|| "Linux".to_string());
+        let os_family = self
+            .os_family
+            .clone()
+            .unwrap_or_else(|| default_os_family(&system).to_string());
+
+        ArchitectureFacts {
+            ansible_architecture: self
+                .architecture
+                .clone()
+                .unwrap_or_else(|| ArchitectureFacts::fallback().ansible_architecture),
+            ansible_system: system,
+            ansible_os_family: os_family,
+            ansible_distribution: self.distribution.clone(),
+            rustle_libc: self.libc.clone(),
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+impl From<&ArchitectureFacts> for FactOverrides {
+    fn from(facts: &ArchitectureFacts) -> Self {
+        FactOverrides {
+            architecture: Some(facts.ansible_architecture.clone()),
+            system: Some(facts.ansible_system.clone()),
+            os_family: Some(facts.ansible_os_family.clone()),
+            distribution: facts.ansible_distribution.clone(),
+            libc: facts.rustle_libc.clone(),
+            extra: facts.extra.clone(),
+        }
+    }
+}
+
+impl FactOverrides {
+    /// These values without `facts`, by fact name.
+    pub fn without(mut self, facts: &[&str]) -> Self {
+        if facts.contains(&"ansible_distribution") {
+            self.distribution = None;
+        }
+        if facts.contains(&"rustle_libc") {
+            self.libc = None;
+        }
+        self.extra.retain(|key, _| !facts.contains(&key.as_str()));
+        self
+    }
+}
+
+/// Declared, gathered and cached sources in the order they win.
+pub fn source_order(prefer: FactPreference) -> [FactSource; 3] {
+    match prefer {
+        FactPreference::Declared => [
+            FactSource::Declared,
+            FactSource::Gathered,
+            FactSource::Cached,
+        ],
+        FactPreference::Gathered => [
+            FactSource::Gathered,
+            FactSource::Declared,
+            FactSource::Cached,
+        ],
+        FactPreference::Cached => [
+            FactSource::Cached,
+            FactSource::Declared,
+            FactSource::Gathered,
+        ],
+    }
+}
+
+/// Take each field from the first of `candidates` that has it. When no
+/// candidate knows a required field, `base` is called and ranked last.
+pub fn merge_sources(
+    mut candidates: Vec<(FactSource, FactOverrides)>,
+    base: impl FnOnce() -> (FactSource, ArchitectureFacts),
+) -> (ArchitectureFacts, BTreeMap<String, FactSource>) {
+    let known = |field: fn(&FactOverrides) -> &Option<String>| {
+        candidates.iter().any(|(_, values)| field(values).is_some())
+    };
+    if !(known(|v| &v.architecture) && known(|v| &v.system) && known(|v| &v.os_family)) {
+        let (source, facts) = base();
+        candidates.push((source, FactOverrides::from(&facts)));
+    }
+
+    let mut sources = BTreeMap::new();
+    // Facts without a field are taken whole from the first source with them
+    let mut extra = serde_json::Map::new();
+    for (source, values) in &candidates {
+        for (key, value) in &values.extra {
+            if !extra.contains_key(key) {
+                extra.insert(key.clone(), value.clone());
+                sources.insert(key.clone(), *source);
+            }
+        }
+    }
+
+    let mut pick = |name: &str, field: fn(&FactOverrides) -> &Option<String>| {
+        candidates.iter().find_map(|(source, values)| {
+            let value = field(values).clone()?;
+            sources.insert(name.to_string(), *source);
+            Some(value)
+        })
+    };
+
+    // The base always has the required fields, so they cannot be missing
+    let facts = ArchitectureFacts {
+        ansible_architecture: pick("ansible_architecture", |v| &v.architecture).unwrap_or_default(),
+        ansible_system: pick("ansible_system", |v| &v.system).unwrap_or_default(),
+        ansible_os_family: pick("ansible_os_family", |v| &v.os_family).unwrap_or_default(),
+        ansible_distribution: pick("ansible_distribution", |v| &v.distribution),
+        rustle_libc: pick("rustle_libc", |v| &v.libc),
+        extra,
+    };
+    (facts, sources)
+}
+
+/// A `--facts-override` file: a YAML or JSON map from host name, group name
+/// or glob pattern (`edge-*`) to pinned fact values.
+#[derive(Debug, Clone, Default)]
+pub struct OverrideFile {
+    entries: BTreeMap<String, HashMap<String, serde_json::Value>>,
+}
+
+impl OverrideFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            FactsError::InvalidConfig(format!("Failed to read facts override file: {e}"))
+        })?;
+        // JSON is a subset of YAML, so one parser covers both
+        let entries = serde_yaml::from_str(&content).map_err(|e| {
+            FactsError::InvalidConfig(format!("Invalid facts override file {path:?}: {e}"))
+        })?;
+        Ok(Self { entries })
+    }
+
+    /// Pinned values for a host. Patterns apply first, then groups, then the
+    /// host's own entry, so the most specific match wins.
+    pub fn resolve(&self, host: &str, inventory: &ParsedInventory) -> Option<FactOverrides> {
+        let mut matches: Vec<(u8, &String)> = Vec::new();
+        for key in self.entries.keys() {
+            if key == host || normalize_hostname(key) == normalize_hostname(host) {
+                matches.push((2, key));
+            } else if group_members(inventory, key).is_some_and(|m| m.contains(host)) {
+                matches.push((1, key));
+            } else if is_pattern(key) && glob_match(key, host) {
+                matches.push((0, key));
+            }
+        }
+        matches.sort();
+
+        matches
+            .into_iter()
+            .filter_map(|(_, key)| FactOverrides::from_vars(host, &self.entries[key]))
+            .reduce(FactOverrides::merge)
+    }
+}
+
+fn is_pattern(key: &str) -> bool {
+    key.contains(['*', '?'])
+}
+
+/// Match `*` (any run of characters) and `?` (one character).
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Split a Rust target triple such as `aarch64-unknown-linux-gnu` into an
+/// Ansible architecture, system and libc.
+pub fn parse_target_triple(triple: &str) -> Option<TargetTriple> {
+    let mut parts = triple.split('-');
+    let arch = parts.next().filter(|a| !a.is_empty())?;
+    let rest: Vec<&str> = parts.collect();
+
+    let system = rest.iter().find_map(|part| match *part {
+        "linux" => Some("Linux"),
+        "darwin" | "apple" => Some("Darwin"),
+        "windows" => Some("Windows"),
+        "freebsd" => Some("FreeBSD"),
+        "netbsd" => Some("NetBSD"),
+        "openbsd" => Some("OpenBSD"),
+        "solaris" | "illumos" => Some("SunOS"),
+        "aix" => Some("AIX"),
+        _ => None,
+    })?;
+
+    let libc = rest.iter().find_map(|part| {
+        if part.starts_with("musl") {
+            Some("musl")
+        } else if part.starts_with("gnu") {
+            Some("gnu")
+        } else {
+            None
+        }
+    });
+
+    Some(TargetTriple {
+        architecture: ArchitectureFacts::normalize_architecture(arch),
+        system: system.to_string(),
+        libc: libc.map(str::to_string),
+    })
+}
+
+/// The Rust target triple to build for a host, the inverse of
+/// [`parse_target_triple`]. Linux hosts with an unknown libc get the `gnu`
+/// target, as rustc does. `None` when the architecture or system has no
+/// Rust target.
+pub fn target_triple(facts: &ArchitectureFacts) -> Option<String> {
+    let arch = match facts.ansible_architecture.as_str() {
+        "riscv64" | "riscv64gc" => "riscv64gc",
+        "armv6" => "arm",
+        "ppc64le" => "powerpc64le",
+        "ppc64" => "powerpc64",
+        "i386" | "i686" => "i686",
+        arch @ ("x86_64" | "aarch64" | "armv7" | "s390x" | "sparc64" | "mips" | "mipsel"
+        | "mips64" | "mips64el") => arch,
+        _ => return None,
+    };
+
+    Some(match facts.ansible_system.as_str() {
+        "Linux" => {
+            let libc = facts.rustle_libc.as_deref().unwrap_or("gnu");
+            let abi = match arch {
+                "armv7" | "arm" => "eabihf",
+                "mips64" | "mips64el" => "abi64",
+                _ => "",
+            };
+            format!("{arch}-unknown-linux-{libc}{abi}")
+        }
+        "Darwin" => format!("{arch}-apple-darwin"),
+        "Windows" => format!("{arch}-pc-windows-msvc"),
+        "FreeBSD" => format!("{arch}-unknown-freebsd"),
+        "NetBSD" => format!("{arch}-unknown-netbsd"),
+        "OpenBSD" => format!("{arch}-unknown-openbsd"),
+        "SunOS" if arch == "x86_64" => "x86_64-pc-solaris".to_string(),
+        // Solaris alone spells 64-bit SPARC sparcv9
+        "SunOS" if arch == "sparc64" => "sparcv9-sun-solaris".to_string(),
+        "AIX" if arch == "powerpc64" => "powerpc64-ibm-aix".to_string(),
+        _ => return None,
+    })
+}
+
+pub(crate) fn default_os_family(system: &str) -> &'static str {
+    match system {
+        "Darwin" => "darwin",
+        "Windows" => "windows",
+        "FreeBSD" | "NetBSD" | "OpenBSD" => "bsd",
+        "SunOS" => "solaris",
+        "AIX" => "aix",
+        "HP-UX" => "hp-ux",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, serde_json::Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), serde_json::json!(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_without_drops_named_facts() {
+        let mut facts = ArchitectureFacts::fallback();
+        facts.ansible_distribution = Some("ubuntu".to_string());
+        facts.rustle_libc = Some("gnu".to_string());
+        facts.extra.insert("region".to_string(), "eu-west-1".into());
+        facts
+            .extra
+            .insert("ansible_kernel".to_string(), "6.8".into());
+
+        let values = FactOverrides::from(&facts).without(&["rustle_libc", "ansible_kernel"]);
+        assert_eq!(values.distribution.as_deref(), Some("ubuntu"));
+        assert_eq!(values.libc, None);
+        assert!(values.extra.contains_key("region"));
+        assert!(!values.extra.contains_key("ansible_kernel"));
+        assert!(values.architecture.is_some());
+    }
+
+    #[test]
+    fn test_parse_target_triple() {
+        assert_eq!(
+            parse_target_triple("aarch64-unknown-linux-musl"),
+            Some(TargetTriple {
+                architecture: "aarch64".to_string(),
+                system: "Linux".to_string(),
+                libc: Some("musl".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_target_triple("x86_64-apple-darwin"),
+            Some(TargetTriple {
+                architecture: "x86_64".to_string(),
+                system: "Darwin".to_string(),
+                libc: None,
+            })
+        );
+        assert_eq!(parse_target_triple("wasm32-unknown-unknown"), None);
+        assert_eq!(parse_target_triple(""), None);
+    }
+
+    #[test]
+    fn test_target_triple() {
+        let facts = |arch: &str, system: &str, libc: Option<&str>| ArchitectureFacts {
+            ansible_architecture: arch.to_string(),
+            ansible_system: system.to_string(),
+            rustle_libc: libc.map(str::to_string),
+            ..ArchitectureFacts::fallback()
+        };
+        let triple = |facts| target_triple(&facts).unwrap_or_default();
+
+        assert_eq!(
+            triple(facts("aarch64", "Linux", Some("musl"))),
+            "aarch64-unknown-linux-musl"
+        );
+        assert_eq!(
+            triple(facts("x86_64", "Linux", None)),
+            "x86_64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            triple(facts("armv7", "Linux", Some("gnu"))),
+            "armv7-unknown-linux-gnueabihf"
+        );
+        assert_eq!(
+            triple(facts("riscv64", "Linux", None)),
+            "riscv64gc-unknown-linux-gnu"
+        );
+        assert_eq!(
+            triple(facts("aarch64", "Darwin", None)),
+            "aarch64-apple-darwin"
+        );
+        assert_eq!(
+            triple(facts("armv6", "Linux", None)),
+            "arm-unknown-linux-gnueabihf"
+        );
+        // As an --arch-map entry may spell it
+        assert_eq!(
+            triple(facts("riscv64gc", "Linux", Some("musl"))),
+            "riscv64gc-unknown-linux-musl"
+        );
+        assert_eq!(
+            triple(facts("sparc64", "SunOS", None)),
+            "sparcv9-sun-solaris"
+        );
+        assert_eq!(
+            triple(facts("sparc64", "Linux", None)),
+            "sparc64-unknown-linux-gnu"
+        );
+        assert_eq!(
+            triple(facts("sparc64", "OpenBSD", None)),
+            "sparc64-unknown-openbsd"
+        );
+        assert_eq!(triple(facts("ppc64", "AIX", None)), "powerpc64-ibm-aix");
+        assert_eq!(target_triple(&facts("hppa", "HP-UX", None)), None);
+        assert_eq!(target_triple(&facts("unknown", "ios", None)), None);
+
+        // Round trips through the parser
+        let parsed = parse_target_triple("armv7-unknown-linux-musleabihf").unwrap();
+        assert_eq!(
+            triple(facts(
+                &parsed.architecture,
+                &parsed.system,
+                parsed.libc.as_deref()
+            )),
+            "armv7-unknown-linux-musleabihf"
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("edge-*", "edge-01"));
+        assert!(glob_match("*-0?", "edge-01"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("edge-*", "core-01"));
+        assert!(!glob_match("edge-?", "edge-01"));
+    }
+
+    #[test]
+    fn test_override_file_precedence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("overrides.yml");
+        std::fs::write(
+            &path,
+            "\"edge-*\":\n  ansible_architecture: armv7\n  rustle_libc: musl\n\
+routers:\n  ansible_architecture: mips\n\
+edge-01:\n  ansible_os_family: openwrt\n",
+        )
+        .unwrap();
+        let file = OverrideFile::load(&path).unwrap();
+
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": {},
+            "groups": {"routers": ["edge-01"]}
+        }))
+        .unwrap();
+
+        let pinned = file.resolve("edge-01", &inventory).unwrap();
+        assert_eq!(pinned.architecture, Some("mips".to_string()));
+        assert_eq!(pinned.libc, Some("musl".to_string()));
+        assert_eq!(pinned.os_family, Some("openwrt".to_string()));
+
+        let pattern_only = file.resolve("edge-02", &inventory).unwrap();
+        assert_eq!(pattern_only.architecture, Some("armv7".to_string()));
+        assert!(file.resolve("core-01", &inventory).is_none());
+    }
+
+    #[test]
+    fn test_overrides_from_vars() {
+        assert_eq!(FactOverrides::from_vars("h", &vars(&[])), None);
+
+        let triple = FactOverrides::from_vars(
+            "h",
+            &vars(&[
+                ("rustle_target_triple", "armv7-unknown-linux-gnueabihf"),
+                ("ansible_os_family", "debian"),
+            ]),
+        )
+        .unwrap();
+        assert!(triple.is_complete());
+        let facts = triple.to_facts();
+        assert_eq!(facts.ansible_architecture, "armv7");
+        assert_eq!(facts.ansible_system, "Linux");
+        assert_eq!(facts.ansible_os_family, "debian");
+        assert_eq!(facts.rustle_libc, Some("gnu".to_string()));
+
+        let family_only =
+            FactOverrides::from_vars("h", &vars(&[("ansible_os_family", "suse")])).unwrap();
+        assert!(!family_only.is_complete());
+        let applied = family_only.apply(&ArchitectureFacts::fallback());
+        assert_eq!(applied.ansible_architecture, "x86_64");
+        assert_eq!(applied.ansible_os_family, "suse");
+    }
+
+    #[test]
+    fn test_merge_sources_precedence() {
+        let declared = FactOverrides {
+            architecture: Some("armv7".to_string()),
+            ..Default::default()
+        };
+        let mut gathered = ArchitectureFacts::fallback();
+        gathered.ansible_distribution = Some("Debian".to_string());
+        let mut cached = ArchitectureFacts::fallback();
+        cached.ansible_architecture = "aarch64".to_string();
+
+        let candidates = |prefer| -> Vec<(FactSource, FactOverrides)> {
+            source_order(prefer)
+                .into_iter()
+                .map(|source| {
+                    let values = match source {
+                        FactSource::Declared => declared.clone(),
+                        FactSource::Gathered => FactOverrides::from(&gathered),
+                        _ => FactOverrides::from(&cached),
+                    };
+                    (source, values)
+                })
+                .collect()
+        };
+        let unused = || -> (FactSource, ArchitectureFacts) { panic!("base not needed") };
+
+        let (facts, sources) = merge_sources(candidates(FactPreference::Declared), unused);
+        assert_eq!(facts.ansible_architecture, "armv7");
+        assert_eq!(sources["ansible_architecture"], FactSource::Declared);
+        assert_eq!(sources["ansible_distribution"], FactSource::Gathered);
+        assert!(!sources.contains_key("rustle_libc"));
+
+        let (facts, sources) = merge_sources(candidates(FactPreference::Gathered), unused);
+        assert_eq!(facts.ansible_architecture, "x86_64");
+        assert_eq!(sources["ansible_architecture"], FactSource::Gathered);
+
+        let (facts, _) = merge_sources(candidates(FactPreference::Cached), unused);
+        assert_eq!(facts.ansible_architecture, "aarch64");
+
+        // Only a partial declaration: the rest comes from the base
+        let family_only = FactOverrides {
+            os_family: Some("suse".to_string()),
+            ..Default::default()
+        };
+        let (facts, sources) = merge_sources(vec![(FactSource::Declared, family_only)], || {
+            (FactSource::Fallback, ArchitectureFacts::fallback())
+        });
+        assert_eq!(facts.ansible_os_family, "suse");
+        assert_eq!(sources["ansible_os_family"], FactSource::Declared);
+        assert_eq!(sources["ansible_architecture"], FactSource::Fallback);
+    }
+}
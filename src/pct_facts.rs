@@ -0,0 +1,140 @@
+//! Facts for Proxmox LXC containers, `ansible_connection: pct`.
+//!
+//! The container is named by its VMID, from `proxmox_vmid` or else
+//! `ansible_host`, and the probe runs as `pct exec <vmid> -- sh -c ...` on
+//! the Proxmox node that hosts it. `ansible_pct_node` names that node, which
+//! is reached over SSH like any other host (with its own inventory vars when
+//! it is in the inventory); without it `pct` is run on this machine, for
+//! runs on the node itself.
+
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::hostname::normalize_hostname;
+use crate::sanitize::shell_quote;
+use crate::ssh_facts::{self, fact_probe, parse_fact_output_with};
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+use tracing::debug;
+
+/// The host var naming the Proxmox node a container runs on.
+pub const NODE_VAR: &str = "ansible_pct_node";
+
+/// The container's VMID, from `proxmox_vmid`, `ansible_host`, the entry
+/// address or the inventory name.
+pub fn vmid(host: &HostEntry) -> Result<u32> {
+    let value = match host.vars.get("proxmox_vmid") {
+        Some(serde_json::Value::Number(number)) => number.to_string(),
+        Some(serde_json::Value::String(value)) => value.clone(),
+        _ => host
+            .vars
+            .get("ansible_host")
+            .and_then(|v| v.as_str())
+            .or(host.address.as_deref())
+            .unwrap_or(&host.name)
+            .to_string(),
+    };
+    value.trim().parse().map_err(|_| {
+        FactsError::InvalidConfig(format!(
+            "host {} has no numeric VMID for pct (got {value:?}); set proxmox_vmid or ansible_host",
+            host.name
+        ))
+    })
+}
+
+/// The Proxmox node a container is reached through, if not this machine.
+pub fn node(host: &HostEntry) -> Option<&str> {
+    host.vars
+        .get(NODE_VAR)?
+        .as_str()
+        .map(str::trim)
+        .filter(|node| !node.is_empty())
+}
+
+/// Gather minimal facts for Proxmox containers. `nodes` holds the inventory
+/// entries of nodes that are themselves inventory hosts.
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    nodes: &HashMap<String, HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(exec_backend::gather_each(hosts, config, |host, config| {
+        let node = node(&host).map(|name| {
+            nodes
+                .get(&normalize_hostname(name))
+                .cloned()
+                .unwrap_or_else(|| HostEntry::new(name))
+        });
+        async move { gather_host_facts(&host, node.as_ref(), &config).await }
+    })
+    .await)
+}
+
+async fn gather_host_facts(
+    host: &HostEntry,
+    node: Option<&HostEntry>,
+    config: &FactsConfig,
+) -> Result<ArchitectureFacts> {
+    let vmid = vmid(host)?.to_string();
+    inject_faults(config, &host.name).await?;
+
+    let output = match node {
+        Some(node) => {
+            debug!("Running pct exec {} on {}", vmid, node.name);
+            let command = format!(
+                "pct exec {vmid} -- sh -c {}",
+                shell_quote(&fact_probe(config)?)
+            );
+            // The node is connected to before the probe runs
+            let budget = config.host_budget() + Duration::from_secs(config.connect_timeout);
+            timeout(budget, ssh_facts::run_on_host(node, &command, config))
+                .await
+                .map_err(|_| FactsError::Timeout(host.name.clone()))??
+        }
+        None => run_local(host, &vmid, config).await?,
+    };
+    parse_fact_output_with(&output, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))
+}
+
+async fn run_local(host: &HostEntry, vmid: &str, config: &FactsConfig) -> Result<String> {
+    let args = [
+        "exec".to_string(),
+        vmid.to_string(),
+        "--".to_string(),
+        "sh".to_string(),
+        "-c".to_string(),
+        fact_probe(config)?,
+    ];
+    exec_backend::run(host, "pct", vmid, Command::new("pct"), &args, config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vmid_and_node() {
+        let mut host = HostEntry::new("app");
+        assert!(vmid(&host).is_err());
+        assert_eq!(node(&host), None);
+
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("101"));
+        assert_eq!(vmid(&host).unwrap(), 101);
+
+        host.vars
+            .insert("proxmox_vmid".to_string(), serde_json::json!(205));
+        host.vars
+            .insert(NODE_VAR.to_string(), serde_json::json!("pve1"));
+        assert_eq!(vmid(&host).unwrap(), 205);
+        assert_eq!(node(&host), Some("pve1"));
+
+        host.vars
+            .insert("proxmox_vmid".to_string(), serde_json::json!("101; id"));
+        assert!(vmid(&host).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Facts for hosts with `ansible_connection: podman`.
+//!
+//! Containers are probed with `podman exec` (or the Podman socket with the
+//! `bollard` feature) through the container gatherer shared with Docker,
+//! reading `ansible_podman_user` and `ansible_podman_working_dir`. Rootless
+//! containers work as long as rustle-facts runs as their owner.
+//!
+//! On macOS and Windows, Podman runs containers inside a `podman machine`
+//! VM. When no machine is running every exec fails with a socket error, so
+//! that is checked once up front and reported as a single clear error.
+
+use crate::config::FactsConfig;
+use crate::docker_facts::{self, ContainerRuntime};
+use crate::error::{FactsError, Result};
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use tracing::{debug, info};
+
+/// Gather minimal facts for hosts in Podman containers
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    let replaying = config.session.as_ref().is_some_and(|s| s.is_replay());
+    if !cfg!(target_os = "linux") && !replaying {
+        check_machine_running().await?;
+    }
+    docker_facts::gather_runtime_facts(ContainerRuntime::Podman, hosts, config).await
+}
+
+/// Fail when Podman needs a machine and none is running. A missing or
+/// unparseable `podman machine list` is left for exec to report.
+async fn check_machine_running() -> Result<()> {
+    let output = match tokio::process::Command::new("podman")
+        .args(["machine", "list", "--format", "json"])
+        .output()
+        .await
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(_) | Err(_) => {
+            debug!("Could not list podman machines; skipping the machine check");
+            return Ok(());
+        }
+    };
+
+    match running_machine(&String::from_utf8_lossy(&output.stdout)) {
+        Some(Some(name)) => {
+            info!("Using podman machine {}", name);
+            Ok(())
+        }
+        Some(None) => Err(FactsError::ConnectionFailed(
+            "podman machine".to_string(),
+            "no podman machine is running; start one with `podman machine start`".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The running machine in `podman machine list --format json` output:
+/// `Some(None)` when machines exist but none runs, `None` when the output
+/// lists no machines or cannot be read.
+fn running_machine(list_json: &str) -> Option<Option<String>> {
+    let machines: Vec<serde_json::Value> = serde_json::from_str(list_json).ok()?;
+    if machines.is_empty() {
+        return None;
+    }
+    Some(
+        machines
+            .iter()
+            .find(|machine| machine["Running"].as_bool() == Some(true))
+            .and_then(|machine| machine["Name"].as_str())
+            .map(|name| name.trim_end_matches('*').to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_machine() {
+        let running = r#"[
+            {"Name": "podman-machine-default*", "Default": true, "Running": true},
+            {"Name": "builder", "Default": false, "Running": false}
+        ]"#;
+        assert_eq!(
+            running_machine(running),
+            Some(Some("podman-machine-default".to_string()))
+        );
+
+        let stopped = r#"[{"Name": "podman-machine-default*", "Running": false}]"#;
+        assert_eq!(running_machine(stopped), Some(None));
+
+        assert_eq!(running_machine("[]"), None);
+        assert_eq!(running_machine("not json"), None);
+    }
+}
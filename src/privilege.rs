@@ -0,0 +1,320 @@
+use crate::error::{FactsError, Result};
+use crate::sanitize::{shell_quote, validate_flags, validate_user};
+use crate::secrets::Secret;
+use crate::types::HostEntry;
+use std::fmt;
+use std::str::FromStr;
+
+/// Supported `become_method` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BecomeMethod {
+    Sudo,
+    /// OpenBSD and Alpine's lightweight sudo replacement
+    Doas,
+    Su,
+}
+
+impl FromStr for BecomeMethod {
+    type Err = FactsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "sudo" => Ok(BecomeMethod::Sudo),
+            "doas" => Ok(BecomeMethod::Doas),
+            "su" => Ok(BecomeMethod::Su),
+            other => Err(FactsError::InvalidConfig(format!(
+                "Unsupported become method '{other}' (expected sudo, doas or su)"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for BecomeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BecomeMethod::Sudo => "sudo",
+            BecomeMethod::Doas => "doas",
+            BecomeMethod::Su => "su",
+        })
+    }
+}
+
+/// Privilege escalation settings for a single host, resolved from the
+/// inventory's `ansible_become*` fields and vars.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BecomeSettings {
+    pub method: BecomeMethod,
+    pub user: Option<String>,
+    pub flags: Option<String>,
+}
+
+impl BecomeSettings {
+    pub fn from_host(host: &HostEntry) -> Result<Option<Self>> {
+        let enabled = host
+            .ansible_become
+            .or_else(|| host.vars.get("ansible_become").and_then(var_as_bool))
+            .unwrap_or(false);
+
+        if !enabled {
+            return Ok(None);
+        }
+
+        let var_str = |key: &str| {
+            host.vars
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+
+        let method = match host
+            .become_method
+            .clone()
+            .or_else(|| var_str("ansible_become_method"))
+        {
+            Some(method) => method.parse()?,
+            None => BecomeMethod::Sudo,
+        };
+
+        Ok(Some(Self {
+            method,
+            user: host
+                .become_user
+                .clone()
+                .or_else(|| var_str("ansible_become_user")),
+            flags: host
+                .become_flags
+                .clone()
+                .or_else(|| var_str("ansible_become_flags")),
+        }))
+    }
+
+    /// Wrap a shell script so it runs with elevated privileges. When a
+    /// password is available it is expected on stdin, never in the command.
+    pub fn wrap_command(&self, script: &str, with_password: bool) -> Result<String> {
+        if let Some(user) = &self.user {
+            validate_user(user)?;
+        }
+        if let Some(flags) = &self.flags {
+            validate_flags(flags)?;
+        }
+
+        let mut parts = vec![self.method.to_string()];
+        match self.method {
+            BecomeMethod::Sudo => {
+                if with_password {
+                    parts.extend(["-S", "-p", "''"].map(String::from));
+                } else {
+                    parts.push("-n".to_string());
+                }
+                self.push_user_and_flags(&mut parts, "-u");
+                parts.extend(["sh".to_string(), "-c".to_string(), shell_quote(script)]);
+            }
+            BecomeMethod::Doas => {
+                // doas only reads passwords from a terminal; see `needs_tty`
+                if !with_password {
+                    parts.push("-n".to_string());
+                }
+                self.push_user_and_flags(&mut parts, "-u");
+                parts.extend(["sh".to_string(), "-c".to_string(), shell_quote(script)]);
+            }
+            BecomeMethod::Su => {
+                if let Some(flags) = &self.flags {
+                    parts.push(flags.clone());
+                }
+                parts.push(shell_quote(self.user.as_deref().unwrap_or("root")));
+                parts.extend(["-c".to_string(), shell_quote(script)]);
+            }
+        }
+
+        Ok(parts.join(" "))
+    }
+
+    /// doas and su refuse to read a password from a pipe, so the connection
+    /// must allocate a terminal for the password to reach them. The password
+    /// is then written only once [`awaits_password`] sees their prompt, as
+    /// the terminal would otherwise echo or discard it.
+    pub fn needs_tty(&self, with_password: bool) -> bool {
+        with_password && self.method != BecomeMethod::Sudo
+    }
+
+    /// Recognize method-specific escalation failures in a probe's output.
+    pub fn classify_failure(&self, host: &str, output: &str) -> Option<FactsError> {
+        let patterns: &[(&str, &str)] = match self.method {
+            BecomeMethod::Sudo => &[
+                ("a password is required", "password required"),
+                ("incorrect password", "incorrect password"),
+                ("is not in the sudoers file", "user not allowed"),
+                ("not allowed to execute", "user not allowed"),
+            ],
+            BecomeMethod::Doas => &[
+                ("Authentication failed", "incorrect password"),
+                ("a password is required", "password required"),
+                ("Operation not permitted", "user not allowed"),
+                ("not permitted", "user not allowed"),
+            ],
+            BecomeMethod::Su => &[
+                ("Authentication failure", "incorrect password"),
+                ("incorrect password", "incorrect password"),
+                ("must be run from a terminal", "password required"),
+                ("does not exist", "unknown target user"),
+            ],
+        };
+
+        patterns
+            .iter()
+            .find(|(needle, _)| output.contains(needle))
+            .map(|(_, reason)| {
+                FactsError::BecomeFailed(
+                    host.to_string(),
+                    self.method.to_string(),
+                    reason.to_string(),
+                )
+            })
+    }
+
+    fn push_user_and_flags(&self, parts: &mut Vec<String>, user_flag: &str) {
+        if let Some(user) = &self.user {
+            parts.push(user_flag.to_string());
+            parts.push(shell_quote(user));
+        }
+        if let Some(flags) = &self.flags {
+            parts.push(flags.clone());
+        }
+    }
+}
+
+/// Whether `output` ends in a password prompt, such as doas's
+/// `doas (user@host) password: ` or su's `Password: `.
+pub fn awaits_password(output: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(output);
+    let last_line = text.rsplit(['\n', '\r']).next().unwrap_or_default();
+    let last_line = last_line.trim_end();
+    last_line.ends_with(':') && last_line.to_lowercase().contains("pass")
+}
+
+/// Replace any occurrence of the secret in text that may end up in logs.
+pub fn redact(text: &str, secret: Option<&Secret>) -> String {
+    match secret {
+        Some(secret) if !secret.expose().is_empty() => text.replace(secret.expose(), "[REDACTED]"),
+        _ => text.to_string(),
+    }
+}
+
+fn var_as_bool(value: &serde_json::Value) -> Option<bool> {
+    match value {
+        serde_json::Value::Bool(b) => Some(*b),
+        serde_json::Value::String(s) => match s.to_lowercase().as_str() {
+            "true" | "yes" | "1" => Some(true),
+            "false" | "no" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_become_settings_from_vars() {
+        let mut host = HostEntry::new("web1");
+        assert!(BecomeSettings::from_host(&host).unwrap().is_none());
+
+        host.vars
+            .insert("ansible_become".to_string(), serde_json::json!("yes"));
+        host.vars.insert(
+            "ansible_become_user".to_string(),
+            serde_json::json!("admin"),
+        );
+
+        let settings = BecomeSettings::from_host(&host).unwrap().unwrap();
+        assert_eq!(settings.method, BecomeMethod::Sudo);
+        assert_eq!(settings.user, Some("admin".to_string()));
+    }
+
+    #[test]
+    fn test_wrap_command_uses_stdin_for_password() {
+        let settings = BecomeSettings {
+            method: BecomeMethod::Sudo,
+            user: Some("root".to_string()),
+            flags: None,
+        };
+
+        assert_eq!(
+            settings.wrap_command("echo 'hi'", true).unwrap(),
+            r"sudo -S -p '' -u 'root' sh -c 'echo '\''hi'\'''"
+        );
+        assert!(settings
+            .wrap_command("uname", false)
+            .unwrap()
+            .starts_with("sudo -n"));
+
+        let hostile = BecomeSettings {
+            method: BecomeMethod::Sudo,
+            user: Some("root; id".to_string()),
+            flags: None,
+        };
+        assert!(hostile.wrap_command("uname", false).is_err());
+    }
+
+    #[test]
+    fn test_awaits_password() {
+        assert!(awaits_password(b"doas (deploy@web1) password: "));
+        assert!(awaits_password(b"\r\nPassword:"));
+        assert!(!awaits_password(
+            b"Password: \r\n===RUSTLE_FACTS_BEGIN===\r\n"
+        ));
+        assert!(!awaits_password(b"ARCH=x86_64\r\n"));
+        assert!(!awaits_password(b""));
+    }
+
+    #[test]
+    fn test_wrap_command_doas_and_su() {
+        let doas = BecomeSettings {
+            method: BecomeMethod::Doas,
+            user: Some("root".to_string()),
+            flags: None,
+        };
+        assert_eq!(
+            doas.wrap_command("uname", false).unwrap(),
+            "doas -n -u 'root' sh -c 'uname'"
+        );
+        assert!(!doas.needs_tty(false));
+        assert!(doas.needs_tty(true));
+
+        let su = BecomeSettings {
+            method: BecomeMethod::Su,
+            user: None,
+            flags: Some("-l".to_string()),
+        };
+        assert_eq!(
+            su.wrap_command("uname", true).unwrap(),
+            "su -l 'root' -c 'uname'"
+        );
+        assert!(matches!(
+            su.classify_failure("web1", "su: Authentication failure"),
+            Some(FactsError::BecomeFailed(_, _, _))
+        ));
+        assert!(su.classify_failure("web1", "ARCH=x86_64").is_none());
+
+        let mut host = HostEntry::new("web1");
+        host.vars
+            .insert("ansible_become".to_string(), serde_json::json!(true));
+        host.vars.insert(
+            "ansible_become_method".to_string(),
+            serde_json::json!("pbrun"),
+        );
+        assert!(BecomeSettings::from_host(&host).is_err());
+    }
+
+    #[test]
+    fn test_redact() {
+        let secret = Secret::new("s3cret");
+        assert_eq!(
+            redact("sudo: s3cret rejected", Some(&secret)),
+            "sudo: [REDACTED] rejected"
+        );
+        assert_eq!(redact("nothing here", None), "nothing here");
+    }
+}
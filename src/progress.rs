@@ -0,0 +1,171 @@
+//! Periodic progress logging for long gathering runs: completed/remaining
+//! counts, rolling throughput and an ETA from observed per-host durations.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Completions used for the rolling hosts-per-second figure.
+const ROLLING_WINDOW: usize = 20;
+
+#[derive(Debug)]
+pub struct ProgressTracker {
+    label: &'static str,
+    total: usize,
+    completed: usize,
+    failed: usize,
+    parallel: usize,
+    interval: Option<Duration>,
+    started: Instant,
+    total_duration: Duration,
+    recent: VecDeque<Instant>,
+}
+
+/// A point-in-time view of a run, as logged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressSnapshot {
+    pub completed: usize,
+    pub failed: usize,
+    pub remaining: usize,
+    pub hosts_per_sec: Option<f64>,
+    pub eta: Option<Duration>,
+}
+
+impl ProgressTracker {
+    /// `interval_secs` of 0 disables periodic reports.
+    pub fn new(label: &'static str, total: usize, parallel: usize, interval_secs: u64) -> Self {
+        Self {
+            label,
+            total,
+            completed: 0,
+            failed: 0,
+            parallel: parallel.max(1),
+            interval: (interval_secs > 0).then(|| Duration::from_secs(interval_secs)),
+            started: Instant::now(),
+            total_duration: Duration::ZERO,
+            recent: VecDeque::with_capacity(ROLLING_WINDOW),
+        }
+    }
+
+    /// Record a finished host and how long it took.
+    pub fn record(&mut self, duration: Duration, success: bool) {
+        self.record_at(Instant::now(), duration, success);
+    }
+
+    fn record_at(&mut self, at: Instant, duration: Duration, success: bool) {
+        self.completed += 1;
+        if !success {
+            self.failed += 1;
+        }
+        self.total_duration += duration;
+        if self.recent.len() == ROLLING_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(at);
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> ProgressSnapshot {
+        let remaining = self.total.saturating_sub(self.completed);
+
+        // Rate over the recent window, measured up to now so a stall shows
+        // up as a falling rate rather than a frozen one
+        let hosts_per_sec = self.recent.front().and_then(|first| {
+            let (since, finished) = if self.recent.len() == self.completed {
+                (self.started, self.recent.len())
+            } else {
+                (*first, self.recent.len() - 1)
+            };
+            let elapsed = now.duration_since(since).as_secs_f64();
+            (elapsed > 0.0).then(|| finished as f64 / elapsed)
+        });
+
+        // Hosts still to go run `parallel` at a time, each taking the mean
+        // observed duration
+        let eta = (self.completed > 0).then(|| {
+            let mean = self.total_duration / self.completed as u32;
+            let waves = remaining.div_ceil(self.parallel) as u32;
+            mean * waves
+        });
+
+        ProgressSnapshot {
+            completed: self.completed,
+            failed: self.failed,
+            remaining,
+            hosts_per_sec,
+            eta,
+        }
+    }
+
+    /// Log the current progress.
+    pub fn report(&self) {
+        let snapshot = self.snapshot();
+        let rate = snapshot
+            .hosts_per_sec
+            .map(|r| format!("{r:.2} hosts/s"))
+            .unwrap_or_else(|| "no hosts finished yet".to_string());
+        let eta = snapshot
+            .eta
+            .map(|eta| format!("ETA {}s", eta.as_secs()))
+            .unwrap_or_else(|| "ETA unknown".to_string());
+        info!(
+            "{}: {}/{} hosts done ({} failed), {} remaining, {}, {}, {}s elapsed",
+            self.label,
+            snapshot.completed,
+            self.total,
+            snapshot.failed,
+            snapshot.remaining,
+            rate,
+            eta,
+            self.started.elapsed().as_secs()
+        );
+    }
+
+    /// Resolves once per report interval; never resolves when reports are
+    /// disabled. Meant for a `tokio::select!` arm next to the task set.
+    pub async fn tick(&self, last: &mut Instant) {
+        match self.interval {
+            Some(interval) => {
+                tokio::time::sleep_until((*last + interval).into()).await;
+                *last = Instant::now();
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_eta_and_rate() {
+        let mut tracker = ProgressTracker::new("test", 10, 2, 10);
+        assert_eq!(tracker.snapshot().eta, None);
+        assert_eq!(tracker.snapshot().hosts_per_sec, None);
+
+        let start = tracker.started;
+        tracker.record_at(start + Duration::from_secs(2), Duration::from_secs(2), true);
+        tracker.record_at(
+            start + Duration::from_secs(4),
+            Duration::from_secs(4),
+            false,
+        );
+
+        let snapshot = tracker.snapshot_at(start + Duration::from_secs(4));
+        assert_eq!(snapshot.completed, 2);
+        assert_eq!(snapshot.failed, 1);
+        assert_eq!(snapshot.remaining, 8);
+        // 2 hosts in 4 seconds
+        assert_eq!(snapshot.hosts_per_sec, Some(0.5));
+        // 8 remaining at 2 in parallel is 4 waves of the 3s mean
+        assert_eq!(snapshot.eta, Some(Duration::from_secs(12)));
+
+        // A stall lowers the rolling rate
+        let stalled = tracker.snapshot_at(start + Duration::from_secs(40));
+        assert_eq!(stalled.hosts_per_sec, Some(0.05));
+    }
+}
@@ -0,0 +1,263 @@
+//! Facts for libvirt VMs through the QEMU guest agent,
+//! `ansible_connection: libvirt_qemu`.
+//!
+//! The probe is started in the guest with the agent's `guest-exec` command
+//! via `virsh qemu-agent-command` and its output collected by polling
+//! `guest-exec-status`, so VMs without any network access still report
+//! their facts. The domain comes from `ansible_host`, then the inventory
+//! name, and `ansible_libvirt_uri` selects the hypervisor connection as in
+//! the `community.libvirt.libvirt_qemu` connection plugin. The guest needs
+//! `qemu-guest-agent` running and a POSIX shell.
+
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::sanitize::validate_container_name;
+use crate::session::run_recorded;
+use crate::ssh_facts::{fact_probe, parse_fact_output_with};
+use crate::types::{ArchitectureFacts, HostEntry};
+use base64::Engine;
+use std::collections::HashMap;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio::time::{sleep, timeout, Duration, Instant};
+use tracing::debug;
+
+/// How often `guest-exec-status` is asked whether the probe has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A libvirt domain and the hypervisor it runs on.
+#[derive(Debug, Clone, PartialEq)]
+struct QemuTarget {
+    domain: String,
+    uri: Option<String>,
+}
+
+impl QemuTarget {
+    fn from_host(host: &HostEntry) -> Result<Self> {
+        let domain = host
+            .vars
+            .get("ansible_host")
+            .and_then(|v| v.as_str())
+            .or(host.address.as_deref())
+            .unwrap_or(&host.name)
+            .to_string();
+        validate_container_name(&domain)?;
+
+        let uri = host
+            .vars
+            .get("ansible_libvirt_uri")
+            .and_then(|v| v.as_str())
+            .map(str::to_string);
+        if let Some(uri) = &uri {
+            if uri.starts_with('-') || !uri.contains(':') {
+                return Err(FactsError::UnsafeIdentifier(
+                    "libvirt uri".to_string(),
+                    uri.clone(),
+                ));
+            }
+        }
+        Ok(QemuTarget { domain, uri })
+    }
+
+    fn virsh_args(&self, request: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(uri) = &self.uri {
+            args.extend(["-c".to_string(), uri.clone()]);
+        }
+        args.extend([
+            "qemu-agent-command".to_string(),
+            self.domain.clone(),
+            request.to_string(),
+        ]);
+        args
+    }
+}
+
+/// The `guest-exec` request that starts the probe.
+fn exec_request(probe: &str) -> String {
+    serde_json::json!({
+        "execute": "guest-exec",
+        "arguments": {
+            "path": "/bin/sh",
+            "arg": ["-c", probe],
+            "capture-output": true
+        }
+    })
+    .to_string()
+}
+
+fn status_request(pid: i64) -> String {
+    serde_json::json!({"execute": "guest-exec-status", "arguments": {"pid": pid}}).to_string()
+}
+
+/// The guest pid from a `guest-exec` reply.
+fn parse_exec_reply(reply: &str) -> Option<i64> {
+    let reply: serde_json::Value = serde_json::from_str(reply).ok()?;
+    reply["return"]["pid"].as_i64()
+}
+
+/// The exit code and decoded stdout from a `guest-exec-status` reply, or
+/// `None` while the probe is still running.
+fn parse_status_reply(reply: &str) -> Result<Option<(i64, String)>> {
+    let reply: serde_json::Value = serde_json::from_str(reply)?;
+    let status = &reply["return"];
+    if !status["exited"].as_bool().unwrap_or(false) {
+        return Ok(None);
+    }
+    let stdout = match status["out-data"].as_str() {
+        Some(encoded) => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| FactsError::ParseError("guest-exec".to_string(), e.to_string()))?;
+            String::from_utf8_lossy(&bytes).to_string()
+        }
+        None => String::new(),
+    };
+    Ok(Some((status["exitcode"].as_i64().unwrap_or(-1), stdout)))
+}
+
+/// Gather minimal facts for hosts using libvirt_qemu connections
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(
+        exec_backend::gather_each(hosts, config, |host, config| async move {
+            gather_host_facts(&host, &config).await
+        })
+        .await,
+    )
+}
+
+async fn gather_host_facts(host: &HostEntry, config: &FactsConfig) -> Result<ArchitectureFacts> {
+    let target = QemuTarget::from_host(host)?;
+    inject_faults(config, &host.name).await?;
+    debug!(
+        "Running the probe in {} through the guest agent",
+        target.domain
+    );
+
+    let reply = agent_command(host, &target, &exec_request(&fact_probe(config)?), config).await?;
+    let pid = parse_exec_reply(&reply).ok_or_else(|| {
+        FactsError::ParseError(
+            host.name.clone(),
+            format!("unexpected guest-exec reply: {reply}"),
+        )
+    })?;
+
+    let deadline = Instant::now() + Duration::from_secs(config.command_timeout);
+    let (exit_code, stdout) = loop {
+        let reply = agent_command(host, &target, &status_request(pid), config).await?;
+        if let Some(status) = parse_status_reply(&reply)
+            .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            return Err(FactsError::Timeout(host.name.clone()));
+        }
+        sleep(POLL_INTERVAL).await;
+    };
+
+    if exit_code != 0 {
+        return Err(FactsError::ConnectionFailed(
+            host.name.clone(),
+            format!("probe exited with {exit_code} in the guest"),
+        ));
+    }
+    parse_fact_output_with(&stdout, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))
+}
+
+/// Send one request to the guest agent and return its reply.
+async fn agent_command(
+    host: &HostEntry,
+    target: &QemuTarget,
+    request: &str,
+    config: &FactsConfig,
+) -> Result<String> {
+    let args = target.virsh_args(request);
+    let timeout_secs = config.connect_timeout.max(1);
+    let output = run_recorded(
+        config.session.as_deref(),
+        "libvirt_qemu",
+        &target.domain,
+        request,
+        || async {
+            let mut cmd = Command::new("virsh");
+            cmd.args(&args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            let output = timeout(Duration::from_secs(timeout_secs), cmd.output())
+                .await
+                .map_err(|_| FactsError::Timeout(host.name.clone()))??;
+            Ok((
+                output.status.code(),
+                String::from_utf8_lossy(&output.stdout).to_string(),
+                String::from_utf8_lossy(&output.stderr).to_string(),
+            ))
+        },
+    )
+    .await?;
+
+    if output.exit_code != Some(0) {
+        return Err(FactsError::ConnectionFailed(
+            host.name.clone(),
+            output.stderr.trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qemu_target_from_host() {
+        let mut host = HostEntry::new("vm1");
+        host.vars.insert(
+            "ansible_libvirt_uri".to_string(),
+            serde_json::json!("qemu+ssh://root@kvm1/system"),
+        );
+        let target = QemuTarget::from_host(&host).unwrap();
+        let args = target.virsh_args("{}");
+        assert_eq!(
+            args,
+            [
+                "-c",
+                "qemu+ssh://root@kvm1/system",
+                "qemu-agent-command",
+                "vm1",
+                "{}"
+            ]
+        );
+
+        host.vars.insert(
+            "ansible_libvirt_uri".to_string(),
+            serde_json::json!("--readonly"),
+        );
+        assert!(QemuTarget::from_host(&host).is_err());
+    }
+
+    #[test]
+    fn test_guest_exec_replies() {
+        let request: serde_json::Value = serde_json::from_str(&exec_request("uname -m")).unwrap();
+        assert_eq!(request["arguments"]["arg"][1], "uname -m");
+
+        assert_eq!(parse_exec_reply(r#"{"return":{"pid":4242}}"#), Some(4242));
+        assert_eq!(parse_exec_reply(r#"{"error":{}}"#), None);
+
+        assert_eq!(
+            parse_status_reply(r#"{"return":{"exited":false}}"#).unwrap(),
+            None
+        );
+        let done = r#"{"return":{"exitcode":0,"out-data":"eDg2XzY0Cg==","exited":true}}"#;
+        assert_eq!(
+            parse_status_reply(done).unwrap(),
+            Some((0, "x86_64\n".to_string()))
+        );
+    }
+}
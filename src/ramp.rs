@@ -0,0 +1,98 @@
+//! Staggered connection start-up.
+//!
+//! Opening thousands of SSH connections at once trips fail2ban, sshd
+//! `MaxStartups` and intrusion detection. The ramp hands out start slots no
+//! faster than `--max-connection-rate` per second and delays each host by up
+//! to `--connect-jitter-ms`, derived from its name so reruns are stable.
+
+use crate::config::FactsConfig;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct ConnectionRamp {
+    spacing: Option<Duration>,
+    jitter: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl ConnectionRamp {
+    /// A `max_per_sec` of 0 means no rate limit.
+    pub fn new(max_per_sec: u32, jitter: Duration) -> Self {
+        Self {
+            spacing: (max_per_sec > 0).then(|| Duration::from_secs(1) / max_per_sec),
+            jitter,
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    pub fn from_config(config: &FactsConfig) -> Self {
+        Self::new(
+            config.max_connection_rate,
+            Duration::from_millis(config.connect_jitter_ms),
+        )
+    }
+
+    /// Wait for this host's turn to connect.
+    pub async fn wait(&self, host: &str) {
+        let start = self.reserve(Instant::now()) + host_jitter(host, self.jitter);
+        tokio::time::sleep_until(start.into()).await;
+    }
+
+    /// Claim the next free start slot at or after `now`.
+    fn reserve(&self, now: Instant) -> Instant {
+        let Some(spacing) = self.spacing else {
+            return now;
+        };
+        let mut next_slot = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = next_slot.map_or(now, |next| next.max(now));
+        *next_slot = Some(slot + spacing);
+        slot
+    }
+}
+
+fn host_jitter(host: &str, max: Duration) -> Duration {
+    let max_ms = max.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    let mut hasher = DefaultHasher::new();
+    host.hash(&mut hasher);
+    Duration::from_millis(hasher.finish() % (max_ms + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_spaces_slots() {
+        let ramp = ConnectionRamp::new(50, Duration::ZERO);
+        let now = Instant::now();
+        let slots: Vec<Instant> = (0..3).map(|_| ramp.reserve(now)).collect();
+        assert_eq!(slots[0], now);
+        assert_eq!(slots[1] - slots[0], Duration::from_millis(20));
+        assert_eq!(slots[2] - slots[1], Duration::from_millis(20));
+
+        // Idle time is not banked as a burst allowance
+        let later = now + Duration::from_secs(5);
+        assert_eq!(ramp.reserve(later), later);
+
+        let unlimited = ConnectionRamp::new(0, Duration::ZERO);
+        assert_eq!(unlimited.reserve(now), now);
+        assert_eq!(unlimited.reserve(now), now);
+    }
+
+    #[test]
+    fn test_host_jitter_is_bounded_and_stable() {
+        let max = Duration::from_millis(250);
+        for host in ["web1", "web2", "db1.example.com"] {
+            let jitter = host_jitter(host, max);
+            assert!(jitter <= max);
+            assert_eq!(jitter, host_jitter(host, max));
+        }
+        assert_eq!(host_jitter("web1", Duration::ZERO), Duration::ZERO);
+    }
+}
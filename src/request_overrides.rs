@@ -0,0 +1,223 @@
+//! Per-request configuration for callers that serve several pipelines.
+//!
+//! A long-running caller (a server enriching one playbook per request)
+//! runs with one `FactsConfig`, but its clients differ in how fresh their
+//! facts must be and which hosts they care about. A request may carry
+//! `RequestOverrides` for the cache TTL, `force_refresh`, the gather
+//! subset, the output `select`ion and a host `limit`; `RequestOverrides::apply` checks them
+//! against the `OverrideBounds` the operator configured and returns the
+//! config for that request. An override outside the bounds is refused
+//! rather than clamped, so a client never silently gets staler facts than
+//! it asked for.
+
+use crate::config::{FactsConfig, GatherSubset};
+use crate::error::{FactsError, Result};
+use crate::select::Selection;
+use serde::{Deserialize, Serialize};
+
+/// Config fields a single request may change.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestOverrides {
+    /// Seconds cached facts stay valid for this request
+    pub cache_ttl: Option<u64>,
+    pub force_refresh: Option<bool>,
+    /// Optional fact groups to gather, as `--gather-subset`
+    pub gather_subset: Option<Vec<GatherSubset>>,
+    /// Projection of the output document, as `--select`
+    pub select: Option<Selection>,
+    /// Host patterns limiting the request, as `--limit`
+    #[serde(default)]
+    pub limit: Vec<String>,
+}
+
+/// What the operator lets a request override.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverrideBounds {
+    pub min_cache_ttl: u64,
+    pub max_cache_ttl: Option<u64>,
+    pub allow_force_refresh: bool,
+    pub allow_gather_subset: bool,
+    pub allow_select: bool,
+    pub allow_limit: bool,
+}
+
+impl Default for OverrideBounds {
+    /// TTL, subset and output changes are allowed; forcing a refresh of every host
+    /// costs the whole fleet a round of connections, so it is not.
+    fn default() -> Self {
+        Self {
+            min_cache_ttl: 0,
+            max_cache_ttl: None,
+            allow_force_refresh: false,
+            allow_gather_subset: true,
+            allow_select: true,
+            allow_limit: true,
+        }
+    }
+}
+
+impl RequestOverrides {
+    /// `config` with these overrides applied, or the first one `bounds`
+    /// does not allow.
+    pub fn apply(&self, config: &FactsConfig, bounds: &OverrideBounds) -> Result<FactsConfig> {
+        let refused = |what: String| {
+            Err(FactsError::InvalidConfig(format!(
+                "request override refused: {what}"
+            )))
+        };
+        let mut config = config.clone();
+
+        if let Some(ttl) = self.cache_ttl {
+            // Every entry is expired at 0, which is a force_refresh by
+            // another name
+            if ttl == 0 && !bounds.allow_force_refresh {
+                return refused(
+                    "cache_ttl 0 refreshes every host, and force_refresh is not allowed"
+                        .to_string(),
+                );
+            }
+            if ttl < bounds.min_cache_ttl {
+                return refused(format!(
+                    "cache_ttl {ttl} is below the minimum of {}",
+                    bounds.min_cache_ttl
+                ));
+            }
+            if let Some(max) = bounds.max_cache_ttl.filter(|max| ttl > *max) {
+                return refused(format!("cache_ttl {ttl} is above the maximum of {max}"));
+            }
+            config.cache_ttl = ttl;
+        }
+
+        if let Some(force_refresh) = self.force_refresh {
+            if force_refresh && !bounds.allow_force_refresh {
+                return refused("force_refresh is not allowed".to_string());
+            }
+            config.force_refresh = force_refresh;
+        }
+
+        if let Some(gather_subset) = &self.gather_subset {
+            if !bounds.allow_gather_subset {
+                return refused("gather_subset is not allowed".to_string());
+            }
+            config.gather_subset = gather_subset.clone();
+        }
+
+        if let Some(select) = &self.select {
+            if !bounds.allow_select {
+                return refused("select is not allowed".to_string());
+            }
+            config.select = Some(select.clone());
+        }
+
+        if !self.limit.is_empty() {
+            if !bounds.allow_limit {
+                return refused("limit is not allowed".to_string());
+            }
+            // Limit patterns are unioned, so adding to the operator's own
+            // would widen it rather than narrow it
+            if !config.limit.is_empty() {
+                return refused(format!(
+                    "limit cannot be combined with the configured limit {}",
+                    config.limit.join(",")
+                ));
+            }
+            config.limit = self.limit.clone();
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_within_bounds() {
+        let config = FactsConfig {
+            cache_ttl: 3600,
+            ..Default::default()
+        };
+        let overrides: RequestOverrides = serde_json::from_str(
+            r#"{"cache_ttl": 60, "gather_subset": ["cloud"], "select": "inventory.host_facts", "limit": ["web*"]}"#,
+        )
+        .unwrap();
+
+        let applied = overrides
+            .apply(&config, &OverrideBounds::default())
+            .unwrap();
+        assert_eq!(applied.cache_ttl, 60);
+        assert_eq!(applied.select.unwrap().to_string(), "inventory.host_facts");
+        assert_eq!(applied.gather_subset, [GatherSubset::Cloud]);
+        assert_eq!(applied.limit, ["web*"]);
+        assert!(!applied.force_refresh);
+    }
+
+    #[test]
+    fn test_overrides_outside_bounds_are_refused() {
+        let config = FactsConfig::default();
+        let bounds = OverrideBounds {
+            min_cache_ttl: 300,
+            max_cache_ttl: Some(86400),
+            allow_limit: false,
+            ..Default::default()
+        };
+        let refused = |overrides: RequestOverrides| overrides.apply(&config, &bounds).is_err();
+
+        assert!(refused(RequestOverrides {
+            cache_ttl: Some(60),
+            ..Default::default()
+        }));
+        assert!(refused(RequestOverrides {
+            cache_ttl: Some(604800),
+            ..Default::default()
+        }));
+        assert!(refused(RequestOverrides {
+            force_refresh: Some(true),
+            ..Default::default()
+        }));
+        let unbounded = OverrideBounds::default();
+        let zero_ttl = RequestOverrides {
+            cache_ttl: Some(0),
+            ..Default::default()
+        };
+        assert!(zero_ttl.apply(&config, &unbounded).is_err());
+        let refreshable = OverrideBounds {
+            allow_force_refresh: true,
+            ..Default::default()
+        };
+        assert_eq!(zero_ttl.apply(&config, &refreshable).unwrap().cache_ttl, 0);
+        assert!(refused(RequestOverrides {
+            limit: vec!["db*".to_string()],
+            ..Default::default()
+        }));
+        let cloud = RequestOverrides {
+            gather_subset: Some(vec![GatherSubset::Cloud]),
+            ..Default::default()
+        };
+        assert!(cloud.apply(&config, &unbounded).is_ok());
+        let no_subset = OverrideBounds {
+            allow_gather_subset: false,
+            ..Default::default()
+        };
+        assert!(cloud.apply(&config, &no_subset).is_err());
+        let limited = FactsConfig {
+            limit: vec!["web*".to_string()],
+            ..Default::default()
+        };
+        let widened = RequestOverrides {
+            limit: vec!["db*".to_string()],
+            ..Default::default()
+        };
+        assert!(widened.apply(&limited, &OverrideBounds::default()).is_err());
+        assert!(!refused(RequestOverrides {
+            cache_ttl: Some(300),
+            force_refresh: Some(false),
+            ..Default::default()
+        }));
+
+        assert!(serde_json::from_str::<RequestOverrides>(r#"{"cache_file": "/tmp/x"}"#).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::error::{FactsError, Result};
+
+/// Characters allowed in SSH targets: DNS names, IPv4/IPv6 literals and an
+/// optional `user@` prefix.
+const HOSTNAME_ALLOWED: &str = "._-:@[]%";
+
+/// Characters allowed in container, jail and VM identifiers.
+const CONTAINER_ALLOWED: &str = "._-";
+
+/// Characters allowed in user names and free-form flag strings that are
+/// interpolated into remote commands.
+const USER_ALLOWED: &str = "._-";
+const DOCKER_USER_ALLOWED: &str = "._-:";
+const PATH_ALLOWED: &str = "._-/";
+const FLAGS_ALLOWED: &str = "._-=, /";
+const DAEMON_ADDRESS_ALLOWED: &str = "._-:@/[]%";
+
+/// Validate a host identifier before it is passed to `ssh` as an argument.
+pub fn validate_hostname(host: &str) -> Result<()> {
+    validate("hostname", host, HOSTNAME_ALLOWED, false)
+}
+
+/// Validate a container or similar runtime identifier before it is passed
+/// to `docker exec` and friends.
+pub fn validate_container_name(name: &str) -> Result<()> {
+    validate("container name", name, CONTAINER_ALLOWED, false)
+}
+
+pub fn validate_user(user: &str) -> Result<()> {
+    validate("user", user, USER_ALLOWED, false)
+}
+
+/// Validate a `docker exec --user` value, which may be `user`, `uid` or
+/// `user:group`.
+pub fn validate_docker_user(user: &str) -> Result<()> {
+    validate("docker user", user, DOCKER_USER_ALLOWED, false)
+}
+
+/// Validate a Docker daemon address such as `tcp://10.0.0.5:2376`,
+/// `ssh://user@host` or `unix:///run/docker.sock`.
+pub fn validate_daemon_address(address: &str) -> Result<()> {
+    validate("docker host", address, DAEMON_ADDRESS_ALLOWED, false)
+}
+
+/// Validate an absolute path such as a `docker exec --workdir`.
+pub fn validate_path(path: &str) -> Result<()> {
+    if !path.starts_with('/') {
+        return Err(FactsError::UnsafeIdentifier(
+            "path".to_string(),
+            path.to_string(),
+        ));
+    }
+    validate("path", path, PATH_ALLOWED, false)
+}
+
+pub fn validate_flags(flags: &str) -> Result<()> {
+    if flags.trim().is_empty() {
+        return Ok(());
+    }
+    validate("flags", flags.trim(), FLAGS_ALLOWED, true)
+}
+
+/// Validate a `ProxyCommand`. It is run by the local shell by design, so
+/// only line breaks and other control characters, which would let it smuggle
+/// extra ssh options or commands, are refused.
+pub fn validate_proxy_command(command: &str) -> Result<()> {
+    if command.trim().is_empty() || command.chars().any(|c| c.is_control()) {
+        return Err(FactsError::UnsafeIdentifier(
+            "proxy command".to_string(),
+            command.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Quote a value for safe interpolation into a POSIX shell command line.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn validate(kind: &str, value: &str, extra_allowed: &str, allow_leading_dash: bool) -> Result<()> {
+    if value.is_empty() {
+        return Err(FactsError::UnsafeIdentifier(
+            kind.to_string(),
+            value.to_string(),
+        ));
+    }
+
+    // A leading dash would be parsed as an option by ssh/docker
+    let starts_with_dash = !allow_leading_dash && value.starts_with('-');
+    let has_forbidden = value
+        .chars()
+        .any(|c| !(c.is_ascii_alphanumeric() || extra_allowed.contains(c)));
+
+    if starts_with_dash || has_forbidden {
+        return Err(FactsError::UnsafeIdentifier(
+            kind.to_string(),
+            value.to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE: &[&str] = &[
+        "web1; rm -rf /",
+        "-oProxyCommand=touch /tmp/pwned",
+        "$(id)",
+        "`id`",
+        "host\nid",
+        "host|id",
+        "host&&id",
+        "host > /etc/passwd",
+        "",
+    ];
+
+    #[test]
+    fn test_validate_hostname() {
+        for valid in [
+            "web1",
+            "web-1.example.com",
+            "deploy@10.0.0.1",
+            "[::1]",
+            "fe80::1%eth0",
+        ] {
+            assert!(validate_hostname(valid).is_ok(), "{valid} should be valid");
+        }
+
+        for hostile in HOSTILE {
+            assert!(
+                validate_hostname(hostile).is_err(),
+                "{hostile:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_container_name() {
+        assert!(validate_container_name("rustle-test_container.1").is_ok());
+        assert!(validate_container_name("user@host").is_err());
+
+        for hostile in HOSTILE {
+            assert!(validate_container_name(hostile).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_docker_user_and_path() {
+        assert!(validate_docker_user("1000:1000").is_ok());
+        assert!(validate_docker_user("app").is_ok());
+        assert!(validate_path("/srv/app_1").is_ok());
+        assert!(validate_path("relative/dir").is_err());
+
+        for hostile in HOSTILE {
+            assert!(validate_docker_user(hostile).is_err());
+            assert!(validate_path(hostile).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_flags() {
+        assert!(validate_flags("").is_ok());
+        assert!(validate_flags("-H -E").is_ok());
+        assert!(validate_flags("-H; id").is_err());
+        assert!(validate_flags("$(id)").is_err());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$(id)"), "'$(id)'");
+    }
+}
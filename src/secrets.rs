@@ -0,0 +1,162 @@
+//! Secrets the SSH backend needs, and where they come from.
+//!
+//! The SSH key passphrase can be read from an entry in the OS keyring under
+//! [`KEYRING_SERVICE`], and so can the become password, which may also come
+//! from a file or the environment; the SSH password is prompted for. All
+//! are held as [`Secret`]s so they never reach a log. The fact cache is
+//! written unencrypted, so there is no cache encryption key to look up;
+//! a keyring entry for it belongs with cache encryption, once there is any.
+
+use crate::error::{FactsError, Result};
+use std::fmt;
+
+/// Service name under which rustle-facts entries are stored in the OS keyring.
+pub const KEYRING_SERVICE: &str = "rustle-facts";
+
+/// A secret value that never prints its contents through `Debug` or `Display`.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+/// Secrets resolved at startup. Never serialized with the rest of the config.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub ssh_passphrase: Option<Secret>,
+    /// From `--ask-pass`, for hosts without `ansible_ssh_pass`
+    pub ssh_password: Option<Secret>,
+    pub become_password: Option<Secret>,
+}
+
+/// Read a secret from the controlling terminal without echoing it.
+#[cfg(unix)]
+pub fn prompt_secret(prompt: &str) -> Result<Secret> {
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufRead, BufReader, Write};
+    use std::process::Command;
+
+    let unavailable = |e: std::io::Error| {
+        FactsError::InvalidConfig(format!(
+            "Cannot prompt for a password without a terminal: {e}"
+        ))
+    };
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(unavailable)?;
+    let stty = |mode: &str| -> Result<()> {
+        Command::new("stty")
+            .arg(mode)
+            .stdin(File::open("/dev/tty")?)
+            .status()?;
+        Ok(())
+    };
+
+    write!(tty, "{prompt}").map_err(unavailable)?;
+    tty.flush().map_err(unavailable)?;
+    stty("-echo")?;
+    let mut line = String::new();
+    let read = BufReader::new(&tty).read_line(&mut line);
+    // Echo comes back on even when the read failed
+    stty("echo")?;
+    writeln!(tty).map_err(unavailable)?;
+    read.map_err(unavailable)?;
+
+    Ok(Secret::new(line.trim_end_matches(['\r', '\n'])))
+}
+
+#[cfg(not(unix))]
+pub fn prompt_secret(_prompt: &str) -> Result<Secret> {
+    Err(FactsError::InvalidConfig(
+        "Password prompting is only supported on Unix".to_string(),
+    ))
+}
+
+/// Look up a named entry in the system keyring (Secret Service on Linux,
+/// Keychain on macOS).
+#[cfg(feature = "keyring")]
+pub fn read_keyring_secret(entry: &str) -> Result<Secret> {
+    use std::process::Command;
+
+    let output = if cfg!(target_os = "macos") {
+        Command::new("security")
+            .args([
+                "find-generic-password",
+                "-s",
+                KEYRING_SERVICE,
+                "-a",
+                entry,
+                "-w",
+            ])
+            .output()
+    } else {
+        Command::new("secret-tool")
+            .args(["lookup", "service", KEYRING_SERVICE, "account", entry])
+            .output()
+    }
+    .map_err(|e| FactsError::InvalidConfig(format!("Failed to query keyring: {e}")))?;
+
+    if !output.status.success() {
+        return Err(FactsError::InvalidConfig(format!(
+            "Keyring entry '{entry}' not found for service '{KEYRING_SERVICE}'"
+        )));
+    }
+
+    let value = String::from_utf8_lossy(&output.stdout);
+    let value = value.trim_end_matches(['\r', '\n']);
+    if value.is_empty() {
+        return Err(FactsError::InvalidConfig(format!(
+            "Keyring entry '{entry}' is empty"
+        )));
+    }
+
+    Ok(Secret::new(value))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn read_keyring_secret(entry: &str) -> Result<Secret> {
+    Err(FactsError::InvalidConfig(format!(
+        "Keyring entry '{entry}' requested but rustle-facts was built without the `keyring` feature"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{secret:?}"), "Secret([REDACTED])");
+        assert_eq!(format!("{secret}"), "[REDACTED]");
+        assert_eq!(secret.expose(), "hunter2");
+
+        let credentials = Credentials {
+            ssh_passphrase: Some(secret),
+            ssh_password: None,
+            become_password: None,
+        };
+        assert!(!format!("{credentials:?}").contains("hunter2"));
+    }
+}
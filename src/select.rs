@@ -0,0 +1,260 @@
+//! `--select` projections applied to the output document before it is
+//! written.
+//!
+//! An expression is a dotted path with an optional leading `.`, e.g.
+//! `inventory.host_facts` or `.plays[0].hosts`. `[N]` indexes an array,
+//! `*` (or `[*]`) projects the rest of the path over every element of an
+//! array or value of an object, and `{a,b}` keeps only the named fields of
+//! an object. A field whose name is not plain, such as a host named by its
+//! FQDN or IP address, is quoted: `inventory.host_facts."web1.example.com"`,
+//! with `\"` and `\\` escaping a quote and a backslash. Elements a
+//! projection finds nothing in are dropped; a path that finds nothing at all
+//! yields `null`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    Wildcard,
+    Fields(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Selection {
+    expression: String,
+    segments: Vec<Segment>,
+}
+
+impl Selection {
+    /// Project `value`, returning `null` when the path does not exist.
+    pub fn apply(&self, value: &Value) -> Value {
+        select(&self.segments, value).unwrap_or(Value::Null)
+    }
+}
+
+fn select(segments: &[Segment], value: &Value) -> Option<Value> {
+    let Some((segment, rest)) = segments.split_first() else {
+        return Some(value.clone());
+    };
+
+    match segment {
+        Segment::Field(name) => select(rest, value.as_object()?.get(name)?),
+        Segment::Index(index) => select(rest, value.as_array()?.get(*index)?),
+        Segment::Fields(names) => {
+            let object = value.as_object()?;
+            let picked: Map<String, Value> = names
+                .iter()
+                .filter_map(|name| Some((name.clone(), object.get(name)?.clone())))
+                .collect();
+            select(rest, &Value::Object(picked))
+        }
+        Segment::Wildcard => match value {
+            Value::Array(items) => Some(Value::Array(
+                items.iter().filter_map(|item| select(rest, item)).collect(),
+            )),
+            Value::Object(object) => Some(Value::Object(
+                object
+                    .iter()
+                    .filter_map(|(key, item)| Some((key.clone(), select(rest, item)?)))
+                    .collect(),
+            )),
+            _ => None,
+        },
+    }
+}
+
+impl FromStr for Selection {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid selection '{expression}': {reason}");
+        let trimmed = expression.trim();
+        let mut rest = trimmed.strip_prefix('.').unwrap_or(trimmed);
+        let mut segments = Vec::new();
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let (inner, after) = after
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unclosed '['"))?;
+                segments.push(match inner.trim() {
+                    "*" => Segment::Wildcard,
+                    index => Segment::Index(
+                        index
+                            .parse()
+                            .map_err(|_| invalid("expected an index or '*' in '[]'"))?,
+                    ),
+                });
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let (inner, after) = after
+                    .split_once('}')
+                    .ok_or_else(|| invalid("unclosed '{'"))?;
+                let names: Vec<String> = inner
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .collect();
+                if names.iter().any(|name| !is_field_name(name)) {
+                    return Err(invalid("expected field names in '{}'"));
+                }
+                segments.push(Segment::Fields(names));
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('"') {
+                let (name, after) = quoted_name(after).ok_or_else(|| invalid("unclosed '\"'"))?;
+                segments.push(Segment::Field(name));
+                rest = after;
+            } else {
+                let end = rest.find(['.', '[', '{']).unwrap_or(rest.len());
+                let name = &rest[..end];
+                segments.push(match name {
+                    "*" => Segment::Wildcard,
+                    name if is_field_name(name) => Segment::Field(name.to_string()),
+                    _ => return Err(invalid("expected a field name")),
+                });
+                rest = &rest[end..];
+            }
+
+            // Segments are joined by '.', which may be left out before '['
+            // and '{'
+            rest = match rest.strip_prefix('.') {
+                Some("") => return Err(invalid("trailing '.'")),
+                Some(after) => after,
+                None if rest.is_empty() || rest.starts_with(['[', '{']) => rest,
+                None => return Err(invalid("expected '.' between segments")),
+            };
+        }
+
+        Ok(Selection {
+            expression: trimmed.to_string(),
+            segments,
+        })
+    }
+}
+
+/// The name up to the closing quote, unescaped, and what follows it.
+fn quoted_name(quoted: &str) -> Option<(String, &str)> {
+    let mut name = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((name, &quoted[index + 1..])),
+            '\\' => name.push(chars.next()?.1),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-".contains(c))
+}
+
+impl TryFrom<String> for Selection {
+    type Error = String;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        expression.parse()
+    }
+}
+
+impl From<Selection> for String {
+    fn from(selection: Selection) -> Self {
+        selection.expression
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// Parse a `--select` value.
+pub fn parse_selection(expression: &str) -> Result<Selection, String> {
+    expression.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(expression: &str, value: &Value) -> Value {
+        parse_selection(expression).unwrap().apply(value)
+    }
+
+    #[test]
+    fn test_apply() {
+        let doc = json!({
+            "plays": [{"hosts": "web"}, {"hosts": "db"}],
+            "inventory": {"host_facts": {
+                "web1": {"ansible_architecture": "x86_64", "ansible_system": "Linux"},
+                "db1": {"ansible_architecture": "aarch64", "ansible_system": "Linux"}
+            }}
+        });
+
+        assert_eq!(apply(".", &doc), doc);
+        assert_eq!(apply(".plays[1].hosts", &doc), json!("db"));
+        assert_eq!(apply("plays[*].hosts", &doc), json!(["web", "db"]));
+        assert_eq!(
+            apply("inventory.host_facts.*.ansible_architecture", &doc),
+            json!({"web1": "x86_64", "db1": "aarch64"})
+        );
+        assert_eq!(
+            apply("inventory.host_facts.web1{ansible_system}", &doc),
+            json!({"ansible_system": "Linux"})
+        );
+        assert_eq!(apply("inventory.missing", &doc), Value::Null);
+        assert_eq!(apply("plays[*].missing", &doc), json!([]));
+    }
+
+    #[test]
+    fn test_quoted_fields() {
+        let doc = json!({"host_facts": {
+            "web1.example.com": {"ansible_system": "Linux"},
+            "10.0.0.5": {"ansible_system": "FreeBSD"},
+            "odd\"name": {"ansible_system": "SunOS"}
+        }});
+        assert_eq!(
+            apply(r#"host_facts."web1.example.com".ansible_system"#, &doc),
+            json!("Linux")
+        );
+        assert_eq!(
+            apply(r#"host_facts."10.0.0.5"{ansible_system}"#, &doc),
+            json!({"ansible_system": "FreeBSD"})
+        );
+        assert_eq!(
+            apply(r#"host_facts."odd\"name".ansible_system"#, &doc),
+            json!("SunOS")
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        for expression in [
+            "plays[",
+            "plays[x]",
+            "inventory.",
+            "a..b",
+            "a{b",
+            "a b",
+            "a{}",
+            r#"a."b"#,
+            r#"a."b"c"#,
+        ] {
+            assert!(
+                parse_selection(expression).is_err(),
+                "{expression} should not parse"
+            );
+        }
+    }
+}
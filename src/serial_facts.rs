@@ -0,0 +1,386 @@
+//! Facts for `ansible_connection: serial` hosts.
+//!
+//! Lab hardware and embedded boards are often reachable only through a
+//! serial console before their network is up. The device named by
+//! `ansible_serial_device` is put in raw mode at `ansible_serial_baud`
+//! (115200 by default) with `stty`, the console is woken with a carriage
+//! return, any `login:`/`Password:` prompt is answered from `ansible_user`
+//! and `ansible_password`, and the probe is typed at the shell prompt. The
+//! console echoes what is typed, so the output is cut from between markers
+//! that only the shell's `printf` can produce.
+
+use crate::config::FactsConfig;
+use crate::error::{FactsError, Result};
+use crate::exec_backend;
+use crate::faults::inject_faults;
+use crate::sanitize::{validate_path, validate_user};
+use crate::secrets::Secret;
+use crate::session::run_recorded;
+use crate::ssh_facts::ProbeStrategy;
+use crate::types::{ArchitectureFacts, HostEntry};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+const DEFAULT_BAUD: u32 = 115_200;
+/// How long to wait before polling the non-blocking device again when it
+/// has nothing to read or no room to write.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Typed as two `printf` arguments so the echoed command line never contains
+/// the joined marker.
+const MARKER_PREFIX: &str = "RUSTLE_FACTS_";
+const BEGIN_MARKER: &str = "RUSTLE_FACTS_BEGIN";
+const END_MARKER: &str = "RUSTLE_FACTS_END";
+
+/// How long a silent console is given before it is woken again.
+const WAKE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// A console device and the login to use on it.
+#[derive(Debug, Clone)]
+struct SerialTarget {
+    host: String,
+    device: String,
+    baud: u32,
+    user: Option<String>,
+    password: Option<Secret>,
+}
+
+impl SerialTarget {
+    fn from_host(host: &HostEntry) -> Result<Self> {
+        let var = |key: &str| {
+            host.vars
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+
+        let device = var("ansible_serial_device")
+            .or_else(|| var("ansible_host").filter(|h| h.starts_with("/dev/")))
+            .ok_or_else(|| {
+                FactsError::InvalidConfig(format!(
+                    "Serial host {} has no ansible_serial_device",
+                    host.name
+                ))
+            })?;
+        validate_path(&device)?;
+
+        let baud = match host.vars.get("ansible_serial_baud") {
+            None => DEFAULT_BAUD,
+            Some(value) => value
+                .as_u64()
+                .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+                .and_then(|baud| u32::try_from(baud).ok())
+                .filter(|baud| *baud > 0)
+                .ok_or_else(|| {
+                    FactsError::InvalidConfig(format!(
+                        "Invalid ansible_serial_baud {value} for {}",
+                        host.name
+                    ))
+                })?,
+        };
+
+        let user = var("ansible_user");
+        if let Some(user) = &user {
+            validate_user(user)?;
+        }
+
+        Ok(SerialTarget {
+            host: host.name.clone(),
+            device,
+            baud,
+            user,
+            password: var("ansible_password").map(Secret::new),
+        })
+    }
+}
+
+/// What the console is waiting for, judged from the text after the last
+/// line break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConsoleState {
+    Login,
+    Password,
+    Shell,
+    Waiting,
+}
+
+fn console_state(buffer: &str) -> ConsoleState {
+    let tail = buffer
+        .rsplit(['\n', '\r'])
+        .next()
+        .unwrap_or_default()
+        .trim_matches(|c: char| c.is_whitespace() || c.is_control());
+    let lower = tail.to_lowercase();
+
+    if lower.ends_with("login:") || lower.ends_with("username:") {
+        ConsoleState::Login
+    } else if lower.ends_with("password:") {
+        ConsoleState::Password
+    } else if tail.ends_with(['#', '$', '>', '%']) {
+        ConsoleState::Shell
+    } else {
+        ConsoleState::Waiting
+    }
+}
+
+/// The line typed at the prompt: the probe between the two markers.
+fn typed_command(command: &str) -> String {
+    format!(
+        "printf '%s%s\\n' {MARKER_PREFIX} BEGIN; {command}; printf '%s%s\\n' {MARKER_PREFIX} END\r"
+    )
+}
+
+/// The probe output once both markers have arrived.
+fn extract_output(buffer: &str) -> Option<&str> {
+    let start = buffer.find(BEGIN_MARKER)? + BEGIN_MARKER.len();
+    let len = buffer[start..].find(END_MARKER)?;
+    Some(buffer[start..start + len].trim_matches(['\r', '\n']))
+}
+
+/// Gather minimal facts for hosts using serial console connections
+pub async fn gather_minimal_facts(
+    hosts: Vec<HostEntry>,
+    config: &FactsConfig,
+) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(
+        exec_backend::gather_each(hosts, config, |host, config| async move {
+            gather_host_facts(&host, &config).await
+        })
+        .await,
+    )
+}
+
+async fn gather_host_facts(host: &HostEntry, config: &FactsConfig) -> Result<ArchitectureFacts> {
+    let target = SerialTarget::from_host(host)?;
+    let strategy = ProbeStrategy::for_host(host)?;
+    inject_faults(config, &host.name).await?;
+
+    let command = strategy.command(config)?;
+    let deadline = Instant::now() + config.host_budget();
+    let output = run_recorded(
+        config.session.as_deref(),
+        "serial",
+        &target.device.clone(),
+        &command,
+        || {
+            let command = command.clone();
+            async move {
+                let stdout =
+                    tokio::task::spawn_blocking(move || converse(&target, &command, deadline))
+                        .await
+                        .map_err(|e| FactsError::TaskJoin(e.to_string()))??;
+                Ok((Some(0), stdout, String::new()))
+            }
+        },
+    )
+    .await?;
+
+    strategy
+        .parse(&output.stdout, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))
+}
+
+/// Log in if asked to, run `command` at the shell prompt and return its
+/// output. Blocking; reads return after at most 100ms so the deadline is
+/// honoured.
+fn converse(target: &SerialTarget, command: &str, deadline: Instant) -> Result<String> {
+    let mut port = open_device(target)?;
+    configure_line(target)?;
+    let mut buffer = String::new();
+    let mut password_sent = false;
+    let mut last_wake = Instant::now();
+    send(&mut port, b"\r")?;
+
+    loop {
+        match console_state(&buffer) {
+            ConsoleState::Shell => break,
+            ConsoleState::Login if password_sent => {
+                return Err(FactsError::AuthenticationFailed(
+                    target.host.clone(),
+                    "the console asked for a login again after the password".to_string(),
+                ));
+            }
+            ConsoleState::Login => {
+                let user = target.user.as_deref().ok_or_else(|| {
+                    FactsError::ConnectionFailed(
+                        target.host.clone(),
+                        "console asked for a login but ansible_user is not set".to_string(),
+                    )
+                })?;
+                debug!("Logging in to {} as {}", target.device, user);
+                send(&mut port, format!("{user}\r").as_bytes())?;
+                buffer.clear();
+            }
+            ConsoleState::Password => {
+                let password = target.password.as_ref().ok_or_else(|| {
+                    FactsError::ConnectionFailed(
+                        target.host.clone(),
+                        "console asked for a password but ansible_password is not set".to_string(),
+                    )
+                })?;
+                send(&mut port, format!("{}\r", password.expose()).as_bytes())?;
+                password_sent = true;
+                buffer.clear();
+            }
+            ConsoleState::Waiting => {
+                if read_more(target, &mut port, &mut buffer, deadline)? == 0
+                    && last_wake.elapsed() >= WAKE_INTERVAL
+                {
+                    send(&mut port, b"\r")?;
+                    last_wake = Instant::now();
+                }
+            }
+        }
+    }
+
+    buffer.clear();
+    send(&mut port, typed_command(command).as_bytes())?;
+    loop {
+        if let Some(output) = extract_output(&buffer) {
+            return Ok(output.to_string());
+        }
+        read_more(target, &mut port, &mut buffer, deadline)?;
+    }
+}
+
+/// Opens the device without making it this process's controlling terminal
+/// and without waiting for carrier detect, which a bare console cable never
+/// raises. The descriptor stays non-blocking; `read_more` polls it.
+fn open_device(target: &SerialTarget) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY);
+    }
+    Ok(options.open(&target.device)?)
+}
+
+/// Raw mode without local echo, ignoring the modem control lines
+/// (`clocal`) so reads and writes don't hang on a missing carrier.
+fn configure_line(target: &SerialTarget) -> Result<()> {
+    let device_flag = if cfg!(target_os = "macos") {
+        "-f"
+    } else {
+        "-F"
+    };
+    let output = std::process::Command::new("stty")
+        .arg(device_flag)
+        .arg(&target.device)
+        .arg(target.baud.to_string())
+        .args(["raw", "-echo", "clocal", "min", "0", "time", "1"])
+        .output()?;
+    if !output.status.success() {
+        return Err(FactsError::ConnectionFailed(
+            target.host.clone(),
+            format!(
+                "could not configure {}: {}",
+                target.device,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// `write_all` for the non-blocking device: waits out a full output buffer
+/// instead of failing, since a long probe can outrun a slow baud rate.
+fn send(port: &mut File, mut bytes: &[u8]) -> Result<()> {
+    while !bytes.is_empty() {
+        match port.write(bytes) {
+            Ok(0) => return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into()),
+            Ok(written) => bytes = &bytes[written..],
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(READ_POLL_INTERVAL);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+fn read_more(
+    target: &SerialTarget,
+    port: &mut File,
+    buffer: &mut String,
+    deadline: Instant,
+) -> Result<usize> {
+    if Instant::now() >= deadline {
+        return Err(FactsError::Timeout(target.host.clone()));
+    }
+    let mut chunk = [0u8; 1024];
+    let read = match port.read(&mut chunk) {
+        Ok(read) => read,
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+            std::thread::sleep(READ_POLL_INTERVAL);
+            0
+        }
+        Err(e) => return Err(e.into()),
+    };
+    buffer.push_str(&String::from_utf8_lossy(&chunk[..read]));
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_target_from_host() {
+        let mut host = HostEntry::new("board1");
+        assert!(SerialTarget::from_host(&host).is_err());
+
+        host.vars.insert(
+            "ansible_serial_device".to_string(),
+            serde_json::json!("/dev/ttyUSB0"),
+        );
+        let target = SerialTarget::from_host(&host).unwrap();
+        assert_eq!(target.device, "/dev/ttyUSB0");
+        assert_eq!(target.baud, 115_200);
+
+        host.vars
+            .insert("ansible_serial_baud".to_string(), serde_json::json!("9600"));
+        assert_eq!(SerialTarget::from_host(&host).unwrap().baud, 9600);
+
+        host.vars.insert(
+            "ansible_serial_device".to_string(),
+            serde_json::json!("/dev/ttyUSB0; reboot"),
+        );
+        assert!(SerialTarget::from_host(&host).is_err());
+    }
+
+    #[test]
+    fn test_console_state() {
+        assert_eq!(console_state(""), ConsoleState::Waiting);
+        assert_eq!(
+            console_state("\r\nBuildroot 2023.02\r\nbuildroot login: "),
+            ConsoleState::Login
+        );
+        assert_eq!(console_state("\r\nPassword: "), ConsoleState::Password);
+        assert_eq!(console_state("\r\n[root@board1 ~]# "), ConsoleState::Shell);
+        assert_eq!(console_state("\r\n/ $ "), ConsoleState::Shell);
+        assert_eq!(
+            console_state("[    3.141] usb 1-1: new device\r\n"),
+            ConsoleState::Waiting
+        );
+    }
+
+    #[test]
+    fn test_extract_output_ignores_echo() {
+        let typed = typed_command("uname -m; uname -s");
+        assert!(!typed.contains(BEGIN_MARKER));
+
+        // The echoed line arrives before the output
+        let mut buffer = typed.replace('\r', "\r\n");
+        assert_eq!(extract_output(&buffer), None);
+        buffer.push_str("RUSTLE_FACTS_BEGIN\r\narmv7l\r\nLinux\r\n");
+        assert_eq!(extract_output(&buffer), None);
+        buffer.push_str("RUSTLE_FACTS_END\r\n# ");
+        assert_eq!(extract_output(&buffer), Some("armv7l\r\nLinux"));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::error::{FactsError, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Version of the session recording file.
+pub const SESSION_VERSION: u32 = 1;
+
+/// One remote command and what it produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    pub backend: String,
+    pub target: String,
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRecording {
+    pub version: String,
+    pub commands: Vec<RecordedCommand>,
+}
+
+/// A record or replay session shared by all gatherers of a run.
+#[derive(Debug)]
+pub enum Session {
+    Record(Mutex<Vec<RecordedCommand>>),
+    Replay(Mutex<Vec<RecordedCommand>>),
+}
+
+impl Session {
+    pub fn recording() -> Self {
+        Session::Record(Mutex::new(Vec::new()))
+    }
+
+    pub fn load_replay(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            FactsError::InvalidConfig(format!("Failed to read replay session: {e}"))
+        })?;
+        let recording: SessionRecording = serde_json::from_str(&content)
+            .map_err(|e| FactsError::InvalidConfig(format!("Invalid replay session: {e}")))?;
+
+        info!(
+            "Replaying {} recorded commands from {:?}",
+            recording.commands.len(),
+            path
+        );
+        Ok(Session::Replay(Mutex::new(recording.commands)))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, Session::Replay(_))
+    }
+
+    /// Take the first recorded result for this command. Repeated commands are
+    /// replayed in the order they were recorded.
+    pub fn take_replay(
+        &self,
+        backend: &str,
+        target: &str,
+        command: &str,
+    ) -> Option<RecordedCommand> {
+        let Session::Replay(commands) = self else {
+            return None;
+        };
+
+        let mut commands = commands.lock().unwrap_or_else(|e| e.into_inner());
+        let index = commands.iter().position(|recorded| {
+            recorded.backend == backend && recorded.target == target && recorded.command == command
+        })?;
+        Some(commands.remove(index))
+    }
+
+    pub fn record(&self, entry: RecordedCommand) {
+        if let Session::Record(commands) = self {
+            commands
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(entry);
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let Session::Record(commands) = self else {
+            return Ok(());
+        };
+
+        let recording = SessionRecording {
+            version: SESSION_VERSION.to_string(),
+            commands: commands.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        };
+
+        std::fs::write(path, serde_json::to_string_pretty(&recording)?)?;
+        info!(
+            "Recorded {} commands to {:?}",
+            recording.commands.len(),
+            path
+        );
+        Ok(())
+    }
+}
+
+/// Replay a command from the session if replaying, otherwise run it live and
+/// record the outcome if recording.
+pub async fn run_recorded<F, Fut>(
+    session: Option<&Session>,
+    backend: &str,
+    target: &str,
+    command: &str,
+    run: F,
+) -> Result<RecordedCommand>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<(Option<i32>, String, String)>>,
+{
+    if let Some(session) = session.filter(|s| s.is_replay()) {
+        return session
+            .take_replay(backend, target, command)
+            .ok_or_else(|| {
+                FactsError::ConnectionFailed(
+                    target.to_string(),
+                    "no recorded output in replay session".to_string(),
+                )
+            });
+    }
+
+    let (exit_code, stdout, stderr) = run().await?;
+    let entry = RecordedCommand {
+        backend: backend.to_string(),
+        target: target.to_string(),
+        command: command.to_string(),
+        exit_code,
+        stdout,
+        stderr,
+    };
+
+    if let Some(session) = session {
+        session.record(entry.clone());
+    }
+
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("session.json");
+
+        let recorder = Session::recording();
+        for stdout in ["first", "second"] {
+            run_recorded(Some(&recorder), "ssh", "web1", "uname", || async move {
+                Ok((Some(0), stdout.to_string(), String::new()))
+            })
+            .await
+            .unwrap();
+        }
+        recorder.save(&path).unwrap();
+
+        let replay = Session::load_replay(&path).unwrap();
+        let never_run = || async { panic!("replay must not execute commands") };
+
+        let first = run_recorded(Some(&replay), "ssh", "web1", "uname", never_run)
+            .await
+            .unwrap();
+        assert_eq!(first.stdout, "first");
+
+        let second = run_recorded(Some(&replay), "ssh", "web1", "uname", never_run)
+            .await
+            .unwrap();
+        assert_eq!(second.stdout, "second");
+
+        assert!(
+            run_recorded(Some(&replay), "ssh", "web2", "uname", never_run)
+                .await
+                .is_err()
+        );
+    }
+}
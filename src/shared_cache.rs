@@ -0,0 +1,225 @@
+//! In-memory fact cache for long-running callers.
+//!
+//! A one-shot run reads the cache file at start and writes it at the end.
+//! A process that enriches many inputs, such as a server handling one
+//! request per playbook, would pay that file I/O on every call. A
+//! `SharedCache` set on `FactsConfig::shared_cache` keeps the cache in
+//! memory instead: each run starts from a copy of it and hands back the
+//! hosts it gathered, so concurrent runs never overwrite each other's
+//! entries. The file is written on every update, or with a write-behind
+//! interval at most that often and once more when the last handle is
+//! dropped.
+
+use crate::cache::{load_cache, save_cache};
+use crate::error::Result;
+use crate::hostname::normalize_hostname;
+use crate::types::FactCache;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+use std::time::Duration;
+use tracing::warn;
+
+/// A fact cache shared between runs, backed by a cache file.
+#[derive(Debug, Clone)]
+pub struct SharedCache {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    cache: RwLock<FactCache>,
+    /// `None` writes the file on every update
+    write_behind: Option<Duration>,
+    dirty: AtomicBool,
+    flusher: Once,
+}
+
+impl SharedCache {
+    /// Load `path` into memory. With `write_behind`, updates reach the
+    /// file at most once per interval, flushed from a task on the Tokio
+    /// runtime of the first update.
+    pub fn open(path: &Path, write_behind: Option<Duration>) -> Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Inner {
+                path: path.to_path_buf(),
+                cache: RwLock::new(load_cache(path)?),
+                write_behind: write_behind.filter(|interval| !interval.is_zero()),
+                dirty: AtomicBool::new(false),
+                flusher: Once::new(),
+            }),
+        })
+    }
+
+    /// A copy of the cache for one run to read and update.
+    pub fn snapshot(&self) -> FactCache {
+        self.inner.read().clone()
+    }
+
+    /// Take the entries of `hosts` from a run's copy of the cache; hosts it
+    /// no longer has are removed. Other hosts keep what is in memory, which
+    /// another run may have updated meanwhile. Runs pass every host they
+    /// gathered, restored or invalidated.
+    pub fn store<'a>(
+        &self,
+        updated: &FactCache,
+        hosts: impl IntoIterator<Item = &'a String>,
+    ) -> Result<()> {
+        {
+            let mut cache = self.inner.write();
+            for host in hosts {
+                let host = normalize_hostname(host);
+                match updated.facts.get(&host) {
+                    Some(cached) => cache.facts.insert(host, cached.clone()),
+                    None => cache.facts.remove(&host),
+                };
+            }
+        }
+        self.inner.dirty.store(true, Ordering::Release);
+
+        match self.inner.write_behind {
+            None => self.flush(),
+            Some(interval) => {
+                self.inner
+                    .flusher
+                    .call_once(|| spawn_flusher(Arc::downgrade(&self.inner), interval));
+                Ok(())
+            }
+        }
+    }
+
+    /// Drop entries older than `ttl` from memory. They reach the file with
+    /// the next store or flush.
+    pub fn cleanup_stale(&self, ttl: u64) -> usize {
+        let removed = self.inner.write().cleanup_stale(ttl);
+        if removed > 0 {
+            self.inner.dirty.store(true, Ordering::Release);
+        }
+        removed
+    }
+
+    /// Write pending updates to the cache file now.
+    pub fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Inner {
+    fn read(&self) -> RwLockReadGuard<'_, FactCache> {
+        // Entries are replaced whole, so a poisoned lock is still usable
+        self.cache
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, FactCache> {
+        self.cache
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn flush(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let snapshot = self.read().clone();
+        save_cache(&self.path, &snapshot).inspect_err(|_| {
+            self.dirty.store(true, Ordering::Release);
+        })
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("Failed to write cache file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Flush every `interval` until the cache is dropped.
+fn spawn_flusher(inner: Weak<Inner>, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let Some(inner) = inner.upgrade() else {
+                break;
+            };
+            if let Err(e) = inner.flush() {
+                warn!("Failed to write cache file {:?}: {}", inner.path, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ArchitectureFacts;
+
+    fn gathered(cache: &mut FactCache, host: &str) -> String {
+        cache.update(host.to_string(), ArchitectureFacts::fallback());
+        host.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_runs_keep_each_others_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("facts.json");
+        let shared = SharedCache::open(&path, None).unwrap();
+
+        let mut first = shared.snapshot();
+        let mut second = shared.snapshot();
+        let web1 = gathered(&mut first, "web1");
+        let db1 = gathered(&mut second, "db1");
+        shared.store(&first, [&web1]).unwrap();
+        shared.store(&second, [&db1]).unwrap();
+
+        let on_disk = load_cache(&path).unwrap();
+        assert!(on_disk.facts.contains_key("web1"));
+        assert!(on_disk.facts.contains_key("db1"));
+
+        // A host the run dropped is dropped from memory too
+        let mut third = shared.snapshot();
+        third.invalidate("web1");
+        shared.store(&third, [&web1]).unwrap();
+        assert!(!shared.snapshot().facts.contains_key("web1"));
+    }
+
+    #[tokio::test]
+    async fn test_write_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("facts.json");
+        let shared = SharedCache::open(&path, Some(Duration::from_secs(60))).unwrap();
+
+        let mut run = shared.snapshot();
+        let web1 = gathered(&mut run, "web1");
+        shared.store(&run, [&web1]).unwrap();
+        assert!(!path.exists());
+
+        shared.flush().unwrap();
+        assert!(load_cache(&path).unwrap().facts.contains_key("web1"));
+
+        let db1 = gathered(&mut run, "db1");
+        shared.store(&run, [&db1]).unwrap();
+        assert!(!load_cache(&path).unwrap().facts.contains_key("db1"));
+        drop(shared);
+        assert!(load_cache(&path).unwrap().facts.contains_key("db1"));
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_stale_reaches_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("facts.json");
+        let shared = SharedCache::open(&path, None).unwrap();
+
+        let mut run = shared.snapshot();
+        let web1 = gathered(&mut run, "web1");
+        shared.store(&run, [&web1]).unwrap();
+        assert_eq!(shared.cleanup_stale(3600), 0);
+        assert_eq!(shared.cleanup_stale(0), 1);
+        shared.flush().unwrap();
+        assert!(load_cache(&path).unwrap().facts.is_empty());
+    }
+}
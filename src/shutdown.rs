@@ -0,0 +1,224 @@
+//! Cooperative shutdown on SIGINT/SIGTERM or when the run deadline expires.
+//!
+//! The first signal asks gatherers to stop: unfinished hosts are abandoned,
+//! facts gathered so far are still cached, and with `--write-partial` the
+//! enriched document is written with `partial: true`. A second signal exits
+//! immediately. An expired `--deadline` stops gatherers the same way, but
+//! the output is always written and the run succeeds. Exceeding
+//! `--max-failed-hosts` or `--max-failed-percent` stops them like a signal.
+
+use crate::error::FactsError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+use tracing::warn;
+
+/// Exit status of an interrupted run, as for a shell killed by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Why gathering was stopped early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Interrupted,
+    DeadlineExpired,
+    TooManyFailures,
+}
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: Arc<watch::Sender<Option<StopReason>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            requested: Arc::new(watch::Sender::new(None)),
+        }
+    }
+}
+
+impl StopReason {
+    /// The error a run stopped for this reason ends with, once the facts
+    /// gathered so far are cached; `None` when it still succeeds.
+    pub fn error(self) -> Option<FactsError> {
+        match self {
+            StopReason::Interrupted => Some(FactsError::Interrupted),
+            StopReason::TooManyFailures => Some(FactsError::TooManyFailures),
+            StopReason::DeadlineExpired => None,
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.stop(StopReason::Interrupted);
+    }
+
+    /// Stop for `reason` unless already stopped; the first reason is kept.
+    pub fn stop(&self, reason: StopReason) {
+        self.requested.send_if_modified(|current| {
+            let unset = current.is_none();
+            if unset {
+                *current = Some(reason);
+            }
+            unset
+        });
+    }
+
+    pub fn reason(&self) -> Option<StopReason> {
+        *self.requested.borrow()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    /// Resolves once shutdown has been requested.
+    pub async fn wait(&self) {
+        let mut requested = self.requested.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = requested.wait_for(Option::is_some).await;
+    }
+
+    /// The next of `tasks` to finish, or `None` once all have or shutdown
+    /// is requested, when the unfinished ones are abandoned. A gatherer
+    /// looping over it keeps the hosts that finished first.
+    pub async fn join_next<T: 'static>(
+        &self,
+        tasks: &mut JoinSet<T>,
+    ) -> Option<Result<T, JoinError>> {
+        tokio::select! {
+            biased;
+            _ = self.wait() => {
+                if !tasks.is_empty() {
+                    warn!("Abandoning {} unfinished hosts", tasks.len());
+                    tasks.abort_all();
+                }
+                None
+            }
+            joined = tasks.join_next() => joined,
+        }
+    }
+
+    /// A shutdown for one run that follows this one and also stops once
+    /// `deadline` has passed. The deadline is cancelled when the guard is
+    /// dropped.
+    pub fn with_deadline(&self, deadline: Duration) -> (Shutdown, DeadlineGuard) {
+        let run = Shutdown::default();
+        let task = tokio::spawn({
+            let parent = self.clone();
+            let run = run.clone();
+            async move {
+                tokio::select! {
+                    _ = parent.wait() => run.stop(parent.reason().unwrap_or(StopReason::Interrupted)),
+                    _ = tokio::time::sleep(deadline) => run.stop(StopReason::DeadlineExpired),
+                }
+            }
+        });
+        (run, DeadlineGuard(task))
+    }
+
+    /// Request shutdown on the first SIGINT/SIGTERM and exit on the second.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            warn!("Interrupted; saving gathered facts (interrupt again to exit immediately)");
+            shutdown.request();
+            wait_for_signal().await;
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        });
+    }
+}
+
+/// Cancels a run deadline when dropped.
+#[derive(Debug)]
+pub struct DeadlineGuard(JoinHandle<()>);
+
+impl Drop for DeadlineGuard {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wait_resolves_after_request() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.is_requested());
+
+        let waiter = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.wait().await }
+        });
+        shutdown.request();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Already requested: resolves immediately
+        assert!(shutdown.is_requested());
+        tokio::time::timeout(Duration::from_secs(1), shutdown.wait())
+            .await
+            .unwrap();
+        shutdown.stop(StopReason::DeadlineExpired);
+        assert_eq!(shutdown.reason(), Some(StopReason::Interrupted));
+    }
+
+    #[tokio::test]
+    async fn test_join_next_keeps_finished_tasks() {
+        let shutdown = Shutdown::default();
+        let mut tasks = JoinSet::new();
+        tasks.spawn(async { 1 });
+        tasks.spawn(std::future::pending::<i32>());
+
+        assert_eq!(shutdown.join_next(&mut tasks).await.unwrap().unwrap(), 1);
+        shutdown.request();
+        assert!(shutdown.join_next(&mut tasks).await.is_none());
+        assert!(tasks.is_empty() || tasks.join_next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_with_deadline() {
+        let parent = Shutdown::default();
+        let (run, _guard) = parent.with_deadline(Duration::from_millis(50));
+        assert!(!run.is_requested());
+        tokio::time::timeout(Duration::from_secs(1), run.wait())
+            .await
+            .unwrap();
+        assert_eq!(run.reason(), Some(StopReason::DeadlineExpired));
+        assert!(!parent.is_requested());
+
+        let (run, _guard) = parent.with_deadline(Duration::from_secs(30));
+        parent.request();
+        run.wait().await;
+        assert_eq!(run.reason(), Some(StopReason::Interrupted));
+    }
+}
@@ -0,0 +1,120 @@
+//! The local OpenSSH client's version, so generated options are ones it
+//! understands. RHEL 7 bastions still ship OpenSSH 7.4, which rejects
+//! `StrictHostKeyChecking=accept-new` with "Bad configuration option".
+
+use std::sync::OnceLock;
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OpenSshVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl OpenSshVersion {
+    /// Parse the `ssh -V` banner, e.g. `OpenSSH_7.4p1, OpenSSL 1.0.2k-fips`
+    /// or `OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2`.
+    pub fn parse(banner: &str) -> Option<Self> {
+        let rest = &banner[banner.find("OpenSSH_")? + "OpenSSH_".len()..];
+        let version = &rest[rest.find(|c: char| c.is_ascii_digit())?..];
+        let (major, rest) = version.split_once('.')?;
+        let minor: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        Some(Self {
+            major: major.parse().ok()?,
+            minor: minor.parse().ok()?,
+        })
+    }
+}
+
+/// What the local client supports. An unknown version (no `ssh` on PATH, or
+/// not OpenSSH) is treated as current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SshFeatures {
+    version: Option<OpenSshVersion>,
+}
+
+impl SshFeatures {
+    pub fn for_version(version: Option<OpenSshVersion>) -> Self {
+        Self { version }
+    }
+
+    /// Run `ssh -V` once per process.
+    pub fn detect() -> Self {
+        static DETECTED: OnceLock<SshFeatures> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            let version = std::process::Command::new("ssh")
+                .arg("-V")
+                .output()
+                .ok()
+                .and_then(|output| {
+                    // Older clients print the banner to stderr, some builds to stdout
+                    let banner = format!(
+                        "{}{}",
+                        String::from_utf8_lossy(&output.stderr),
+                        String::from_utf8_lossy(&output.stdout)
+                    );
+                    OpenSshVersion::parse(&banner)
+                });
+            match version {
+                Some(v) => info!("Detected OpenSSH client {}.{}", v.major, v.minor),
+                None => debug!("Could not detect the OpenSSH client version"),
+            }
+            Self::for_version(version)
+        })
+    }
+
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        self.version
+            .is_none_or(|version| version >= OpenSshVersion { major, minor })
+    }
+
+    /// `StrictHostKeyChecking=accept-new`, added in 7.6
+    pub fn accept_new(&self) -> bool {
+        self.at_least(7, 6)
+    }
+
+    /// `ControlPersist`, added in 5.6
+    pub fn control_persist(&self) -> bool {
+        self.at_least(5, 6)
+    }
+
+    /// The `%C` connection hash in `ControlPath`, added in 6.7
+    pub fn control_path_hash(&self) -> bool {
+        self.at_least(6, 7)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            OpenSshVersion::parse("OpenSSH_7.4p1, OpenSSL 1.0.2k-fips  26 Jan 2017"),
+            Some(OpenSshVersion { major: 7, minor: 4 })
+        );
+        assert_eq!(
+            OpenSshVersion::parse("OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2"),
+            Some(OpenSshVersion { major: 8, minor: 1 })
+        );
+        assert_eq!(
+            OpenSshVersion::parse("Sun_SSH_1.5, SSH protocols 1.5/2.0"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_features_by_version() {
+        let rhel7 = SshFeatures::for_version(OpenSshVersion::parse("OpenSSH_7.4p1"));
+        assert!(!rhel7.accept_new());
+        assert!(rhel7.control_path_hash());
+
+        let current = SshFeatures::for_version(OpenSshVersion::parse("OpenSSH_9.6p1"));
+        assert!(current.accept_new());
+
+        let unknown = SshFeatures::for_version(None);
+        assert!(unknown.accept_new());
+        assert!(unknown.control_persist());
+    }
+}
@@ -0,0 +1,347 @@
+//! Host settings from OpenSSH client config files.
+//!
+//! ssh(1) applies `~/.ssh/config` (or the `--ssh-config` file) itself, but
+//! host key scans, the native backend and the cache only see the inventory.
+//! An inventory that names hosts by their ssh_config alias would otherwise
+//! scan and cache the alias rather than the machine behind it, and the DNS
+//! precheck would look up a name ssh never resolves. The `HostName`,
+//! `User`, `Port`, `IdentityFile` and proxy of each host are read here, and
+//! only here, the way ssh reads them: blocks in file order, first value wins. `Include`
+//! is followed; `Match` blocks are skipped, as their conditions cannot be
+//! evaluated without connecting.
+
+use crate::error::{FactsError, Result};
+use crate::overrides::glob_match;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Includes nested deeper than this are ignored, as ssh does.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The parsed blocks of an ssh_config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    /// `Host` patterns; `None` applies to every host
+    patterns: Option<Vec<String>>,
+    /// Lowercase keyword and its value, in file order
+    options: Vec<(String, String)>,
+}
+
+/// What ssh_config sets for one host.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfigHost {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    /// The `ProxyJump` or `ProxyCommand`, whichever comes first
+    pub proxy: Option<String>,
+}
+
+impl SshConfig {
+    /// The config ssh would read: `path` when given, else `~/.ssh/config`.
+    /// A file that does not exist is an empty config, as it is to ssh.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match dirs::home_dir() {
+                Some(home) => home.join(".ssh").join("config"),
+                None => return Ok(Self::default()),
+            },
+        };
+        if !path.exists() {
+            debug!("No SSH config at {:?}", path);
+            return Ok(Self::default());
+        }
+        let mut config = Self::default();
+        config.read_file(&path, None, 0)?;
+        Ok(config)
+    }
+
+    /// Parse config text. `Include`s are resolved against `~/.ssh`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+        config.read(text, Path::new("ssh_config"), None, 0)?;
+        Ok(config)
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        patterns: Option<Vec<String>>,
+        depth: usize,
+    ) -> Result<()> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            FactsError::InvalidConfig(format!("Failed to read SSH config {path:?}: {e}"))
+        })?;
+        self.read(&text, path, patterns, depth)
+    }
+
+    fn read(
+        &mut self,
+        text: &str,
+        path: &Path,
+        patterns: Option<Vec<String>>,
+        depth: usize,
+    ) -> Result<()> {
+        let mut block = Block {
+            patterns,
+            options: Vec::new(),
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, value) = split_line(line);
+            if value.is_empty() {
+                return Err(FactsError::InvalidConfig(format!(
+                    "{}:{}: {keyword} has no value",
+                    path.display(),
+                    number + 1
+                )));
+            }
+
+            match keyword.as_str() {
+                "host" => {
+                    self.blocks.push(block);
+                    block = Block {
+                        patterns: Some(value.split_whitespace().map(unquote).collect()),
+                        options: Vec::new(),
+                    };
+                }
+                "match" => {
+                    self.blocks.push(block);
+                    let all = value.eq_ignore_ascii_case("all");
+                    block = Block {
+                        patterns: if all { None } else { Some(Vec::new()) },
+                        options: Vec::new(),
+                    };
+                }
+                "include" if depth < MAX_INCLUDE_DEPTH => {
+                    self.blocks.push(block.clone());
+                    block.options.clear();
+                    for include in value.split_whitespace().map(unquote) {
+                        for file in include_files(&include) {
+                            self.read_file(&file, block.patterns.clone(), depth + 1)?;
+                        }
+                    }
+                }
+                _ => block.options.push((keyword, unquote(value))),
+            }
+        }
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// The settings ssh would use for `host`, the name given to it.
+    pub fn lookup(&self, host: &str) -> SshConfigHost {
+        let first = |keyword: &str| {
+            self.blocks
+                .iter()
+                .filter(|block| block.matches(host))
+                .flat_map(|block| &block.options)
+                .find(|(key, _)| key == keyword)
+                .map(|(_, value)| value.as_str())
+        };
+        SshConfigHost {
+            hostname: first("hostname").map(|name| expand_tokens(name, host)),
+            user: first("user").map(str::to_string),
+            port: first("port").and_then(|port| port.parse().ok()),
+            identity_file: first("identityfile")
+                .filter(|file| !file.eq_ignore_ascii_case("none"))
+                .map(|file| expand_tokens(&expand_home(file), host)),
+            proxy: self
+                .blocks
+                .iter()
+                .filter(|block| block.matches(host))
+                .flat_map(|block| &block.options)
+                .find(|(key, _)| key == "proxyjump" || key == "proxycommand")
+                .map(|(_, value)| value.clone())
+                .filter(|proxy| !proxy.eq_ignore_ascii_case("none")),
+        }
+    }
+}
+
+impl Block {
+    /// Whether `host` matches a pattern and no negated one.
+    fn matches(&self, host: &str) -> bool {
+        let Some(patterns) = &self.patterns else {
+            return true;
+        };
+        let host = host.to_lowercase();
+        let mut matched = false;
+        for pattern in patterns {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_prefix('!') {
+                Some(negated) if glob_match(negated, &host) => return false,
+                Some(_) => {}
+                None => matched |= glob_match(&pattern, &host),
+            }
+        }
+        matched
+    }
+}
+
+/// `Keyword value` or `Keyword=value`, with the keyword lowercased.
+fn split_line(line: &str) -> (String, &str) {
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim();
+    (keyword.to_lowercase(), rest)
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).display().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Expand the `%h`, `%d` and `%%` tokens ssh allows in these keywords.
+fn expand_tokens(value: &str, host: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(host),
+            Some('d') => expanded.push_str(
+                &dirs::home_dir()
+                    .map(|home| home.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// The files an `Include` names, relative to `~/.ssh` unless absolute, with
+/// `*` and `?` in the file name expanded.
+fn include_files(include: &str) -> Vec<PathBuf> {
+    let path = PathBuf::from(expand_home(include));
+    let path = match dirs::home_dir() {
+        Some(home) if path.is_relative() => home.join(".ssh").join(path),
+        _ => path,
+    };
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    if !name.contains(['*', '?']) {
+        return if path.is_file() {
+            vec![path]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let Some(dir) = path.parent() else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|file| file.is_file())
+        .filter(|file| {
+            file.file_name()
+                .and_then(|file| file.to_str())
+                .is_some_and(|file| glob_match(name, file))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_follows_ssh_precedence() {
+        let config = SshConfig::parse(
+            "# bastion aliases
+Host web1
+    HostName 10.0.0.5
+    Port 2222
+
+Host web* !web9
+    ProxyJump bastion
+    User deploy
+    Port 22
+    IdentityFile /keys/%h.pem
+
+Host *
+    User=nobody
+    HostName %h.example.com
+",
+        )
+        .unwrap();
+
+        let web1 = config.lookup("web1");
+        assert_eq!(web1.hostname.as_deref(), Some("10.0.0.5"));
+        assert_eq!(web1.port, Some(2222));
+        assert_eq!(web1.user.as_deref(), Some("deploy"));
+        assert_eq!(web1.identity_file.as_deref(), Some("/keys/web1.pem"));
+        assert_eq!(web1.proxy.as_deref(), Some("bastion"));
+
+        let web9 = config.lookup("WEB9");
+        assert_eq!(web9.hostname.as_deref(), Some("WEB9.example.com"));
+        assert_eq!(web9.user.as_deref(), Some("nobody"));
+        assert_eq!(web9.port, None);
+        assert_eq!(web9.proxy, None);
+    }
+
+    #[test]
+    fn test_include_and_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let included = dir.path().join("db.conf");
+        std::fs::write(&included, "HostName 10.0.1.7\n").unwrap();
+        let main = dir.path().join("config");
+        std::fs::write(
+            &main,
+            format!(
+                "Match exec \"false\"\n    User root\nHost db1\n    Include {}\n",
+                dir.path().join("*.conf").display()
+            ),
+        )
+        .unwrap();
+
+        let config = SshConfig::load(Some(&main)).unwrap();
+        assert_eq!(config.lookup("db1").hostname.as_deref(), Some("10.0.1.7"));
+        assert_eq!(config.lookup("db2").hostname, None);
+        assert_eq!(config.lookup("db1").user, None);
+
+        let missing = SshConfig::load(Some(&dir.path().join("nope"))).unwrap();
+        assert_eq!(missing, SshConfig::default());
+        assert!(SshConfig::parse("Host\n").is_err());
+    }
+}
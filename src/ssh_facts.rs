@@ -1,11 +1,28 @@
-use crate::config::FactsConfig;
+use crate::arch_table::ArchTable;
+use crate::config::{FactsConfig, GatherSubset, HostKeyPolicy, SshBackend};
+use crate::dns;
 use crate::error::{FactsError, Result};
-use crate::types::ArchitectureFacts;
-use std::collections::HashMap;
+use crate::faults::inject_faults;
+use crate::network_facts;
+use crate::overrides::default_os_family;
+use crate::privilege::{awaits_password, redact, BecomeSettings};
+use crate::progress::ProgressTracker;
+use crate::ramp::ConnectionRamp;
+use crate::sanitize::{validate_hostname, validate_proxy_command};
+use crate::secrets::Secret;
+use crate::session::run_recorded;
+use crate::shutdown::StopReason;
+use crate::ssh_client::SshFeatures;
+use crate::ssh_config::SshConfig;
+use crate::types::{ArchitectureFacts, HostEntry, HostKeys, UnreachableReason};
+use crate::windows_facts;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::AsyncReadExt;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
 use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
@@ -13,114 +30,949 @@ use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
 pub async fn gather_minimal_facts(
-    hosts: &[String],
+    hosts: &[HostEntry],
     config: &FactsConfig,
 ) -> Result<HashMap<String, ArchitectureFacts>> {
+    Ok(gather_facts_with_host_keys(hosts, config).await?.facts)
+}
+
+/// What an SSH gathering run learned.
+#[derive(Debug, Default)]
+pub struct SshGathering {
+    pub facts: HashMap<String, ArchitectureFacts>,
+    /// SHA-256 host key fingerprints observed for each successfully probed
+    /// host, by key type
+    pub host_keys: HashMap<String, HostKeys>,
+    /// Hosts that could not be reached, and why
+    pub unreachable: BTreeMap<String, UnreachableReason>,
+}
+
+/// Gather facts and also return the host key fingerprints and unreachable
+/// hosts seen along the way.
+pub async fn gather_facts_with_host_keys(
+    hosts: &[HostEntry],
+    config: &FactsConfig,
+) -> Result<SshGathering> {
+    if config.ssh_backend == SshBackend::Native && !cfg!(feature = "native-ssh") {
+        return Err(native_backend_unavailable());
+    }
+
     let semaphore = Arc::new(Semaphore::new(config.parallel_connections));
+    let ramp = Arc::new(ConnectionRamp::from_config(config));
+    let features = SshFeatures::detect();
+    let failure_limit = config.failure_limit(hosts.len());
+    let mut failures = 0;
+    let mut failed_hosts = Vec::new();
+    let mut unreachable = BTreeMap::new();
+
+    // Names that do not exist fail now rather than after a connect timeout;
+    // a replayed session never connects, so there is nothing to save
+    let replaying = config.session.as_ref().is_some_and(|s| s.is_replay());
+    let unresolvable = if config.no_dns_precheck || replaying {
+        BTreeMap::new()
+    } else {
+        dns::find_unresolvable(hosts, config).await
+    };
+    for (host, detail) in &unresolvable {
+        warn!(
+            "{}",
+            FactsError::DnsResolution(host.clone(), detail.clone())
+        );
+        unreachable.insert(host.clone(), UnreachableReason::DnsFailure);
+        failed_hosts.push(host.clone());
+        failures += 1;
+    }
+    if let Some(limit) = failure_limit.filter(|limit| failures > *limit) {
+        error!(
+            "{} of {} SSH hosts do not resolve, more than the allowed {}; aborting",
+            failures,
+            hosts.len(),
+            limit
+        );
+        config.shutdown.stop(StopReason::TooManyFailures);
+    }
+    let hosts: Vec<&HostEntry> = hosts
+        .iter()
+        .filter(|host| !unresolvable.contains_key(&host.name))
+        .collect();
+
     let mut tasks = JoinSet::new();
 
-    for host in hosts {
-        let host = host.clone();
-        let config = config.clone();
+    for host in &hosts {
+        let host = (*host).clone();
+        let config = config.for_host(&host);
         let sem = semaphore.clone();
+        let ramp = ramp.clone();
 
         tasks.spawn(async move {
-            let _permit = sem
-                .acquire()
-                .await
-                .map_err(|e| FactsError::TaskJoin(format!("Failed to acquire semaphore: {e}")))?;
-
-            match timeout(
-                Duration::from_secs(config.timeout),
-                gather_single_host_facts(&host, &config),
-            )
-            .await
-            {
-                Ok(Ok((h, facts))) => Ok((h, facts)),
-                Ok(Err(e)) => {
-                    warn!("Failed to gather facts from {}: {}", host, e);
-                    Err(e)
+            let _permit = match sem.acquire().await {
+                Ok(permit) => permit,
+                Err(e) => {
+                    let e = FactsError::TaskJoin(format!("Failed to acquire semaphore: {e}"));
+                    return (Duration::ZERO, Err(e));
                 }
-                Err(_) => {
-                    warn!("Timeout gathering facts from {}", host);
-                    Err(FactsError::Timeout(host))
+            };
+            ramp.wait(&host.name).await;
+
+            let started = Instant::now();
+            let attempt = async {
+                match timeout(
+                    config.host_budget(),
+                    gather_single_host_facts(&host, &config, features),
+                )
+                .await
+                {
+                    Ok(Ok(gathered)) => Ok(gathered),
+                    Ok(Err(e)) => {
+                        warn!("Failed to gather facts from {}: {}", host.name, e);
+                        Err(e)
+                    }
+                    Err(_) => {
+                        warn!("Timeout gathering facts from {}", host.name);
+                        Err(FactsError::Timeout(host.name.clone()))
+                    }
                 }
-            }
+            };
+            // Direct hosts share nothing, so one failing says nothing of the rest
+            let result = match breaker_backend(&host) {
+                Some(backend) => config.through_breaker(&backend, attempt).await,
+                None => attempt.await,
+            };
+            (started.elapsed(), result)
         });
     }
 
+    let mut progress = ProgressTracker::new(
+        "SSH fact gathering",
+        hosts.len(),
+        config.parallel_connections,
+        config.progress_interval,
+    );
+    let mut last_report = Instant::now();
+
     let mut results = HashMap::new();
-    let mut failed_hosts = Vec::new();
+    let mut host_keys = HashMap::new();
+    let mut mismatched_hosts = Vec::new();
+
+    loop {
+        // Report on a timer rather than per completion so a stalled run still logs
+        let joined = tokio::select! {
+            biased;
+            _ = config.shutdown.wait() => {
+                warn!("Abandoning {} unfinished SSH hosts", tasks.len());
+                tasks.abort_all();
+                break;
+            }
+            joined = tasks.join_next() => match joined {
+                Some(joined) => joined,
+                None => break,
+            },
+            _ = progress.tick(&mut last_report) => {
+                progress.report();
+                continue;
+            }
+        };
+        let (duration, result) = match joined {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                error!("Task panic: {}", e);
+                continue;
+            }
+        };
+        progress.record(duration, result.is_ok());
+        if result.is_err() {
+            failures += 1;
+            if let Some(limit) = failure_limit.filter(|limit| failures > *limit) {
+                error!(
+                    "{} of {} SSH hosts failed, more than the allowed {}; aborting",
+                    failures,
+                    hosts.len() + unresolvable.len(),
+                    limit
+                );
+                config.shutdown.stop(StopReason::TooManyFailures);
+            }
+        }
+        if let Err(e) = &result {
+            if let Some((host, reason)) = unreachable_reason(e) {
+                unreachable.insert(host.to_string(), reason);
+            }
+        }
 
-    while let Some(result) = tasks.join_next().await {
         match result {
-            Ok(Ok((host, facts))) => {
+            Ok((host, facts, keys)) => {
                 info!("Successfully gathered facts from {}", host);
+                if !keys.is_empty() {
+                    host_keys.insert(host.clone(), keys);
+                }
                 results.insert(host, facts);
             }
-            Ok(Err(e)) => {
+            Err(FactsError::HostKeyMismatch(host)) => {
+                error!("{}", FactsError::HostKeyMismatch(host.clone()));
+                mismatched_hosts.push(host);
+            }
+            // Reported once, when the breaker opened
+            Err(e @ FactsError::CircuitOpen(..)) => debug!("{}", e),
+            Err(e) => {
                 error!("Error gathering facts: {}", e);
-                if let FactsError::ConnectionFailed(host, _) = &e {
+                if let FactsError::ConnectionFailed(host, _)
+                | FactsError::AuthenticationFailed(host, _) = &e
+                {
                     failed_hosts.push(host.clone());
                 }
             }
-            Err(e) => {
-                error!("Task panic: {}", e);
-            }
         }
     }
 
-    if !failed_hosts.is_empty() {
+    let auth_failed: Vec<&String> = unreachable
+        .iter()
+        .filter(|(_, reason)| **reason == UnreachableReason::AuthenticationFailed)
+        .map(|(host, _)| host)
+        .collect();
+    if !auth_failed.is_empty() {
         warn!(
-            "Failed to gather facts from {} hosts, using fallback facts",
-            failed_hosts.len()
+            "Authentication failed for {} hosts; check their users and keys: {}",
+            auth_failed.len(),
+            auth_failed
+                .iter()
+                .map(|host| host.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
         );
+    }
+
+    // A changed host key under TOFU fails only that host, and is never
+    // papered over with fallback facts here
+    if !mismatched_hosts.is_empty() {
+        mismatched_hosts.sort();
+        error!(
+            "Host keys of {} hosts changed; they were not gathered: {}",
+            mismatched_hosts.len(),
+            mismatched_hosts.join(", ")
+        );
+    }
+
+    // The other failed hosts are left out, so the caller can tell them from
+    // gathered ones and fall back through the cache and declared facts first
+    if !failed_hosts.is_empty() {
+        warn!("Failed to gather facts from {} hosts", failed_hosts.len());
         for host in failed_hosts {
-            if ArchitectureFacts::is_localhost(&host) {
+            if !config.no_implicit_local && ArchitectureFacts::is_localhost(&host) {
                 info!("Using local system detection for failed localhost connection");
                 results.insert(host, ArchitectureFacts::from_local_system());
+            }
+        }
+    }
+
+    Ok(SshGathering {
+        facts: results,
+        host_keys,
+        unreachable,
+    })
+}
+
+/// The host an error is about and why it could not be reached, for errors
+/// that mean the connection never got as far as running the probe.
+fn unreachable_reason(error: &FactsError) -> Option<(&str, UnreachableReason)> {
+    match error {
+        FactsError::DnsResolution(host, _) => Some((host, UnreachableReason::DnsFailure)),
+        FactsError::Timeout(host) => Some((host, UnreachableReason::Timeout)),
+        FactsError::HostKeyMismatch(host) => Some((host, UnreachableReason::HostKeyMismatch)),
+        FactsError::AuthenticationFailed(host, _) => {
+            Some((host, UnreachableReason::AuthenticationFailed))
+        }
+        FactsError::ConnectionFailed(host, detail) => {
+            let reason = if detail.contains("Could not resolve hostname") {
+                UnreachableReason::DnsFailure
+            } else if detail.contains("Connection refused") {
+                UnreachableReason::ConnectionRefused
+            } else if detail.contains("Connection timed out") {
+                UnreachableReason::Timeout
             } else {
-                results.insert(host, ArchitectureFacts::fallback());
+                UnreachableReason::ConnectionFailed
+            };
+            Some((host, reason))
+        }
+        _ => None,
+    }
+}
+
+/// What is run on a host to learn its facts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeStrategy {
+    /// The POSIX probe script
+    Script,
+    /// Only `uname -m; uname -s`, for restricted or minimal shells
+    Raw,
+    /// The vendor `show version` of a network device
+    Network(String),
+    /// The PowerShell probe, for Windows hosts
+    Windows,
+}
+
+impl ProbeStrategy {
+    /// `ansible_network_os` selects the network probe and a PowerShell or
+    /// cmd `ansible_shell_type` the Windows one; otherwise the
+    /// `rustle_facts_strategy` host var picks `script` (the default) or `raw`.
+    pub fn for_host(host: &HostEntry) -> Result<Self> {
+        if let Some(network_os) = network_facts::network_os(host) {
+            return Ok(ProbeStrategy::Network(network_os));
+        }
+        if windows_facts::is_windows_shell(host) {
+            return Ok(ProbeStrategy::Windows);
+        }
+
+        let strategy = host
+            .vars
+            .get("rustle_facts_strategy")
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_lowercase());
+        match strategy.as_deref() {
+            None | Some("script") => Ok(ProbeStrategy::Script),
+            Some("raw") => Ok(ProbeStrategy::Raw),
+            Some(other) => Err(FactsError::InvalidConfig(format!(
+                "Unsupported rustle_facts_strategy '{other}' for {} (expected script or raw)",
+                host.name
+            ))),
+        }
+    }
+
+    pub fn command(&self, config: &FactsConfig) -> Result<String> {
+        Ok(match self {
+            ProbeStrategy::Script => fact_probe(config)?,
+            ProbeStrategy::Raw => "uname -m; uname -s".to_string(),
+            ProbeStrategy::Network(_) => network_facts::SHOW_VERSION.to_string(),
+            ProbeStrategy::Windows => windows_facts::probe_command(),
+        })
+    }
+
+    /// Parse what [`Self::command`] printed. `arch_table` sees the
+    /// architecture as the host spelled it.
+    pub fn parse(&self, output: &str, arch_table: &ArchTable) -> Result<ArchitectureFacts> {
+        match self {
+            ProbeStrategy::Script => parse_fact_output_with(output, arch_table),
+            ProbeStrategy::Raw => parse_raw_output_with(output, arch_table),
+            ProbeStrategy::Network(network_os) => {
+                Ok(network_facts::parse_show_version(network_os, output))
             }
+            ProbeStrategy::Windows => windows_facts::parse_probe_output(output),
         }
     }
+}
+
+/// Parse `uname -m; uname -s` output. Banner lines are skipped by taking the
+/// last two single-word lines.
+pub fn parse_raw_output(output: &str) -> Result<ArchitectureFacts> {
+    parse_raw_output_with(output, &ArchTable::default())
+}
+
+/// [`parse_raw_output`], normalizing the architecture through `arch_table`.
+pub fn parse_raw_output_with(output: &str, arch_table: &ArchTable) -> Result<ArchitectureFacts> {
+    let words: Vec<&str> = output
+        .lines()
+        .map(|line| line.trim_matches(|c: char| c.is_whitespace() || c.is_control()))
+        .filter(|line| {
+            !line.is_empty()
+                && line
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+        })
+        .collect();
 
-    Ok(results)
+    let [arch, system] = words[words.len().saturating_sub(2)..] else {
+        return Err(FactsError::ParseError(
+            "unknown".to_string(),
+            "Expected uname -m and uname -s output".to_string(),
+        ));
+    };
+
+    Ok(ArchitectureFacts {
+        ansible_architecture: arch_table.normalize(arch),
+        ansible_system: system.to_string(),
+        ansible_os_family: default_os_family(system).to_string(),
+        ansible_distribution: None,
+        rustle_libc: None,
+        extra: serde_json::Map::new(),
+    })
 }
 
 async fn gather_single_host_facts(
-    host: &str,
+    host: &HostEntry,
     config: &FactsConfig,
-) -> Result<(String, ArchitectureFacts)> {
-    debug!("Gathering facts from host: {}", host);
+    features: SshFeatures,
+) -> Result<(String, ArchitectureFacts, HostKeys)> {
+    debug!("Gathering facts from host: {}", host.name);
+    inject_faults(config, &host.name).await?;
+
+    let strategy = ProbeStrategy::for_host(host)?;
+    let mut command = strategy.command(config)?;
+    let mut become_password = None;
+    let mut request_tty = false;
+
+    // Only the full script is escalated; raw, network and Windows targets
+    // have shells that could not run the wrapper anyway
+    let become_settings = match strategy {
+        ProbeStrategy::Script => BecomeSettings::from_host(host)?,
+        _ => None,
+    };
+    if let Some(settings) = &become_settings {
+        become_password = config.credentials.become_password.as_ref();
+        debug!(
+            "Escalating fact probe on {} via {}",
+            host.name, settings.method
+        );
+        command = settings.wrap_command(&command, become_password.is_some())?;
+        request_tty = settings.needs_tty(become_password.is_some());
+    }
+
+    // ssh records the key it was offered in the known_hosts file, which gives
+    // us the real host key fingerprint without a separate ssh-keyscan round trip
+    let target = SshTarget::for_config(host, config)?;
+    let scratch_known_hosts = KnownHostsFile::new();
+    let (known_hosts, lookup_host) = match config.host_key_policy {
+        HostKeyPolicy::Ignore => (scratch_known_hosts.path(), None),
+        HostKeyPolicy::Tofu => {
+            if let Some(parent) = config.known_hosts_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            (
+                config.known_hosts_file.as_path(),
+                Some(target.known_hosts_name()),
+            )
+        }
+    };
 
-    let command = build_fact_gathering_command();
+    let output = execute_ssh_command(
+        host,
+        &target,
+        &host_ssh_options(host, config, features)?,
+        &command,
+        become_password,
+        request_tty,
+        known_hosts,
+        config,
+    )
+    .await
+    .map_err(|e| match (&become_settings, e) {
+        (Some(settings), FactsError::ConnectionFailed(name, detail)) => settings
+            .classify_failure(&name, &detail)
+            .unwrap_or(FactsError::ConnectionFailed(name, detail)),
+        (_, e) => e,
+    })?;
 
-    let output = execute_ssh_command(host, &command, config).await?;
+    let facts = strategy
+        .parse(&output, &config.arch_table)
+        .map_err(|e| FactsError::ParseError(host.name.clone(), e.to_string()))?;
 
-    let facts = parse_fact_output(&output)
-        .map_err(|e| FactsError::ParseError(host.to_string(), e.to_string()))?;
+    let keys = match fingerprint_known_hosts(known_hosts, lookup_host.as_deref()).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            debug!("Could not fingerprint host key for {}: {}", host.name, e);
+            HostKeys::new()
+        }
+    };
 
-    Ok((host.to_string(), facts))
+    Ok((host.name.clone(), facts, keys))
 }
 
-async fn execute_ssh_command(host: &str, command: &str, config: &FactsConfig) -> Result<String> {
-    let ssh_host = if host.contains('@') {
-        host.to_string()
-    } else {
-        format!("{}@{}", get_ssh_user(host), host)
+/// Run `command` on `host` over SSH with the host's own options and return
+/// its output. Used to relay probes through a gateway host.
+pub(crate) async fn run_on_host(
+    host: &HostEntry,
+    command: &str,
+    config: &FactsConfig,
+) -> Result<String> {
+    let config = config.for_host(host);
+    let scratch_known_hosts = KnownHostsFile::new();
+    let known_hosts = match config.host_key_policy {
+        HostKeyPolicy::Ignore => scratch_known_hosts.path(),
+        HostKeyPolicy::Tofu => {
+            if let Some(parent) = config.known_hosts_file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            config.known_hosts_file.as_path()
+        }
+    };
+    execute_ssh_command(
+        host,
+        &SshTarget::for_config(host, &config)?,
+        &host_ssh_options(host, &config, SshFeatures::detect())?,
+        command,
+        None,
+        false,
+        known_hosts,
+        &config,
+    )
+    .await
+}
+
+/// Per-host `-o` options from the host entry and its vars, limited to what
+/// the local client supports. `config` is the host's own, from
+/// `FactsConfig::for_host`.
+fn host_ssh_options(
+    host: &HostEntry,
+    config: &FactsConfig,
+    features: SshFeatures,
+) -> Result<Vec<String>> {
+    // Clients older than 7.6 have no accept-new; with `no` they still record
+    // new keys, and a changed key is caught from ssh's warning instead
+    let strict_host_key_checking = match config.host_key_policy {
+        HostKeyPolicy::Tofu if features.accept_new() => "accept-new",
+        _ => "no",
+    };
+    let mut options = vec![format!("StrictHostKeyChecking={strict_host_key_checking}")];
+
+    // A silent connection is probed every third of the connect timeout and
+    // dropped after three unanswered probes, instead of hanging until the
+    // host budget runs out
+    let alive_interval = (config.connect_timeout / 3).clamp(1, 15);
+    options.push(format!("ServerAliveInterval={alive_interval}"));
+    options.push("ServerAliveCountMax=3".to_string());
+
+    // Pipelined hosts keep a master connection open for the steps that follow
+    if pipelining_enabled(host) && features.control_persist() {
+        let control_dir = config
+            .cache_file
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(std::env::temp_dir);
+        std::fs::create_dir_all(&control_dir)?;
+        options.push("ControlMaster=auto".to_string());
+        options.push("ControlPersist=60s".to_string());
+        let socket = if features.control_path_hash() {
+            "cm-%C"
+        } else {
+            "cm-%r@%h:%p"
+        };
+        options.push(format!(
+            "ControlPath={}",
+            control_dir.join(socket).display()
+        ));
+    }
+
+    let proxy_command = ["ansible_ssh_proxy_command", "proxy_command"]
+        .iter()
+        .find_map(|key| host.vars.get(*key)?.as_str());
+    if let Some(proxy_command) = proxy_command {
+        validate_proxy_command(proxy_command)?;
+        options.push(format!("ProxyCommand={}", proxy_command.trim()));
+    }
+
+    Ok(options)
+}
+
+/// The shared path an SSH host is reached through, for its circuit
+/// breaker: its proxy command or jump host. Hosts connected to directly
+/// have none.
+fn breaker_backend(host: &HostEntry) -> Option<String> {
+    if let Some(proxy) = ["ansible_ssh_proxy_command", "proxy_command"]
+        .iter()
+        .find_map(|key| host.vars.get(*key)?.as_str())
+    {
+        return Some(format!("ssh via {}", proxy.trim()));
+    }
+    let args = [
+        host.ssh_common_args.as_deref(),
+        host.ssh_extra_args.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .chain(
+        ["ansible_ssh_common_args", "ansible_ssh_extra_args"]
+            .iter()
+            .filter_map(|key| host.vars.get(*key)?.as_str()),
+    );
+    args.flat_map(proxy_jump)
+        .next()
+        .map(|jump| format!("ssh via {jump}"))
+}
+
+/// The jump host named by `-J host` or `-o ProxyJump=host` in SSH args.
+fn proxy_jump(args: &str) -> Option<String> {
+    let mut words = args.split_whitespace();
+    while let Some(word) = words.next() {
+        let option = match word {
+            "-J" => return words.next().map(str::to_string),
+            "-o" => words.next()?,
+            _ => match word.strip_prefix("-J") {
+                Some(jump) => return Some(jump.to_string()),
+                None => word.strip_prefix("-o").unwrap_or(word),
+            },
+        };
+        let option = option.trim_matches(|c| c == '\'' || c == '"');
+        if let Some((key, value)) = option.split_once(['=', ' ']) {
+            if key.eq_ignore_ascii_case("proxyjump") && value != "none" {
+                return Some(value.to_string());
+            }
+        }
+    }
+    None
+}
+
+fn pipelining_enabled(host: &HostEntry) -> bool {
+    host.ssh_pipelining.unwrap_or_else(|| {
+        ["ansible_ssh_pipelining", "ansible_pipelining"]
+            .iter()
+            .find_map(|key| match host.vars.get(*key)? {
+                serde_json::Value::Bool(enabled) => Some(*enabled),
+                serde_json::Value::String(s) => {
+                    Some(matches!(s.to_lowercase().as_str(), "true" | "yes" | "1"))
+                }
+                _ => None,
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Where and as whom an SSH host is reached, read the way Ansible reads
+/// it: `ansible_host` (or the entry address) over the inventory name, then
+/// `ansible_port`, `ansible_user`, the private key file and
+/// `ansible_ssh_pass`. A `user@` prefix on the inventory name still names
+/// the user. ssh_config settings for the host fill in what the inventory
+/// leaves unset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshTarget {
+    /// The name given to ssh(1), which applies ssh_config to it again
+    pub hostname: String,
+    /// `hostname` after ssh_config's `HostName`: what is connected to
+    pub address: String,
+    pub port: Option<u16>,
+    pub user: String,
+    pub key_file: Option<String>,
+    pub password: Option<Secret>,
+}
+
+impl SshTarget {
+    pub fn for_host(host: &HostEntry) -> Result<Self> {
+        Self::with_ssh_config(host, None)
+    }
+
+    /// The target with the run's ssh_config applied.
+    pub fn for_config(host: &HostEntry, config: &FactsConfig) -> Result<Self> {
+        Self::with_ssh_config(host, config.parsed_ssh_config.as_deref())
+    }
+
+    pub fn with_ssh_config(host: &HostEntry, ssh_config: Option<&SshConfig>) -> Result<Self> {
+        let var = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| host.vars.get(*key)?.as_str())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let (name_user, name) = match host.name.split_once('@') {
+            Some((user, name)) => (Some(user.to_string()), name),
+            None => (None, host.name.as_str()),
+        };
+
+        let hostname = var(&["ansible_host", "ansible_ssh_host"])
+            .or_else(|| host.address.clone())
+            .unwrap_or_else(|| name.to_string());
+        let configured = ssh_config
+            .map(|ssh_config| ssh_config.lookup(&hostname))
+            .unwrap_or_default();
+        let address = configured.hostname.unwrap_or_else(|| hostname.clone());
+        let port = host
+            .port
+            .or_else(|| {
+                ["ansible_port", "ansible_ssh_port"].iter().find_map(|key| {
+                    match host.vars.get(*key)? {
+                        serde_json::Value::Number(port) => port.as_u64()?.try_into().ok(),
+                        serde_json::Value::String(port) => port.trim().parse().ok(),
+                        _ => None,
+                    }
+                })
+            })
+            .or(configured.port);
+        let user = host
+            .user
+            .clone()
+            .or_else(|| var(&["ansible_user", "ansible_ssh_user"]))
+            .or(name_user)
+            .or(configured.user)
+            .unwrap_or_else(default_ssh_user);
+        let key_file = host
+            .ssh_private_key_file
+            .clone()
+            .or_else(|| var(&["ansible_ssh_private_key_file", "ansible_private_key_file"]))
+            .or(configured.identity_file);
+        // Not trimmed: surrounding spaces may be part of a password
+        let password = [
+            "ansible_ssh_pass",
+            "ansible_ssh_password",
+            "ansible_password",
+        ]
+        .iter()
+        .find_map(|key| host.vars.get(*key)?.as_str())
+        .map(Secret::new);
+
+        validate_hostname(&format!("{user}@{hostname}"))?;
+        validate_hostname(&address)?;
+        Ok(Self {
+            hostname,
+            address,
+            port,
+            user,
+            key_file,
+            password,
+        })
+    }
+
+    /// The login password: the host's own, else the one from `--ask-pass`.
+    pub fn password<'a>(&'a self, config: &'a FactsConfig) -> Option<&'a Secret> {
+        self.password
+            .as_ref()
+            .or(config.credentials.ssh_password.as_ref())
+    }
+
+    /// `user@hostname`, as given to ssh(1).
+    pub fn destination(&self) -> String {
+        format!("{}@{}", self.user, self.hostname)
+    }
+
+    /// The name ssh records the host's key under in known_hosts.
+    pub fn known_hosts_name(&self) -> String {
+        match self.port {
+            Some(port) if port != 22 => format!("[{}]:{port}", self.address),
+            _ => self.address.clone(),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_ssh_command(
+    host_entry: &HostEntry,
+    target: &SshTarget,
+    host_options: &[String],
+    command: &str,
+    stdin_secret: Option<&Secret>,
+    request_tty: bool,
+    known_hosts: &Path,
+    config: &FactsConfig,
+) -> Result<String> {
+    let host = host_entry.name.as_str();
+
+    let session = config.session.as_deref();
+    let result = match config.ssh_backend {
+        SshBackend::Openssh => {
+            let (mut ssh_cmd, _askpass) = openssh_command(
+                target,
+                host_options,
+                command,
+                stdin_secret.is_some(),
+                request_tty,
+                known_hosts,
+                config,
+            )?;
+            run_recorded(session, "ssh", host, command, || async move {
+                let mut child = ssh_cmd
+                    .spawn()
+                    .map_err(|e| FactsError::ConnectionFailed(host.to_string(), e.to_string()))?;
+
+                let mut stdin_handle = child.stdin.take();
+                if let (Some(secret), false) = (stdin_secret, request_tty) {
+                    if let Some(mut handle) = stdin_handle.take() {
+                        handle
+                            .write_all(format!("{}\n", secret.expose()).as_bytes())
+                            .await?;
+                        // Dropping the handle closes stdin so the remote side sees EOF
+                    }
+                }
+
+                let (stdout_handle, stderr_handle) = (child.stdout.take(), child.stderr.take());
+                let read_stdout = async {
+                    let mut stdout = Vec::new();
+                    if let Some(handle) = stdout_handle {
+                        read_answering_prompt(handle, stdin_handle, stdin_secret, &mut stdout)
+                            .await?;
+                    }
+                    Ok::<_, std::io::Error>(stdout)
+                };
+                let read_stderr = async {
+                    let mut stderr = Vec::new();
+                    if let Some(mut handle) = stderr_handle {
+                        handle.read_to_end(&mut stderr).await?;
+                    }
+                    Ok::<_, std::io::Error>(stderr)
+                };
+                let (stdout, stderr) = tokio::try_join!(read_stdout, read_stderr)?;
+
+                let status = child
+                    .wait()
+                    .await
+                    .map_err(|e| FactsError::ConnectionFailed(host.to_string(), e.to_string()))?;
+
+                Ok((
+                    status.code(),
+                    redact(&String::from_utf8_lossy(&stdout), stdin_secret),
+                    redact(&String::from_utf8_lossy(&stderr), stdin_secret),
+                ))
+            })
+            .await?
+        }
+        SshBackend::Native => {
+            run_recorded(session, "ssh", host, command, || async {
+                let (code, stdout, stderr) = run_native(
+                    host_entry,
+                    target,
+                    host_options,
+                    command,
+                    stdin_secret,
+                    request_tty,
+                    known_hosts,
+                    config,
+                )
+                .await?;
+                Ok((
+                    code,
+                    redact(&stdout, stdin_secret),
+                    redact(&stderr, stdin_secret),
+                ))
+            })
+            .await?
+        }
     };
 
+    // Without accept-new a changed key is only a warning on an otherwise
+    // successful connection
+    if config.host_key_policy == HostKeyPolicy::Tofu
+        && result
+            .stderr
+            .contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+    {
+        return Err(FactsError::HostKeyMismatch(host.to_string()));
+    }
+
+    if result.exit_code != Some(0) {
+        // With a remote terminal, error messages arrive on stdout
+        let stderr_str = if result.stderr.trim().is_empty() {
+            &result.stdout
+        } else {
+            &result.stderr
+        };
+        if stderr_str.contains("REMOTE HOST IDENTIFICATION HAS CHANGED")
+            || stderr_str.contains("Host key verification failed")
+        {
+            return Err(FactsError::HostKeyMismatch(host.to_string()));
+        }
+        if let Some(denied) = auth_failure(result.exit_code, stderr_str) {
+            return Err(FactsError::AuthenticationFailed(
+                host.to_string(),
+                denied.to_string(),
+            ));
+        }
+        let status = result
+            .exit_code
+            .map_or_else(|| "signal".to_string(), |code| code.to_string());
+        return Err(FactsError::ConnectionFailed(
+            host.to_string(),
+            format!("Command failed with exit status: {status} - {stderr_str}"),
+        ));
+    }
+
+    Ok(result.stdout)
+}
+
+/// Read ssh's stdout to the end. When `stdin` is still open, the secret is
+/// written to it only once the output ends in a password prompt: on a
+/// remote terminal, input sent earlier is echoed back into the output or
+/// discarded when doas or su switch the terminal to no-echo mode.
+async fn read_answering_prompt(
+    mut stdout: tokio::process::ChildStdout,
+    mut stdin: Option<tokio::process::ChildStdin>,
+    secret: Option<&Secret>,
+    output: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = stdout.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        output.extend_from_slice(&chunk[..read]);
+        if let Some(secret) = secret.filter(|_| awaits_password(output)) {
+            if let Some(mut handle) = stdin.take() {
+                handle
+                    .write_all(format!("{}\n", secret.expose()).as_bytes())
+                    .await?;
+            }
+        }
+    }
+}
+
+/// The `Permission denied (publickey,password).` line ssh(1) prints when
+/// the host rejected every credential. ssh exits 255 for its own errors,
+/// so a remote command printing the same words is not mistaken for one.
+fn auth_failure(exit_code: Option<i32>, stderr: &str) -> Option<&str> {
+    if exit_code != Some(255) {
+        return None;
+    }
+    stderr
+        .lines()
+        .map(str::trim)
+        .find(|line| line.contains("Permission denied"))
+}
+
+/// The ssh(1) invocation that runs `command` on `target`, with the askpass
+/// helper it uses, which must be kept until ssh exits.
+fn openssh_command(
+    target: &SshTarget,
+    host_options: &[String],
+    command: &str,
+    pipe_stdin: bool,
+    request_tty: bool,
+    known_hosts: &Path,
+    config: &FactsConfig,
+) -> Result<(Command, Option<AskpassHelper>)> {
     let mut ssh_cmd = Command::new("ssh");
     ssh_cmd
         .arg("-o")
-        .arg("StrictHostKeyChecking=no")
+        .arg(format!("UserKnownHostsFile={}", known_hosts.display()))
         .arg("-o")
-        .arg("UserKnownHostsFile=/dev/null")
-        .arg("-o")
-        .arg(format!("ConnectTimeout={}", config.timeout))
-        .arg("-o")
-        .arg("BatchMode=yes");
+        .arg(format!("ConnectTimeout={}", config.connect_timeout));
+
+    let passphrase = config.credentials.ssh_passphrase.as_ref();
+    let password = target.password(config);
+    let mut askpass = None;
+    if passphrase.is_some() || password.is_some() {
+        // BatchMode would suppress the prompts entirely, so hand the
+        // secrets to ssh through a forced askpass helper instead.
+        let helper = askpass.insert(AskpassHelper::create()?);
+        ssh_cmd
+            .arg("-o")
+            .arg("BatchMode=no")
+            .env("SSH_ASKPASS", helper.path())
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env(
+                "DISPLAY",
+                std::env::var("DISPLAY").unwrap_or_else(|_| ":0".into()),
+            );
+        if let Some(passphrase) = passphrase {
+            ssh_cmd.env(ASKPASS_SECRET_ENV, passphrase.expose());
+        }
+        match password {
+            Some(password) => {
+                // A wrong password fails once instead of being offered again
+                ssh_cmd
+                    .arg("-o")
+                    .arg("NumberOfPasswordPrompts=1")
+                    .env(ASKPASS_PASSWORD_ENV, password.expose());
+            }
+            // Only the key passphrase may be asked for
+            None => {
+                ssh_cmd
+                    .arg("-o")
+                    .arg("PasswordAuthentication=no")
+                    .arg("-o")
+                    .arg("KbdInteractiveAuthentication=no");
+            }
+        }
+    } else {
+        ssh_cmd.arg("-o").arg("BatchMode=yes");
+    }
+
+    for option in host_options {
+        ssh_cmd.arg("-o").arg(option);
+    }
 
     if let Some(ssh_config_path) = &config.ssh_config {
         if ssh_config_path.exists() {
@@ -129,59 +981,301 @@ async fn execute_ssh_command(host: &str, command: &str, config: &FactsConfig) ->
         }
     }
 
+    if request_tty {
+        // A remote terminal lets doas/su read the password written to stdin
+        // once they prompt for it; the terminal merges stderr into stdout,
+        // which the parser tolerates
+        ssh_cmd.arg("-tt");
+    }
+
+    if let Some(port) = target.port {
+        ssh_cmd.arg("-p").arg(port.to_string());
+    }
+    if let Some(key_file) = &target.key_file {
+        ssh_cmd.arg("-i").arg(key_file);
+    }
+
     ssh_cmd
-        .arg(ssh_host.clone())
+        .arg(target.destination())
         .arg(command)
+        .stdin(if pipe_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    let mut child = ssh_cmd
-        .spawn()
-        .map_err(|e| FactsError::ConnectionFailed(host.to_string(), e.to_string()))?;
+    Ok((ssh_cmd, askpass))
+}
+
+/// Run a command through the built-in client.
+#[cfg(feature = "native-ssh")]
+#[allow(clippy::too_many_arguments)]
+async fn run_native(
+    host: &HostEntry,
+    target: &SshTarget,
+    host_options: &[String],
+    command: &str,
+    stdin_secret: Option<&Secret>,
+    request_tty: bool,
+    known_hosts: &Path,
+    config: &FactsConfig,
+) -> Result<(Option<i32>, String, String)> {
+    crate::native_ssh::exec(
+        host,
+        target,
+        host_options,
+        command,
+        stdin_secret,
+        request_tty,
+        known_hosts,
+        config,
+    )
+    .await
+}
 
-    let mut stdout = Vec::new();
-    let mut stderr = Vec::new();
+#[cfg(not(feature = "native-ssh"))]
+#[allow(clippy::too_many_arguments)]
+async fn run_native(
+    _host: &HostEntry,
+    _target: &SshTarget,
+    _host_options: &[String],
+    _command: &str,
+    _stdin_secret: Option<&Secret>,
+    _request_tty: bool,
+    _known_hosts: &Path,
+    _config: &FactsConfig,
+) -> Result<(Option<i32>, String, String)> {
+    Err(native_backend_unavailable())
+}
 
-    if let Some(mut stdout_handle) = child.stdout.take() {
-        stdout_handle.read_to_end(&mut stdout).await?;
-    }
+fn native_backend_unavailable() -> FactsError {
+    FactsError::InvalidConfig(
+        "--ssh-backend native requested but rustle-facts was built without the `native-ssh` feature"
+            .to_string(),
+    )
+}
+
+const ASKPASS_SECRET_ENV: &str = "RUSTLE_FACTS_ASKPASS_SECRET";
+const ASKPASS_PASSWORD_ENV: &str = "RUSTLE_FACTS_ASKPASS_PASSWORD";
+
+/// A tiny askpass script that echoes the secret from the ssh child's
+/// environment, so the secret itself is never written to disk. Passphrase
+/// prompts get the key passphrase and all others the login password; with
+/// no password configured they are refused rather than answered with the
+/// passphrase.
+///
+/// The script lives in a directory only this user can enter, created fresh
+/// for each connection so another local user cannot plant or swap it, and
+/// both are removed when the helper is dropped.
+struct AskpassHelper {
+    dir: PathBuf,
+    script: PathBuf,
+}
+
+impl AskpassHelper {
+    fn create() -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let write_failed =
+            |e: std::io::Error| FactsError::Ssh(format!("Failed to write askpass helper: {e}"));
+
+        let mut attempts = 0;
+        let dir = loop {
+            let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let nanos = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.subsec_nanos())
+                .unwrap_or_default();
+            let dir = std::env::temp_dir().join(format!(
+                "rustle-facts-askpass-{}-{id}-{nanos:x}",
+                std::process::id()
+            ));
+            let mut builder = std::fs::DirBuilder::new();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::DirBuilderExt;
+                builder.mode(0o700);
+            }
+            // mkdir fails on anything already there, symlinks included
+            match builder.create(&dir) {
+                Ok(()) => break dir,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 16 => {
+                    attempts += 1;
+                }
+                Err(e) => return Err(write_failed(e)),
+            }
+        };
+        let helper = Self {
+            script: dir.join("askpass.sh"),
+            dir,
+        };
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o700);
+        }
+        let mut file = options.open(&helper.script).map_err(write_failed)?;
+        std::io::Write::write_all(
+            &mut file,
+            format!(
+                "#!/bin/sh\ncase \"$1\" in\n*assphrase*) printf '%s\\n' \"${ASKPASS_SECRET_ENV}\" ;;\n*) [ -n \"${{{ASKPASS_PASSWORD_ENV}+set}}\" ] || exit 1\n   printf '%s\\n' \"${ASKPASS_PASSWORD_ENV}\" ;;\nesac\n"
+            )
+            .as_bytes(),
+        )
+        .map_err(write_failed)?;
 
-    if let Some(mut stderr_handle) = child.stderr.take() {
-        stderr_handle.read_to_end(&mut stderr).await?;
+        Ok(helper)
     }
 
-    let status = child
-        .wait()
-        .await
-        .map_err(|e| FactsError::ConnectionFailed(host.to_string(), e.to_string()))?;
+    fn path(&self) -> &Path {
+        &self.script
+    }
+}
 
-    if !status.success() {
-        let stderr_str = String::from_utf8_lossy(&stderr);
-        return Err(FactsError::ConnectionFailed(
-            host.to_string(),
-            format!("Command failed with exit status: {status} - {stderr_str}"),
-        ));
+impl Drop for AskpassHelper {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
     }
+}
 
-    Ok(String::from_utf8_lossy(&stdout).to_string())
+/// The user for hosts that name none, as ssh(1) would pick it.
+fn default_ssh_user() -> String {
+    std::env::var("USER").unwrap_or_else(|_| "root".to_string())
 }
 
-fn get_ssh_user(host: &str) -> String {
-    if host.contains('@') {
-        host.split('@').next().unwrap_or("root").to_string()
-    } else {
-        std::env::var("USER").unwrap_or_else(|_| "root".to_string())
+/// Lines the probe prints around its facts, so banners, MOTDs and profile
+/// output before or after them are ignored.
+pub const FACTS_BEGIN_MARKER: &str = "===RUSTLE_FACTS_BEGIN===";
+pub const FACTS_END_MARKER: &str = "===RUSTLE_FACTS_END===";
+
+/// The probe run on hosts: the `--probe-script` in place of the built-in
+/// one, with the `--gather-subset` snippets and then the `--probe-append`
+/// snippet run just before the end marker so their output is read with the
+/// other facts.
+pub(crate) fn fact_probe(config: &FactsConfig) -> Result<String> {
+    let script = match &config.probe_script {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| {
+            FactsError::InvalidConfig(format!("Failed to read probe script {path:?}: {e}"))
+        })?,
+        None => build_fact_gathering_command(),
+    };
+    let mut snippets = Vec::new();
+    if config.gather_subset.contains(&GatherSubset::Cloud) {
+        snippets.push(CLOUD_PROBE.trim());
     }
+    snippets.extend(
+        config
+            .probe_append
+            .as_deref()
+            .map(str::trim)
+            .filter(|snippet| !snippet.is_empty()),
+    );
+    if snippets.is_empty() {
+        return Ok(script);
+    }
+    let snippet = snippets.join("\n");
+
+    let end = format!("echo \"{FACTS_END_MARKER}\"");
+    Ok(match script.rfind(&end) {
+        Some(at) => format!("{}{}\n{}", &script[..at], snippet, &script[at..]),
+        None => format!("{}\n{}", script.trim_end(), snippet),
+    })
+}
+
+/// Asks the EC2 (IMDSv2, then v1), GCE and Azure metadata services in turn
+/// for the instance type and region, with curl or else wget. Each request
+/// gives up after a second, so hosts outside a cloud pay a few seconds.
+const CLOUD_PROBE: &str = r#"
+rustle_get() {
+    if command -v curl >/dev/null 2>&1; then
+        curl -fsS --max-time 1 ${2:+-H "$2"} "$1" 2>/dev/null
+    elif command -v wget >/dev/null 2>&1; then
+        wget -q -T 1 -O - ${2:+--header="$2"} "$1" 2>/dev/null
+    fi
 }
+token=$(curl -fsS --max-time 1 -X PUT -H "X-aws-ec2-metadata-token-ttl-seconds: 60" http://169.254.169.254/latest/api/token 2>/dev/null)
+aws_header=${token:+"X-aws-ec2-metadata-token: $token"}
+instance_type=$(rustle_get http://169.254.169.254/latest/meta-data/instance-type "$aws_header")
+if [ -n "$instance_type" ]; then
+    echo "CLOUD_PROVIDER=aws"
+    echo "INSTANCE_TYPE=$instance_type"
+    echo "REGION=$(rustle_get http://169.254.169.254/latest/meta-data/placement/region "$aws_header")"
+else
+    machine_type=$(rustle_get http://metadata.google.internal/computeMetadata/v1/instance/machine-type "Metadata-Flavor: Google")
+    if [ -n "$machine_type" ]; then
+        zone=$(rustle_get http://metadata.google.internal/computeMetadata/v1/instance/zone "Metadata-Flavor: Google")
+        zone=${zone##*/}
+        echo "CLOUD_PROVIDER=gce"
+        echo "INSTANCE_TYPE=${machine_type##*/}"
+        echo "REGION=${zone%-*}"
+    else
+        azure="http://169.254.169.254/metadata/instance/compute"
+        vm_size=$(rustle_get "$azure/vmSize?api-version=2021-02-01&format=text" "Metadata: true")
+        if [ -n "$vm_size" ]; then
+            echo "CLOUD_PROVIDER=azure"
+            echo "INSTANCE_TYPE=$vm_size"
+            echo "REGION=$(rustle_get "$azure/location?api-version=2021-02-01&format=text" "Metadata: true")"
+        fi
+    fi
+fi
+"#;
 
-fn build_fact_gathering_command() -> String {
+pub(crate) fn build_fact_gathering_command() -> String {
     r#"
-    echo "ARCH=$(uname -m)"
+    echo "===RUSTLE_FACTS_BEGIN==="
+    case "$(uname -s)" in
+        SunOS)
+            # uname -m names the platform (i86pc, sun4v), not the ISA
+            echo "ARCH=$(isainfo -k 2>/dev/null || uname -p)"
+            ;;
+        AIX)
+            # uname -m is the machine serial number
+            if [ "$(getconf KERNEL_BITMODE 2>/dev/null)" = "64" ]; then
+                echo "ARCH=ppc64"
+            else
+                echo "ARCH=ppc"
+            fi
+            ;;
+        HP-UX)
+            case "$(uname -m)" in
+                9000/*) echo "ARCH=hppa" ;;
+                *) echo "ARCH=$(uname -m)" ;;
+            esac
+            ;;
+        *)
+            echo "ARCH=$(uname -m)"
+            ;;
+    esac
     echo "SYSTEM=$(uname -s)"
-    if [ -f /etc/os-release ]; then
-        . /etc/os-release
+    # Read rather than source os-release: a line ash cannot parse would end
+    # the whole probe
+    rustle_release_value() {
+        sed -n "s/^$1=//p" "$2" 2>/dev/null | head -n 1 | tr -d "\"'"
+    }
+    ID=""
+    release=""
+    for candidate in /etc/os-release /usr/lib/os-release; do
+        if [ -z "$release" ] && [ -r "$candidate" ]; then
+            release=$candidate
+        fi
+    done
+    if [ -n "$release" ]; then
+        ID=$(rustle_release_value ID "$release")
+        ID_LIKE=$(rustle_release_value ID_LIKE "$release")
         echo "OS_FAMILY=${ID_LIKE:-$ID}"
         echo "DISTRIBUTION=$ID"
+    elif [ -f /etc/alpine-release ]; then
+        ID=alpine
+        echo "OS_FAMILY=alpine"
+        echo "DISTRIBUTION=alpine"
+    elif [ -f /etc/openwrt_release ]; then
+        ID=openwrt
+        echo "OS_FAMILY=openwrt"
+        echo "DISTRIBUTION=openwrt"
     elif [ -f /etc/redhat-release ]; then
         echo "OS_FAMILY=rhel"
         echo "DISTRIBUTION=rhel"
@@ -189,22 +1283,105 @@ fn build_fact_gathering_command() -> String {
         echo "OS_FAMILY=darwin"
         echo "DISTRIBUTION=macos"
     else
-        echo "OS_FAMILY=unknown"
-        echo "DISTRIBUTION=unknown"
+        case "$(uname -s)" in
+            SunOS) echo "OS_FAMILY=solaris"; echo "DISTRIBUTION=solaris" ;;
+            AIX) echo "OS_FAMILY=aix"; echo "DISTRIBUTION=aix" ;;
+            HP-UX) echo "OS_FAMILY=hp-ux"; echo "DISTRIBUTION=hpux" ;;
+            *) echo "OS_FAMILY=unknown"; echo "DISTRIBUTION=unknown" ;;
+        esac
+    fi
+    echo "KERNEL=$(uname -r)"
+    echo "MACHINE_ID=$(cat /etc/machine-id /var/lib/dbus/machine-id 2>/dev/null | head -n 1)"
+    if [ "$(uname -s)" = "Linux" ]; then
+        if [ "${ID:-}" = "alpine" ]; then
+            echo "LIBC=musl"
+        elif ls /lib/ld-linux* /lib64/ld-linux* >/dev/null 2>&1; then
+            echo "LIBC=gnu"
+        elif ls /lib/ld-musl-* >/dev/null 2>&1; then
+            echo "LIBC=musl"
+        else
+            case "$(ldd --version 2>&1)" in
+                *musl*) echo "LIBC=musl" ;;
+                *GLIBC*|*"GNU libc"*) echo "LIBC=gnu" ;;
+            esac
+        fi
+        if [ -f /sys/fs/cgroup/cgroup.controllers ]; then
+            echo "CGROUP_VERSION=2"
+        elif [ -d /sys/fs/cgroup ]; then
+            echo "CGROUP_VERSION=1"
+        fi
+        if [ -f /.dockerenv ] || [ -f /run/.containerenv ] || [ -f /run/systemd/container ] \
+            || grep -qE 'docker|kubepods|containerd|libpod|lxc' /proc/1/cgroup 2>/dev/null; then
+            echo "CONTAINER=yes"
+        else
+            echo "CONTAINER=no"
+        fi
+    fi
+    if [ -d /run/systemd/system ]; then
+        echo "SERVICE_MGR=systemd"
+    elif [ -d /run/openrc ]; then
+        echo "SERVICE_MGR=openrc"
+    elif [ "$(uname -s)" = "Darwin" ]; then
+        echo "SERVICE_MGR=launchd"
+    else
+        echo "SERVICE_MGR=$(cat /proc/1/comm 2>/dev/null)"
     fi
+    echo "===RUSTLE_FACTS_END==="
     "#
     .trim()
     .to_string()
 }
 
-pub fn parse_fact_output(output: &str) -> Result<ArchitectureFacts> {
-    let mut facts = HashMap::new();
+/// Keys the built-in probe prints; any others are custom facts.
+const PROBE_KEYS: &[&str] = &[
+    "ARCH",
+    "SYSTEM",
+    "OS_FAMILY",
+    "DISTRIBUTION",
+    "LIBC",
+    "SERVICE_MGR",
+    "KERNEL",
+    "MACHINE_ID",
+    "CLOUD_PROVIDER",
+    "INSTANCE_TYPE",
+    "REGION",
+    "CGROUP_VERSION",
+    "CONTAINER",
+];
 
-    for line in output.lines() {
-        if let Some((key, value)) = line.split_once('=') {
-            facts.insert(key.trim().to_string(), value.trim().to_string());
-        }
+/// The facts a probe run with `config` reports whenever the host has them.
+/// A gathered host without one of these does not have it, so it is not
+/// filled in from an older cache entry.
+pub(crate) fn probed_facts(config: &FactsConfig) -> Vec<&'static str> {
+    let mut facts = vec![
+        "ansible_distribution",
+        "rustle_libc",
+        "ansible_kernel",
+        "ansible_machine_id",
+        "ansible_service_mgr",
+        "rustle_cgroup_version",
+        "rustle_in_container",
+    ];
+    if config.gather_subset.contains(&GatherSubset::Cloud) {
+        facts.extend(["cloud_provider", "instance_type", "region"]);
     }
+    if config.probe_append.is_some() || config.probe_script.is_some() {
+        facts.push("custom");
+    }
+    facts
+}
+
+/// Parse probe output into facts. Never panics: unparseable input yields a
+/// `ParseError`. Pairs the built-in probe does not print, such as those of a
+/// `--probe-append` snippet, are kept in the `custom` fact.
+pub fn parse_fact_output(output: &str) -> Result<ArchitectureFacts> {
+    parse_fact_output_with(output, &ArchTable::default())
+}
+
+/// [`parse_fact_output`], normalizing the architecture through
+/// `arch_table` so `--arch-map` entries match the spelling the host printed.
+pub fn parse_fact_output_with(output: &str, arch_table: &ArchTable) -> Result<ArchitectureFacts> {
+    let facts = parse_fact_pairs(output);
 
     let architecture = facts
         .get("ARCH")
@@ -216,33 +1393,363 @@ pub fn parse_fact_output(output: &str) -> Result<ArchitectureFacts> {
         .ok_or_else(|| FactsError::ParseError("unknown".to_string(), "Missing SYSTEM".to_string()))?
         .clone();
 
-    let os_family = facts
-        .get("OS_FAMILY")
-        .unwrap_or(&"unknown".to_string())
-        .clone();
-
+    // The probe reports ${ID_LIKE:-$ID}; unknown distros keep that value
     let distribution = facts.get("DISTRIBUTION").cloned();
+    let raw_family = facts.get("OS_FAMILY");
+    let os_family = ArchitectureFacts::normalize_os_family(
+        distribution.as_deref(),
+        raw_family.map(String::as_str),
+    )
+    .map(str::to_string)
+    .or_else(|| raw_family.filter(|family| *family != "unknown").cloned())
+    .unwrap_or_else(|| default_os_family(&system).to_string());
+
+    let mut extra = custom_facts(&facts);
+    for (key, fact) in [
+        ("KERNEL", "ansible_kernel"),
+        ("MACHINE_ID", "ansible_machine_id"),
+        ("CLOUD_PROVIDER", "cloud_provider"),
+        ("INSTANCE_TYPE", "instance_type"),
+        ("REGION", "region"),
+    ] {
+        if let Some(value) = facts.get(key) {
+            extra.insert(fact.to_string(), value.clone().into());
+        }
+    }
+    if let Some(service_mgr) = facts.get("SERVICE_MGR").and_then(|s| service_manager(s)) {
+        extra.insert("ansible_service_mgr".to_string(), service_mgr.into());
+    }
+    if let Some(version) = facts
+        .get("CGROUP_VERSION")
+        .and_then(|v| v.parse::<u8>().ok())
+    {
+        extra.insert("rustle_cgroup_version".to_string(), version.into());
+    }
+    if let Some(container) = facts.get("CONTAINER") {
+        extra.insert(
+            "rustle_in_container".to_string(),
+            (container == "yes").into(),
+        );
+    }
 
     Ok(ArchitectureFacts {
-        ansible_architecture: ArchitectureFacts::normalize_architecture(&architecture),
+        ansible_architecture: arch_table.normalize(&architecture),
         ansible_system: system,
         ansible_os_family: os_family,
         ansible_distribution: distribution,
+        rustle_libc: facts.get("LIBC").cloned(),
+        extra,
     })
 }
 
-pub fn generate_ssh_fingerprint(host: &str) -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
+/// Map what the probe found, usually the name of PID 1, to an
+/// `ansible_service_mgr` value. A PID 1 that is no init system, as in most
+/// containers, gives `None`.
+pub(crate) fn service_manager(found: &str) -> Option<&'static str> {
+    match found.trim() {
+        "systemd" => Some("systemd"),
+        "openrc" | "openrc-init" => Some("openrc"),
+        "runit" | "runsvdir" => Some("runit"),
+        "s6-svscan" => Some("s6"),
+        "launchd" => Some("launchd"),
+        "init" => Some("sysvinit"),
+        _ => None,
+    }
+}
 
-    let mut hasher = DefaultHasher::new();
-    host.hash(&mut hasher);
-    format!("{:x}", hasher.finish())
+fn custom_facts(pairs: &HashMap<String, String>) -> serde_json::Map<String, serde_json::Value> {
+    let custom: BTreeMap<&String, &String> = pairs
+        .iter()
+        .filter(|(key, _)| !PROBE_KEYS.contains(&key.as_str()))
+        .collect();
+    let mut extra = serde_json::Map::new();
+    if !custom.is_empty() {
+        extra.insert("custom".to_string(), serde_json::json!(custom));
+    }
+    extra
 }
 
-#[cfg(test)]
-mod tests {
+/// Parse raw probe output that may not be valid UTF-8.
+pub fn parse_fact_bytes(output: &[u8]) -> Result<ArchitectureFacts> {
+    parse_fact_output(&String::from_utf8_lossy(output))
+}
+
+/// Extract `KEY=value` pairs from probe output.
+///
+/// Tolerates CRLF line endings, control characters and banner noise, and
+/// ignores lines whose key is not a plain identifier. When the output has
+/// the probe's begin marker, only the lines between it and the end marker
+/// are read. Quoted values may span several lines and are joined with
+/// spaces. Empty values are dropped, and the last occurrence of a key wins.
+pub fn parse_fact_pairs(output: &str) -> HashMap<String, String> {
+    let lines = fact_region(output.lines().map(clean_line).collect());
+    let mut pairs = HashMap::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = &lines[i];
+        i += 1;
+
+        let Some((key, value)) = split_assignment(line) else {
+            continue;
+        };
+
+        let mut value = value.to_string();
+        if let Some(quote) = unclosed_quote(&value) {
+            // Continue until the closing quote, but never swallow the next fact
+            while i < lines.len() && split_assignment(&lines[i]).is_none() {
+                value.push(' ');
+                value.push_str(lines[i].trim());
+                i += 1;
+                if value.ends_with(quote) {
+                    break;
+                }
+            }
+        }
+
+        let value = strip_quotes(&value);
+        if !value.is_empty() {
+            pairs.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    pairs
+}
+
+/// The lines between the begin and end markers, or every line when the
+/// output has no begin marker. A missing end marker keeps the rest.
+fn fact_region(mut lines: Vec<String>) -> Vec<String> {
+    let Some(begin) = lines
+        .iter()
+        .position(|line| line.trim() == FACTS_BEGIN_MARKER)
+    else {
+        return lines;
+    };
+    lines.drain(..=begin);
+    if let Some(end) = lines
+        .iter()
+        .position(|line| line.trim() == FACTS_END_MARKER)
+    {
+        lines.truncate(end);
+    }
+    lines
+}
+
+fn clean_line(line: &str) -> String {
+    line.chars()
+        .filter(|c| *c == '\t' || !(c.is_control() || *c == '\u{feff}'))
+        .collect()
+}
+
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    let is_identifier = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_identifier.then(|| (key, value.trim()))
+}
+
+fn unclosed_quote(value: &str) -> Option<char> {
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let closed = value.len() > 1 && value.ends_with(quote);
+    (!closed).then_some(quote)
+}
+
+fn strip_quotes(value: &str) -> &str {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote) {
+            return inner.strip_suffix(quote).unwrap_or(inner).trim();
+        }
+    }
+    value
+}
+
+/// Throwaway known_hosts file for a single connection, removed on drop.
+struct KnownHostsFile(PathBuf);
+
+impl KnownHostsFile {
+    fn new() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self(std::env::temp_dir().join(format!(
+            "rustle-facts-{}-{id}.known_hosts",
+            std::process::id()
+        )))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for KnownHostsFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+async fn fingerprint_known_hosts(path: &Path, host: Option<&str>) -> Result<HostKeys> {
+    if !path.exists() {
+        return Ok(HostKeys::new());
+    }
+
+    let mut keygen = Command::new("ssh-keygen");
+    keygen.arg("-l").arg("-E").arg("sha256").arg("-f").arg(path);
+    if let Some(host) = host {
+        keygen.arg("-F").arg(host);
+    }
+    let output = keygen.output().await?;
+
+    Ok(host_key_fingerprints(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Remove a host's recorded key from a known_hosts file. Keys for a port
+/// other than 22 are recorded as `[host]:port`, which is what is looked up
+/// when `port` is given or `host` is written that way. Returns whether an
+/// entry was found.
+pub async fn forget_host_key(known_hosts: &Path, host: &str, port: Option<u16>) -> Result<bool> {
+    let (hostname, port) = match host
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once("]:"))
+    {
+        Some((hostname, written_port)) => {
+            let written_port = written_port.parse().map_err(|_| {
+                FactsError::InvalidConfig(format!("invalid port in host key name {host}"))
+            })?;
+            (hostname, Some(port.unwrap_or(written_port)))
+        }
+        None => (host, port),
+    };
+    validate_hostname(hostname)?;
+    let name = match port {
+        Some(port) if port != 22 => format!("[{hostname}]:{port}"),
+        _ => hostname.to_string(),
+    };
+
+    if !known_hosts.exists() {
+        return Ok(false);
+    }
+
+    // -R succeeds whether or not the host had a key; -F exits 1 when it has none
+    let found = Command::new("ssh-keygen")
+        .arg("-F")
+        .arg(&name)
+        .arg("-f")
+        .arg(known_hosts)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?
+        .success();
+    if !found {
+        return Ok(false);
+    }
+
+    let output = Command::new("ssh-keygen")
+        .arg("-R")
+        .arg(&name)
+        .arg("-f")
+        .arg(known_hosts)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(FactsError::Ssh(format!(
+            "ssh-keygen -R failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    // ssh-keygen leaves a .old backup behind; the managed file has no use for it
+    let mut backup = known_hosts.as_os_str().to_owned();
+    backup.push(".old");
+    let _ = std::fs::remove_file(backup);
+
+    Ok(true)
+}
+
+/// Fetch a host's current key fingerprints with `ssh-keyscan`, without
+/// logging in.
+pub async fn scan_host_keys(host: &str, port: Option<u16>, timeout_secs: u64) -> Result<HostKeys> {
+    let hostname = host.rsplit('@').next().unwrap_or(host);
+    validate_hostname(hostname)?;
+
+    let mut keyscan = Command::new("ssh-keyscan");
+    keyscan.arg("-T").arg(timeout_secs.to_string());
+    if let Some(port) = port {
+        keyscan.arg("-p").arg(port.to_string());
+    }
+    let scanned = keyscan
+        .arg(hostname)
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| FactsError::ConnectionFailed(host.to_string(), e.to_string()))?;
+
+    if scanned.stdout.is_empty() {
+        return Ok(HostKeys::new());
+    }
+
+    let mut keygen = Command::new("ssh-keygen")
+        .args(["-l", "-E", "sha256", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = keygen.stdin.take() {
+        stdin.write_all(&scanned.stdout).await?;
+    }
+
+    let output = keygen.wait_with_output().await?;
+    Ok(host_key_fingerprints(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// The fingerprints in `ssh-keygen -l` output, by key type.
+fn host_key_fingerprints(keygen_output: &str) -> HostKeys {
+    // `-l -f` prints `bits SHA256:... comment (TYPE)`; with `-F` it is
+    // `host TYPE SHA256:... comment`
+    keygen_output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let at = parts.iter().position(|part| part.starts_with("SHA256:"))?;
+            let key_type = match parts.last() {
+                Some(last) if last.starts_with('(') && last.ends_with(')') => {
+                    last.trim_matches(['(', ')'])
+                }
+                _ => parts.get(at.checked_sub(1)?)?,
+            };
+            Some((key_type.to_uppercase(), parts[at].to_string()))
+        })
+        .collect()
+}
+
+/// The key to record when a host offers several: the type OpenSSH
+/// negotiates first. Comparisons use the recorded type, not this order.
+pub fn preferred_host_key(keys: &HostKeys) -> Option<(&String, &String)> {
+    const PREFERENCE: [&str; 3] = ["ED25519", "ECDSA", "RSA"];
+
+    PREFERENCE
+        .iter()
+        .find_map(|wanted| keys.get_key_value(*wanted))
+        .or_else(|| keys.iter().next())
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::secrets::Credentials;
+    use crate::ssh_client::OpenSshVersion;
 
     #[test]
     fn test_parse_fact_output() {
@@ -258,6 +1765,37 @@ DISTRIBUTION=ubuntu
         assert_eq!(facts.ansible_system, "Linux");
         assert_eq!(facts.ansible_os_family, "debian");
         assert_eq!(facts.ansible_distribution, Some("ubuntu".to_string()));
+        assert!(!facts.extra.contains_key("ansible_service_mgr"));
+        assert_eq!(facts.rustle_libc, None);
+
+        let output = "ARCH=x86_64\nSYSTEM=Linux\nKERNEL=6.1.0-18-amd64\nMACHINE_ID=4c1d\n";
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.extra["ansible_kernel"], "6.1.0-18-amd64");
+        assert_eq!(facts.extra["ansible_machine_id"], "4c1d");
+        assert!(!facts.extra.contains_key("custom"));
+
+        let output = "ARCH=x86_64\nSYSTEM=Linux\nCGROUP_VERSION=2\nCONTAINER=yes\n";
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.extra["rustle_cgroup_version"], 2);
+        assert_eq!(facts.extra["rustle_in_container"], true);
+
+        let alpine = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=alpine\nLIBC=musl\n";
+        let facts = parse_fact_output(alpine).unwrap();
+        assert_eq!(facts.rustle_libc, Some("musl".to_string()));
+        assert!(facts.extra.is_empty());
+    }
+
+    #[test]
+    fn test_parse_fact_output_service_mgr() {
+        let parse = |service_mgr: &str| {
+            let output = format!("ARCH=x86_64\nSYSTEM=Linux\nSERVICE_MGR={service_mgr}\n");
+            parse_fact_output(&output).unwrap().extra
+        };
+        assert_eq!(parse("systemd")["ansible_service_mgr"], "systemd");
+        assert_eq!(parse("runsvdir")["ansible_service_mgr"], "runit");
+        assert_eq!(parse("init")["ansible_service_mgr"], "sysvinit");
+        // A container whose PID 1 is its application
+        assert!(parse("nginx").is_empty());
     }
 
     #[test]
@@ -276,6 +1814,685 @@ DISTRIBUTION=macos
         assert_eq!(facts.ansible_distribution, Some("macos".to_string()));
     }
 
+    #[test]
+    fn test_parse_fact_output_legacy_unix() {
+        let solaris = "ARCH=sparcv9\nSYSTEM=SunOS\nOS_FAMILY=solaris\nDISTRIBUTION=solaris\n";
+        let facts = parse_fact_output(solaris).unwrap();
+        assert_eq!(facts.ansible_architecture, "sparc64");
+        assert_eq!(facts.ansible_os_family, "solaris");
+
+        let omnios = "ARCH=amd64\nSYSTEM=SunOS\nOS_FAMILY=omnios\nDISTRIBUTION=omnios\n";
+        let facts = parse_fact_output(omnios).unwrap();
+        assert_eq!(facts.ansible_architecture, "x86_64");
+        assert_eq!(facts.ansible_os_family, "solaris");
+
+        let aix = "ARCH=ppc64\nSYSTEM=AIX\nOS_FAMILY=aix\nDISTRIBUTION=aix\n";
+        let facts = parse_fact_output(aix).unwrap();
+        assert_eq!(facts.ansible_architecture, "ppc64");
+        assert_eq!(facts.ansible_os_family, "aix");
+
+        // A probe without a family branch for the system falls back on it
+        let hpux = "ARCH=hppa\nSYSTEM=HP-UX\nOS_FAMILY=unknown\nDISTRIBUTION=unknown\n";
+        let facts = parse_fact_output(hpux).unwrap();
+        assert_eq!(facts.ansible_architecture, "hppa");
+        assert_eq!(facts.ansible_os_family, "hp-ux");
+    }
+
+    #[test]
+    fn test_parse_fact_output_tolerates_noise() {
+        let output = "\u{feff}Welcome to web1!\r\n\x1b[0mLast login: today\r\n\
+ARCH=x86_64\r\nSYSTEM=Linux\r\nfoo bar = baz\r\nUNEXPECTED=1\r\n\
+OS_FAMILY=\"rhel\r\nfedora\"\r\nDISTRIBUTION='rocky'\r\n";
+
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.ansible_architecture, "x86_64");
+        assert_eq!(facts.ansible_system, "Linux");
+        assert_eq!(facts.ansible_os_family, "redhat");
+        assert_eq!(facts.ansible_distribution, Some("rocky".to_string()));
+    }
+
+    #[test]
+    fn test_parse_fact_output_maps_id_like() {
+        let oracle = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=fedora\nDISTRIBUTION=ol\n";
+        assert_eq!(
+            parse_fact_output(oracle).unwrap().ansible_os_family,
+            "redhat"
+        );
+
+        let amazon =
+            "ARCH=aarch64\nSYSTEM=Linux\nOS_FAMILY=\"centos rhel fedora\"\nDISTRIBUTION=amzn\n";
+        assert_eq!(
+            parse_fact_output(amazon).unwrap().ansible_os_family,
+            "redhat"
+        );
+
+        let tumbleweed = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=\"opensuse suse\"\nDISTRIBUTION=opensuse-tumbleweed\n";
+        assert_eq!(
+            parse_fact_output(tumbleweed).unwrap().ansible_os_family,
+            "suse"
+        );
+
+        let gentoo = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=gentoo\nDISTRIBUTION=gentoo\n";
+        assert_eq!(
+            parse_fact_output(gentoo).unwrap().ansible_os_family,
+            "gentoo"
+        );
+
+        let void = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=void\nDISTRIBUTION=void\n";
+        assert_eq!(parse_fact_output(void).unwrap().ansible_os_family, "void");
+
+        // Unknown distros keep what the probe reported
+        let slackware = "ARCH=x86_64\nSYSTEM=Linux\nOS_FAMILY=slackware\nDISTRIBUTION=slackware\n";
+        assert_eq!(
+            parse_fact_output(slackware).unwrap().ansible_os_family,
+            "slackware"
+        );
+    }
+
+    #[test]
+    fn test_parse_fact_output_reads_between_markers() {
+        let output = "ARCH=sparc
+SYSTEM=SunOS
+echo \"===RUSTLE_FACTS_BEGIN===\"
+===RUSTLE_FACTS_BEGIN===
+ARCH=aarch64
+SYSTEM=Linux
+OS_FAMILY=debian
+===RUSTLE_FACTS_END===
+DISTRIBUTION=bogus
+LIBC=musl
+";
+
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.ansible_architecture, "aarch64");
+        assert_eq!(facts.ansible_system, "Linux");
+        assert_eq!(facts.ansible_distribution, None);
+        assert_eq!(facts.rustle_libc, None);
+
+        // Truncated after the facts: the rest of the output is kept
+        let truncated = "motd
+===RUSTLE_FACTS_BEGIN===
+ARCH=x86_64
+SYSTEM=Linux
+";
+        assert_eq!(
+            parse_fact_output(truncated).unwrap().ansible_architecture,
+            "x86_64"
+        );
+    }
+
+    #[test]
+    fn test_arch_map_sees_the_host_spelling() {
+        let output =
+            "===RUSTLE_FACTS_BEGIN===\nARCH=armv7l\nSYSTEM=Linux\n===RUSTLE_FACTS_END===\n";
+        let table = ArchTable::from_entries([("armv7l".to_string(), "armv6".to_string())]);
+        assert_eq!(
+            parse_fact_output_with(output, &table)
+                .unwrap()
+                .ansible_architecture,
+            "armv6"
+        );
+        assert_eq!(
+            parse_fact_output(output).unwrap().ansible_architecture,
+            "armv7"
+        );
+    }
+
+    #[test]
+    fn test_gather_subset_cloud() {
+        let config = FactsConfig {
+            gather_subset: vec![GatherSubset::Cloud],
+            probe_append: Some("echo RACK=b12".to_string()),
+            ..Default::default()
+        };
+        let probe = fact_probe(&config).unwrap();
+        let cloud = probe.find("CLOUD_PROVIDER=aws").unwrap();
+        assert!(cloud < probe.find("RACK=").unwrap());
+        assert!(!fact_probe(&FactsConfig::default())
+            .unwrap()
+            .contains("CLOUD_PROVIDER"));
+
+        let output = "ARCH=x86_64\nSYSTEM=Linux\nCLOUD_PROVIDER=gce\n\
+INSTANCE_TYPE=e2-medium\nREGION=us-central1\n";
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.extra["cloud_provider"], "gce");
+        assert_eq!(facts.extra["instance_type"], "e2-medium");
+        assert_eq!(facts.extra["region"], "us-central1");
+        assert!(!facts.extra.contains_key("custom"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_password_waits_for_prompt() {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("printf 'Password: '; read answer; echo \"answer=$answer\"")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let secret = Secret::new("hunter2");
+        let mut output = Vec::new();
+        read_answering_prompt(
+            child.stdout.take().unwrap(),
+            child.stdin.take(),
+            Some(&secret),
+            &mut output,
+        )
+        .await
+        .unwrap();
+        child.wait().await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Password: answer=hunter2\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_probe_runs_under_sh() {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(build_fact_gathering_command())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let facts = parse_fact_bytes(&output.stdout).unwrap();
+        assert!(!facts.ansible_architecture.is_empty());
+        assert!(!facts.ansible_system.is_empty());
+
+        // OpenWrt's os-release says ID_LIKE="lede openwrt"
+        let openwrt = "ARCH=mips\nSYSTEM=Linux\nOS_FAMILY=lede openwrt\nDISTRIBUTION=openwrt\n";
+        assert_eq!(
+            parse_fact_output(openwrt).unwrap().ansible_os_family,
+            "openwrt"
+        );
+    }
+
+    #[test]
+    fn test_probe_append_adds_custom_facts() {
+        let config = FactsConfig {
+            probe_append: Some("echo \"RACK=$(cat /etc/rack)\"\n".to_string()),
+            ..Default::default()
+        };
+        let probe = fact_probe(&config).unwrap();
+        let snippet = probe.find("RACK=").unwrap();
+        assert!(snippet > probe.find("DISTRIBUTION=").unwrap());
+        assert!(snippet < probe.find(FACTS_END_MARKER).unwrap());
+
+        let output = "===RUSTLE_FACTS_BEGIN===
+ARCH=x86_64
+SYSTEM=Linux
+RACK=b12
+===RUSTLE_FACTS_END===
+";
+        let facts = parse_fact_output(output).unwrap();
+        assert_eq!(facts.extra["custom"], serde_json::json!({"RACK": "b12"}));
+        assert!(parse_fact_output("ARCH=x86_64\nSYSTEM=Linux\n")
+            .unwrap()
+            .extra
+            .is_empty());
+
+        let missing = FactsConfig {
+            probe_script: Some("/nonexistent/probe.sh".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            fact_probe(&missing),
+            Err(FactsError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_fact_pairs_unclosed_quote_stops_at_next_fact() {
+        let pairs = parse_fact_pairs("OS_FAMILY=\"debian\nSYSTEM=Linux\nARCH=\n");
+        assert_eq!(pairs.get("OS_FAMILY").map(String::as_str), Some("debian"));
+        assert_eq!(pairs.get("SYSTEM").map(String::as_str), Some("Linux"));
+        assert!(!pairs.contains_key("ARCH"));
+
+        assert!(parse_fact_bytes(b"ARCH=\xff\xfe\nSYSTEM=Linux").is_ok());
+        assert!(parse_fact_output("=\n\"\n'").is_err());
+    }
+
+    #[test]
+    fn test_auth_failure_is_told_from_network_errors() {
+        let denied = "Warning: Permanently added 'web1' to the list of known hosts.\r\n\
+deploy@web1: Permission denied (publickey,password).\r\n";
+        assert_eq!(
+            auth_failure(Some(255), denied),
+            Some("deploy@web1: Permission denied (publickey,password).")
+        );
+        // The probe itself failing on a file is not an SSH login failure
+        assert_eq!(
+            auth_failure(Some(1), "cat: /etc/rack: Permission denied"),
+            None
+        );
+
+        let error = FactsError::AuthenticationFailed("web1".to_string(), denied.to_string());
+        assert_eq!(
+            unreachable_reason(&error),
+            Some(("web1", UnreachableReason::AuthenticationFailed))
+        );
+        assert_eq!(
+            unreachable_reason(&FactsError::Timeout("web1".to_string())),
+            Some(("web1", UnreachableReason::Timeout))
+        );
+    }
+
+    #[test]
+    fn test_host_key_fingerprints_by_type() {
+        let output = "\
+3072 SHA256:rsaFingerprint web1 (RSA)
+256 SHA256:ed25519Fingerprint web1 (ED25519)
+256 SHA256:ecdsaFingerprint web1 (ECDSA)
+";
+        let keys = host_key_fingerprints(output);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys["RSA"], "SHA256:rsaFingerprint");
+        assert_eq!(
+            preferred_host_key(&keys),
+            Some((
+                &"ED25519".to_string(),
+                &"SHA256:ed25519Fingerprint".to_string()
+            ))
+        );
+        assert!(host_key_fingerprints("").is_empty());
+        assert!(host_key_fingerprints("garbage line").is_empty());
+
+        let known_hosts = "\
+# Host [web1]:2222 found: line 1
+[web1]:2222 RSA SHA256:rsaFingerprint root@web1
+[web1]:2222 ECDSA SHA256:ecdsaFingerprint root@web1
+";
+        let keys = host_key_fingerprints(known_hosts);
+        assert_eq!(keys["ECDSA"], "SHA256:ecdsaFingerprint");
+        assert_eq!(preferred_host_key(&keys).unwrap().0, "ECDSA");
+    }
+
+    #[test]
+    fn test_probe_strategy_raw() {
+        let mut host = HostEntry::new("appliance");
+        assert_eq!(
+            ProbeStrategy::for_host(&host).unwrap(),
+            ProbeStrategy::Script
+        );
+
+        host.vars.insert(
+            "rustle_facts_strategy".to_string(),
+            serde_json::json!("raw"),
+        );
+        let strategy = ProbeStrategy::for_host(&host).unwrap();
+        assert_eq!(strategy, ProbeStrategy::Raw);
+        assert_eq!(
+            strategy.command(&FactsConfig::default()).unwrap(),
+            "uname -m; uname -s"
+        );
+
+        let facts = strategy
+            .parse(
+                "Welcome to the appliance shell\r\narmv7l\r\nLinux\r\n",
+                &ArchTable::default(),
+            )
+            .unwrap();
+        assert_eq!(facts.ansible_architecture, "armv7");
+        assert_eq!(facts.ansible_system, "Linux");
+        assert_eq!(facts.ansible_distribution, None);
+        assert!(parse_raw_output("Linux\n").is_err());
+
+        let table = ArchTable::from_entries([("armv7l".to_string(), "armv6".to_string())]);
+        let facts = strategy.parse("armv7l\nLinux\n", &table).unwrap();
+        assert_eq!(facts.ansible_architecture, "armv6");
+
+        host.vars.insert(
+            "rustle_facts_strategy".to_string(),
+            serde_json::json!("shell"),
+        );
+        assert!(ProbeStrategy::for_host(&host).is_err());
+
+        host.vars.insert(
+            "ansible_shell_type".to_string(),
+            serde_json::json!("powershell"),
+        );
+        assert_eq!(
+            ProbeStrategy::for_host(&host).unwrap(),
+            ProbeStrategy::Windows
+        );
+    }
+
+    #[test]
+    fn test_host_ssh_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FactsConfig {
+            connect_timeout: 9,
+            cache_file: dir.path().join("arch-facts.json"),
+            ..Default::default()
+        };
+        let features = SshFeatures::for_version(None);
+        let mut host = HostEntry::new("db1");
+        assert_eq!(
+            host_ssh_options(&host, &config, features).unwrap(),
+            [
+                "StrictHostKeyChecking=no",
+                "ServerAliveInterval=3",
+                "ServerAliveCountMax=3"
+            ]
+        );
+
+        host.ssh_pipelining = Some(true);
+        let options = host_ssh_options(&host, &config, features).unwrap();
+        assert!(options.contains(&"ControlMaster=auto".to_string()));
+        assert!(options
+            .iter()
+            .any(|o| o.starts_with("ControlPath=") && o.ends_with("cm-%C")));
+
+        host.ssh_pipelining = None;
+        host.vars.insert(
+            "ansible_ssh_proxy_command".to_string(),
+            serde_json::json!("cloudflared access ssh --hostname %h"),
+        );
+        assert_eq!(
+            host_ssh_options(&host, &config, features)
+                .unwrap()
+                .last()
+                .unwrap(),
+            "ProxyCommand=cloudflared access ssh --hostname %h"
+        );
+
+        host.vars.insert(
+            "ansible_ssh_proxy_command".to_string(),
+            serde_json::json!("tsh proxy ssh %r@%h:%p\n-oPermitLocalCommand=yes"),
+        );
+        assert!(host_ssh_options(&host, &config, features).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_known_hosts_entries_with_ports() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key");
+        let generated = std::process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status();
+        if !generated.is_ok_and(|status| status.success()) {
+            return;
+        }
+        let public_key = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+        let known_hosts = dir.path().join("known_hosts");
+        std::fs::write(&known_hosts, format!("[web1]:2222 {public_key}")).unwrap();
+
+        let keys = fingerprint_known_hosts(&known_hosts, Some("[web1]:2222"))
+            .await
+            .unwrap();
+        assert!(keys["ED25519"].starts_with("SHA256:"));
+        assert!(fingerprint_known_hosts(&known_hosts, Some("web1"))
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(!forget_host_key(&known_hosts, "web1", None).await.unwrap());
+        assert!(forget_host_key(&known_hosts, "web1", Some(2222))
+            .await
+            .unwrap());
+        assert!(!forget_host_key(&known_hosts, "[web1]:2222", None)
+            .await
+            .unwrap());
+        assert!(!std::fs::read_to_string(&known_hosts)
+            .unwrap()
+            .contains("web1"));
+    }
+
+    #[test]
+    fn test_breaker_backend_is_the_shared_path() {
+        let mut host = HostEntry::new("web1");
+        assert_eq!(breaker_backend(&host), None);
+
+        host.vars.insert(
+            "ansible_ssh_common_args".to_string(),
+            serde_json::json!("-o ProxyJump=bastion.example.com"),
+        );
+        assert_eq!(
+            breaker_backend(&host).as_deref(),
+            Some("ssh via bastion.example.com")
+        );
+
+        host.vars.remove("ansible_ssh_common_args");
+        host.ssh_extra_args = Some("-J admin@bastion".to_string());
+        assert_eq!(
+            breaker_backend(&host).as_deref(),
+            Some("ssh via admin@bastion")
+        );
+
+        host.vars.insert(
+            "ansible_ssh_proxy_command".to_string(),
+            serde_json::json!("nc -X 5 -x socks:1080 %h %p"),
+        );
+        assert_eq!(
+            breaker_backend(&host).as_deref(),
+            Some("ssh via nc -X 5 -x socks:1080 %h %p")
+        );
+        assert_eq!(proxy_jump("-o ProxyJump=none -o Compression=yes"), None);
+    }
+
+    #[test]
+    fn test_ssh_target_uses_connection_vars() {
+        let mut host = HostEntry::new("web1");
+        host.vars
+            .insert("ansible_host".to_string(), serde_json::json!("10.0.0.5"));
+        host.vars
+            .insert("ansible_port".to_string(), serde_json::json!("2222"));
+        host.vars
+            .insert("ansible_user".to_string(), serde_json::json!("deploy"));
+        host.vars.insert(
+            "ansible_ssh_private_key_file".to_string(),
+            serde_json::json!("~/.ssh/web"),
+        );
+        let target = SshTarget::for_host(&host).unwrap();
+        assert_eq!(target.destination(), "deploy@10.0.0.5");
+        assert_eq!(target.known_hosts_name(), "[10.0.0.5]:2222");
+
+        let config = FactsConfig::default();
+        let (command, _askpass) = openssh_command(
+            &target,
+            &[],
+            "true",
+            false,
+            false,
+            Path::new("/dev/null"),
+            &config,
+        )
+        .unwrap();
+        let args: Vec<String> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert!(args.windows(2).any(|pair| pair == ["-p", "2222"]));
+        assert!(args.windows(2).any(|pair| pair == ["-i", "~/.ssh/web"]));
+        assert!(args.ends_with(&["deploy@10.0.0.5".to_string(), "true".to_string()]));
+
+        // Entry fields win over vars; a user@ name still names the user
+        host.port = Some(22);
+        host.user = Some("admin".to_string());
+        let target = SshTarget::for_host(&host).unwrap();
+        assert_eq!(target.destination(), "admin@10.0.0.5");
+        assert_eq!(target.known_hosts_name(), "10.0.0.5");
+
+        let target = SshTarget::for_host(&HostEntry::new("ops@db1")).unwrap();
+        assert_eq!(target.destination(), "ops@db1");
+        assert_eq!(target.port, None);
+
+        let mut host = HostEntry::new("db1");
+        host.vars.insert(
+            "ansible_host".to_string(),
+            serde_json::json!("db1; rm -rf /"),
+        );
+        assert!(SshTarget::for_host(&host).is_err());
+    }
+
+    #[test]
+    fn test_ssh_target_applies_ssh_config() {
+        let ssh_config = SshConfig::parse(
+            "Host web1\n    HostName 10.0.0.5\n    Port 2222\n    User deploy\n    IdentityFile /keys/web\n",
+        )
+        .unwrap();
+        let mut host = HostEntry::new("web1");
+        let target = SshTarget::with_ssh_config(&host, Some(&ssh_config)).unwrap();
+        // ssh(1) is still given the alias so the rest of its config applies
+        assert_eq!(target.destination(), "deploy@web1");
+        assert_eq!(target.address, "10.0.0.5");
+        assert_eq!(target.known_hosts_name(), "[10.0.0.5]:2222");
+        assert_eq!(target.key_file.as_deref(), Some("/keys/web"));
+
+        // The inventory wins over ssh_config
+        host.vars
+            .insert("ansible_user".to_string(), serde_json::json!("admin"));
+        host.vars
+            .insert("ansible_port".to_string(), serde_json::json!(22));
+        let target = SshTarget::with_ssh_config(&host, Some(&ssh_config)).unwrap();
+        assert_eq!(target.destination(), "admin@web1");
+        assert_eq!(target.known_hosts_name(), "10.0.0.5");
+    }
+
+    #[test]
+    fn test_password_is_given_through_askpass() {
+        let config = FactsConfig::default();
+        let args_and_env = |host: &HostEntry| {
+            let target = SshTarget::for_host(host).unwrap();
+            let (command, _askpass) = openssh_command(
+                &target,
+                &[],
+                "true",
+                false,
+                false,
+                Path::new("/dev/null"),
+                &config,
+            )
+            .unwrap();
+            let command = command.as_std();
+            let args: Vec<String> = command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect();
+            let password = command
+                .get_envs()
+                .find(|(name, _)| *name == ASKPASS_PASSWORD_ENV)
+                .and_then(|(_, value)| Some(value?.to_string_lossy().to_string()));
+            (args, password)
+        };
+
+        let mut host = HostEntry::new("web1");
+        let (args, password) = args_and_env(&host);
+        assert!(args.contains(&"BatchMode=yes".to_string()));
+        assert_eq!(password, None);
+
+        host.vars
+            .insert("ansible_ssh_pass".to_string(), serde_json::json!("hunter2"));
+        let (args, password) = args_and_env(&host);
+        assert!(args.contains(&"BatchMode=no".to_string()));
+        assert!(args.contains(&"NumberOfPasswordPrompts=1".to_string()));
+        assert_eq!(password.as_deref(), Some("hunter2"));
+        assert!(!format!("{:?}", SshTarget::for_host(&host).unwrap()).contains("hunter2"));
+
+        let config = FactsConfig {
+            credentials: Credentials {
+                ssh_passphrase: Some(Secret::new("key-passphrase")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let target = SshTarget::for_host(&HostEntry::new("web2")).unwrap();
+        let (command, _askpass) = openssh_command(
+            &target,
+            &[],
+            "true",
+            false,
+            false,
+            Path::new("/dev/null"),
+            &config,
+        )
+        .unwrap();
+        let args: Vec<String> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        assert!(args.contains(&"BatchMode=no".to_string()));
+        assert!(args.contains(&"PasswordAuthentication=no".to_string()));
+        assert!(args.contains(&"KbdInteractiveAuthentication=no".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_askpass_helper_answers_by_prompt() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let helper = AskpassHelper::create().unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&helper.dir), 0o700);
+        assert_eq!(mode(helper.path()), 0o700);
+
+        let answer = |prompt: &str, password: Option<&str>| {
+            let mut command = std::process::Command::new(helper.path());
+            command
+                .arg(prompt)
+                .env(ASKPASS_SECRET_ENV, "key-passphrase")
+                .env_remove(ASKPASS_PASSWORD_ENV);
+            if let Some(password) = password {
+                command.env(ASKPASS_PASSWORD_ENV, password);
+            }
+            let output = command.output().unwrap();
+            (
+                output.status.success(),
+                String::from_utf8(output.stdout).unwrap(),
+            )
+        };
+        assert_eq!(
+            answer(
+                "Enter passphrase for key '/root/.ssh/id_ed25519': ",
+                Some("login-password")
+            ),
+            (true, "key-passphrase\n".to_string())
+        );
+        assert_eq!(
+            answer("deploy@web1's password: ", Some("login-password")),
+            (true, "login-password\n".to_string())
+        );
+        // The passphrase is never offered to a server asking for a password
+        assert_eq!(
+            answer("(deploy@web1) Password: ", None),
+            (false, String::new())
+        );
+
+        let dir = helper.dir.clone();
+        drop(helper);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_host_ssh_options_adapt_to_client_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FactsConfig {
+            host_key_policy: HostKeyPolicy::Tofu,
+            cache_file: dir.path().join("arch-facts.json"),
+            ..Default::default()
+        };
+        let mut host = HostEntry::new("bastion");
+        host.ssh_pipelining = Some(true);
+
+        let current = SshFeatures::for_version(OpenSshVersion::parse("OpenSSH_9.6p1"));
+        let options = host_ssh_options(&host, &config, current).unwrap();
+        assert_eq!(options[0], "StrictHostKeyChecking=accept-new");
+
+        let rhel7 = SshFeatures::for_version(OpenSshVersion::parse("OpenSSH_7.4p1"));
+        let options = host_ssh_options(&host, &config, rhel7).unwrap();
+        assert_eq!(options[0], "StrictHostKeyChecking=no");
+        assert!(options.iter().any(|o| o.ends_with("cm-%C")));
+
+        let rhel6 = SshFeatures::for_version(OpenSshVersion::parse("OpenSSH_5.3p1"));
+        let options = host_ssh_options(&host, &config, rhel6).unwrap();
+        assert!(!options.iter().any(|o| o.starts_with("Control")));
+    }
+
     #[test]
     fn test_architecture_normalization() {
         assert_eq!(
@@ -0,0 +1,128 @@
+//! A small Jinja subset for connection vars such as
+//! `ansible_host: "{{ inventory_hostname }}.internal.example.com"`.
+//!
+//! Supports `{{ name }}` lookups with the `default('x')`, `lower`, `upper`
+//! and `trim` filters. Looked-up values are rendered in turn, so templates
+//! may refer to other templated vars.
+
+use std::collections::HashMap;
+
+const MAX_DEPTH: usize = 8;
+
+/// Whether a value contains template syntax.
+pub fn is_template(value: &str) -> bool {
+    value.contains("{{")
+}
+
+/// Render a template against `vars`. Fails on undefined vars without a
+/// `default`, unknown filters and unterminated expressions.
+pub fn render(template: &str, vars: &HashMap<String, serde_json::Value>) -> Result<String, String> {
+    render_at(template, vars, 0)
+}
+
+fn render_at(
+    template: &str,
+    vars: &HashMap<String, serde_json::Value>,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("template nested too deeply: '{template}'"));
+    }
+
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unterminated expression in '{template}'"))?;
+        out.push_str(&evaluate(after[..end].trim(), vars, depth)?);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn evaluate(
+    expr: &str,
+    vars: &HashMap<String, serde_json::Value>,
+    depth: usize,
+) -> Result<String, String> {
+    let mut parts = expr.split('|').map(str::trim);
+    let name = parts.next().unwrap_or_default();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("unsupported expression '{expr}'"));
+    }
+
+    let mut value = match vars.get(name) {
+        Some(serde_json::Value::String(s)) if is_template(s) => {
+            Some(render_at(s, vars, depth + 1)?)
+        }
+        Some(serde_json::Value::String(s)) => Some(s.clone()),
+        Some(serde_json::Value::Null) | None => None,
+        Some(other) => Some(other.to_string()),
+    };
+
+    for filter in parts {
+        value = match filter {
+            "lower" => value.map(|v| v.to_lowercase()),
+            "upper" => value.map(|v| v.to_uppercase()),
+            "trim" => value.map(|v| v.trim().to_string()),
+            _ => match filter
+                .strip_prefix("default(")
+                .and_then(|arg| arg.strip_suffix(')'))
+            {
+                Some(arg) => {
+                    value.or_else(|| Some(arg.trim().trim_matches(['"', '\'']).to_string()))
+                }
+                None => return Err(format!("unsupported filter '{filter}'")),
+            },
+        };
+    }
+
+    value.ok_or_else(|| format!("'{name}' is undefined"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, serde_json::Value)]) -> HashMap<String, serde_json::Value> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let vars = vars(&[
+            ("inventory_hostname", serde_json::json!("Web1")),
+            ("domain", serde_json::json!("{{ region }}.example.com")),
+            ("region", serde_json::json!("eu")),
+            ("port", serde_json::json!(2222)),
+        ]);
+
+        assert_eq!(
+            render("{{ inventory_hostname | lower }}.{{ domain }}", &vars).unwrap(),
+            "web1.eu.example.com"
+        );
+        assert_eq!(render("{{port}}", &vars).unwrap(), "2222");
+        assert_eq!(render("{{ zone | default('a') }}", &vars).unwrap(), "a");
+        assert_eq!(render("plain", &vars).unwrap(), "plain");
+
+        assert!(render("{{ missing }}", &vars).is_err());
+        assert!(render("{{ region | shuffle }}", &vars).is_err());
+        assert!(render("{{ region", &vars).is_err());
+    }
+
+    #[test]
+    fn test_render_rejects_cycles() {
+        let vars = vars(&[
+            ("a", serde_json::json!("{{ b }}")),
+            ("b", serde_json::json!("{{ a }}")),
+        ]);
+        assert!(render("{{ a }}", &vars).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Docker container pool shared by the integration tests and benchmarks.
+//!
+//! Containers are named `rustle-test-<label>`, so a pool left running by one
+//! test binary (or the benchmark harness) is reused by the next instead of
+//! being pulled and started again. Shared containers exit on their own after
+//! [`CONTAINER_LIFETIME_SECS`]; pools created with [`ContainerPool::start`]
+//! are removed when dropped.
+
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long a shared pool container stays up before exiting by itself.
+pub const CONTAINER_LIFETIME_SECS: u64 = 900;
+
+/// An OS image in the pool and the facts it is expected to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestImage {
+    pub label: &'static str,
+    pub image: &'static str,
+    pub distribution: &'static str,
+    pub os_family: &'static str,
+}
+
+pub const DEFAULT_IMAGES: &[TestImage] = &[
+    TestImage {
+        label: "ubuntu",
+        image: "ubuntu:24.04",
+        distribution: "ubuntu",
+        os_family: "debian",
+    },
+    TestImage {
+        label: "alpine",
+        image: "alpine:3.20",
+        distribution: "alpine",
+        os_family: "alpine",
+    },
+    TestImage {
+        label: "rocky",
+        image: "rockylinux:9",
+        distribution: "rocky",
+        os_family: "redhat",
+    },
+    TestImage {
+        label: "arch",
+        image: "archlinux:latest",
+        distribution: "arch",
+        os_family: "archlinux",
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct PooledContainer {
+    pub name: String,
+    pub image: TestImage,
+}
+
+#[derive(Debug)]
+pub struct ContainerPool {
+    containers: Vec<PooledContainer>,
+    owned: bool,
+}
+
+impl ContainerPool {
+    /// Start a private pool whose containers are removed on drop.
+    pub fn start(images: &[TestImage]) -> Result<Self> {
+        let suffix = format!("-{}", std::process::id());
+        let containers = launch(images, &suffix, false)?;
+        Ok(Self {
+            containers,
+            owned: true,
+        })
+    }
+
+    /// The process-wide pool over [`DEFAULT_IMAGES`], reusing containers that
+    /// are already running. `None` when Docker is unavailable or an image
+    /// could not be started.
+    pub fn shared() -> Option<&'static Self> {
+        static POOL: OnceLock<Option<ContainerPool>> = OnceLock::new();
+        POOL.get_or_init(|| {
+            if !docker_available() {
+                return None;
+            }
+            match launch(DEFAULT_IMAGES, "", true) {
+                Ok(containers) => Some(Self {
+                    containers,
+                    owned: false,
+                }),
+                Err(e) => {
+                    warn!("Failed to start shared container pool: {:#}", e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+    }
+
+    pub fn containers(&self) -> &[PooledContainer] {
+        &self.containers
+    }
+
+    pub fn get(&self, label: &str) -> Option<&PooledContainer> {
+        self.containers.iter().find(|c| c.image.label == label)
+    }
+
+    /// A minimal playbook with one `docker` host per container, named by label.
+    pub fn playbook_json(&self) -> String {
+        let hosts: serde_json::Map<String, serde_json::Value> = self
+            .containers
+            .iter()
+            .map(|c| {
+                (
+                    c.image.label.to_string(),
+                    serde_json::json!({
+                        "ansible_connection": "docker",
+                        "ansible_host": c.name,
+                    }),
+                )
+            })
+            .collect();
+        let labels: Vec<&str> = self.containers.iter().map(|c| c.image.label).collect();
+
+        serde_json::json!({
+            "metadata": {
+                "file_path": "testsupport",
+                "version": null,
+                "created_at": null,
+                "checksum": null
+            },
+            "plays": [],
+            "variables": {},
+            "facts_required": true,
+            "vault_ids": [],
+            "inventory": {
+                "hosts": hosts,
+                "groups": { "docker": labels },
+                "variables": {}
+            }
+        })
+        .to_string()
+    }
+}
+
+impl Drop for ContainerPool {
+    fn drop(&mut self) {
+        if self.owned {
+            for container in &self.containers {
+                remove_container(&container.name);
+            }
+        }
+    }
+}
+
+/// Whether a Docker daemon is reachable. `docker info` needs a running
+/// daemon, unlike `docker --version`.
+pub fn docker_available() -> bool {
+    Command::new("docker")
+        .arg("info")
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+fn launch(images: &[TestImage], suffix: &str, reuse: bool) -> Result<Vec<PooledContainer>> {
+    let mut containers: Vec<PooledContainer> = Vec::new();
+    for image in images {
+        let name = format!("rustle-test-{}{}", image.label, suffix);
+        if reuse && is_running(&name) {
+            debug!("Reusing running container {}", name);
+        } else {
+            remove_container(&name);
+            if let Err(e) = start_container(&name, image.image) {
+                if !reuse {
+                    containers.iter().for_each(|c| remove_container(&c.name));
+                }
+                return Err(e);
+            }
+        }
+        containers.push(PooledContainer {
+            name,
+            image: *image,
+        });
+    }
+
+    // Give freshly started containers time to settle
+    thread::sleep(Duration::from_secs(1));
+    Ok(containers)
+}
+
+fn start_container(name: &str, image: &str) -> Result<()> {
+    let present = docker(&["image", "inspect", image]).is_ok();
+    if !present {
+        docker(&["pull", image]).with_context(|| format!("Failed to pull {image}"))?;
+    }
+
+    let lifetime = CONTAINER_LIFETIME_SECS.to_string();
+    docker(&[
+        "run", "-d", "--rm", "--name", name, image, "sleep", &lifetime,
+    ])
+    .with_context(|| format!("Failed to start container {name}"))?;
+    Ok(())
+}
+
+fn is_running(name: &str) -> bool {
+    docker(&["inspect", "-f", "{{.State.Running}}", name])
+        .map(|out| out.trim() == "true")
+        .unwrap_or(false)
+}
+
+fn remove_container(name: &str) {
+    let _ = docker(&["rm", "-f", name]);
+}
+
+fn docker(args: &[&str]) -> Result<String> {
+    let output = Command::new("docker").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "docker {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -1,5 +1,13 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::analysis::{GroupFacts, HostsByFact, MatrixEntry, MixedGroup};
+use crate::breaker::BackendHealth;
+use crate::clock::{Clock, SystemClock};
+use crate::verify::FactDrift;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ArchitectureFacts {
@@ -7,15 +15,33 @@ pub struct ArchitectureFacts {
     pub ansible_system: String,
     pub ansible_os_family: String,
     pub ansible_distribution: Option<String>,
+    /// C library the host's binaries link against (`gnu`, `musl`), when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rustle_libc: Option<String>,
+    /// Facts outside the fields above, kept as-is so consumers built
+    /// against this struct still read output that carries newer facts
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl ArchitectureFacts {
+    /// Every fact a host can carry, as serialized
+    pub const FIELDS: &'static [&'static str] = &[
+        "ansible_architecture",
+        "ansible_system",
+        "ansible_os_family",
+        "ansible_distribution",
+        "rustle_libc",
+    ];
+
     pub fn fallback() -> Self {
         Self {
             ansible_architecture: "x86_64".to_string(),
             ansible_system: "Linux".to_string(),
             ansible_os_family: "debian".to_string(),
             ansible_distribution: None,
+            rustle_libc: None,
+            extra: serde_json::Map::new(),
         }
     }
 
@@ -27,36 +53,75 @@ impl ArchitectureFacts {
             arch => arch.to_string(),
         };
 
-        let (system, os_family, distribution) = match std::env::consts::OS {
+        let (system, os_family, distribution, libc) = match std::env::consts::OS {
             "macos" => (
                 "Darwin".to_string(),
                 "darwin".to_string(),
                 Some("macOS".to_string()),
+                None,
             ),
-            "linux" => ("Linux".to_string(), "debian".to_string(), None), // Default to debian family
-            "windows" => ("Windows".to_string(), "windows".to_string(), None),
-            os => (os.to_string(), "unknown".to_string(), None),
+            "linux" => {
+                let release = crate::localhost::LinuxRelease::detect();
+                (
+                    "Linux".to_string(),
+                    release.os_family,
+                    release.distribution,
+                    release.libc,
+                )
+            }
+            "windows" => ("Windows".to_string(), "windows".to_string(), None, None),
+            os => (os.to_string(), "unknown".to_string(), None, None),
+        };
+
+        let service_mgr = match std::env::consts::OS {
+            "macos" => Some("launchd"),
+            "linux" => crate::localhost::linux_service_manager(),
+            _ => None,
         };
+        let mut extra = serde_json::Map::new();
+        if let Some(service_mgr) = service_mgr {
+            extra.insert("ansible_service_mgr".to_string(), service_mgr.into());
+        }
+        if std::env::consts::OS == "linux" {
+            extra.extend(crate::localhost::linux_identity());
+            extra.extend(crate::localhost::linux_isolation());
+        }
 
         Self {
             ansible_architecture: architecture,
             ansible_system: system,
             ansible_os_family: os_family,
             ansible_distribution: distribution,
+            rustle_libc: libc,
+            extra,
         }
     }
 
+    /// The canonical name of a built-in architecture spelling; see
+    /// [`crate::arch_table`] for the `--arch-map` additions.
     pub fn normalize_architecture(arch: &str) -> String {
-        match arch.to_lowercase().as_str() {
-            "x86_64" | "amd64" => "x86_64".to_string(),
-            "aarch64" | "arm64" => "aarch64".to_string(),
-            "armv7l" | "armhf" => "armv7".to_string(),
-            _ => arch.to_string(),
-        }
+        crate::arch_table::builtin_architecture(arch)
+            .map(str::to_string)
+            .unwrap_or_else(|| arch.to_string())
+    }
+
+    /// Map an os-release `ID` and its `ID_LIKE` list (`"rhel fedora"`) to
+    /// an OS family. The ID is tried first, then each `ID_LIKE` entry in
+    /// order; `None` when none of them is a known distribution.
+    pub fn normalize_os_family(id: Option<&str>, id_like: Option<&str>) -> Option<&'static str> {
+        id.into_iter()
+            .chain(id_like.into_iter().flat_map(|like| like.split([' ', ','])))
+            .map(|candidate| candidate.trim().trim_matches(['"', '\'']).to_lowercase())
+            .find_map(|candidate| {
+                OS_FAMILIES
+                    .iter()
+                    .find(|(_, ids)| ids.contains(&candidate.as_str()))
+                    .map(|(family, _)| *family)
+            })
     }
 
     pub fn is_localhost(hostname: &str) -> bool {
-        matches!(hostname, "localhost" | "127.0.0.1" | "::1")
+        crate::localhost::is_loopback(hostname)
     }
 
     pub fn should_use_local_detection(
@@ -64,13 +129,141 @@ impl ArchitectureFacts {
         host_vars: &std::collections::HashMap<String, serde_json::Value>,
     ) -> bool {
         // Use local detection if it's localhost or if ansible_connection is local
-        Self::is_localhost(hostname)
+        Self::is_implicitly_local(hostname, host_vars)
             || host_vars
                 .get("ansible_connection")
                 .and_then(|v| v.as_str())
                 .map(|s| s == "local")
                 .unwrap_or(false)
     }
+
+    /// Whether the host's name or `ansible_host` is this machine, whatever
+    /// its connection says.
+    pub fn is_implicitly_local(
+        hostname: &str,
+        host_vars: &std::collections::HashMap<String, serde_json::Value>,
+    ) -> bool {
+        crate::localhost::is_local_target(hostname)
+            || host_vars
+                .get("ansible_host")
+                .and_then(|v| v.as_str())
+                .is_some_and(crate::localhost::is_local_target)
+    }
+}
+
+/// OS families by the distribution IDs (and `lsb_release -si` names) that
+/// belong to them. Derivatives not listed are usually caught by `ID_LIKE`.
+const OS_FAMILIES: &[(&str, &[&str])] = &[
+    (
+        "debian",
+        &[
+            "debian",
+            "ubuntu",
+            "linuxmint",
+            "mint",
+            "pop",
+            "raspbian",
+            "kali",
+            "devuan",
+            "elementary",
+            "neon",
+            "zorin",
+        ],
+    ),
+    (
+        "redhat",
+        &[
+            "rhel",
+            "redhat",
+            "redhatenterpriseserver",
+            "redhatenterprise",
+            "centos",
+            "fedora",
+            "rocky",
+            "almalinux",
+            "oracle",
+            "oraclelinux",
+            "ol",
+            "amzn",
+            "amazon",
+            "scientific",
+            "cloudlinux",
+            "eurolinux",
+            "virtuozzo",
+        ],
+    ),
+    (
+        "suse",
+        &[
+            "suse",
+            "opensuse",
+            "opensuse-leap",
+            "opensuse-tumbleweed",
+            "sles",
+            "sled",
+            "sles_sap",
+            "sle-micro",
+        ],
+    ),
+    (
+        "archlinux",
+        &[
+            "arch",
+            "archarm",
+            "manjaro",
+            "manjaro-arm",
+            "endeavouros",
+            "garuda",
+            "artix",
+        ],
+    ),
+    ("alpine", &["alpine", "postmarketos"]),
+    ("openwrt", &["openwrt", "lede"]),
+    ("void", &["void"]),
+    ("nixos", &["nixos"]),
+    ("gentoo", &["gentoo", "funtoo", "calculate"]),
+    (
+        "solaris",
+        &["solaris", "omnios", "openindiana", "smartos", "illumos"],
+    ),
+];
+
+/// Where a host's fact value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FactSource {
+    /// Inventory vars or `--facts-override`
+    Declared,
+    /// Gathered from the host in this run
+    Gathered,
+    /// Cached by an earlier run
+    Cached,
+    /// Detected on the local system
+    Local,
+    /// Gathering failed and an expired cache entry was used, with
+    /// `--allow-stale-on-error`
+    Stale,
+    /// Nothing was known; the default facts were used
+    Fallback,
+}
+
+/// Why facts could not be gathered from a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnreachableReason {
+    /// The host's name does not resolve
+    DnsFailure,
+    /// The host answered but refused the connection
+    ConnectionRefused,
+    /// No answer within the timeout
+    Timeout,
+    /// Any other connection error
+    ConnectionFailed,
+    /// The host answered but refused the credentials, so the fix is its
+    /// user, keys or password rather than the network
+    AuthenticationFailed,
+    /// The host offered a different key than the one recorded for it
+    HostKeyMismatch,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -89,6 +282,7 @@ pub struct Task {
     pub name: Option<String>,
     pub module: String,
     pub args: serde_json::Value,
+    #[serde(serialize_with = "sorted_map")]
     pub vars: HashMap<String, serde_json::Value>,
     pub when: Option<String>,
     pub loop_items: Option<Vec<serde_json::Value>>,
@@ -105,6 +299,7 @@ pub struct Task {
 pub struct ParsedPlay {
     pub name: Option<String>,
     pub hosts: String,
+    #[serde(serialize_with = "sorted_optional_map")]
     pub vars: Option<HashMap<String, serde_json::Value>>,
     pub tasks: Vec<Task>,
     pub handlers: Vec<serde_json::Value>,
@@ -120,6 +315,7 @@ pub struct HostEntry {
     pub address: Option<String>,
     pub port: Option<u16>,
     pub user: Option<String>,
+    #[serde(serialize_with = "sorted_map")]
     pub vars: HashMap<String, serde_json::Value>,
     pub groups: Vec<String>,
     pub connection: Option<String>,
@@ -134,26 +330,226 @@ pub struct HostEntry {
     pub become_flags: Option<String>,
 }
 
+impl HostEntry {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            address: None,
+            port: None,
+            user: None,
+            vars: HashMap::new(),
+            groups: vec![],
+            connection: None,
+            ssh_private_key_file: None,
+            ssh_common_args: None,
+            ssh_extra_args: None,
+            ssh_pipelining: None,
+            connection_timeout: None,
+            ansible_become: None,
+            become_method: None,
+            become_user: None,
+            become_flags: None,
+        }
+    }
+
+    /// This entry reduced to what decides how the host is reached: only
+    /// `ansible_*` and `rustle_*` connection vars and the container vars are
+    /// kept, and none that hold a password, token or secret.
+    pub fn connection_snapshot(&self) -> HostEntry {
+        const CONTAINER_VARS: &[&str] = &["docker_context", "proxmox_vmid"];
+        let is_secret = |name: &str| {
+            ["pass", "token", "secret"]
+                .iter()
+                .any(|word| name.contains(word))
+        };
+        let vars = self
+            .vars
+            .iter()
+            .filter(|(name, _)| {
+                name.starts_with("ansible_")
+                    || name.starts_with("rustle_")
+                    || CONTAINER_VARS.contains(&name.as_str())
+            })
+            .filter(|(name, _)| !is_secret(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        HostEntry {
+            vars,
+            ..self.clone()
+        }
+    }
+}
+
+/// How a host is reached, parsed from `ansible_connection`. Fully
+/// qualified plugin names such as `community.docker.docker` are accepted,
+/// and values no backend handles are kept as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ConnectionType {
+    Local,
+    /// OpenSSH, including `paramiko` and `network_cli`
+    Ssh,
+    Docker,
+    Podman,
+    /// containerd containers, through `nerdctl exec`
+    Nerdctl,
+    Kubectl,
+    Serial,
+    /// LXD containers and VMs, through `lxc exec`; also `lxc`
+    Lxd,
+    /// FreeBSD jails, through `jexec`
+    Jail,
+    /// Proxmox LXC containers, through `pct exec` on their node
+    Pct,
+    /// libvirt VMs, through the QEMU guest agent
+    LibvirtQemu,
+    Mock,
+    Other(String),
+}
+
+impl ConnectionType {
+    /// Every connection a backend handles, which is every variant but
+    /// `Other`.
+    pub const HANDLED: [ConnectionType; 12] = [
+        ConnectionType::Local,
+        ConnectionType::Ssh,
+        ConnectionType::Docker,
+        ConnectionType::Podman,
+        ConnectionType::Nerdctl,
+        ConnectionType::Kubectl,
+        ConnectionType::Serial,
+        ConnectionType::Lxd,
+        ConnectionType::Jail,
+        ConnectionType::Pct,
+        ConnectionType::LibvirtQemu,
+        ConnectionType::Mock,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConnectionType::Local => "local",
+            ConnectionType::Ssh => "ssh",
+            ConnectionType::Docker => "docker",
+            ConnectionType::Podman => "podman",
+            ConnectionType::Nerdctl => "nerdctl",
+            ConnectionType::Kubectl => "kubectl",
+            ConnectionType::Serial => "serial",
+            ConnectionType::Lxd => "lxd",
+            ConnectionType::Jail => "jail",
+            ConnectionType::Pct => "pct",
+            ConnectionType::LibvirtQemu => "libvirt_qemu",
+            ConnectionType::Mock => "mock",
+            ConnectionType::Other(other) => other,
+        }
+    }
+}
+
+impl FromStr for ConnectionType {
+    type Err = Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim().to_lowercase();
+        let plugin = value.rsplit('.').next().unwrap_or(&value);
+        Ok(match plugin {
+            "local" => ConnectionType::Local,
+            "ssh" | "smart" | "paramiko" | "paramiko_ssh" | "network_cli" => ConnectionType::Ssh,
+            "docker" | "docker_api" => ConnectionType::Docker,
+            "podman" => ConnectionType::Podman,
+            "nerdctl" => ConnectionType::Nerdctl,
+            "kubectl" => ConnectionType::Kubectl,
+            "serial" => ConnectionType::Serial,
+            "lxd" | "lxc" => ConnectionType::Lxd,
+            "jail" => ConnectionType::Jail,
+            "pct" | "proxmox_pct_remote" => ConnectionType::Pct,
+            "libvirt_qemu" => ConnectionType::LibvirtQemu,
+            "mock" => ConnectionType::Mock,
+            _ => ConnectionType::Other(value),
+        })
+    }
+}
+
+impl From<String> for ConnectionType {
+    fn from(value: String) -> Self {
+        let Ok(connection) = value.parse();
+        connection
+    }
+}
+
+impl From<ConnectionType> for String {
+    fn from(connection: ConnectionType) -> Self {
+        connection.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ConnectionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A problem in the inventory that does not stop enrichment but probably
+/// makes part of it wrong.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum InventoryDiagnostic {
+    /// A group lists a host the hosts map does not define
+    UndefinedGroupHost { group: String, host: String },
+    /// A group's children include a group that does not exist
+    UnknownChildGroup { group: String, child: String },
+    /// Spellings of one host name (case, trailing dot, punycode), which
+    /// are merged into one host
+    CaseDuplicateHosts { hosts: Vec<String> },
+    /// A host's vars are neither an object nor empty, so they are ignored
+    NonObjectVars { host: String },
+}
+
+impl fmt::Display for InventoryDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedGroupHost { group, host } => {
+                write!(
+                    f,
+                    "group '{group}' lists host '{host}', which is not in the hosts map"
+                )
+            }
+            Self::UnknownChildGroup { group, child } => {
+                write!(f, "group '{group}' has unknown child group '{child}'")
+            }
+            Self::CaseDuplicateHosts { hosts } => {
+                write!(
+                    f,
+                    "hosts {} are the same name and are merged",
+                    hosts.join(", ")
+                )
+            }
+            Self::NonObjectVars { host } => {
+                write!(f, "vars of host '{host}' are not an object and are ignored")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupEntry {
     pub name: String,
     pub hosts: Vec<String>,
     pub children: Vec<String>,
+    #[serde(serialize_with = "sorted_map")]
     pub vars: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum InventoryHosts {
-    Simple(HashMap<String, serde_json::Value>),
-    Detailed(HashMap<String, HostEntry>),
+    Simple(#[serde(serialize_with = "sorted_map")] HashMap<String, serde_json::Value>),
+    Detailed(#[serde(serialize_with = "sorted_map")] HashMap<String, HostEntry>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum InventoryGroups {
-    Simple(HashMap<String, Vec<String>>),
-    Detailed(HashMap<String, GroupEntry>),
+    Simple(#[serde(serialize_with = "sorted_map")] HashMap<String, Vec<String>>),
+    Detailed(#[serde(serialize_with = "sorted_map")] HashMap<String, GroupEntry>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -161,6 +557,7 @@ pub struct ParsedInventory {
     pub hosts: InventoryHosts,
     pub groups: InventoryGroups,
     #[serde(default)]
+    #[serde(serialize_with = "sorted_map")]
     pub variables: HashMap<String, serde_json::Value>,
 }
 
@@ -168,6 +565,7 @@ pub struct ParsedInventory {
 pub struct ParsedPlaybook {
     pub metadata: PlaybookMetadata,
     pub plays: Vec<ParsedPlay>,
+    #[serde(serialize_with = "sorted_map")]
     pub variables: HashMap<String, serde_json::Value>,
     pub facts_required: bool,
     pub vault_ids: Vec<String>,
@@ -176,34 +574,122 @@ pub struct ParsedPlaybook {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnrichedInventory {
+    /// Version of the `host_facts` layout; see [`FACTS_SCHEMA_VERSION`]
+    #[serde(default = "unversioned_facts_schema")]
+    pub facts_schema_version: u32,
     #[serde(flatten)]
     pub base: ParsedInventory,
+    #[serde(serialize_with = "sorted_map")]
     pub host_facts: HashMap<String, ArchitectureFacts>,
+    /// Source of each host's fact values, keyed by host then fact
+    #[serde(default)]
+    #[serde(serialize_with = "sorted_map")]
+    pub fact_sources: HashMap<String, BTreeMap<String, FactSource>>,
+    /// Hosts removed by `--exclude` or `--only-hosts-from`; they have no
+    /// facts, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_hosts: Vec<String>,
+    /// Hosts given expired cached facts because gathering failed, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stale_hosts: Vec<String>,
+    /// Labels of the run that gathered each host's facts, or of this run
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub host_labels: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Version of the host facts written in `EnrichedInventory`. It is bumped
+/// when an existing fact changes meaning or is removed; new facts do not
+/// bump it, since they land in [`ArchitectureFacts::extra`] for older
+/// readers.
+pub const FACTS_SCHEMA_VERSION: u32 = 1;
+
+/// Output written before the version was recorded has the first layout.
+fn unversioned_facts_schema() -> u32 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EnrichedPlaybook {
     pub metadata: PlaybookMetadata,
     pub plays: Vec<ParsedPlay>,
+    #[serde(serialize_with = "sorted_map")]
     pub variables: HashMap<String, serde_json::Value>,
     pub facts_required: bool,
     pub vault_ids: Vec<String>,
     pub inventory: EnrichedInventory,
+    /// Facts of the hosts each play targets, keyed by play index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub play_host_facts: Option<BTreeMap<usize, BTreeMap<String, ArchitectureFacts>>>,
+    /// Hosts per value of the `--group-by-fact` fact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hosts_by_fact: Option<HostsByFact>,
+    /// Architecture and OS family counts of each inventory group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_facts: Option<BTreeMap<String, GroupFacts>>,
+    /// Set when the run was interrupted or hit its deadline, so some hosts
+    /// may have fallback facts
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub partial: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Serialize a map with its keys sorted, so the same inputs always produce
+/// byte-identical output whatever the hash seed.
+fn sorted_map<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Serialize + Ord,
+    V: Serialize,
+    S: Serializer,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+fn sorted_optional_map<K, V, S>(
+    map: &Option<HashMap<K, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: Serialize + Ord,
+    V: Serialize,
+    S: Serializer,
+{
+    map.as_ref()
+        .map(|map| map.iter().collect::<BTreeMap<_, _>>())
+        .serialize(serializer)
+}
+
+/// Major version of the fact cache file, written as `<major>.0`.
+pub const CACHE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FactCache {
     pub version: String,
+    #[serde(serialize_with = "sorted_map")]
     pub facts: HashMap<String, CachedFact>,
+    #[serde(skip, default = "default_clock")]
+    pub clock: Arc<dyn Clock>,
 }
 
 impl FactCache {
     pub fn new() -> Self {
         Self {
-            version: "1.0".to_string(),
+            version: format!("{CACHE_VERSION}.0"),
             facts: HashMap::new(),
+            clock: default_clock(),
         }
     }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
 }
 
 impl Default for FactCache {
@@ -212,17 +698,76 @@ impl Default for FactCache {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A host's SHA-256 key fingerprints by key type (`ED25519`, `ECDSA`,
+/// `RSA`).
+pub type HostKeys = BTreeMap<String, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedFact {
     pub facts: ArchitectureFacts,
     pub timestamp: i64,
+    #[serde(default)]
     pub ssh_fingerprint: String,
+    /// The type of the key `ssh_fingerprint` is of; unset in entries
+    /// written by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key_type: Option<String>,
+    /// `--label`s of the run that gathered these facts
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    /// The host's connection parameters when it was gathered, with
+    /// `--cache-inventory`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<HostEntry>,
+    /// Where an SSH host was reached once ssh_config was applied, as its
+    /// known_hosts entry names it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct EnrichmentReport {
     pub total_hosts: usize,
     pub facts_gathered: usize,
+    /// Hosts whose facts were declared in inventory vars instead of gathered
+    pub facts_declared: usize,
     pub cache_hits: usize,
     pub duration: std::time::Duration,
+    pub host_key_changes: Vec<String>,
+    /// Hosts whose machine ID differs from the cached one, so another
+    /// machine (a clone or a reinstall) now answers to the name, sorted
+    pub machine_id_changes: Vec<String>,
+    /// Inventory aliases that were given another host's facts, alias -> source
+    pub shared_facts: BTreeMap<String, String>,
+    /// Play-targeted groups whose hosts need more than one build target
+    pub mixed_groups: Vec<MixedGroup>,
+    /// Unique build targets and the hosts that need them
+    pub matrix: Vec<MatrixEntry>,
+    /// Hosts per value of the `--group-by-fact` fact, when requested
+    pub hosts_by_fact: Option<HostsByFact>,
+    /// Hosts that were given `ArchitectureFacts::fallback()`, sorted
+    pub fallback_hosts: Vec<String>,
+    /// Hosts not gathered before `--deadline` expired, sorted
+    pub timed_out_hosts: Vec<String>,
+    /// Hosts whose connection no backend supports; they get fallback facts
+    pub unsupported_connections: BTreeMap<String, ConnectionType>,
+    /// Hosts skipped by `--exclude` or `--only-hosts-from`, sorted
+    pub excluded_hosts: Vec<String>,
+    /// Hosts left out by `--limit`, sorted
+    pub limited_hosts: Vec<String>,
+    /// SSH hosts that could not be reached, and why
+    pub unreachable_hosts: BTreeMap<String, UnreachableReason>,
+    /// Non-fatal inventory problems, sorted
+    pub inventory_diagnostics: Vec<InventoryDiagnostic>,
+    /// Hosts given expired cached facts by `--allow-stale-on-error`, sorted
+    pub stale_hosts: Vec<String>,
+    /// Uncached hosts whose host key matched another host's cache entry,
+    /// new name -> cached name
+    pub renamed_hosts: BTreeMap<String, String>,
+    /// Cached hosts re-gathered by `--verify-sample`, sorted
+    pub verified_hosts: Vec<String>,
+    /// Cached facts that `--verify-sample` found to be out of date
+    pub fact_drift: Vec<FactDrift>,
+    /// Outcomes per backend, including any whose circuit breaker opened
+    pub backend_health: BTreeMap<String, BackendHealth>,
 }
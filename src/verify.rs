@@ -0,0 +1,143 @@
+//! `--verify-sample`: re-gather a random share of cache hits and compare.
+//!
+//! Long cache TTLs save connections but hide hosts that were rebuilt on
+//! another architecture or distribution. Re-gathering a few percent of the
+//! cached hosts on every run catches that drift at a fraction of the cost
+//! of `--force-refresh`. Sampled hosts whose re-gather fails keep their
+//! cached facts.
+
+use crate::types::ArchitectureFacts;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+
+/// A cached fact that no longer matches the host.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FactDrift {
+    pub host: String,
+    pub fact: String,
+    pub cached: serde_json::Value,
+    pub gathered: serde_json::Value,
+}
+
+impl fmt::Display for FactDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} was {} in the cache but is now {}",
+            self.host, self.fact, self.cached, self.gathered
+        )
+    }
+}
+
+/// Parse a `--verify-sample` share such as `5%`, `5` or `0.5%`.
+pub fn parse_sample(value: &str) -> Result<f64, String> {
+    let number = value.trim().trim_end_matches('%').trim();
+    let percent: f64 = number
+        .parse()
+        .map_err(|_| format!("{value:?} is not a percentage"))?;
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("{value:?} is not between 0% and 100%"));
+    }
+    Ok(percent)
+}
+
+/// A random `percent` share of `hosts`, at least one host when the share
+/// is not zero. The choice differs from run to run.
+pub fn choose_sample(hosts: &[String], percent: f64) -> Vec<String> {
+    if hosts.is_empty() || percent <= 0.0 {
+        return Vec::new();
+    }
+    let count = ((hosts.len() as f64 * percent / 100.0).ceil() as usize).clamp(1, hosts.len());
+    let seed = RandomState::new();
+    let mut shuffled: Vec<&String> = hosts.iter().collect();
+    shuffled.sort_by_cached_key(|host| seed.hash_one(host));
+    let mut sample: Vec<String> = shuffled.into_iter().take(count).cloned().collect();
+    sample.sort();
+    sample
+}
+
+/// Facts that differ between the cached and re-gathered values of each
+/// host, sorted by host then fact.
+pub fn find_drift(
+    cached: &HashMap<String, ArchitectureFacts>,
+    gathered: &HashMap<String, ArchitectureFacts>,
+) -> Vec<FactDrift> {
+    let mut drift = Vec::new();
+    for (host, cached_facts) in cached {
+        let Some(gathered_facts) = gathered.get(host) else {
+            continue;
+        };
+        let (Ok(cached_values), Ok(gathered_values)) = (
+            serde_json::to_value(cached_facts),
+            serde_json::to_value(gathered_facts),
+        ) else {
+            continue;
+        };
+        for fact in ArchitectureFacts::FIELDS {
+            let before = cached_values.get(*fact).unwrap_or(&serde_json::Value::Null);
+            let after = gathered_values
+                .get(*fact)
+                .unwrap_or(&serde_json::Value::Null);
+            if before != after {
+                drift.push(FactDrift {
+                    host: host.clone(),
+                    fact: fact.to_string(),
+                    cached: before.clone(),
+                    gathered: after.clone(),
+                });
+            }
+        }
+    }
+    drift.sort_by(|a, b| a.host.cmp(&b.host).then_with(|| a.fact.cmp(&b.fact)));
+    drift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sample() {
+        assert_eq!(parse_sample("5%"), Ok(5.0));
+        assert_eq!(parse_sample("0.5"), Ok(0.5));
+        assert!(parse_sample("150%").is_err());
+        assert!(parse_sample("some").is_err());
+    }
+
+    #[test]
+    fn test_choose_sample() {
+        let hosts: Vec<String> = (0..40).map(|n| format!("web{n:02}")).collect();
+        let sample = choose_sample(&hosts, 5.0);
+        assert_eq!(sample.len(), 2);
+        assert!(sample.iter().all(|host| hosts.contains(host)));
+
+        assert_eq!(choose_sample(&hosts[..3], 1.0).len(), 1);
+        assert_eq!(choose_sample(&hosts[..3], 100.0).len(), 3);
+        assert!(choose_sample(&hosts, 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_find_drift() {
+        let cached = HashMap::from([
+            ("web1".to_string(), ArchitectureFacts::fallback()),
+            ("web2".to_string(), ArchitectureFacts::fallback()),
+        ]);
+        let gathered = HashMap::from([(
+            "web1".to_string(),
+            ArchitectureFacts {
+                ansible_architecture: "aarch64".to_string(),
+                ..ArchitectureFacts::fallback()
+            },
+        )]);
+
+        let drift = find_drift(&cached, &gathered);
+        assert_eq!(drift.len(), 1);
+        assert_eq!(
+            drift[0].to_string(),
+            "web1: ansible_architecture was \"x86_64\" in the cache but is now \"aarch64\""
+        );
+    }
+}
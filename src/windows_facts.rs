@@ -0,0 +1,133 @@
+//! Facts for Windows hosts reached over OpenSSH.
+//!
+//! Hosts that declare `ansible_shell_type: powershell` or `cmd` cannot run
+//! the POSIX probe, so they are sent a PowerShell script instead. It is
+//! passed with `-EncodedCommand`, which both shells accept without any
+//! quoting. The architecture comes from `PROCESSOR_ARCHITECTURE`, preferring
+//! `PROCESSOR_ARCHITEW6432` so a 32-bit shell on a 64-bit host reports the
+//! host's architecture.
+
+use crate::error::{FactsError, Result};
+use crate::ssh_facts::parse_fact_pairs;
+use crate::types::{ArchitectureFacts, HostEntry};
+use base64::Engine;
+
+const PROBE_SCRIPT: &str = r#"
+$arch = if ($env:PROCESSOR_ARCHITEW6432) { $env:PROCESSOR_ARCHITEW6432 } else { $env:PROCESSOR_ARCHITECTURE }
+$caption = (Get-CimInstance Win32_OperatingSystem -ErrorAction SilentlyContinue).Caption
+Write-Output '===RUSTLE_FACTS_BEGIN==='
+Write-Output "ARCH=$arch"
+Write-Output "DISTRIBUTION=$caption"
+Write-Output '===RUSTLE_FACTS_END==='
+"#;
+
+/// Whether the host's login shell is PowerShell or cmd.
+pub fn is_windows_shell(host: &HostEntry) -> bool {
+    host.vars
+        .get("ansible_shell_type")
+        .and_then(|v| v.as_str())
+        .map(|shell| shell.trim().to_lowercase())
+        .is_some_and(|shell| matches!(shell.as_str(), "powershell" | "pwsh" | "cmd"))
+}
+
+/// The probe as a `powershell -EncodedCommand` invocation, whose argument
+/// is the script in base64 UTF-16LE.
+pub fn probe_command() -> String {
+    let utf16: Vec<u8> = PROBE_SCRIPT
+        .trim()
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    format!(
+        "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+        base64::engine::general_purpose::STANDARD.encode(utf16)
+    )
+}
+
+/// Build facts from the probe's output.
+pub fn parse_probe_output(output: &str) -> Result<ArchitectureFacts> {
+    let pairs = parse_fact_pairs(output);
+    let arch = pairs.get("ARCH").ok_or_else(|| {
+        FactsError::ParseError(
+            "unknown".to_string(),
+            "Missing PROCESSOR_ARCHITECTURE".to_string(),
+        )
+    })?;
+
+    Ok(ArchitectureFacts {
+        ansible_architecture: normalize_processor_architecture(arch),
+        ansible_system: "Windows".to_string(),
+        ansible_os_family: "windows".to_string(),
+        ansible_distribution: pairs.get("DISTRIBUTION").cloned(),
+        rustle_libc: None,
+        extra: serde_json::Map::new(),
+    })
+}
+
+/// `PROCESSOR_ARCHITECTURE` spells 32-bit x86 as `x86`; the other values
+/// (`AMD64`, `ARM64`) are known aliases.
+fn normalize_processor_architecture(arch: &str) -> String {
+    match arch.trim().to_lowercase().as_str() {
+        "x86" => "i686".to_string(),
+        _ => ArchitectureFacts::normalize_architecture(arch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_windows_shell() {
+        let mut host = HostEntry::new("win1");
+        assert!(!is_windows_shell(&host));
+
+        host.vars.insert(
+            "ansible_shell_type".to_string(),
+            serde_json::json!("PowerShell"),
+        );
+        assert!(is_windows_shell(&host));
+
+        host.vars
+            .insert("ansible_shell_type".to_string(), serde_json::json!("sh"));
+        assert!(!is_windows_shell(&host));
+    }
+
+    #[test]
+    fn test_probe_command_encodes_script() {
+        let command = probe_command();
+        let encoded = command.rsplit(' ').next().unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap();
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(String::from_utf16(&units).unwrap(), PROBE_SCRIPT.trim());
+    }
+
+    #[test]
+    fn test_parse_probe_output() {
+        let output = "#< CLIXML\r\n===RUSTLE_FACTS_BEGIN===\r\nARCH=AMD64\r\n\
+DISTRIBUTION=Microsoft Windows Server 2022 Standard\r\n===RUSTLE_FACTS_END===\r\n";
+        let facts = parse_probe_output(output).unwrap();
+        assert_eq!(facts.ansible_architecture, "x86_64");
+        assert_eq!(facts.ansible_system, "Windows");
+        assert_eq!(facts.ansible_os_family, "windows");
+        assert_eq!(
+            facts.ansible_distribution.as_deref(),
+            Some("Microsoft Windows Server 2022 Standard")
+        );
+
+        let arm = parse_probe_output("ARCH=ARM64\n").unwrap();
+        assert_eq!(arm.ansible_architecture, "aarch64");
+        assert_eq!(
+            parse_probe_output("ARCH=x86\n")
+                .unwrap()
+                .ansible_architecture,
+            "i686"
+        );
+        assert!(parse_probe_output("'uname' is not recognized\r\n").is_err());
+    }
+}
@@ -1,9 +1,27 @@
 use rustle_facts::{
-    enrich_with_facts, gather_minimal_facts, parse_fact_output, ArchitectureFacts, FactsConfig,
+    gather_minimal_facts, parse_fact_output, ArchitectureFacts, EnrichmentReport, FactsConfig,
+    HostEntry,
 };
-use std::io::Cursor;
+use std::io::{Cursor, Read, Write};
+use std::time::Duration;
 use tempfile::tempdir;
 
+/// `enrich_with_facts` without the `~/.ssh/config` of whoever runs the
+/// tests, unless a test names its own file.
+async fn enrich_with_facts<R: Read, W: Write>(
+    input: R,
+    output: W,
+    config: &FactsConfig,
+) -> rustle_facts::Result<EnrichmentReport> {
+    let mut config = config.clone();
+    if config.ssh_config.is_none() {
+        config
+            .parsed_ssh_config
+            .get_or_insert_with(Default::default);
+    }
+    rustle_facts::enrich_with_facts(input, output, &config).await
+}
+
 #[test]
 fn test_parse_various_outputs() {
     let ubuntu_output = r#"
@@ -38,14 +56,14 @@ DISTRIBUTION=rhel
 "#;
 
     let facts = parse_fact_output(rhel_output).unwrap();
-    assert_eq!(facts.ansible_os_family, "rhel");
+    assert_eq!(facts.ansible_os_family, "redhat");
 }
 
 #[tokio::test]
 async fn test_localhost_facts() {
-    let hosts = vec!["localhost".to_string()];
+    let hosts = vec![HostEntry::new("localhost")];
     let config = FactsConfig {
-        timeout: 5,
+        connect_timeout: 5,
         ..Default::default()
     };
 
@@ -125,6 +143,412 @@ async fn test_cache_behavior() {
     }
 }
 
+#[tokio::test]
+async fn test_declared_facts_skip_gathering() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "appliance.invalid": {
+                    "rustle_target_triple": "aarch64-unknown-linux-musl",
+                    "ansible_os_family": "alpine"
+                },
+                "localhost": {"ansible_connection": "local", "ansible_os_family": "custom"}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_declared, 1);
+    assert_eq!(report.facts_gathered, 1);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = &enriched["inventory"]["host_facts"];
+    assert_eq!(
+        host_facts["appliance.invalid"]["ansible_architecture"],
+        "aarch64"
+    );
+    assert_eq!(host_facts["appliance.invalid"]["ansible_system"], "Linux");
+    assert_eq!(
+        host_facts["appliance.invalid"]["ansible_os_family"],
+        "alpine"
+    );
+    assert_eq!(
+        host_facts["appliance.invalid"]["rustle_target_triple"],
+        "aarch64-unknown-linux-musl"
+    );
+    // Partial declarations are overlaid on gathered facts
+    assert_eq!(host_facts["localhost"]["ansible_os_family"], "custom");
+
+    let fact_sources = &enriched["inventory"]["fact_sources"];
+    assert_eq!(
+        fact_sources["appliance.invalid"]["ansible_architecture"],
+        "declared"
+    );
+    assert_eq!(fact_sources["localhost"]["ansible_os_family"], "declared");
+    assert_eq!(
+        fact_sources["localhost"]["ansible_architecture"],
+        "gathered"
+    );
+}
+
+#[tokio::test]
+async fn test_gathered_hosts_do_not_keep_facts_they_no_longer_have() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let cache_file = dir.path().join("facts.json");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let cached = serde_json::json!({
+        "version": "1.0",
+        "facts": {
+            "localhost": {
+                "facts": {
+                    "ansible_architecture": "x86_64",
+                    "ansible_system": "Linux",
+                    "ansible_os_family": "debian",
+                    "ansible_distribution": null,
+                    "ansible_service_mgr": "stale-init",
+                    "region": "eu-west-1"
+                },
+                "timestamp": now
+            }
+        }
+    });
+    std::fs::write(&cache_file, cached.to_string()).unwrap();
+
+    let config = FactsConfig {
+        cache_file,
+        force_refresh: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let facts = &enriched["inventory"]["host_facts"]["localhost"];
+    // The gather covered the service manager, so the cached one is dropped
+    assert_ne!(facts["ansible_service_mgr"], "stale-init");
+    // Cloud facts were not asked for, so the cached ones still apply
+    assert_eq!(facts["region"], "eu-west-1");
+    assert_eq!(
+        enriched["inventory"]["fact_sources"]["localhost"]["region"],
+        "cached"
+    );
+}
+
+#[tokio::test]
+async fn test_prefer_gathered_over_declared() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "localhost": {"ansible_connection": "local", "ansible_architecture": "sparc64"}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        prefer: rustle_facts::config::FactPreference::Gathered,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_declared, 0);
+    assert_eq!(report.facts_gathered, 1);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["localhost"]["ansible_architecture"],
+        ArchitectureFacts::from_local_system().ansible_architecture
+    );
+    assert_eq!(
+        enriched["inventory"]["fact_sources"]["localhost"]["ansible_architecture"],
+        "gathered"
+    );
+}
+
+#[tokio::test]
+async fn test_facts_override_file_wins_over_inventory() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "edge-01.invalid": {"ansible_architecture": "x86_64"},
+                "edge-02.invalid": {}
+            },
+            "groups": {"edge": ["edge-01.invalid", "edge-02.invalid"]},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("overrides.yml");
+    std::fs::write(
+        &path,
+        "edge:\n  rustle_target_triple: armv7-unknown-linux-musleabihf\n\
+edge-02.invalid:\n  ansible_os_family: openwrt\n",
+    )
+    .unwrap();
+
+    let config = FactsConfig {
+        no_cache: true,
+        facts_override: Some(path),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_declared, 2);
+    assert_eq!(report.facts_gathered, 0);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = &enriched["inventory"]["host_facts"];
+    assert_eq!(
+        host_facts["edge-01.invalid"]["ansible_architecture"],
+        "armv7"
+    );
+    assert_eq!(host_facts["edge-01.invalid"]["rustle_libc"], "musl");
+    assert_eq!(
+        host_facts["edge-02.invalid"]["ansible_os_family"],
+        "openwrt"
+    );
+}
+
+#[tokio::test]
+async fn test_arch_map_adds_vendor_spellings() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "nas.invalid": {"ansible_architecture": "armv7-marvell"},
+                "pi-zero.invalid": {"ansible_architecture": "armv7l"},
+                "mainframe.invalid": {"ansible_architecture": "s390x"}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("arch.yml");
+    std::fs::write(&path, "armv7-marvell: armhf\narmv7l: armv6\n").unwrap();
+
+    let config = FactsConfig {
+        no_cache: true,
+        arch_map: Some(path),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = &enriched["inventory"]["host_facts"];
+    assert_eq!(host_facts["nas.invalid"]["ansible_architecture"], "armv7");
+    // Matched as spelled, not after the built-in armv7l -> armv7
+    assert_eq!(
+        host_facts["pi-zero.invalid"]["ansible_architecture"],
+        "armv6"
+    );
+    assert_eq!(
+        host_facts["mainframe.invalid"]["ansible_architecture"],
+        "s390x"
+    );
+}
+
+#[tokio::test]
+async fn test_play_host_facts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [
+            {"name": "deploy web", "hosts": "web", "tasks": [], "handlers": [], "roles": []},
+            {"name": "deploy db", "hosts": "all:!web", "tasks": [], "handlers": [], "roles": []}
+        ],
+        "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "web.invalid": {"ansible_architecture": "arm64"},
+                "db.invalid": {"ansible_architecture": "x86_64"}
+            },
+            "groups": {"web": ["web.invalid"], "db": ["db.invalid"]},
+            "variables": {}
+        }
+    }"#;
+
+    let mut config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert!(enriched.get("play_host_facts").is_none());
+
+    config.play_host_facts = true;
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let plays = &enriched["play_host_facts"];
+    assert_eq!(plays["0"]["web.invalid"]["ansible_architecture"], "aarch64");
+    assert!(plays["0"].get("db.invalid").is_none());
+    assert_eq!(plays["1"].as_object().unwrap().len(), 1);
+    assert_eq!(plays["1"]["db.invalid"]["ansible_architecture"], "x86_64");
+
+    config.select =
+        Some(rustle_facts::select::parse_selection("play_host_facts.*.*.ansible_system").unwrap());
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let selected: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        selected,
+        serde_json::json!({"0": {"web.invalid": "Linux"}, "1": {"db.invalid": "Linux"}})
+    );
+}
+
+#[tokio::test]
+async fn test_mixed_architecture_groups() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [
+            {"name": "deploy web", "hosts": "web", "tasks": [], "handlers": [], "roles": []},
+            {"name": "deploy db", "hosts": "db:!web", "tasks": [], "handlers": [], "roles": []}
+        ],
+        "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "web-arm.invalid": {"ansible_architecture": "arm64"},
+                "web-x86.invalid": {"ansible_architecture": "x86_64"},
+                "db.invalid": {"ansible_architecture": "x86_64"}
+            },
+            "groups": {
+                "web": ["web-arm.invalid", "web-x86.invalid"],
+                "db": ["db.invalid"]
+            },
+            "variables": {}
+        }
+    }"#;
+
+    let mut config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+
+    let report = enrich_with_facts(Cursor::new(input_json), Vec::new(), &config)
+        .await
+        .unwrap();
+    assert_eq!(report.mixed_groups.len(), 1);
+    assert_eq!(report.mixed_groups[0].group, "web");
+    assert_eq!(report.mixed_groups[0].plays, vec!["deploy web"]);
+    assert!(report.mixed_groups[0].mixes_architectures());
+
+    config.matrix = true;
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.matrix.len(), 2);
+    let matrix: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(matrix["matrix"][0]["architecture"], "aarch64");
+    assert_eq!(matrix["matrix"][0]["hosts"][0], "web-arm.invalid");
+    assert_eq!(
+        matrix["matrix"][1]["hosts"],
+        serde_json::json!(["db.invalid", "web-x86.invalid"])
+    );
+
+    config.matrix = false;
+    config.group_by_fact = Some("ansible_architecture".to_string());
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let by_arch = report.hosts_by_fact.unwrap();
+    assert_eq!(by_arch.values["x86_64"], ["db.invalid", "web-x86.invalid"]);
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["hosts_by_fact"]["values"]["aarch64"],
+        serde_json::json!(["web-arm.invalid"])
+    );
+    config.group_by_fact = None;
+
+    config.group_facts = true;
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["group_facts"]["web"]["architectures"],
+        serde_json::json!({"aarch64": 1, "x86_64": 1})
+    );
+    assert_eq!(enriched["group_facts"]["db"]["hosts"], 1);
+    config.group_facts = false;
+
+    config.assertions =
+        vec![
+            rustle_facts::assertions::parse_assertion("ansible_architecture in [x86_64]").unwrap(),
+        ];
+    let result = enrich_with_facts(Cursor::new(input_json), Vec::new(), &config).await;
+    match result {
+        Err(rustle_facts::FactsError::AssertionFailed(violations)) => {
+            assert!(violations.contains("web-arm.invalid"));
+            assert!(!violations.contains("web-x86.invalid"));
+        }
+        other => panic!("expected assertion failure, got {other:?}"),
+    }
+    config.assertions.clear();
+
+    config.fail_on_mixed_arch = true;
+    let result = enrich_with_facts(Cursor::new(input_json), Vec::new(), &config).await;
+    assert!(matches!(
+        result,
+        Err(rustle_facts::FactsError::MixedArchitectures(_))
+    ));
+}
+
 #[test]
 fn test_architecture_normalization() {
     let test_cases = vec![
@@ -137,6 +561,9 @@ fn test_architecture_normalization() {
         ("ARM64", "aarch64"),
         ("armv7l", "armv7"),
         ("armhf", "armv7"),
+        ("ppc64el", "ppc64le"),
+        ("riscv64", "riscv64"),
+        ("mipsle", "mipsel"),
         ("custom-arch", "custom-arch"),
     ];
 
@@ -157,3 +584,952 @@ fn test_fallback_facts() {
     assert_eq!(fallback.ansible_os_family, "debian");
     assert_eq!(fallback.ansible_distribution, None);
 }
+
+#[test]
+fn test_host_vars_override_timeouts() {
+    let config = FactsConfig {
+        connect_timeout: 10,
+        command_timeout: 30,
+        ..Default::default()
+    };
+
+    let mut host = HostEntry::new("slow.example.com");
+    host.vars
+        .insert("ansible_timeout".to_string(), serde_json::json!(45));
+    host.vars.insert(
+        "rustle_command_timeout".to_string(),
+        serde_json::json!("120"),
+    );
+
+    let host_config = config.for_host(&host);
+    assert_eq!(host_config.connect_timeout, 45);
+    assert_eq!(host_config.command_timeout, 120);
+    assert_eq!(host_config.host_budget(), Duration::from_secs(165));
+
+    let defaults = config.for_host(&HostEntry::new("fast.example.com"));
+    assert_eq!(defaults.connect_timeout, 10);
+    assert_eq!(defaults.command_timeout, 30);
+}
+
+#[tokio::test]
+async fn test_interrupted_run_writes_partial_output() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "declared.invalid": {"ansible_architecture": "aarch64"},
+                "unreachable.invalid": {}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let mut config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+    config.shutdown.request();
+
+    let mut output = Vec::new();
+    let result = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(matches!(result, Err(rustle_facts::FactsError::Interrupted)));
+    assert!(output.is_empty());
+
+    config.write_partial = true;
+    let mut output = Vec::new();
+    let result = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(matches!(result, Err(rustle_facts::FactsError::Interrupted)));
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(enriched["partial"], true);
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["declared.invalid"]["ansible_architecture"],
+        "aarch64"
+    );
+}
+
+#[tokio::test]
+async fn test_compressed_input_and_output() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"appliance.invalid": {"ansible_architecture": "aarch64"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+    let compressed_input = zstd::encode_all(input_json.as_bytes(), 0).unwrap();
+
+    let config = FactsConfig {
+        no_cache: true,
+        compress_output: rustle_facts::config::OutputCompression::Gzip,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(compressed_input), &mut output, &config)
+        .await
+        .unwrap();
+
+    let mut decompressed = Vec::new();
+    std::io::Read::read_to_end(
+        &mut flate2::read::GzDecoder::new(output.as_slice()),
+        &mut decompressed,
+    )
+    .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&decompressed).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["appliance.invalid"]["ansible_architecture"],
+        "aarch64"
+    );
+}
+
+#[tokio::test]
+async fn test_unsupported_connection_is_reported() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"win1.invalid": {"ansible_connection": "winrm"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(
+        report.unsupported_connections.get("win1.invalid"),
+        Some(&rustle_facts::ConnectionType::Other("winrm".to_string()))
+    );
+    assert_eq!(report.fallback_hosts, vec!["win1.invalid".to_string()]);
+    assert_eq!(report.facts_gathered, 0);
+}
+
+#[tokio::test]
+async fn test_excluded_hosts_are_skipped() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "localhost": {"ansible_connection": "local"},
+                "decom1.invalid": {},
+                "old-db.invalid": {}
+            },
+            "groups": {"retired": ["old-db.invalid"]},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        excluded_hosts: vec!["decom*".to_string(), "retired".to_string()],
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.excluded_hosts, ["decom1.invalid", "old-db.invalid"]);
+    assert_eq!(report.total_hosts, 1);
+    assert!(report.fallback_hosts.is_empty());
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = enriched["inventory"]["host_facts"].as_object().unwrap();
+    assert_eq!(host_facts.keys().collect::<Vec<_>>(), ["localhost"]);
+    assert_eq!(
+        enriched["inventory"]["skipped_hosts"],
+        serde_json::json!(["decom1.invalid", "old-db.invalid"])
+    );
+}
+
+#[tokio::test]
+async fn test_limit_skips_unmatched_hosts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "localhost": {"ansible_connection": "local"},
+                "web1.invalid": {},
+                "db1.invalid": {}
+            },
+            "groups": {"local": ["localhost"]},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        limit: vec!["local".to_string()],
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.limited_hosts, ["db1.invalid", "web1.invalid"]);
+    assert!(report.excluded_hosts.is_empty());
+    assert_eq!(report.total_hosts, 1);
+    assert!(report.fallback_hosts.is_empty());
+}
+
+#[tokio::test]
+async fn test_allow_empty_passes_hostless_input_through() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [{"name": "render", "hosts": "localhost", "tasks": [], "handlers": [], "roles": []}],
+        "variables": {}, "facts_required": false, "vault_ids": [],
+        "inventory": {"hosts": {}, "groups": {}, "variables": {}}
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let refused = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(refused.unwrap_err().to_string().contains("No hosts found"));
+
+    let config = FactsConfig {
+        allow_empty: true,
+        ..config
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.total_hosts, 0);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(enriched["inventory"]["host_facts"], serde_json::json!({}));
+    assert_eq!(enriched["plays"][0]["name"], "render");
+}
+
+#[tokio::test]
+async fn test_unresolvable_host_is_reported_as_dns_failure() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"gone.invalid": {}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(
+        report.unreachable_hosts.get("gone.invalid"),
+        Some(&rustle_facts::UnreachableReason::DnsFailure)
+    );
+}
+
+#[tokio::test]
+async fn test_unreachable_ssh_host_counts_as_fallback() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"srv1.invalid": {}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let mut config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.fallback_hosts, ["srv1.invalid"]);
+
+    config.fallback_severity = rustle_facts::config::FallbackSeverity::Error;
+    let mut output = Vec::new();
+    let result = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(
+        matches!(result, Err(rustle_facts::FactsError::FallbackFacts(ref hosts)) if hosts == "srv1.invalid")
+    );
+}
+
+#[tokio::test]
+async fn test_unreachable_ssh_host_is_not_cached() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"srv1.invalid": {}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let config = FactsConfig {
+        cache_file: dir.path().join("cache.json"),
+        connect_timeout: 2,
+        ..Default::default()
+    };
+
+    // The second run finds nothing cached and falls back again
+    for _ in 0..2 {
+        let mut output = Vec::new();
+        let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+            .await
+            .unwrap();
+        assert_eq!(report.fallback_hosts, ["srv1.invalid"]);
+
+        let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            enriched["inventory"]["fact_sources"]["srv1.invalid"]["ansible_architecture"],
+            "fallback"
+        );
+        let cache = std::fs::read_to_string(&config.cache_file).unwrap_or_default();
+        assert!(!cache.contains("srv1.invalid"));
+    }
+}
+
+#[tokio::test]
+async fn test_unreachable_network_device_keeps_its_platform() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"core-sw1.invalid": {"ansible_network_os": "cisco.ios.ios"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert!(report.unreachable_hosts.contains_key("core-sw1.invalid"));
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let facts = &enriched["inventory"]["host_facts"]["core-sw1.invalid"];
+    assert_eq!(facts["ansible_system"], "ios");
+    assert_eq!(facts["ansible_os_family"], "network");
+    assert_eq!(facts["ansible_architecture"], "unknown");
+}
+
+#[tokio::test]
+async fn test_hostname_spellings_are_merged() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [{"name": "web", "hosts": "Web01.Example.COM", "tasks": [], "handlers": [], "roles": []}],
+        "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "Web01.Example.COM": {"ansible_architecture": "aarch64", "ansible_system": "Linux"},
+                "web01.example.com.": {"ansible_os_family": "debian"}
+            },
+            "groups": {"web": ["web01.example.com"]},
+            "variables": {}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        play_host_facts: true,
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.total_hosts, 1);
+    assert_eq!(report.facts_declared, 1);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = enriched["inventory"]["host_facts"].as_object().unwrap();
+    // The merged host keeps a name the user wrote
+    assert_eq!(host_facts.keys().collect::<Vec<_>>(), ["Web01.Example.COM"]);
+    assert_eq!(
+        host_facts["Web01.Example.COM"]["ansible_architecture"],
+        "aarch64"
+    );
+    assert_eq!(
+        enriched["inventory"]["groups"]["web"],
+        serde_json::json!(["Web01.Example.COM"])
+    );
+    assert!(enriched["play_host_facts"]["0"]["Web01.Example.COM"].is_object());
+}
+
+#[tokio::test]
+async fn test_output_is_byte_identical_across_runs() {
+    let hosts: serde_json::Map<String, serde_json::Value> = (0..40)
+        .map(|i| {
+            let facts = serde_json::json!({
+                "ansible_architecture": if i % 2 == 0 { "x86_64" } else { "aarch64" },
+                "ansible_system": "Linux",
+                "ansible_os_family": "debian"
+            });
+            (format!("host{i:02}"), facts)
+        })
+        .collect();
+    let input = serde_json::json!({
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {"hosts": hosts, "groups": {}, "variables": {}}
+    })
+    .to_string();
+
+    let config = FactsConfig {
+        no_cache: true,
+        ..Default::default()
+    };
+    let mut first = Vec::new();
+    enrich_with_facts(Cursor::new(&input), &mut first, &config)
+        .await
+        .unwrap();
+    let mut second = Vec::new();
+    enrich_with_facts(Cursor::new(&input), &mut second, &config)
+        .await
+        .unwrap();
+    assert_eq!(first, second);
+
+    // Library types serialize their maps sorted too
+    let parsed = rustle_facts::parse_playbook(input.as_bytes()).unwrap();
+    let json = serde_json::to_string(&parsed.inventory).unwrap();
+    let positions: Vec<usize> = (0..40)
+        .map(|i| json.find(&format!("\"host{i:02}\"")).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[tokio::test]
+async fn test_labels_are_recorded_in_cache_and_output() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let config = FactsConfig {
+        cache_file: dir.path().join("facts.json"),
+        labels: std::collections::BTreeMap::from([
+            ("datacenter".to_string(), "eu1".to_string()),
+            ("run_id".to_string(), "42".to_string()),
+        ]),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_labels"]["localhost"],
+        serde_json::json!({"datacenter": "eu1", "run_id": "42"})
+    );
+
+    let cache: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&config.cache_file).unwrap()).unwrap();
+    assert_eq!(
+        cache["facts"]["localhost"]["labels"],
+        serde_json::json!({"datacenter": "eu1", "run_id": "42"})
+    );
+
+    // A later run served from cache reports where the facts came from
+    let config = FactsConfig {
+        labels: std::collections::BTreeMap::from([("run_id".to_string(), "43".to_string())]),
+        ..config
+    };
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_labels"]["localhost"]["run_id"],
+        "42"
+    );
+}
+
+#[tokio::test]
+async fn test_cache_inventory_records_connection_parameters() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "localhost": {
+                    "ansible_connection": "local",
+                    "ansible_become_password": "hunter2",
+                    "app_release": "2024.1"
+                }
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let config = FactsConfig {
+        cache_file: dir.path().join("facts.json"),
+        cache_inventory: true,
+        ..Default::default()
+    };
+    enrich_with_facts(Cursor::new(input_json), &mut Vec::new(), &config)
+        .await
+        .unwrap();
+
+    let cache = rustle_facts::cache::load_cache(&config.cache_file).unwrap();
+    let shown = cache.describe("localhost").unwrap();
+    assert_eq!(shown["inventory"]["name"], "localhost");
+    assert_eq!(
+        shown["inventory"]["vars"],
+        serde_json::json!({"ansible_connection": "local"})
+    );
+    assert!(!std::fs::read_to_string(&config.cache_file)
+        .unwrap()
+        .contains("hunter2"));
+}
+
+#[tokio::test]
+async fn test_verify_sample_reports_drift() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let cache_file = dir.path().join("facts.json");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let stale = serde_json::json!({
+        "version": "1.0",
+        "facts": {
+            "localhost": {
+                "facts": {
+                    "ansible_architecture": "sparc64",
+                    "ansible_system": "Linux",
+                    "ansible_os_family": "debian",
+                    "ansible_distribution": null
+                },
+                "timestamp": now
+            }
+        }
+    });
+    std::fs::write(&cache_file, stale.to_string()).unwrap();
+
+    let config = FactsConfig {
+        cache_file: cache_file.clone(),
+        verify_sample: Some(100.0),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.verified_hosts, ["localhost"]);
+    let drift = report
+        .fact_drift
+        .iter()
+        .find(|drift| drift.fact == "ansible_architecture")
+        .unwrap();
+    assert_eq!(drift.cached, "sparc64");
+
+    // The re-gathered facts replace the drifted ones
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_ne!(
+        enriched["inventory"]["host_facts"]["localhost"]["ansible_architecture"],
+        "sparc64"
+    );
+}
+
+#[tokio::test]
+async fn test_shared_cache_keeps_evictions_without_new_facts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let cache_file = dir.path().join("facts.json");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let entry = |timestamp: u64| {
+        serde_json::json!({
+            "facts": {
+                "ansible_architecture": "x86_64",
+                "ansible_system": "Linux",
+                "ansible_os_family": "debian",
+                "ansible_distribution": null
+            },
+            "timestamp": timestamp
+        })
+    };
+    let cached = serde_json::json!({
+        "version": "1.0",
+        "facts": {"localhost": entry(now), "retired": entry(1000)}
+    });
+    std::fs::write(&cache_file, cached.to_string()).unwrap();
+
+    let shared = rustle_facts::shared_cache::SharedCache::open(&cache_file, None).unwrap();
+    let config = FactsConfig {
+        cache_file: cache_file.clone(),
+        shared_cache: Some(shared.clone()),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    // Every host was a cache hit, yet the expired entry is still dropped
+    assert!(!shared.snapshot().facts.contains_key("retired"));
+    let on_disk: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&cache_file).unwrap()).unwrap();
+    assert!(on_disk["facts"].get("retired").is_none());
+    assert!(on_disk["facts"].get("localhost").is_some());
+}
+
+#[tokio::test]
+async fn test_failed_hosts_file_round_trip() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "localhost": {"ansible_connection": "local"},
+                "gone.invalid": {}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let failed_file = dir.path().join("failed.txt");
+    let config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        failed_hosts_out: Some(failed_file.clone()),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&failed_file).unwrap(),
+        "gone.invalid\n"
+    );
+
+    let config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        only_hosts_from: Some(failed_file),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.total_hosts, 1);
+    assert_eq!(report.excluded_hosts, ["localhost"]);
+}
+
+#[tokio::test]
+async fn test_failed_hosts_file_is_written_when_the_run_fails() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "declared.invalid": {"ansible_architecture": "aarch64"},
+                "unreachable.invalid": {}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let failed_file = dir.path().join("failed.txt");
+    let config = FactsConfig {
+        no_cache: true,
+        failed_hosts_out: Some(failed_file.clone()),
+        ..Default::default()
+    };
+    config.shutdown.request();
+
+    let mut output = Vec::new();
+    let result = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(matches!(result, Err(rustle_facts::FactsError::Interrupted)));
+    assert_eq!(
+        std::fs::read_to_string(&failed_file).unwrap(),
+        "unreachable.invalid\n"
+    );
+}
+
+#[tokio::test]
+async fn test_unreadable_ssh_config_does_not_fail_the_run() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let ssh_config = dir.path().join("ssh_config");
+    std::fs::write(&ssh_config, "Host\n").unwrap();
+    let config = FactsConfig {
+        no_cache: true,
+        ssh_config: Some(ssh_config),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_gathered, 1);
+}
+
+#[tokio::test]
+async fn test_allow_stale_on_error_serves_expired_facts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"gone.invalid": {}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let cache_file = dir.path().join("facts.json");
+    let expired = serde_json::json!({
+        "version": "1.0",
+        "facts": {
+            "gone.invalid": {
+                "facts": {
+                    "ansible_architecture": "aarch64",
+                    "ansible_system": "Linux",
+                    "ansible_os_family": "redhat",
+                    "ansible_distribution": "rocky"
+                },
+                "timestamp": 1000
+            }
+        }
+    });
+    std::fs::write(&cache_file, expired.to_string()).unwrap();
+
+    let config = FactsConfig {
+        cache_file,
+        connect_timeout: 2,
+        allow_stale_on_error: true,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.stale_hosts, ["gone.invalid"]);
+    assert!(report.fallback_hosts.is_empty());
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let inventory = &enriched["inventory"];
+    assert_eq!(
+        inventory["host_facts"]["gone.invalid"]["ansible_architecture"],
+        "aarch64"
+    );
+    assert_eq!(
+        inventory["fact_sources"]["gone.invalid"]["ansible_architecture"],
+        "stale"
+    );
+    assert_eq!(
+        inventory["stale_hosts"],
+        serde_json::json!(["gone.invalid"])
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_cached_facts_are_passed_through() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"db1": {}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let cache_file = dir.path().join("facts.json");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let cache = serde_json::json!({
+        "version": "1.0",
+        "facts": {
+            "db1": {
+                "facts": {
+                    "ansible_architecture": "x86_64",
+                    "ansible_system": "Linux",
+                    "ansible_os_family": "debian",
+                    "ansible_distribution": "ubuntu",
+                    "rustle_kernel": "6.8.0"
+                },
+                "timestamp": now
+            }
+        }
+    });
+    std::fs::write(&cache_file, cache.to_string()).unwrap();
+
+    let config = FactsConfig {
+        cache_file,
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.cache_hits, 1);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(enriched["inventory"]["facts_schema_version"], 1);
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["db1"]["rustle_kernel"],
+        "6.8.0"
+    );
+    let facts: ArchitectureFacts =
+        serde_json::from_value(enriched["inventory"]["host_facts"]["db1"].clone()).unwrap();
+    assert_eq!(facts.extra["rustle_kernel"], "6.8.0");
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_skips_hosts_behind_a_dead_gateway() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"db1": {}, "db2": {}, "db3": {}},
+            "groups": {},
+            "variables": {"rustle_facts_gateway": "gone.invalid"}
+        }
+    }"#;
+
+    let config = FactsConfig {
+        no_cache: true,
+        connect_timeout: 2,
+        parallel_connections: 1,
+        circuit_breaker: Some(1),
+        ..Default::default()
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    let gateway = &report.backend_health["gateway gone.invalid"];
+    assert_eq!(gateway.failed, 1);
+    assert_eq!(gateway.skipped, 2);
+    assert!(gateway.tripped_by.is_some());
+    assert_eq!(report.fallback_hosts, ["db1", "db2", "db3"]);
+}
+
+#[tokio::test]
+async fn test_fact_plugins_extend_gathered_facts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {"localhost": {"ansible_connection": "local"}},
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+
+    let dir = tempdir().unwrap();
+    let mut config = FactsConfig {
+        cache_file: dir.path().join("facts.json"),
+        ..Default::default()
+    };
+    config
+        .fact_plugins
+        .register("greeting", |transport| async move {
+            let output = transport.run("echo hello").await?;
+            let mut facts = serde_json::Map::new();
+            facts.insert("greeting".to_string(), output.trim().into());
+            Ok(facts)
+        });
+
+    let mut output = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["localhost"]["greeting"],
+        "hello"
+    );
+
+    let cache: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&config.cache_file).unwrap()).unwrap();
+    assert_eq!(cache["facts"]["localhost"]["facts"]["greeting"], "hello");
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "mock")]
+
+use rustle_facts::faults::FaultKind;
+use rustle_facts::{enrich_with_facts, FactsConfig};
+use std::io::Cursor;
+use std::path::PathBuf;
+use tempfile::tempdir;
+
+fn mock_config(cache_file: PathBuf) -> FactsConfig {
+    FactsConfig {
+        cache_file,
+        mock_fixture: Some(PathBuf::from("tests/fixtures/mock_facts.json")),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_mock_enrichment_is_deterministic() {
+    let input_json = include_str!("fixtures/mock_playbook.json");
+    let dir = tempdir().unwrap();
+    let config = mock_config(dir.path().join("cache.json"));
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    assert_eq!(report.total_hosts, 3);
+    assert_eq!(report.facts_gathered, 2);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = &enriched["inventory"]["host_facts"];
+
+    assert_eq!(host_facts["web1"]["ansible_architecture"], "aarch64");
+    assert_eq!(host_facts["web1"]["ansible_distribution"], "ubuntu");
+    assert_eq!(host_facts["db1"]["ansible_os_family"], "redhat");
+    // Failed mock hosts get the same fallback as unreachable SSH hosts
+    assert_eq!(host_facts["broken"]["ansible_architecture"], "x86_64");
+    assert_eq!(host_facts["broken"]["ansible_os_family"], "debian");
+}
+
+#[tokio::test]
+async fn test_mock_enrichment_uses_cache_on_second_run() {
+    let input_json = include_str!("fixtures/mock_playbook.json");
+    let dir = tempdir().unwrap();
+    let config = mock_config(dir.path().join("cache.json"));
+
+    let mut first = Vec::new();
+    enrich_with_facts(Cursor::new(input_json), &mut first, &config)
+        .await
+        .unwrap();
+
+    let mut second = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut second, &config)
+        .await
+        .unwrap();
+
+    // Only the failing host is retried, and it still produces nothing
+    assert_eq!(report.facts_gathered, 0);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&second).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["web1"]["ansible_architecture"],
+        "aarch64"
+    );
+}
+
+#[tokio::test]
+async fn test_injected_failure_falls_back() {
+    let input_json = include_str!("fixtures/mock_playbook.json");
+    let mut config = FactsConfig {
+        no_cache: true,
+        ..mock_config(PathBuf::from("unused.json"))
+    };
+    config
+        .fault_injection
+        .failures
+        .insert("web1".to_string(), FaultKind::Refused);
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_gathered, 1);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["web1"]["ansible_architecture"],
+        "x86_64"
+    );
+}
+
+#[tokio::test]
+async fn test_aliases_share_gathered_facts() {
+    let input_json = r#"{
+        "metadata": {"file_path": null, "version": null, "created_at": null, "checksum": null},
+        "plays": [], "variables": {}, "facts_required": true, "vault_ids": [],
+        "inventory": {
+            "hosts": {
+                "app": {
+                    "ansible_connection": "mock",
+                    "ansible_host": "10.0.0.5",
+                    "rustle_mock_output": "ARCH=arm64\nSYSTEM=Linux\nOS_FAMILY=debian"
+                },
+                "app-metrics": {"ansible_connection": "mock", "ansible_host": "10.0.0.5"}
+            },
+            "groups": {},
+            "variables": {}
+        }
+    }"#;
+    let dir = tempdir().unwrap();
+    let config = FactsConfig {
+        cache_file: dir.path().join("cache.json"),
+        ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+
+    assert_eq!(report.shared_facts.get("app-metrics").unwrap(), "app");
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let host_facts = &enriched["inventory"]["host_facts"];
+    assert_eq!(host_facts["app-metrics"]["ansible_architecture"], "aarch64");
+    assert_eq!(host_facts["app-metrics"], host_facts["app"]);
+
+    let cache: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&config.cache_file).unwrap()).unwrap();
+    assert!(cache["facts"]["app-metrics"].is_object());
+}
+
+#[tokio::test]
+async fn test_deadline_writes_partial_output() {
+    let input_json = include_str!("fixtures/mock_playbook.json");
+    let mut config = FactsConfig {
+        no_cache: true,
+        deadline: 1,
+        ..mock_config(PathBuf::from("unused.json"))
+    };
+    config.fault_injection.latency = Some(std::time::Duration::from_secs(30));
+
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_gathered, 0);
+    assert_eq!(report.timed_out_hosts, ["broken", "db1", "web1"]);
+
+    let enriched: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(enriched["partial"], true);
+    assert_eq!(
+        enriched["inventory"]["host_facts"]["web1"]["ansible_architecture"],
+        "x86_64"
+    );
+}
+
+#[tokio::test]
+async fn test_max_failed_hosts_aborts() {
+    let input_json = include_str!("fixtures/mock_playbook.json");
+    let config = FactsConfig {
+        no_cache: true,
+        max_failed_hosts: Some(0),
+        ..mock_config(PathBuf::from("unused.json"))
+    };
+
+    let mut output = Vec::new();
+    let result = enrich_with_facts(Cursor::new(input_json), &mut output, &config).await;
+    assert!(matches!(
+        result,
+        Err(rustle_facts::FactsError::TooManyFailures)
+    ));
+    assert!(output.is_empty());
+
+    // One failure in three hosts is within 50%
+    let config = FactsConfig {
+        no_cache: true,
+        max_failed_percent: Some(50),
+        ..mock_config(PathBuf::from("unused.json"))
+    };
+    let mut output = Vec::new();
+    let report = enrich_with_facts(Cursor::new(input_json), &mut output, &config)
+        .await
+        .unwrap();
+    assert_eq!(report.facts_gathered, 2);
+}
@@ -0,0 +1,81 @@
+use proptest::prelude::*;
+use rustle_facts::{
+    normalize_inventory, parse_fact_bytes, parse_fact_output, parse_fact_pairs, parse_playbook,
+    ArchitectureFacts, ParsedInventory,
+};
+use std::collections::HashMap;
+
+fn fact_value() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9_.-]{1,16}"
+}
+
+/// Banner and MOTD lines that never look like a fact assignment.
+fn noise_line() -> impl Strategy<Value = String> {
+    "[^=\r\n]{0,40}"
+}
+
+proptest! {
+    #[test]
+    fn parse_fact_output_never_panics(output in any::<String>()) {
+        let _ = parse_fact_output(&output);
+        let _ = parse_fact_pairs(&output);
+    }
+
+    #[test]
+    fn parse_fact_bytes_never_panics(output in any::<Vec<u8>>()) {
+        let _ = parse_fact_bytes(&output);
+    }
+
+    #[test]
+    fn parse_fact_output_survives_crlf_and_noise(
+        arch in fact_value(),
+        system in fact_value(),
+        family in fact_value(),
+        banner in prop::collection::vec(noise_line(), 0..5),
+        trailer in prop::collection::vec(noise_line(), 0..5),
+        crlf in any::<bool>(),
+    ) {
+        // Known distro IDs are normalized to their family
+        prop_assume!(ArchitectureFacts::normalize_os_family(None, Some(&family)).is_none());
+        let eol = if crlf { "\r\n" } else { "\n" };
+        let mut lines = banner;
+        lines.push(format!("ARCH={arch}"));
+        lines.push(format!("SYSTEM={system}"));
+        lines.push(format!("OS_FAMILY=\"{family}\""));
+        lines.push("UNEXPECTED_KEY=whatever".to_string());
+        lines.extend(trailer);
+
+        let facts = parse_fact_output(&lines.join(eol)).unwrap();
+        prop_assert_eq!(facts.ansible_architecture, ArchitectureFacts::normalize_architecture(&arch));
+        prop_assert_eq!(facts.ansible_system, system);
+        prop_assert_eq!(facts.ansible_os_family, family);
+    }
+
+    #[test]
+    fn parse_playbook_never_panics(input in any::<Vec<u8>>()) {
+        let _ = parse_playbook(&input);
+    }
+
+    #[test]
+    fn normalize_inventory_covers_every_named_host(
+        hosts in prop::collection::hash_set("[a-z][a-z0-9-]{0,12}", 0..8),
+        grouped in prop::collection::hash_set("[a-z][a-z0-9-]{0,12}", 0..8),
+    ) {
+        let inventory: ParsedInventory = serde_json::from_value(serde_json::json!({
+            "hosts": hosts.iter().map(|h| (h.clone(), serde_json::json!({}))).collect::<HashMap<_, _>>(),
+            "groups": { "web": grouped },
+        }))
+        .unwrap();
+
+        match normalize_inventory(&inventory) {
+            Ok(entries) => {
+                let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+                for host in hosts.iter().chain(grouped.iter()) {
+                    prop_assert!(names.contains(&host.as_str()));
+                }
+                prop_assert!(names.windows(2).all(|w| w[0] < w[1]));
+            }
+            Err(_) => prop_assert!(hosts.is_empty() && grouped.is_empty()),
+        }
+    }
+}
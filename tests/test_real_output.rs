@@ -14,6 +14,7 @@ async fn test_real_rustle_parse_output() {
         cache_file: cache_file.clone(),
         no_cache: false,
         force_refresh: true,
+        parsed_ssh_config: Some(Default::default()),
         ..Default::default()
     };
 